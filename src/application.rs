@@ -1,47 +1,143 @@
 // Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
 // SPDX-License-Identifier: GPL-3.0
 
-use crate::application::calibration::calibration;
+use crate::application::calibration::{
+    abort_calibration, calibration, CalibrationSnapshot, SampleCapture, StickScope,
+    TriggerThresholdCapture, TriggerThresholds,
+};
+use crate::application::calibration_editor::{calibration_editor, CalibrationEditor};
+use crate::application::calibration_flag_watch::CalibrationFlagWatch;
+use crate::application::calibration_log::{CalibrationLog, CALIBRATION_LOG_FILE_NAME};
+use crate::application::confirmation::confirmation_dialog;
+use crate::application::device_id::{find_by_serial_number, DeviceId};
 use crate::application::device_info::device_info;
-use crate::application::flash::{flash, Flash};
-use crate::application::font::{with_gamepad_font, GAMEPAD_FONT_SYMBOL};
-use crate::application::output::{output, Output};
+use crate::application::device_task::DeviceTask;
+use crate::application::diagnostics::BundleComponents;
+use crate::application::error_detail::{error_detail_window, ErrorDetail};
+use crate::application::flash::{flash, offline_flash, CachedFlashMirror, Flash};
+use crate::application::font::{check_gamepad_glyphs, with_gamepad_font, GAMEPAD_FONT_SYMBOL};
+use crate::application::health::{health, DriftCheck, DriftCheckStep, Health};
+use crate::application::i18n::{set_language, t, tf};
+use crate::application::lightbar::{BatteryLightbar, BATTERY_LIGHTBAR_INTERVAL};
+use crate::application::output::{output, overlay, Output, StickPlotColors, StickPlotStyle};
+use crate::application::remap::Remap;
+use crate::application::settings::{settings_window, Settings};
+use crate::application::stick_presets::{StickPresetStore, STICK_PRESETS_FILE_NAME};
 use crate::application::test_commands::test_commands;
-use crate::dual_shock_4::{DualShock4, TestCommand, TestData};
+use crate::dual_shock_4::{
+    is_dual_shock_4, CalibrationDeviceType, ConnectionType, DeviceOptions, DualShock4,
+    StickCenterCalibration, TestCommand, TestData, UsbBtMode,
+};
 use device_info::DeviceInfo;
 use eframe::egui::panel::{Side, TopBottomSide};
-use eframe::egui::{Color32, Context, FontFamily, Response, RichText, ScrollArea, ViewportBuilder};
+use eframe::egui::{
+    Color32, Context, FontFamily, Key, Modifiers, Response, RichText, ScrollArea, Sense,
+    ViewportBuilder, ViewportCommand,
+};
 use eframe::{egui, Frame};
 use font::GAMEPAD_FONT_FAMILY;
-use hidapi::{HidApi, HidError};
-use log::{error, info};
+use hidapi::{DeviceInfo as HidDeviceInfo, HidApi, HidError};
+use log::{error, info, warn};
+use std::collections::VecDeque;
 use std::ffi::CString;
 use std::fmt::{Display, Formatter};
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::time::Duration;
+use std::mem;
+use std::panic::Location;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
 mod calibration;
+mod calibration_editor;
+mod calibration_flag_watch;
+mod calibration_log;
+mod confirmation;
+mod device_id;
 mod device_info;
+mod device_task;
+mod diagnostics;
+mod error_detail;
 mod flash;
+mod health;
+mod i18n;
+mod lightbar;
 mod output;
+mod recording;
+mod remap;
+mod settings;
+mod stick_presets;
 mod test_commands;
+mod undo;
 
 mod font;
 
 const UNDEFINED_STRING: &str = "undefined";
+// Toggles the overlay mode
+const OVERLAY_HOTKEY: egui::Key = egui::Key::F9;
+
+const USB_INTERFACE_NUMBER: i32 = 0;
+// Bluetooth and some platform backends don't report interface numbers at all
+const UNKNOWN_INTERFACE_NUMBER: i32 = -1;
+// Backends that can't parse the report descriptor report a zero usage page
+const UNKNOWN_USAGE_PAGE: u16 = 0x00;
+const GENERIC_DESKTOP_USAGE_PAGE: u16 = 0x01;
+const GAMEPAD_USAGE: u16 = 0x05;
+
+const RECONNECT_WINDOW: Duration = Duration::from_secs(10);
+const RECONNECT_ATTEMPT_INTERVAL: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_ATTEMPTS: u32 = 5;
+// Gives the current user access to the hidraw nodes of Sony devices, USB and Bluetooth
+const LINUX_UDEV_RULE: &str = concat!(
+    "KERNEL==\"hidraw*\", ATTRS{idVendor}==\"054c\", MODE=\"0660\", TAG+=\"uaccess\"\n",
+    "KERNEL==\"hidraw*\", KERNELS==\"*054C:*\", MODE=\"0660\", TAG+=\"uaccess\"",
+);
+// Progress of an operation dropped without finishing, e.g. by leaving its panel
+const PROGRESS_STALE_AFTER: Duration = Duration::from_secs(2);
+// Quick device operations would only make the busy spinner flicker
+const BUSY_SPINNER_DELAY: Duration = Duration::from_millis(200);
+const DISCONNECT_EXPECTED_TIMEOUT: Duration = Duration::from_secs(5);
+// Only sent after this long without other output reports
+const KEEP_AWAKE_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Debug)]
 pub enum Error {
-    DualShock4Error(crate::dual_shock_4::Error),
+    DualShock4(crate::dual_shock_4::Error),
     HidError(HidError),
-    EframeError(eframe::Error),
+    Eframe(eframe::Error),
+    // What was being done and the cause
+    Io(String, std::io::Error),
+    Logger(log::SetLoggerError),
+    Startup(String),
+    // Serial number no listed device reports
+    DeviceNotFound(String),
+    // Serial number and the count of listed devices reporting it
+    AmbiguousSerialNumber(String, usize),
 }
 
 impl std::error::Error for Error {}
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Error::DualShock4(error) => write!(f, "{}", error),
+            Error::Io(context, error) => write!(f, "{}: {}", context, error),
+            Error::Logger(error) => write!(f, "Cannot initialize logging: {}", error),
+            Error::Startup(message) => write!(f, "{}", message),
+            Error::Eframe(error) => write!(f, "{}", error),
+            Error::DeviceNotFound(serial_number) => {
+                write!(
+                    f,
+                    "No controller with serial number {} is connected",
+                    serial_number
+                )
+            }
+            Error::AmbiguousSerialNumber(serial_number, count) => write!(
+                f,
+                "{} connected controllers report serial number {}, select one by path",
+                count, serial_number
+            ),
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
@@ -53,13 +149,19 @@ impl From<HidError> for Error {
 
 impl From<crate::dual_shock_4::Error> for Error {
     fn from(value: crate::dual_shock_4::Error) -> Self {
-        Self::DualShock4Error(value)
+        Self::DualShock4(value)
     }
 }
 
 impl From<eframe::Error> for Error {
     fn from(value: eframe::Error) -> Self {
-        Self::EframeError(value)
+        Self::Eframe(value)
+    }
+}
+
+impl From<log::SetLoggerError> for Error {
+    fn from(value: log::SetLoggerError) -> Self {
+        Self::Logger(value)
     }
 }
 
@@ -67,17 +169,121 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 pub enum Status {
     Ok,
-    Error(Box<dyn std::error::Error>),
+    Error(ErrorDetail),
     Message(String),
+    Progress(Progress),
+    // The operation finished or was cancelled
+    ProgressFinished(&'static str),
+}
+
+// Long operation shown in the status bar, one at a time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    // Updates of other operations are ignored until this one finishes
+    pub operation: &'static str,
+    pub current: usize,
+    pub total: usize,
+    pub label: String,
+}
+
+impl Progress {
+    fn is_finished(&self) -> bool {
+        self.current >= self.total
+    }
+
+    fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 1f32;
+        }
+        self.current as f32 / self.total as f32
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub device: DeviceOptions,
+    pub settings_file: PathBuf,
+    pub data_dir: PathBuf,
+    pub log_file: PathBuf,
+    // From the command line, takes precedence over the settings
+    pub connection_type: Option<ConnectionType>,
+    // Start in the overlay mode
+    pub overlay: bool,
+    pub safe_mode: bool,
+    // Advanced diagnostics are shown
+    pub dev: bool,
+    // Serial number or path from the command line, opened at the start
+    pub startup_device: Option<String>,
 }
 
 pub struct Application {
+    options: Options,
+    settings: Settings,
+    settings_open: bool,
+    // Waits for the confirmation of showing the Test Commands panel
+    test_commands_confirmation: bool,
     api: HidApi,
     devices: Vec<Device>,
     ui_state: UIState,
-    status_receiver: Receiver<Status>,
     status_handler: StatusHandler,
     last_status: Status,
+    // Detail text of the last error, shown in its window
+    error_detail: Option<String>,
+    error_detail_open: bool,
+    // Flash mirror cache of the last disconnected device
+    flash_mirror_cache: Option<CachedFlashMirror>,
+    reconnect: Option<Reconnect>,
+    // Borderless transparent window with only the buttons and sticks
+    overlay: bool,
+    // Write capable panels and actions are unavailable until the restart
+    safe_mode: bool,
+    // Active operation with the time of its last update
+    progress: Option<(Progress, Instant)>,
+    // Flash file inspector shown while no device is connected
+    offline_flash: Option<Flash>,
+    // Calibration file editor shown while no device is connected
+    offline_calibration: Option<CalibrationEditor>,
+    // Device list row highlighted by the arrow keys, Enter selects it
+    device_cursor: Option<usize>,
+    // None forces a refresh on the next frame
+    last_device_refresh: Option<Instant>,
+}
+
+// Pending auto-reconnect to a device that disappeared from the list
+struct Reconnect {
+    device_key: String,
+    panel: Panel,
+    lost_at: Instant,
+    attempts: u32,
+    last_attempt: Option<Instant>,
+}
+
+impl Reconnect {
+    fn new(device_key: String, panel: Panel) -> Self {
+        Self {
+            device_key,
+            panel,
+            lost_at: Instant::now(),
+            attempts: 0,
+            last_attempt: None,
+        }
+    }
+
+    fn expired(&self) -> bool {
+        self.lost_at.elapsed() > RECONNECT_WINDOW || self.attempts >= RECONNECT_MAX_ATTEMPTS
+    }
+
+    fn attempt_allowed(&self) -> bool {
+        self.last_attempt
+            .is_none_or(|last_attempt| last_attempt.elapsed() >= RECONNECT_ATTEMPT_INTERVAL)
+    }
+
+    fn matches(&self, device: &HidDeviceInfo) -> bool {
+        let serial_number_matches = device.serial_number().is_some_and(|serial_number| {
+            !serial_number.is_empty() && serial_number == self.device_key
+        });
+        serial_number_matches || device.path().to_string_lossy() == self.device_key
+    }
 }
 
 enum UIState {
@@ -89,6 +295,73 @@ pub struct DeviceConnected {
     device: ConnectedDevice,
     panel: Panel,
     permanent: bool,
+    // Serial number, or path when the device doesn't report one
+    device_key: String,
+    flash_mirror_cache: Option<CachedFlashMirror>,
+    // Calibration data captured when a wizard was started
+    calibration_snapshot: Option<CalibrationSnapshot>,
+    calibration_log: CalibrationLog,
+    // Waits for the discard confirmation of unsaved changes
+    pending_panel: Option<Panel>,
+    // Device type of the started calibration, panels can't be switched until it's stopped
+    active_calibration: Option<CalibrationDeviceType>,
+    battery_lightbar: BatteryLightbar,
+    stick_presets: StickPresetStore,
+    // Copy of the settings remap, saved back when changed
+    remap: Remap,
+    safe_mode: bool,
+    // Runs once after connecting, None when finished or skipped
+    drift_check: Option<DriftCheck>,
+    drift_warning: Option<String>,
+    // Polled while the Calibration panel is shown
+    calibration_flag_watch: CalibrationFlagWatch,
+    // Advisory, dismissed by the user
+    interference_warning: bool,
+    // Copy of the settings colors
+    stick_plot_colors: StickPlotColors,
+    stick_plot_style: StickPlotStyle,
+    stick_center_scope: StickScope,
+    // Stick center samples being captured in a batch
+    sample_capture: Option<SampleCapture>,
+    // Left and right, known after a trigger calibration of this connection
+    trigger_thresholds: [Option<TriggerThresholds>; 2],
+    trigger_capture: TriggerThresholdCapture,
+    diagnostics_bundle: BundleComponents,
+    log_file: PathBuf,
+    data_dir: PathBuf,
+    dev: bool,
+    // Waits for the confirmation of the USB/BT mode switch
+    usb_bt_control_confirmation: Option<UsbBtMode>,
+    // Time of the USB/BT mode switch, the device list is refreshed every frame until the device
+    // disappears or DISCONNECT_EXPECTED_TIMEOUT passes
+    disconnect_expected: Option<Instant>,
+    // Copy of the settings flag
+    auto_flash_write: bool,
+    // Copy of the settings flag, the panel and its unlock combination are unavailable without it
+    test_commands: bool,
+    auto_flash_write_confirmed: bool,
+    // Successful stick center calibration waiting to be written
+    auto_flash_write_pending: Option<StickCenterCalibration>,
+    auto_flash_write_task: Option<DeviceTask<bool>>,
+}
+
+impl DeviceConnected {
+    fn switch_panel(&mut self, panel: Panel) {
+        if self.panel.has_unsaved_changes() {
+            self.pending_panel = Some(panel);
+        } else {
+            self.panel = panel;
+        }
+    }
+
+    // Two feature reports, done when the state may have changed outside the Flash checkbox.
+    // The last known value stays when the read fails
+    fn refresh_permanent(&mut self, sh: StatusHandler) {
+        let ConnectedDevice::DualShock4(_, ds4) = &self.device;
+        self.permanent = sh
+            .handle_error(ds4.read_permanent())
+            .unwrap_or(self.permanent);
+    }
 }
 
 impl ConnectedDevice {
@@ -99,21 +372,28 @@ impl ConnectedDevice {
     }
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Device {
-    DualShock4(CString),
+    DualShock4(DeviceId),
 }
 
 impl Device {
     pub fn path(&self) -> &CString {
         match self {
-            Device::DualShock4(path) => path,
+            Device::DualShock4(id) => id.path(),
+        }
+    }
+
+    fn is_moved(&self, other: &Device) -> bool {
+        match (self, other) {
+            (Device::DualShock4(id), Device::DualShock4(other)) => id.is_moved(other),
         }
     }
 }
 
+// Long operations hold a clone of the device until they finish
 pub enum ConnectedDevice {
-    DualShock4(Device, DualShock4),
+    DualShock4(Device, Arc<DualShock4>),
 }
 
 impl ConnectedDevice {
@@ -126,29 +406,61 @@ impl ConnectedDevice {
 
 enum Panel {
     DeviceInfo(DeviceInfo),
+    Health(Health),
     Output(Output),
     Calibration(calibration::Panel),
     Flash(Flash),
     Test(Option<TestData>, Option<String>, Option<String>),
 }
 
-#[derive(Clone)]
+impl Panel {
+    fn has_unsaved_changes(&self) -> bool {
+        match self {
+            Panel::Calibration(panel) => panel.has_unsaved_changes(),
+            _ => false,
+        }
+    }
+}
+
+// Clones share one queue that lives as long as any of them and is drained by the UI, so a
+// handler kept by a panel across a reconnect can't lose its statuses. Clones may be moved to
+// other threads
+#[derive(Clone, Default)]
 pub struct StatusHandler {
-    status_sender: Sender<Status>,
+    statuses: Arc<Mutex<VecDeque<Status>>>,
 }
 
 impl StatusHandler {
-    pub fn new(status_sender: Sender<Status>) -> Self {
-        Self { status_sender }
+    fn send(&self, status: Status) {
+        let mut statuses = self.lock();
+        // Every successful call reports Ok, only the latest of a run is kept
+        if matches!(status, Status::Ok) && matches!(statuses.back(), Some(Status::Ok)) {
+            return;
+        }
+        statuses.push_back(status);
     }
 
+    // Statuses in the order they were sent since the last call
+    fn take(&self) -> VecDeque<Status> {
+        mem::take(&mut *self.lock())
+    }
+
+    // A thread that panicked while sending leaves the queue usable
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Status>> {
+        self.statuses
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Both keep the caller location for the error detail
+    #[track_caller]
     fn handle_error<'a, T, E: std::error::Error + 'static>(
         &self,
         result: std::result::Result<T, E>,
     ) -> Option<T> {
         match result {
             Ok(result) => {
-                let _ = self.status_sender.send(Status::Ok);
+                self.send(Status::Ok);
                 Some(result)
             }
             Err(error) => {
@@ -161,56 +473,131 @@ impl StatusHandler {
     fn message(&self, message: impl Into<String>) {
         let string: String = message.into();
         info!("Message: {:?}", string);
-        let _ = self.status_sender.send(Status::Message(string));
+        self.send(Status::Message(string));
     }
 
+    #[track_caller]
     fn error(&self, error: Box<dyn std::error::Error>) {
-        error!("{:?}", error);
-        let _ = self.status_sender.send(Status::Error(error));
+        if matches!(
+            error.downcast_ref::<crate::dual_shock_4::Error>(),
+            Some(crate::dual_shock_4::Error::Cancelled)
+        ) {
+            info!("Cancelled by the user");
+            self.send(Status::Ok);
+            return;
+        }
+        let location = Location::caller();
+        error!("{:?} at {}", error, location);
+        self.send(Status::Error(ErrorDetail::new(error, location)));
+    }
+
+    fn progress(&self, progress: Progress) {
+        self.send(Status::Progress(progress));
+    }
+
+    fn progress_finished(&self, operation: &'static str) {
+        self.send(Status::ProgressFinished(operation));
     }
 }
 
 impl eframe::App for Application {
     fn update(&mut self, ctx: &Context, frame: &mut Frame) {
+        check_gamepad_glyphs(ctx);
         self.update_ui(ctx, frame);
     }
+
+    fn clear_color(&self, visuals: &egui::Visuals) -> [f32; 4] {
+        if self.overlay {
+            Color32::TRANSPARENT.to_normalized_gamma_f32()
+        } else {
+            visuals.panel_fill.to_normalized_gamma_f32()
+        }
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if let UIState::DeviceConnected(state) = &self.ui_state {
+            let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+            if let Err(e) = ds4.restore_output_state() {
+                warn!("Cannot restore the output state of the device: {}", e);
+            }
+        }
+    }
 }
 
 impl Application {
-    pub fn new(cc: &eframe::CreationContext<'_>) -> Result<Self> {
+    pub fn new(cc: &eframe::CreationContext<'_>, mut options: Options) -> Result<Self> {
         Self::setup_assets(cc);
+        if options.device.dry_run {
+            warn!("Dry-run mode: reports are logged instead of being sent to the device");
+        }
+
+        let settings = Settings::load(&options.settings_file);
+        set_language(settings.language);
+        options.device.report_retries = settings.report_retries;
+        options.device.input_report_drain_count = settings.input_report_drain_count;
+        options.device.connection_type = options.connection_type.or(settings.connection_type);
 
         let api = HidApi::new().map_err(Error::HidError)?;
 
         let devices = Vec::new();
-        let (status_sender, status_receiver) = channel();
-        let status_handler = StatusHandler::new(status_sender);
+        let status_handler = StatusHandler::default();
         let ui_state = UIState::DeviceNotConnected;
         let last_status = Status::Ok;
 
+        let overlay = options.overlay;
+        let safe_mode = options.safe_mode || settings.safe_mode;
+        options.device.safe_mode = safe_mode;
+        if safe_mode {
+            info!("Safe mode: flash, test commands and calibration writes are disabled");
+        }
         let mut self_ = Self {
+            options,
+            settings,
+            settings_open: false,
+            test_commands_confirmation: false,
             api,
             devices,
             ui_state,
-            status_receiver,
             status_handler,
             last_status,
+            error_detail: None,
+            error_detail_open: false,
+            flash_mirror_cache: None,
+            reconnect: None,
+            overlay,
+            safe_mode,
+            progress: None,
+            offline_flash: None,
+            offline_calibration: None,
+            device_cursor: None,
+            last_device_refresh: None,
         };
 
         Self::refresh_devices(&mut self_)?;
+        if let Some(device) = self_.options.startup_device.clone() {
+            self_.open_startup_device(&device);
+        }
         Ok(self_)
     }
 
-    pub fn show() -> Result<()> {
-        let options = eframe::NativeOptions {
-            viewport: ViewportBuilder::default().with_resizable(true).with_inner_size((800.0, 800.0)),
+    pub fn show(options: Options) -> Result<()> {
+        let native_options = eframe::NativeOptions {
+            viewport: ViewportBuilder::default()
+                .with_resizable(true)
+                .with_inner_size((800.0, 800.0))
+                // Transparent only in the overlay mode, the panels are opaque otherwise
+                .with_transparent(true)
+                .with_decorations(!options.overlay),
             ..Default::default()
         };
 
         let _ = eframe::run_native(
             "DS4 Utils",
-            options,
-            Box::new(|cc| Ok(Box::new(Application::new(cc).unwrap()))),
+            native_options,
+            Box::new(|cc| match Application::new(cc, options) {
+                Ok(application) => Ok(Box::new(application)),
+                Err(e) => Err(e.to_string().into()),
+            }),
         )?;
         Ok(())
     }
@@ -231,29 +618,121 @@ impl Application {
 
     fn refresh_devices(&mut self) -> Result<()> {
         self.api.refresh_devices().map_err(Error::HidError)?;
-        let devices: Vec<Device> = self
-            .api
-            .device_list()
-            .filter(|device| is_dual_shock_4(device.vendor_id(), device.product_id()))
-            .map(|device| Device::DualShock4(CString::from(device.path())))
+        let devices: Vec<Device> = dual_shock_4_devices(&self.api)
+            .map(|device| Device::DualShock4(DeviceId::from(device)))
             .collect();
-        let contains_current_device = if let UIState::DeviceConnected(state) = &mut self.ui_state {
-            let current = state.device.device().clone();
-            if devices.contains(&current) {
-                true
-            } else {
-                false
-            }
+        let mut reconnect_candidate = self.reconnect.as_ref().and_then(|reconnect| {
+            dual_shock_4_devices(&self.api)
+                .find(|device| reconnect.matches(device))
+                .map(|device| Device::DualShock4(DeviceId::from(device)))
+        });
+        let current_device = if let UIState::DeviceConnected(state) = &self.ui_state {
+            let Device::DualShock4(current) = state.device.device();
+            current
+                .find_listed(devices.iter().map(|Device::DualShock4(id)| id))
+                .map(|id| Device::DualShock4(id.clone()))
         } else {
-            false
+            None
         };
-        if !contains_current_device {
-            self.ui_state = UIState::DeviceNotConnected;
+        match current_device {
+            Some(device)
+                if self
+                    .device()
+                    .is_some_and(|current| current.is_moved(&device)) =>
+            {
+                // The open handle belongs to the old path, the device is reopened like after a
+                // reconnect, so its panel and cached state are kept
+                info!("Device moved to {:?}, reopening", device.path());
+                if let Some(state) = self.disconnect() {
+                    self.reconnect = Some(Reconnect::new(state.device_key, state.panel));
+                    reconnect_candidate = Some(device);
+                }
+            }
+            Some(_) => {}
+            None => {
+                if let Some(state) = self.disconnect() {
+                    if self.settings.auto_reconnect {
+                        self.status_handler
+                            .message("Device disconnected, waiting for it to reconnect");
+                        self.reconnect = Some(Reconnect::new(state.device_key, state.panel));
+                    }
+                }
+            }
         }
         self.devices = devices;
+        self.try_reconnect(reconnect_candidate);
         Ok(())
     }
 
+    // Returns the state of the disconnected device, its flash mirror cache is kept
+    fn disconnect(&mut self) -> Option<DeviceConnected> {
+        match mem::replace(&mut self.ui_state, UIState::DeviceNotConnected) {
+            UIState::DeviceConnected(mut state) => {
+                self.flash_mirror_cache = state.flash_mirror_cache.take();
+                // A calibration interrupted by the disconnect can't be compared with it later
+                state.calibration_snapshot = None;
+                Some(state)
+            }
+            UIState::DeviceNotConnected => None,
+        }
+    }
+
+    fn try_reconnect(&mut self, candidate: Option<Device>) {
+        let sh = self.status_handler.clone();
+        let Some(reconnect) = &mut self.reconnect else {
+            return;
+        };
+        if reconnect.expired() {
+            self.reconnect = None;
+            sh.message("Device did not reconnect, select it from the list");
+            return;
+        }
+        let Some(device) = candidate else {
+            return;
+        };
+        if !reconnect.attempt_allowed() {
+            return;
+        }
+        reconnect.attempts += 1;
+        reconnect.last_attempt = Some(Instant::now());
+        info!(
+            "Reconnecting to {:?}, attempt {}",
+            device, reconnect.attempts
+        );
+        self.open_device(&device);
+        if let UIState::DeviceConnected(state) = &mut self.ui_state {
+            if let Some(reconnect) = self.reconnect.take() {
+                if state.device_key == reconnect.device_key {
+                    Self::restore_panel(state, reconnect.panel, sh.clone());
+                }
+                sh.message("Device reconnected");
+            }
+        }
+    }
+
+    fn restore_panel(state: &mut DeviceConnected, previous: Panel, sh: StatusHandler) {
+        state.panel = match previous {
+            Panel::DeviceInfo(_) => return,
+            Panel::Health(_) => Panel::Health(Health::from_connected_device(&state.device)),
+            Panel::Output(output) => Panel::Output(output),
+            Panel::Calibration(_) => {
+                match calibration::Panel::info_from_device_connected(state, sh.clone()) {
+                    Some(panel) => Panel::Calibration(panel),
+                    None => return,
+                }
+            }
+            Panel::Flash(_) => {
+                state.refresh_permanent(sh);
+                Panel::Flash(Flash::from_cache(state.flash_mirror_cache.as_ref()))
+            }
+            Panel::Test(_, _, _) if !state.test_commands => return,
+            Panel::Test(_, _, _) => {
+                let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+                Panel::Test(sh.handle_error(ds4.read_test_data()), None, None)
+            }
+        };
+    }
+
     fn device(&self) -> Option<&Device> {
         match &self.ui_state {
             UIState::DeviceNotConnected => None,
@@ -261,92 +740,606 @@ impl Application {
         }
     }
 
+    // Selection by the user, cancels a pending auto-reconnect
     fn update_device(&mut self, device: Option<&Device>) {
         if device == self.device() {
             return;
         }
+        self.reconnect = None;
+        match device {
+            None => {
+                self.disconnect();
+            }
+            Some(device) => self.open_device(device),
+        }
+    }
+
+    // Paths may change when the device reconnects, so the path of the serial number is looked up
+    // in a refreshed device list. Open errors are reported like for a device selected in the list
+    pub fn open_by_serial(&mut self, serial_number: &str) -> Result<()> {
+        self.refresh_devices()?;
+        let ids = self.devices.iter().map(|Device::DualShock4(id)| id);
+        let device = Device::DualShock4(find_by_serial_number(ids, serial_number)?.clone());
+        self.update_device(Some(&device));
+        Ok(())
+    }
+
+    // A listed path is opened as is, anything else is taken for a serial number
+    fn open_startup_device(&mut self, device: &str) {
+        let listed = self
+            .devices
+            .iter()
+            .find(|listed| listed.path().to_string_lossy() == device)
+            .cloned();
+        let opened = match listed {
+            Some(listed) => {
+                self.update_device(Some(&listed));
+                Ok(())
+            }
+            None => self.open_by_serial(device),
+        };
+        if let Err(e) = opened {
+            self.status_handler.error(Box::new(e));
+        }
+    }
+
+    fn open_device(&mut self, device: &Device) {
         let sh = self.status_handler.clone();
+        self.disconnect();
         self.ui_state = match device {
-            None => UIState::DeviceNotConnected,
-            Some(device) => match device {
-                Device::DualShock4(path) => {
-                    let hid_device = self.api.open_path(path.as_ref());
-                    if let Some(hid_device) = sh.handle_error(hid_device) {
-                        let connected_device = ConnectedDevice::DualShock4(
-                            device.clone(),
-                            DualShock4::new(path.clone(), hid_device),
-                        );
-                        if let Some(device_info) =
-                            sh.handle_error(DeviceInfo::from_connected_device(&connected_device))
-                        {
-                            let ConnectedDevice::DualShock4(_, ds4) = &connected_device;
-                            let permanent = ds4.read_permanent().unwrap_or(false);
-                            UIState::DeviceConnected(DeviceConnected {
-                                device: connected_device,
-                                panel: Panel::DeviceInfo(device_info),
-                                permanent,
-                            })
-                        } else {
-                            UIState::DeviceNotConnected
-                        }
+            Device::DualShock4(id) => {
+                let path = id.path();
+                let ds4 = self
+                    .api
+                    .open_path(path.as_ref())
+                    .map_err(Error::from)
+                    .and_then(|hid_device| {
+                        Ok(DualShock4::new(
+                            path.clone(),
+                            hid_device,
+                            self.options.device.clone(),
+                        )?)
+                    });
+                if let Some(ds4) = sh.handle_error(ds4) {
+                    let connected_device =
+                        ConnectedDevice::DualShock4(device.clone(), Arc::new(ds4));
+                    if let Some(device_info) =
+                        sh.handle_error(DeviceInfo::from_connected_device(&connected_device))
+                    {
+                        let ConnectedDevice::DualShock4(_, ds4) = &connected_device;
+                        let permanent = ds4.read_permanent().unwrap_or(false);
+                        let interference_warning = ds4.interference_suspected();
+                        let device_key = device_info
+                            .serial_number
+                            .clone()
+                            .filter(|serial_number| !serial_number.is_empty())
+                            .unwrap_or_else(|| path.to_string_lossy().into_owned());
+                        let flash_mirror_cache = self
+                            .flash_mirror_cache
+                            .take()
+                            .filter(|cache| cache.is_for(&device_key));
+                        UIState::DeviceConnected(DeviceConnected {
+                            device: connected_device,
+                            panel: Panel::DeviceInfo(device_info),
+                            permanent,
+                            device_key,
+                            flash_mirror_cache,
+                            calibration_snapshot: None,
+                            calibration_log: CalibrationLog::new(
+                                self.options.data_dir.join(CALIBRATION_LOG_FILE_NAME),
+                                self.settings.scrub_calibration_log,
+                            ),
+                            pending_panel: None,
+                            active_calibration: None,
+                            battery_lightbar: BatteryLightbar::default(),
+                            stick_presets: StickPresetStore::load(
+                                self.options.data_dir.join(STICK_PRESETS_FILE_NAME),
+                            ),
+                            remap: self.settings.remap.clone(),
+                            safe_mode: self.safe_mode,
+                            drift_check: self
+                                .settings
+                                .drift_check_on_connect
+                                .then(|| DriftCheck::new(Instant::now())),
+                            drift_warning: None,
+                            calibration_flag_watch: CalibrationFlagWatch::default(),
+                            interference_warning,
+                            stick_plot_colors: self.settings.stick_plot_colors,
+                            stick_plot_style: self.settings.stick_plot_style,
+                            stick_center_scope: StickScope::default(),
+                            sample_capture: None,
+                            trigger_thresholds: [None; 2],
+                            trigger_capture: TriggerThresholdCapture::default(),
+                            diagnostics_bundle: BundleComponents::default(),
+                            log_file: self.options.log_file.clone(),
+                            data_dir: self.options.data_dir.clone(),
+                            dev: self.options.dev,
+                            usb_bt_control_confirmation: None,
+                            disconnect_expected: None,
+                            auto_flash_write: self.settings.auto_flash_write,
+                            test_commands: self.settings.test_commands,
+                            auto_flash_write_confirmed: false,
+                            auto_flash_write_pending: None,
+                            auto_flash_write_task: None,
+                        })
                     } else {
                         UIState::DeviceNotConnected
                     }
+                } else {
+                    UIState::DeviceNotConnected
                 }
-            },
+            }
         };
     }
 
     fn update_ui(&mut self, ctx: &Context, _frame: &mut Frame) {
+        if ctx.input(|input| input.key_pressed(OVERLAY_HOTKEY)) {
+            self.set_overlay(ctx, !self.overlay);
+        }
+        self.update_drift_check(ctx);
+        if self.overlay {
+            self.show_overlay(ctx);
+            self.update_battery_lightbar(ctx);
+            self.update_keep_awake(ctx);
+            return;
+        }
         self.show_status_bar(ctx);
         self.show_devices(ctx);
         self.show_content(ctx);
+        self.save_remap();
+        self.show_settings(ctx);
+        error_detail_window(
+            ctx,
+            &self.error_detail,
+            &mut self.error_detail_open,
+            self.status_handler.clone(),
+        );
+        self.update_battery_lightbar(ctx);
+        self.update_keep_awake(ctx);
+    }
+
+    fn set_overlay(&mut self, ctx: &Context, overlay: bool) {
+        self.overlay = overlay;
+        ctx.send_viewport_cmd(ViewportCommand::Decorations(!overlay));
+    }
+
+    fn show_overlay(&mut self, ctx: &Context) {
+        let sh = self.status_handler.clone();
+        // There is no status bar, the latest status is shown after leaving the overlay
+        self.receive_statuses();
+        let mut exit = false;
+        egui::CentralPanel::default()
+            .frame(egui::Frame::none())
+            .show(ctx, |ui| {
+                // The window has no title bar, so it's moved by dragging the background
+                let background = ui.interact(
+                    ui.max_rect(),
+                    ui.id().with("Overlay background"),
+                    egui::Sense::click_and_drag(),
+                );
+                if background.drag_started() {
+                    ctx.send_viewport_cmd(ViewportCommand::StartDrag);
+                }
+                background.context_menu(|ui| {
+                    if ui.button(t("overlay.exit")).clicked() {
+                        exit = true;
+                        ui.close_menu();
+                    }
+                });
+                if let UIState::DeviceConnected(state) = &self.ui_state {
+                    overlay(ui, ctx, state, sh);
+                } else {
+                    ui.label(t("overlay.not_connected"));
+                }
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Max), |ui| {
+                    let grip = ui
+                        .add(egui::Label::new("◢").sense(egui::Sense::drag()))
+                        .on_hover_text(t("help.overlay"));
+                    if grip.drag_started() {
+                        ctx.send_viewport_cmd(ViewportCommand::BeginResize(
+                            egui::ResizeDirection::SouthEast,
+                        ));
+                    }
+                });
+            });
+        if exit {
+            self.set_overlay(ctx, false);
+        }
+    }
+
+    fn update_battery_lightbar(&mut self, ctx: &Context) {
+        if !self.settings.battery_lightbar {
+            return;
+        }
+        if let UIState::DeviceConnected(state) = &mut self.ui_state {
+            let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+            let sh = self.status_handler.clone();
+            let color = state.battery_lightbar.update(Instant::now(), || {
+                let data = sh.handle_error(ds4.read_last_data()).flatten()?;
+                Some(data.battery_capacity_percent())
+            });
+            if let Some(color) = color {
+                let _ = sh.handle_error(ds4.set_lightbar(color));
+            }
+            ctx.request_repaint_after(BATTERY_LIGHTBAR_INTERVAL);
+        }
+    }
+
+    fn update_keep_awake(&mut self, ctx: &Context) {
+        if !self.settings.keep_awake {
+            return;
+        }
+        if let UIState::DeviceConnected(state) = &self.ui_state {
+            let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+            if ds4.connection_type() == ConnectionType::Bluetooth {
+                let _ = self
+                    .status_handler
+                    .handle_error(ds4.keep_awake(KEEP_AWAKE_INTERVAL));
+                ctx.request_repaint_after(KEEP_AWAKE_INTERVAL);
+            }
+        }
+    }
+
+    fn update_drift_check(&mut self, ctx: &Context) {
+        let UIState::DeviceConnected(state) = &mut self.ui_state else {
+            return;
+        };
+        let Some(drift_check) = &mut state.drift_check else {
+            return;
+        };
+        let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+        let data = self
+            .status_handler
+            .handle_error(ds4.read_last_data())
+            .flatten();
+        match drift_check.update(Instant::now(), data.as_ref()) {
+            DriftCheckStep::Sampling => {
+                ctx.request_repaint();
+                return;
+            }
+            DriftCheckStep::Moved => info!("Drift check aborted, a stick was moved"),
+            DriftCheckStep::Finished { left, right } => {
+                let sticks: Vec<String> = [
+                    (t("drift_check.left_stick"), left),
+                    (t("drift_check.right_stick"), right),
+                ]
+                .into_iter()
+                .filter_map(|(stick, offset)| {
+                    Some(tf(
+                        "drift_check.offset",
+                        &[&stick, &format!("{:.3}", offset?)],
+                    ))
+                })
+                .collect();
+                if !sticks.is_empty() {
+                    let warning = tf("drift_check.warning", &[&sticks.join(", ")]);
+                    warn!("Drift check: {}", warning);
+                    state.drift_warning = Some(warning);
+                }
+            }
+        }
+        state.drift_check = None;
+    }
+
+    fn save_remap(&mut self) {
+        if let UIState::DeviceConnected(state) = &self.ui_state {
+            if state.remap != self.settings.remap {
+                self.settings.remap = state.remap.clone();
+                let _ = self
+                    .status_handler
+                    .handle_error(self.settings.save(&self.options.settings_file));
+            }
+        }
+    }
+
+    fn show_settings(&mut self, ctx: &Context) {
+        let lightbar = (self.settings.battery_lightbar, self.settings.lightbar_color);
+        if settings_window(
+            ctx,
+            &mut self.settings_open,
+            &mut self.settings,
+            self.safe_mode,
+            &mut self.test_commands_confirmation,
+        ) {
+            set_language(self.settings.language);
+            self.options.device.report_retries = self.settings.report_retries;
+            self.options.device.input_report_drain_count = self.settings.input_report_drain_count;
+            // The connected device keeps its connection type until it's reopened
+            self.options.device.connection_type = self
+                .options
+                .connection_type
+                .or(self.settings.connection_type);
+            if let UIState::DeviceConnected(state) = &mut self.ui_state {
+                let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+                ds4.set_report_retries(self.settings.report_retries);
+                ds4.set_input_report_drain_count(self.settings.input_report_drain_count);
+                state.calibration_log.scrub_serial_number = self.settings.scrub_calibration_log;
+                state.stick_plot_colors = self.settings.stick_plot_colors;
+                state.stick_plot_style = self.settings.stick_plot_style;
+                state.auto_flash_write = self.settings.auto_flash_write;
+                state.test_commands = self.settings.test_commands;
+                // Restores the chosen color when the battery mode is turned off
+                if lightbar != (self.settings.battery_lightbar, self.settings.lightbar_color) {
+                    state.battery_lightbar.reset();
+                    if !self.settings.battery_lightbar {
+                        let _ = self
+                            .status_handler
+                            .handle_error(ds4.set_lightbar(self.settings.lightbar_color));
+                    }
+                }
+            }
+            let _ = self
+                .status_handler
+                .handle_error(self.settings.save(&self.options.settings_file));
+        }
+    }
+
+    // Progress updates are kept apart, so they don't replace the last status
+    fn receive_statuses(&mut self) -> bool {
+        let statuses = self.status_handler.take();
+        let received = !statuses.is_empty();
+        for status in statuses {
+            match status {
+                Status::Progress(progress) => {
+                    let now = Instant::now();
+                    match &mut self.progress {
+                        Some((active, _)) if active.operation != progress.operation => {
+                            warn!(
+                                "{} progress ignored while {} is running",
+                                progress.operation, active.operation
+                            );
+                        }
+                        _ if progress.is_finished() => self.progress = None,
+                        active => *active = Some((progress, now)),
+                    }
+                }
+                Status::ProgressFinished(operation) => {
+                    if let Some((active, _)) = &self.progress {
+                        if active.operation == operation {
+                            self.progress = None;
+                        }
+                    }
+                }
+                status => {
+                    if let Status::Error(detail) = &status {
+                        self.error_detail = Some(detail.text());
+                    }
+                    self.last_status = status;
+                }
+            }
+        }
+        if let Some((_, updated)) = &self.progress {
+            if updated.elapsed() > PROGRESS_STALE_AFTER {
+                self.progress = None;
+            }
+        }
+        received
     }
 
     fn show_status_bar(&mut self, ctx: &Context) {
-        if let Ok(status) = self.status_receiver.try_recv() {
-            self.last_status = status;
+        if self.receive_statuses() {
             ctx.request_repaint();
         }
+        let dry_run = self.options.device.dry_run;
+        let safe_mode = self.safe_mode;
         egui::TopBottomPanel::new(TopBottomSide::Bottom, "Status")
             .exact_height(32.0)
-            .show(ctx, |ui| match &self.last_status {
-                Status::Ok => {
-                    ui.label(RichText::new("⬤ Ok").color(Color32::GREEN));
-                }
-                Status::Error(error) => {
-                    ui.horizontal(|ui| {
-                        ui.label(RichText::new(format!("⬤ {}", error)).color(Color32::RED));
-                    });
-                }
-                Status::Message(message) => {
-                    ui.horizontal(|ui| {
-                        ui.label(RichText::new(format!("⬤ {}", message)).color(Color32::GREEN));
-                    });
-                }
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if dry_run {
+                        ui.label(RichText::new("DRY RUN").strong().color(Color32::YELLOW));
+                        ui.separator();
+                    }
+                    if safe_mode {
+                        ui.label(
+                            RichText::new("SAFE MODE")
+                                .strong()
+                                .color(Color32::LIGHT_BLUE),
+                        )
+                        .on_hover_text(t("help.safe_mode"));
+                        ui.separator();
+                    }
+                    if let UIState::DeviceConnected(state) = &mut self.ui_state {
+                        let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+                        match ds4.busy_for() {
+                            Some(busy_for) if busy_for >= BUSY_SPINNER_DELAY => {
+                                ui.spinner().on_hover_text(t("help.device_busy"));
+                                ui.separator();
+                            }
+                            Some(busy_for) => {
+                                ctx.request_repaint_after(BUSY_SPINNER_DELAY - busy_for)
+                            }
+                            None => {}
+                        }
+                        if ds4.is_connection_type_forced() {
+                            let text = format!("FORCED {}", ds4.connection_type()).to_uppercase();
+                            ui.label(RichText::new(text).strong().color(Color32::YELLOW))
+                                .on_hover_text(t("help.forced_connection_type"));
+                            ui.separator();
+                        }
+                        if state.drift_check.is_some() {
+                            ui.label(t("drift_check.sampling"))
+                                .on_hover_text(t("help.drift_check"));
+                            if ui.small_button(t("drift_check.skip")).clicked() {
+                                state.drift_check = None;
+                            }
+                            ui.separator();
+                        }
+                        if let Some(warning) = &state.drift_warning {
+                            ui.label(
+                                RichText::new(format!("⚠ {}", warning)).color(Color32::YELLOW),
+                            );
+                            if ui
+                                .small_button("✖")
+                                .on_hover_text(t("common.dismiss"))
+                                .clicked()
+                            {
+                                state.drift_warning = None;
+                            }
+                            ui.separator();
+                        }
+                        if state.interference_warning {
+                            ui.label(
+                                RichText::new(format!("⚠ {}", t("interference.warning")))
+                                    .color(Color32::YELLOW),
+                            )
+                            .on_hover_text(t("help.interference"));
+                            if ui
+                                .small_button("✖")
+                                .on_hover_text(t("common.dismiss"))
+                                .clicked()
+                            {
+                                state.interference_warning = false;
+                            }
+                            ui.separator();
+                        }
+                    }
+                    if let Some((progress, _)) = &self.progress {
+                        ui.add(
+                            egui::ProgressBar::new(progress.fraction())
+                                .text(&progress.label)
+                                .desired_width(320f32),
+                        );
+                        ui.separator();
+                    }
+                    match &self.last_status {
+                        Status::Ok => {
+                            ui.label(RichText::new("⬤ Ok").color(Color32::GREEN));
+                        }
+                        Status::Error(detail) => {
+                            let text =
+                                RichText::new(format!("⬤ {}", detail.message)).color(Color32::RED);
+                            if ui
+                                .add(egui::Label::new(text).sense(Sense::click()))
+                                .on_hover_text(t("help.error_detail"))
+                                .clicked()
+                            {
+                                self.error_detail_open = true;
+                            }
+                        }
+                        Status::Message(message) => {
+                            ui.label(RichText::new(format!("⬤ {}", message)).color(Color32::GREEN));
+                        }
+                        // Received apart by receive_statuses
+                        Status::Progress(_) | Status::ProgressFinished(_) => {}
+                    }
+                });
             });
     }
 
     fn show_devices(&mut self, ctx: &Context) {
         let sh = self.status_handler.clone();
-        let _ = sh.handle_error(self.refresh_devices());
+        let focused = ctx.input(|input| {
+            let viewport = input.viewport();
+            viewport.focused != Some(false) && viewport.minimized != Some(true)
+        });
+        let refresh_interval = self.settings.device_refresh_interval(focused);
+        let disconnect_expected = match &mut self.ui_state {
+            UIState::DeviceConnected(state) => {
+                // The controller may ignore the switch and stay connected
+                if state
+                    .disconnect_expected
+                    .is_some_and(|sent| sent.elapsed() >= DISCONNECT_EXPECTED_TIMEOUT)
+                {
+                    state.disconnect_expected = None;
+                }
+                state.disconnect_expected.is_some()
+            }
+            _ => false,
+        };
+        if disconnect_expected
+            || self
+                .last_device_refresh
+                .is_none_or(|refreshed| refreshed.elapsed() >= refresh_interval)
+        {
+            self.last_device_refresh = Some(Instant::now());
+            let _ = sh.handle_error(self.refresh_devices());
+        }
+        let (cursor_moved, enter) = self.navigate_devices(ctx);
         egui::SidePanel::new(Side::Left, "List").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button(t("settings")).clicked() {
+                    self.settings_open = !self.settings_open;
+                }
+                if ui
+                    .button("🔄")
+                    .on_hover_text(t("help.refresh_devices"))
+                    .clicked()
+                {
+                    self.last_device_refresh = None;
+                    ctx.request_repaint();
+                }
+            });
+            ui.separator();
+            if self.devices.is_empty() {
+                no_devices_hint(ui);
+            }
             ScrollArea::vertical().show(ui, |ui| {
                 let mut current = self.device().cloned();
-                for device in &self.devices {
+                for (index, device) in self.devices.iter().enumerate() {
                     ui.horizontal(|ui| {
                         ui.label(with_gamepad_font(GAMEPAD_FONT_SYMBOL));
-                        ui.selectable_value(
+                        let mut response = ui.selectable_value(
                             &mut current,
                             Some(device.clone()),
                             device.path().to_str().unwrap(),
                         );
+                        if response.clicked() {
+                            self.device_cursor = Some(index);
+                        } else if self.device_cursor == Some(index) {
+                            response = response.highlight();
+                            if cursor_moved {
+                                response.scroll_to_me(None);
+                            }
+                            if enter {
+                                current = Some(device.clone());
+                            }
+                        }
+                        if ui.small_button("📋").on_hover_text("Copy path").clicked() {
+                            // Paths are UTF-8 on supported platforms, lossy is a fallback
+                            let path = match device.path().to_str() {
+                                Ok(path) => path.to_string(),
+                                Err(_) => device.path().to_string_lossy().into_owned(),
+                            };
+                            ui.ctx().copy_text(path);
+                        }
                     });
                 }
                 self.update_device(current.as_ref());
             });
         });
-        ctx.request_repaint_after(Duration::from_secs(1));
+        ctx.request_repaint_after(refresh_interval);
+    }
+
+    // Arrow keys move the device cursor with wrapping, returns if it moved and if Enter was
+    // pressed. Keys are left to the focused widget, like a text field
+    fn navigate_devices(&mut self, ctx: &Context) -> (bool, bool) {
+        let len = self.devices.len();
+        self.device_cursor = self.device_cursor.filter(|cursor| *cursor < len);
+        if len == 0 || ctx.memory(|memory| memory.focused().is_some()) {
+            return (false, false);
+        }
+        let (up, down, enter) = ctx.input_mut(|input| {
+            (
+                input.consume_key(Modifiers::NONE, Key::ArrowUp),
+                input.consume_key(Modifiers::NONE, Key::ArrowDown),
+                input.consume_key(Modifiers::NONE, Key::Enter),
+            )
+        });
+        // Starts from the connected device
+        let cursor = self.device_cursor.or_else(|| {
+            let device = self.device()?;
+            self.devices.iter().position(|listed| listed == device)
+        });
+        let moved = match (up, down) {
+            (true, false) => Some(cursor.map_or(len - 1, |cursor| (cursor + len - 1) % len)),
+            (false, true) => Some(cursor.map_or(0, |cursor| (cursor + 1) % len)),
+            _ => None,
+        };
+        if moved.is_some() {
+            self.device_cursor = moved;
+        }
+        (moved.is_some(), enter)
     }
 
     fn show_content(&mut self, ctx: &Context) {
@@ -358,59 +1351,166 @@ impl Application {
                 Self::show_panel_selector(ui, state, sh.clone());
                 ui.separator();
                 Self::show_panel(ui, ctx, state, sh.clone());
+                Self::show_discard_confirmation(ctx, state);
+            } else if self.reconnect.is_some() {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Waiting for the controller to reconnect...");
+                    if ui.button("Cancel").clicked() {
+                        self.reconnect = None;
+                    }
+                });
+            } else if let Some(flash) = &mut self.offline_flash {
+                if ui.button("Close Flash File Inspector").clicked() {
+                    self.offline_flash = None;
+                    return;
+                }
+                ui.separator();
+                offline_flash(ui, flash, sh.clone());
+            } else if let Some(editor) = &mut self.offline_calibration {
+                if ui.button(t("calibration.editor_close")).clicked() {
+                    self.offline_calibration = None;
+                    return;
+                }
+                ui.separator();
+                calibration_editor(ui, editor, sh.clone());
             } else {
                 ui.label("Please, select controller from the list");
+                if ui
+                    .button("Inspect Flash File")
+                    .on_hover_text(t("help.offline_flash"))
+                    .clicked()
+                {
+                    self.offline_flash = Some(Flash::default());
+                }
+                if ui
+                    .button(t("calibration.editor_open"))
+                    .on_hover_text(t("help.calibration_editor"))
+                    .clicked()
+                {
+                    self.offline_calibration = Some(CalibrationEditor::default());
+                }
             }
         });
     }
 
     fn show_panel_selector(ui: &mut egui::Ui, state: &mut DeviceConnected, sh: StatusHandler) {
         ui.horizontal(|ui| {
+            ui.add_enabled_ui(state.active_calibration.is_none(), |ui| {
+                Self::show_panel_switch_buttons(ui, state, sh.clone());
+            });
+            if state.active_calibration.is_some() {
+                ui.separator();
+                ui.label(RichText::new(t("calibration.in_progress")).color(Color32::YELLOW));
+                if ui
+                    .button(t("calibration.abort"))
+                    .on_hover_text(t("help.abort_calibration"))
+                    .clicked()
+                {
+                    abort_calibration(state, sh.clone());
+                }
+            }
+        });
+    }
+
+    fn show_panel_switch_buttons(
+        ui: &mut egui::Ui,
+        state: &mut DeviceConnected,
+        sh: StatusHandler,
+    ) {
+        {
             if panel_switch_button(
                 ui,
                 matches!(&state.panel, Panel::DeviceInfo(_)),
                 "Device Info",
+                t("help.device_info"),
             )
                 .clicked()
             {
                 if let Some(device_info) =
                     sh.handle_error(DeviceInfo::from_connected_device(&state.device))
                 {
-                    state.panel = Panel::DeviceInfo(device_info);
+                    state.switch_panel(Panel::DeviceInfo(device_info));
                 }
             }
-            if panel_switch_button(ui, matches!(&state.panel, Panel::Output(_)), "Output").clicked()
+            if panel_switch_button(
+                ui,
+                matches!(&state.panel, Panel::Health(_)),
+                "Health",
+                t("help.health"),
+            )
+            .clicked()
+            {
+                state.switch_panel(Panel::Health(Health::from_connected_device(&state.device)));
+            }
+            if panel_switch_button(
+                ui,
+                matches!(&state.panel, Panel::Output(_)),
+                "Output",
+                t("help.output"),
+            )
+            .clicked()
             {
-                state.panel = Panel::Output(Output::default());
+                state.switch_panel(Panel::Output(Output::default()));
             }
             if panel_switch_button(
                 ui,
                 matches!(&state.panel, Panel::Calibration(_)),
                 "Calibration",
+                t("help.calibration"),
             )
                 .clicked()
             {
                 if let Some(panel) =
                     calibration::Panel::info_from_device_connected(state, sh.clone())
                 {
-                    state.panel = Panel::Calibration(panel);
+                    state.switch_panel(Panel::Calibration(panel));
                 }
             }
-            if panel_switch_button(ui, matches!(&state.panel, Panel::Flash(_)), "Flash").clicked() {
-                state.panel = Panel::Flash(Flash::default());
+            if state.safe_mode {
+                return;
             }
             if panel_switch_button(
                 ui,
-                matches!(&state.panel, Panel::Test(_, _, _)),
-                "Test Commands",
+                matches!(&state.panel, Panel::Flash(_)),
+                "Flash",
+                t("help.flash"),
             )
+            .clicked()
+            {
+                state.refresh_permanent(sh.clone());
+                let flash = Flash::from_cache(state.flash_mirror_cache.as_ref());
+                state.switch_panel(Panel::Flash(flash));
+            }
+            if state.test_commands
+                && panel_switch_button(
+                    ui,
+                    matches!(&state.panel, Panel::Test(_, _, _)),
+                    "Test Commands",
+                    t("help.test_commands"),
+                )
                 .clicked()
             {
                 let ConnectedDevice::DualShock4(_, ds4) = &state.device;
                 let test_data = sh.handle_error(ds4.read_test_data());
-                state.panel = Panel::Test(test_data, None, None);
+                state.switch_panel(Panel::Test(test_data, None, None));
             }
-        });
+        }
+    }
+
+    fn show_discard_confirmation(ctx: &Context, state: &mut DeviceConnected) {
+        if state.pending_panel.is_none() {
+            return;
+        }
+        match confirmation_dialog(
+            ctx,
+            t("common.unsaved_changes"),
+            t("common.discard_changes"),
+        ) {
+            Some(true) => state.panel = state.pending_panel.take().unwrap(),
+            Some(false) => state.pending_panel = None,
+            None => {}
+        }
     }
 
     fn show_panel(
@@ -419,10 +1519,22 @@ impl Application {
         state: &mut DeviceConnected,
         sh: StatusHandler,
     ) {
+        // Other panels read the input, so only the paused Output panel stops the polling
+        let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+        ds4.set_input_paused(matches!(&state.panel, Panel::Output(output) if output.paused));
         match &state.panel {
-            Panel::DeviceInfo(info) => device_info(ui, info),
+            Panel::DeviceInfo(_) => device_info(ui, state, sh.clone()),
+            Panel::Health(_) => health(ui, ctx, state, sh.clone()),
             Panel::Output(_) => output(ui, ctx, state, sh.clone()),
             Panel::Calibration(_) => calibration(ui, ctx, state, sh.clone()),
+            // The buttons are hidden, but nothing else may open these panels in safe mode
+            Panel::Flash(_) | Panel::Test(_, _, _) if state.safe_mode => {
+                ui.label(t("common.safe_mode_unavailable"));
+            }
+            // Turned off in the settings while the panel was shown
+            Panel::Test(_, _, _) if !state.test_commands => {
+                ui.label(t("common.test_commands_disabled"));
+            }
             Panel::Flash(_) => flash(ui, ctx, state, sh.clone()),
             Panel::Test(_, _, _) => test_commands(ui, ctx, state, sh.clone()),
             _ => {
@@ -432,14 +1544,59 @@ impl Application {
     }
 }
 
+// Most "no devices" cases are permissions or another application holding the controller
+fn no_devices_hint(ui: &mut egui::Ui) {
+    ui.label(RichText::new(t("devices.none_found")).strong());
+    if cfg!(target_os = "linux") {
+        ui.label(t("devices.hint_linux"));
+        ui.code(LINUX_UDEV_RULE);
+        if ui.small_button(t("devices.copy_udev_rule")).clicked() {
+            ui.ctx().copy_text(LINUX_UDEV_RULE.to_string());
+        }
+    } else if cfg!(target_os = "macos") {
+        ui.label(t("devices.hint_macos"));
+    } else if cfg!(target_os = "windows") {
+        ui.label(t("devices.hint_windows"));
+    }
+    ui.separator();
+}
+
 fn global_styles(ui: &mut egui::Ui) {
     ui.style_mut().spacing.slider_width = 150f32;
 }
 
-fn panel_switch_button(ui: &mut egui::Ui, selected: bool, text: &str) -> Response {
+// Help is shown on hover, texts are in the string tables under help.*
+fn panel_switch_button(ui: &mut egui::Ui, selected: bool, text: &str, help: &str) -> Response {
     ui.add(egui::SelectableLabel::new(selected, text))
+        .on_hover_text(help)
+}
+
+// Controller of the command line modes, a listed path is taken as is, anything else for a serial
+// number like with the GUI startup device
+pub fn find_device(api: &HidApi, device: Option<&str>) -> Result<DeviceId> {
+    let ids: Vec<DeviceId> = dual_shock_4_devices(api).map(DeviceId::from).collect();
+    match device {
+        Some(device) => match ids.iter().find(|id| id.path().to_string_lossy() == device) {
+            Some(id) => Ok(id.clone()),
+            None => find_by_serial_number(&ids, device).cloned(),
+        },
+        None => ids
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::Startup("No controller found".to_string())),
+    }
+}
+
+fn dual_shock_4_devices(api: &HidApi) -> impl Iterator<Item = &HidDeviceInfo> {
+    api.device_list()
+        .filter(|device| is_dual_shock_4(device.vendor_id(), device.product_id()))
+        .filter(|device| is_dual_shock_4_interface(device))
 }
 
-fn is_dual_shock_4(vendor_id: u16, product_id: u16) -> bool {
-    vendor_id == 0x054c && (product_id == 0x05c4 || product_id == 0x09cc)
+fn is_dual_shock_4_interface(device: &HidDeviceInfo) -> bool {
+    let interface_number = device.interface_number();
+    let usage_page = device.usage_page();
+    (interface_number == USB_INTERFACE_NUMBER || interface_number == UNKNOWN_INTERFACE_NUMBER)
+        && (usage_page == UNKNOWN_USAGE_PAGE
+            || (usage_page == GENERIC_DESKTOP_USAGE_PAGE && device.usage() == GAMEPAD_USAGE))
 }
@@ -1,30 +1,97 @@
 // Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
 // SPDX-License-Identifier: GPL-3.0
 
+use crate::application::calibration_flag_watch::calibration_flag_watch;
+use crate::application::calibration_log::SessionResult;
+use crate::application::confirmation::confirmation_dialog;
+use crate::application::device_task::{poll_task, DeviceTask};
+use crate::application::flash::{poll_stick_center_calibration, spawn_stick_center_calibration};
 use crate::application::font::{
     button_cross, button_triangle, with_gamepad_font, GAMEPAD_FONT_LEFT_ANALOG_CLOCKWISE,
     GAMEPAD_FONT_RIGHT_ANALOG_CLOCKWISE,
 };
-use crate::application::output::{circle_line, trigger_bar};
+use crate::application::i18n::{t, tf};
+use crate::application::output::{circle_line, stick_plot_legend, trigger_bar, StickPlotStyle};
+use crate::application::stick_presets::StickPreset;
+use crate::application::undo::{undo_redo_buttons, UndoStack};
 use crate::application::{panel_switch_button, ConnectedDevice, DeviceConnected, StatusHandler};
+use crate::dual_shock_4;
 use crate::dual_shock_4::{
     AnalogStickCalibrationType, CalibrationData, CalibrationDeviceType, CalibrationFlag,
-    CalibrationResult, CalibrationState, CalibrationType, Data, MotionCalibration,
-    StickCenterCalibration, StickMinMaxCalibration, StickPosition, TriggerKeyCalibrationType,
-    TriggerKeyLeftRight,
+    CalibrationResult, CalibrationState, CalibrationType, CalibrationWrite, ConnectionType, Data,
+    DualShock4, MotionCalibration, MotionCalibrationValues, StickCenterCalibration,
+    StickMinMaxCalibration, StickPosition, TriggerKeyCalibrationType, TriggerKeyLeftRight,
 };
 use eframe::egui;
-use eframe::egui::{Color32, ScrollArea, SliderClamping};
+use eframe::egui::{Color32, RichText, ScrollArea, SliderClamping};
 use egui_plot::Points;
+use log::{info, warn};
+use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+const SAMPLE_CAPTURE_COUNT: usize = 10;
+// Gives the stick a few reports between the samples
+const SAMPLE_CAPTURE_INTERVAL: Duration = Duration::from_millis(200);
 
-#[derive(Clone)]
 pub enum Panel {
     Info(Info),
     Wizard(CalibrationWizard),
-    MotionSensor(MotionCalibration),
+    MotionSensor(MotionSensor),
+    StickPresets(StickPresets),
+}
+
+pub struct StickPresets {
+    calibration: StickCenterCalibration,
+    // Model the form values came from, the device or a loaded preset
+    product_id: u16,
+    selected: Option<usize>,
+    name: String,
+    calibrate_confirmation: bool,
+    calibration_task: Option<DeviceTask<StickCenterCalibration>>,
+    // The result is compared with it, otherwise the result is stored in the form
+    compared_preset: Option<StickPreset>,
+}
+
+#[derive(Clone)]
+pub struct MotionSensor {
+    calibration: MotionCalibration,
+    // Last read from or written to the device
+    device_calibration: MotionCalibration,
+    history: UndoStack<MotionCalibration>,
+    edit_start: Option<MotionCalibration>,
+    // Selects the layout of the gyroscope values
+    connection_type: ConnectionType,
+}
+
+impl MotionSensor {
+    pub fn new(calibration: MotionCalibration, connection_type: ConnectionType) -> Self {
+        Self {
+            connection_type,
+            device_calibration: calibration.clone(),
+            calibration,
+            history: UndoStack::default(),
+            edit_start: None,
+        }
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.calibration != self.device_calibration
+    }
+
+    pub fn calibration(&self) -> &MotionCalibration {
+        &self.calibration
+    }
 }
 
 impl Panel {
+    pub fn has_unsaved_changes(&self) -> bool {
+        match self {
+            Panel::MotionSensor(motion_sensor) => motion_sensor.is_dirty(),
+            _ => false,
+        }
+    }
+
     pub fn info_from_device_connected(
         device_connected: &DeviceConnected,
         sh: StatusHandler,
@@ -45,57 +112,227 @@ pub enum CalibrationWizard {
     Failed,
 }
 
+// Sticks of the center calibration, values of the other stick are left out of the diff
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StickScope {
+    #[default]
+    Both,
+    Left,
+    Right,
+}
+
+impl StickScope {
+    const ALL: [StickScope; 3] = [StickScope::Both, StickScope::Left, StickScope::Right];
+
+    fn text(&self) -> &'static str {
+        match self {
+            StickScope::Both => t("calibration.scope_both"),
+            StickScope::Left => t("calibration.scope_left"),
+            StickScope::Right => t("calibration.scope_right"),
+        }
+    }
+
+    // A single stick needs a side selector in the params of the calibration device type
+    fn is_supported(&self, device_type: &CalibrationDeviceType) -> bool {
+        *self == StickScope::Both || device_type.selects_side()
+    }
+
+    fn includes(&self, name: &str) -> bool {
+        match self {
+            StickScope::Both => true,
+            StickScope::Left => !name.starts_with("calibration.right_"),
+            StickScope::Right => !name.starts_with("calibration.left_"),
+        }
+    }
+}
+
+// Stick center samples taken one per interval from the repaint loop, so the UI isn't blocked
+#[derive(Debug, Clone)]
+pub struct SampleCapture {
+    remaining: usize,
+    captured: usize,
+    next_at: Instant,
+}
+
+impl SampleCapture {
+    pub fn new(count: usize, now: Instant) -> Self {
+        Self {
+            remaining: count,
+            captured: 0,
+            next_at: now,
+        }
+    }
+
+    // True when the next sample should be taken now
+    fn take_due(&mut self, now: Instant) -> bool {
+        if self.remaining == 0 || now < self.next_at {
+            return false;
+        }
+        self.remaining -= 1;
+        self.captured += 1;
+        self.next_at = now + SAMPLE_CAPTURE_INTERVAL;
+        true
+    }
+
+    fn is_finished(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+// Raw trigger values, the effective 0% is at the dead zone and 100% at the max
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriggerThresholds {
+    pub min: u8,
+    pub dead_zone: u8,
+    pub max: u8,
+}
+
+impl TriggerThresholds {
+    // 0..=1 between the dead zone and the max, None when the range is empty
+    pub fn calibrated(&self, raw: u8) -> Option<f32> {
+        (self.max > self.dead_zone).then(|| {
+            let range = f32::from(self.max - self.dead_zone);
+            (f32::from(raw.saturating_sub(self.dead_zone)) / range).min(1f32)
+        })
+    }
+}
+
+// The layout of the trigger calibration data isn't known, so the thresholds are taken from
+// the values seen while the controller records its samples
+#[derive(Debug, Clone, Default)]
+pub struct TriggerThresholdCapture {
+    // Lowest and last value of the release step, the trigger may still be moving when it starts
+    released: [Option<(u8, u8)>; 2],
+    pressed: [Option<u8>; 2],
+}
+
+impl TriggerThresholdCapture {
+    fn record(&mut self, type_: &TriggerKeyCalibrationType, values: [u8; 2]) {
+        for (i, value) in values.into_iter().enumerate() {
+            match type_ {
+                TriggerKeyCalibrationType::RecordMaxSample(_)
+                | TriggerKeyCalibrationType::RecordRangeSample(_) => {
+                    self.pressed[i] = Some(self.pressed[i].map_or(value, |max| max.max(value)))
+                }
+                TriggerKeyCalibrationType::RecordMinSample(_) => {
+                    self.released[i] = Some(
+                        self.released[i].map_or((value, value), |(min, _)| (min.min(value), value)),
+                    )
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Left and right, None for a trigger without both steps recorded
+    fn thresholds(&self) -> [Option<TriggerThresholds>; 2] {
+        [0, 1].map(|i| match (self.released[i], self.pressed[i]) {
+            (Some((min, dead_zone)), Some(max)) => Some(TriggerThresholds {
+                min,
+                dead_zone,
+                max,
+            }),
+            _ => None,
+        })
+    }
+}
+
 #[derive(Clone)]
 pub struct Info {
     flag: CalibrationFlag,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotValue {
+    Number(i16),
+    Bytes(Vec<u8>),
+}
+
+impl Display for SnapshotValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotValue::Number(value) => write!(f, "{}", value),
+            SnapshotValue::Bytes(bytes) => write!(f, "{}", hex::encode(bytes)),
+        }
+    }
+}
+
+// Named calibration values, names are string table keys
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationSnapshot {
+    values: Vec<(&'static str, SnapshotValue)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SnapshotDiff {
+    pub name: &'static str,
+    pub before: Option<SnapshotValue>,
+    pub after: SnapshotValue,
+}
+
+impl SnapshotDiff {
+    pub fn changed(&self) -> bool {
+        self.before.as_ref() != Some(&self.after)
+    }
+}
+
+impl CalibrationSnapshot {
+    pub fn from_calibration_data(calibration_data: &CalibrationData) -> Self {
+        use SnapshotValue::{Bytes, Number};
+        let values = match calibration_data {
+            // Samples differ on every run, only the calculated center is compared
+            CalibrationData::StickCenter(calculated, _) => vec![
+                ("calibration.left_x_center", Number(calculated.left_x())),
+                ("calibration.left_y_center", Number(calculated.left_y())),
+                ("calibration.right_x_center", Number(calculated.right_x())),
+                ("calibration.right_y_center", Number(calculated.right_y())),
+            ],
+            CalibrationData::StickMinMax(calibration) => vec![
+                ("calibration.left_min_x", Number(calibration.left_min_x())),
+                ("calibration.left_max_x", Number(calibration.left_max_x())),
+                ("calibration.left_min_y", Number(calibration.left_min_y())),
+                ("calibration.left_max_y", Number(calibration.left_max_y())),
+                ("calibration.right_min_x", Number(calibration.right_min_x())),
+                ("calibration.right_max_x", Number(calibration.right_max_x())),
+                ("calibration.right_min_y", Number(calibration.right_min_y())),
+                ("calibration.right_max_y", Number(calibration.right_max_y())),
+            ],
+            CalibrationData::Triggers(calibration) => {
+                vec![("calibration.data", Bytes(calibration.buf.clone()))]
+            }
+            CalibrationData::None(data) => vec![("calibration.unknown_data", Bytes(data.clone()))],
+        };
+        Self { values }
+    }
+
+    // Values of the other snapshot compared with the same named values of this one
+    pub fn diff(&self, after: &CalibrationSnapshot) -> Vec<SnapshotDiff> {
+        after
+            .values
+            .iter()
+            .map(|(name, value)| SnapshotDiff {
+                name,
+                before: self
+                    .values
+                    .iter()
+                    .find(|(before_name, _)| before_name == name)
+                    .map(|(_, before)| before.clone()),
+                after: value.clone(),
+            })
+            .collect()
+    }
+}
+
 pub fn calibration(
     ui: &mut egui::Ui,
     ctx: &egui::Context,
     state: &mut DeviceConnected,
     sh: StatusHandler,
 ) {
-    ui.horizontal(|ui| {
-        if panel_switch_button(
-            ui,
-            matches!(state.panel, super::Panel::Calibration(Panel::Info(_))),
-            "Calibration Info",
-        )
-            .clicked()
-        {
-            if let Some(panel) = Panel::info_from_device_connected(state, sh.clone()) {
-                state.panel = super::Panel::Calibration(panel);
-            }
-        }
-        if panel_switch_button(
-            ui,
-            matches!(state.panel, super::Panel::Calibration(Panel::Wizard(_))),
-            "Calibration Wizard",
-        )
-            .clicked()
-        {
-            update_calibration_wizard_panel(state, sh.clone());
-        }
-        if panel_switch_button(
-            ui,
-            matches!(
-                state.panel,
-                super::Panel::Calibration(Panel::MotionSensor(_))
-            ),
-            "Motion Sensor",
-        )
-            .clicked()
-        {
-            let ConnectedDevice::DualShock4(_, ds4) = &state.device;
-            if let Some(calibration_from_device) =
-                sh.handle_error(ds4.read_motion_calibration_data())
-            {
-                state.panel =
-                    super::Panel::Calibration(Panel::MotionSensor(calibration_from_device));
-            }
-        }
+    ui.add_enabled_ui(state.active_calibration.is_none(), |ui| {
+        ui.horizontal(|ui| calibration_panel_switch_buttons(ui, state, sh.clone()));
     });
+    calibration_flag_watch(ui, ctx, state);
     ui.separator();
     match &state.panel {
         super::Panel::Calibration(Panel::Info(_)) => info_panel(ui, state, sh.clone()),
@@ -109,7 +346,7 @@ pub fn calibration(
             stick_min_max_calibration(ui, ctx, state, sh.clone())
         }
         super::Panel::Calibration(Panel::Wizard(CalibrationWizard::Success(_, _))) => {
-            calibration_success(ui, state, sh.clone())
+            calibration_success(ui, ctx, state, sh.clone())
         }
         super::Panel::Calibration(Panel::Wizard(CalibrationWizard::Failed)) => {
             calibration_failed(ui, state, sh.clone())
@@ -120,26 +357,240 @@ pub fn calibration(
         super::Panel::Calibration(Panel::Wizard(CalibrationWizard::TriggerKey(type_))) => {
             triggers_calibration(ui, ctx, state, type_.clone(), sh.clone())
         }
+        super::Panel::Calibration(Panel::StickPresets(_)) => {
+            stick_presets(ui, ctx, state, sh.clone())
+        }
         _ => {
-            ui.label("Unknown calibration sub-panel");
+            ui.label(t("calibration.unknown_sub_panel"));
+        }
+    };
+}
+
+fn calibration_panel_switch_buttons(
+    ui: &mut egui::Ui,
+    state: &mut DeviceConnected,
+    sh: StatusHandler,
+) {
+    if panel_switch_button(
+        ui,
+        matches!(state.panel, super::Panel::Calibration(Panel::Info(_))),
+        t("calibration.info"),
+        t("help.calibration_info"),
+    )
+    .clicked()
+    {
+        if let Some(panel) = Panel::info_from_device_connected(state, sh.clone()) {
+            state.switch_panel(super::Panel::Calibration(panel));
+        }
+    }
+    if panel_switch_button(
+        ui,
+        matches!(state.panel, super::Panel::Calibration(Panel::Wizard(_))),
+        t("calibration.wizard"),
+        t("help.calibration_wizard"),
+    )
+    .clicked()
+    {
+        update_calibration_wizard_panel(state, sh.clone());
+    }
+    if panel_switch_button(
+        ui,
+        matches!(
+            state.panel,
+            super::Panel::Calibration(Panel::MotionSensor(_))
+        ),
+        t("calibration.motion_sensor"),
+        t("help.motion_sensor"),
+    )
+    .clicked()
+    {
+        let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+        if let Some(calibration_from_device) = sh.handle_error(ds4.read_motion_calibration_data()) {
+            state.switch_panel(super::Panel::Calibration(Panel::MotionSensor(
+                MotionSensor::new(calibration_from_device, ds4.connection_type()),
+            )));
+        }
+    }
+    if panel_switch_button(
+        ui,
+        matches!(
+            state.panel,
+            super::Panel::Calibration(Panel::StickPresets(_))
+        ),
+        t("calibration.stick_presets"),
+        t("help.stick_presets"),
+    )
+    .clicked()
+    {
+        let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+        if let Some(calibration) = sh.handle_error(ds4.read_flash_stick_center_calibration()) {
+            state.switch_panel(super::Panel::Calibration(Panel::StickPresets(
+                StickPresets {
+                    calibration,
+                    product_id: ds4.device_info().product_id(),
+                    selected: None,
+                    name: String::new(),
+                    calibrate_confirmation: false,
+                    calibration_task: None,
+                    compared_preset: None,
+                },
+            )));
         }
+    }
+}
+
+fn stick_presets(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    state: &mut DeviceConnected,
+    sh: StatusHandler,
+) {
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    let super::Panel::Calibration(Panel::StickPresets(presets)) = &mut state.panel else {
+        return;
     };
+    let store = &mut state.stick_presets;
+    let product_id = ds4.device_info().product_id();
+    ui.horizontal(|ui| {
+        let selected = presets.selected.and_then(|index| store.presets.get(index));
+        egui::ComboBox::from_label(t("calibration.preset"))
+            .selected_text(selected.map(|preset| preset.name.as_str()).unwrap_or(""))
+            .show_ui(ui, |ui| {
+                for (index, preset) in store.presets.iter().enumerate() {
+                    ui.selectable_value(&mut presets.selected, Some(index), &preset.name);
+                }
+            });
+        let selected = presets.selected.and_then(|index| store.presets.get(index));
+        if ui
+            .add_enabled(
+                selected.is_some(),
+                egui::Button::new(t("calibration.load_preset")),
+            )
+            .clicked()
+        {
+            if let Some(preset) = selected {
+                match preset.validate(product_id) {
+                    Ok(()) => {
+                        presets.calibration = preset.calibration();
+                        presets.product_id = preset.product_id;
+                        presets.name = preset.name.clone();
+                    }
+                    Err(e) => sh.error(e.into()),
+                }
+            }
+        }
+        if ui
+            .add_enabled(
+                selected.is_some(),
+                egui::Button::new(t("calibration.delete_preset")),
+            )
+            .clicked()
+        {
+            if let Some(index) = presets.selected.take() {
+                store.presets.remove(index);
+                let _ = sh.handle_error(store.save());
+            }
+        }
+    });
+    ui.separator();
+    stick_center_calibration_form(ui, &mut presets.calibration);
+    ui.separator();
+    ui.horizontal(|ui| {
+        ui.add(
+            egui::TextEdit::singleline(&mut presets.name).hint_text(t("calibration.preset_name")),
+        );
+        let name = presets.name.trim();
+        if ui
+            .add_enabled(
+                !name.is_empty(),
+                egui::Button::new(t("calibration.save_preset")),
+            )
+            .clicked()
+        {
+            store.insert(StickPreset::from_calibration(
+                name.to_string(),
+                presets.product_id,
+                &presets.calibration,
+            ));
+            if sh.handle_error(store.save()).is_some() {
+                sh.message(tf("calibration.preset_saved", &[&name]));
+            }
+        }
+    });
+    // No known command writes a given stick center, the firmware only measures the resting sticks
+    ui.add_enabled(false, egui::Button::new(t("calibration.apply_preset")))
+        .on_disabled_hover_text(t("help.apply_preset_unsupported"));
+    let write_supported = ds4.is_write_supported(CalibrationWrite::StickCenter);
+    if ui
+        .add_enabled(
+            !state.safe_mode && write_supported && presets.calibration_task.is_none(),
+            egui::Button::new(t("calibration.calibrate_stick_center")),
+        )
+        .on_hover_text(t("help.calibrate_stick_center"))
+        .on_disabled_hover_text(if write_supported {
+            t("help.safe_mode")
+        } else {
+            t("calibration.stick_center_write_not_supported")
+        })
+        .clicked()
+    {
+        presets.calibrate_confirmation = true;
+    }
+    if presets.calibrate_confirmation {
+        match confirmation_dialog(
+            ctx,
+            t("calibration.calibrate_stick_center"),
+            t("calibration.calibrate_stick_center_confirmation"),
+        ) {
+            Some(true) => {
+                presets.calibrate_confirmation = false;
+                let preset = StickPreset::from_calibration(
+                    presets.name.clone(),
+                    presets.product_id,
+                    &presets.calibration,
+                );
+                match preset.validate(product_id) {
+                    Ok(()) => {
+                        presets.calibration_task = spawn_stick_center_calibration(ds4, &sh);
+                        presets.compared_preset = Some(preset);
+                    }
+                    Err(e) => sh.error(e.into()),
+                }
+            }
+            Some(false) => presets.calibrate_confirmation = false,
+            None => {}
+        }
+    }
+    if let Some(calibrated) =
+        poll_stick_center_calibration(ui, ctx, &mut presets.calibration_task, &sh)
+    {
+        match presets.compared_preset.take() {
+            Some(preset) => sh.message(tf(
+                "calibration.preset_deviation",
+                &[&preset.max_deviation(&calibrated)],
+            )),
+            None => {
+                presets.calibration = calibrated;
+                presets.product_id = product_id;
+            }
+        }
+    }
 }
 
 fn info_panel(ui: &mut egui::Ui, state: &mut DeviceConnected, _sh: StatusHandler) {
     if let super::Panel::Calibration(Panel::Info(info)) = &state.panel {
         ui.columns(2, |columns| {
-            columns[0].label("Accelerometer Calibrated: ");
+            columns[0].label(format!("{}: ", t("calibration.accelerometer_calibrated")));
             columns[1].label(info.flag.is_accelerometer_calib_ok().to_string());
-            columns[0].label("Gyroscope Calibrated: ");
+            columns[0].label(format!("{}: ", t("calibration.gyroscope_calibrated")));
             columns[1].label(info.flag.is_gyroscope_calib_ok().to_string());
-            columns[0].label("Sticks Min/Max Calibrated: ");
+            columns[0].label(format!("{}: ", t("calibration.sticks_min_max_calibrated")));
             columns[1].label(info.flag.is_stick_min_max_calib_ok().to_string());
-            columns[0].label("Sticks Centers Calibrated: ");
+            columns[0].label(format!("{}: ", t("calibration.sticks_center_calibrated")));
             columns[1].label(info.flag.is_stick_center_calib_ok().to_string());
-            columns[0].label("Left Trigger Calibrated: ");
+            columns[0].label(format!("{}: ", t("calibration.left_trigger_calibrated")));
             columns[1].label(info.flag.is_l2_calib_ok().to_string());
-            columns[0].label("Right Trigger Calibrated: ");
+            columns[0].label(format!("{}: ", t("calibration.right_trigger_calibrated")));
             columns[1].label(info.flag.is_r2_calib_ok().to_string());
         });
     }
@@ -147,7 +598,60 @@ fn info_panel(ui: &mut egui::Ui, state: &mut DeviceConnected, _sh: StatusHandler
 
 fn update_calibration_wizard_panel(state: &mut DeviceConnected, sh: StatusHandler) {
     if let Some(wizard) = sh.handle_error(calibration_wizard_panel(state)) {
-        state.panel = super::Panel::Calibration(Panel::Wizard(wizard));
+        match &wizard {
+            CalibrationWizard::Success(_, calibration_data) => {
+                state
+                    .calibration_log
+                    .finish(SessionResult::Completed, Some(calibration_data));
+                if let CalibrationData::StickCenter(calculated, _) = calibration_data {
+                    if state.auto_flash_write && !state.safe_mode {
+                        state.auto_flash_write_pending = Some(calculated.clone());
+                    }
+                }
+            }
+            CalibrationWizard::Failed => state.calibration_log.finish(SessionResult::Failed, None),
+            _ => {}
+        }
+        if matches!(
+            wizard,
+            CalibrationWizard::Success(..) | CalibrationWizard::Failed
+        ) {
+            state.calibration_flag_watch.refresh();
+        }
+        state.active_calibration = match &wizard {
+            CalibrationWizard::AnalogStickCenter => Some(CalibrationDeviceType::AnalogStick(
+                AnalogStickCalibrationType::Center,
+            )),
+            CalibrationWizard::AnalogStickMinMax => Some(CalibrationDeviceType::AnalogStick(
+                AnalogStickCalibrationType::MinMax,
+            )),
+            // Keeps the type the trigger calibration was started with
+            CalibrationWizard::TriggerKey(_) => {
+                state
+                    .active_calibration
+                    .take()
+                    .or(Some(CalibrationDeviceType::TriggerKey(
+                        TriggerKeyCalibrationType::Unknown(TriggerKeyLeftRight::Both),
+                    )))
+            }
+            _ => None,
+        };
+        if !matches!(wizard, CalibrationWizard::AnalogStickCenter) {
+            state.sample_capture = None;
+        }
+        if !matches!(wizard, CalibrationWizard::TriggerKey(_)) {
+            let capture = std::mem::take(&mut state.trigger_capture);
+            if let CalibrationWizard::Success(_, CalibrationData::Triggers(_)) = &wizard {
+                for (thresholds, captured) in state
+                    .trigger_thresholds
+                    .iter_mut()
+                    .zip(capture.thresholds())
+                {
+                    *thresholds = captured.or(*thresholds);
+                }
+            }
+        }
+        state.switch_panel(super::Panel::Calibration(Panel::Wizard(wizard)));
     }
 }
 
@@ -171,7 +675,7 @@ fn calibration_wizard_panel(state: &mut DeviceConnected) -> super::Result<Calibr
             let calibration_result = ds4.read_calibration_result()?;
             match calibration_result {
                 CalibrationResult::Completed(device) => {
-                    let calibration_data = ds4.read_calibration_data()?;
+                    let calibration_data = ds4.read_finished_calibration_data()?;
                     CalibrationWizard::Success(device, calibration_data)
                 }
                 CalibrationResult::NotCompleted(_) => CalibrationWizard::Failed,
@@ -186,7 +690,12 @@ fn calibration_wizard_start(ui: &mut egui::Ui, state: &mut DeviceConnected, sh:
     start_calibration_buttons(ui, state, sh.clone());
 }
 
-fn calibration_success(ui: &mut egui::Ui, state: &mut DeviceConnected, sh: StatusHandler) {
+fn calibration_success(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    state: &mut DeviceConnected,
+    sh: StatusHandler,
+) {
     if let super::Panel::Calibration(Panel::Wizard(CalibrationWizard::Success(
                                                        calibration_device_type,
                                                        calibration_data,
@@ -195,64 +704,257 @@ fn calibration_success(ui: &mut egui::Ui, state: &mut DeviceConnected, sh: Statu
         ui.horizontal(|ui| {
             match calibration_device_type {
                 CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::Center) => {
-                    ui.label("Analog Sticks Center calibrated successful!");
+                    ui.label(t("calibration.sticks_center_success"));
                 }
                 CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::MinMax) => {
-                    ui.label("Analog Sticks Min/Max calibrated successful!");
+                    ui.label(t("calibration.sticks_min_max_success"));
                 }
                 CalibrationDeviceType::MotionSensor => {
-                    ui.label("Motion Sensor calibrated successful!");
+                    ui.label(t("calibration.motion_sensor_success"));
                 }
                 CalibrationDeviceType::TriggerKey(_) => {
-                    ui.label("Trigger Key calibrated successful!");
+                    ui.label(t("calibration.trigger_key_success"));
                 }
                 _ => {}
             };
         });
 
+        if let Some(snapshot) = &state.calibration_snapshot {
+            ui.separator();
+            let mut diff = snapshot.diff(&CalibrationSnapshot::from_calibration_data(
+                calibration_data,
+            ));
+            if let CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::Center) =
+                calibration_device_type
+            {
+                let scope = state.stick_center_scope;
+                ui.label(tf("calibration.scope_of", &[&scope.text()]));
+                diff.retain(|value| scope.includes(value.name));
+            }
+            calibration_diff_grid(ui, &diff);
+        }
         ui.separator();
         calibration_data_form(ui, &calibration_data);
     }
     ui.separator();
     start_calibration_buttons(ui, state, sh.clone());
+    auto_flash_write(ctx, state, sh.clone());
+    poll_auto_flash_write(ui, ctx, state, sh);
+}
+
+// Asks once per connection, later calibrations are written without a dialog
+fn auto_flash_write(ctx: &egui::Context, state: &mut DeviceConnected, sh: StatusHandler) {
+    let Some(calibration) = state.auto_flash_write_pending.clone() else {
+        return;
+    };
+    if !state.auto_flash_write_confirmed {
+        match confirmation_dialog(
+            ctx,
+            t("calibration.auto_flash_write"),
+            t("calibration.auto_flash_write_confirmation"),
+        ) {
+            Some(true) => state.auto_flash_write_confirmed = true,
+            Some(false) => {
+                info!("Auto flash write: declined");
+                state.auto_flash_write_pending = None;
+                return;
+            }
+            None => return,
+        }
+    }
+    state.auto_flash_write_pending = None;
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    state.auto_flash_write_task =
+        sh.handle_error(DeviceTask::spawn(ds4, "flash-verify", move |ds4| {
+            verify_stick_center_calibration_stored(ds4, &calibration)
+        }));
+}
+
+fn poll_auto_flash_write(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    state: &mut DeviceConnected,
+    sh: StatusHandler,
+) {
+    let text = t("calibration.auto_flash_write_running");
+    match poll_task(ui, ctx, &mut state.auto_flash_write_task, text)
+        .map(|result| sh.handle_error(result))
+    {
+        Some(Some(true)) => sh.message(t("calibration.auto_flash_write_verified")),
+        Some(Some(false)) => sh.error(t("calibration.auto_flash_write_not_permanent").into()),
+        _ => {}
+    }
+}
+
+// The controller stores the calibration itself while the permanent mode is on, so it's only
+// read back from the flash. False when the permanent mode is off
+fn verify_stick_center_calibration_stored(
+    ds4: &DualShock4,
+    calibration: &StickCenterCalibration,
+) -> dual_shock_4::Result<bool> {
+    if !ds4.read_permanent()? {
+        warn!("Auto flash write: the permanent mode is off, the calibration isn't stored");
+        return Ok(false);
+    }
+    info!("Auto flash write: reading the stick center calibration back");
+    ds4.verify_flash_stick_center_calibration(calibration)?;
+    info!("Auto flash write: stick center calibration verified");
+    Ok(true)
 }
 
 fn calibration_failed(ui: &mut egui::Ui, state: &mut DeviceConnected, sh: StatusHandler) {
-    ui.heading("Calibration Failed!");
+    ui.heading(t("calibration.failed"));
     start_calibration_buttons(ui, state, sh.clone());
 }
 
+fn calibration_diff_grid(ui: &mut egui::Ui, diff: &[SnapshotDiff]) {
+    ui.label(t("calibration.changes_since_start"));
+    egui::Grid::new("CalibrationDiff")
+        .num_columns(3)
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("");
+            ui.label(t("calibration.before"));
+            ui.label(t("calibration.after"));
+            ui.end_row();
+            for value in diff {
+                let before = value
+                    .before
+                    .as_ref()
+                    .map(|before| before.to_string())
+                    .unwrap_or("-".to_string());
+                if value.changed() {
+                    let color = ui.visuals().warn_fg_color;
+                    ui.label(RichText::new(t(value.name)).color(color));
+                    ui.label(RichText::new(before).color(color));
+                    ui.label(RichText::new(value.after.to_string()).color(color));
+                } else {
+                    ui.label(t(value.name));
+                    ui.label(before);
+                    ui.label(value.after.to_string());
+                }
+                ui.end_row();
+            }
+        });
+}
+
+// Failing to read the previous data must not prevent the calibration
+fn take_calibration_snapshot(state: &mut DeviceConnected) {
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    state.calibration_snapshot = match ds4.read_calibration_data() {
+        Ok(calibration_data) => Some(CalibrationSnapshot::from_calibration_data(
+            &calibration_data,
+        )),
+        Err(e) => {
+            warn!("Cannot snapshot calibration data: {}", e);
+            None
+        }
+    };
+}
+
+// Snapshot and session record are taken before the device starts the calibration
+fn start_calibration(
+    state: &mut DeviceConnected,
+    device_type: CalibrationDeviceType,
+    sh: StatusHandler,
+) {
+    take_calibration_snapshot(state);
+    state.sample_capture = None;
+    state.calibration_log.start(&state.device_key, &device_type);
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    let started =
+        sh.handle_error(ds4.set_calibration_command(CalibrationType::Start(device_type.clone())));
+    match started {
+        Some(_) => state.active_calibration = Some(device_type),
+        None => state.calibration_log.finish(SessionResult::Failed, None),
+    }
+}
+
+// Stops the calibration with the same device type it was started with
+pub fn abort_calibration(state: &mut DeviceConnected, sh: StatusHandler) {
+    let Some(device_type) = state.active_calibration.clone() else {
+        return;
+    };
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    if sh
+        .handle_error(ds4.set_calibration_command(CalibrationType::Stop(device_type)))
+        .is_some()
+    {
+        state.active_calibration = None;
+        state.calibration_log.finish(SessionResult::Failed, None);
+        state.calibration_flag_watch.refresh();
+        update_calibration_wizard_panel(state, sh);
+    }
+}
+
 fn start_calibration_buttons(ui: &mut egui::Ui, state: &mut DeviceConnected, sh: StatusHandler) {
     {
-        let ConnectedDevice::DualShock4(_, ds4) = &state.device;
         let mut panel_update_required = false;
+        // Calibration results are stored by the controller, so only reading is left in safe mode
+        let enabled = !state.safe_mode;
+        let stick_center = CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::Center);
+        ui.horizontal(|ui| {
+            ui.label(t("calibration.scope"));
+            for scope in StickScope::ALL {
+                ui.add_enabled_ui(enabled && scope.is_supported(&stick_center), |ui| {
+                    ui.radio_value(&mut state.stick_center_scope, scope, scope.text())
+                })
+                .response
+                .on_disabled_hover_text(t("help.stick_scope_unsupported"));
+            }
+        });
         if ui
-            .button("Calibrate Analog Sticks Center Position")
+            .add_enabled(
+                enabled,
+                egui::Button::new(t("calibration.calibrate_sticks_center")),
+            )
+            .on_hover_text(t("help.calibrate_sticks_center"))
             .clicked()
         {
-            let _ = sh.handle_error(ds4.set_calibration_command(CalibrationType::Start(
-                CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::Center),
-            )));
+            start_calibration(state, stick_center, sh.clone());
             panel_update_required = true;
         }
-        if ui.button("Calibrate Analog Sticks Min/Max Range").clicked() {
-            let _ = sh.handle_error(ds4.set_calibration_command(CalibrationType::Start(
+        if ui
+            .add_enabled(
+                enabled,
+                egui::Button::new(t("calibration.calibrate_sticks_min_max")),
+            )
+            .on_hover_text(t("help.calibrate_sticks_min_max"))
+            .clicked()
+        {
+            start_calibration(
+                state,
                 CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::MinMax),
-            )));
+                sh.clone(),
+            );
             panel_update_required = true;
         }
-        if ui.button("Calibrate Triggers Keys").clicked() {
-            let _ = sh.handle_error(ds4.set_calibration_command(CalibrationType::Start(
+        if ui
+            .add_enabled(
+                enabled,
+                egui::Button::new(t("calibration.calibrate_triggers")),
+            )
+            .on_hover_text(t("help.calibrate_triggers"))
+            .clicked()
+        {
+            start_calibration(
+                state,
                 CalibrationDeviceType::TriggerKey(TriggerKeyCalibrationType::Unknown(
                     TriggerKeyLeftRight::Both,
                 )),
-            )));
+                sh.clone(),
+            );
             state.panel = super::Panel::Calibration(Panel::Wizard(CalibrationWizard::TriggerKey(
                 TriggerKeyCalibrationType::Unknown(TriggerKeyLeftRight::Both),
             )));
             panel_update_required = false;
         }
-        if ui.button("Force Read Calibration Data").clicked() {
+        if ui
+            .button(t("calibration.force_read_data"))
+            .on_hover_text(t("help.force_read_data"))
+            .clicked()
+        {
+            let ConnectedDevice::DualShock4(_, ds4) = &state.device;
             if let Some(calibration_data) = sh.handle_error(ds4.read_calibration_data()) {
                 state.panel = super::Panel::Calibration(Panel::Wizard(CalibrationWizard::Success(
                     CalibrationDeviceType::None,
@@ -272,78 +974,78 @@ fn calibration_data_form(ui: &mut egui::Ui, calibration_data: &CalibrationData)
         ui.add_enabled_ui(false, |ui| match calibration_data {
             CalibrationData::StickCenter(calculated, samples) => {
                 let mut calculated = calculated.clone();
-                ui.label("Calculated calibration data: ");
+                ui.label(format!("{}: ", t("calibration.calculated_data")));
                 stick_center_calibration_form(ui, &mut calculated);
                 for (i, sample) in samples.iter().enumerate() {
-                    ui.label(format!("Collected sample data {}:", i));
+                    ui.label(format!("{}:", tf("calibration.sample_data", &[&i])));
                     let mut sample = sample.clone();
                     stick_center_calibration_form(ui, &mut sample);
                 }
             }
             CalibrationData::StickMinMax(calibration) => {
                 let mut calibration = calibration.clone();
-                ui.label("Calibration data: ");
+                ui.label(format!("{}: ", t("calibration.data")));
                 stick_min_max_calibration_form(ui, &mut calibration);
             }
             CalibrationData::Triggers(calibration) => {
-                ui.label("Calibration data: ");
+                ui.label(format!("{}: ", t("calibration.data")));
                 ui.label(hex::encode(calibration.buf.as_slice()));
             }
             CalibrationData::None(data) => {
-                ui.label("Unknown calibration data: ");
+                ui.label(format!("{}: ", t("calibration.unknown_data")));
                 ui.label(hex::encode(data));
             }
         });
     });
 }
 
-fn stick_center_calibration_form(ui: &mut egui::Ui, calibration: &mut StickCenterCalibration) {
+pub fn stick_center_calibration_form(ui: &mut egui::Ui, calibration: &mut StickCenterCalibration) {
     ui.columns(2, |columns| {
         let mut left_x_center = calibration.left_x();
         let mut left_y_center = calibration.left_y();
         let mut right_x_center = calibration.right_x();
         let mut right_y_center = calibration.right_y();
-        if columns[0]
-            .add(center_calibration_slider(
-                &mut left_x_center,
-                "Left Stick X-Axis Center",
-            ))
-            .changed()
+        if center_calibration_value(
+            &mut columns[0],
+            &mut left_x_center,
+            t("calibration.left_x_center"),
+        )
+        .changed()
         {
             calibration.set_left_x(left_x_center);
         }
-        if columns[1]
-            .add(center_calibration_slider(
-                &mut right_x_center,
-                "Right Stick X-Axis Center",
-            ))
-            .changed()
+        if center_calibration_value(
+            &mut columns[1],
+            &mut right_x_center,
+            t("calibration.right_x_center"),
+        )
+        .changed()
         {
             calibration.set_right_x(right_x_center);
         }
 
-        if columns[0]
-            .add(center_calibration_slider(
-                &mut left_y_center,
-                "Left Stick Y-Axis Center",
-            ))
-            .changed()
+        if center_calibration_value(
+            &mut columns[0],
+            &mut left_y_center,
+            t("calibration.left_y_center"),
+        )
+        .changed()
         {
             calibration.set_left_y(left_y_center);
         }
-        if columns[1]
-            .add(center_calibration_slider(
-                &mut right_y_center,
-                "Right Stick Y-Axis Center",
-            ))
-            .changed()
+        if center_calibration_value(
+            &mut columns[1],
+            &mut right_y_center,
+            t("calibration.right_y_center"),
+        )
+        .changed()
         {
             calibration.set_right_y(right_y_center);
         }
     });
 }
 
-fn stick_min_max_calibration_form(ui: &mut egui::Ui, calibration: &mut StickMinMaxCalibration) {
+pub fn stick_min_max_calibration_form(ui: &mut egui::Ui, calibration: &mut StickMinMaxCalibration) {
     let mut left_min_x = calibration.left_min_x();
     let mut left_max_x = calibration.left_max_x();
     let mut left_min_y = calibration.left_min_y();
@@ -353,62 +1055,38 @@ fn stick_min_max_calibration_form(ui: &mut egui::Ui, calibration: &mut StickMinM
     let mut right_min_y = calibration.right_min_y();
     let mut right_max_y = calibration.right_max_y();
     ui.columns(2, |columns| {
-        columns[0].label("Left Stick X-Axis");
+        columns[0].label(t("calibration.left_x_axis"));
         columns[1].label("");
-        if columns[0]
-            .add(min_calibration_slider(&mut left_min_x, "Min"))
-            .changed()
-        {
+        if min_calibration_value(&mut columns[0], &mut left_min_x, t("common.min")).changed() {
             calibration.set_left_min_x(left_min_x);
         }
-        if columns[1]
-            .add(max_calibration_slider(&mut left_max_x, "Max"))
-            .changed()
-        {
+        if max_calibration_value(&mut columns[1], &mut left_max_x, t("common.max")).changed() {
             calibration.set_left_max_x(left_max_x);
         }
-        columns[0].label("Left Stick Y-Axis");
+        columns[0].label(t("calibration.left_y_axis"));
         columns[1].label("");
-        if columns[0]
-            .add(min_calibration_slider(&mut left_min_y, "Min"))
-            .changed()
-        {
+        if min_calibration_value(&mut columns[0], &mut left_min_y, t("common.min")).changed() {
             calibration.set_left_min_y(left_min_y);
         }
-        if columns[1]
-            .add(max_calibration_slider(&mut left_max_y, "Max"))
-            .changed()
-        {
-            calibration.set_left_min_y(left_max_y);
+        if max_calibration_value(&mut columns[1], &mut left_max_y, t("common.max")).changed() {
+            calibration.set_left_max_y(left_max_y);
         }
 
-        columns[0].label("Right Stick X-Axis");
+        columns[0].label(t("calibration.right_x_axis"));
         columns[1].label("");
-        if columns[0]
-            .add(min_calibration_slider(&mut right_min_x, "Min"))
-            .changed()
-        {
+        if min_calibration_value(&mut columns[0], &mut right_min_x, t("common.min")).changed() {
             calibration.set_right_min_x(right_min_x);
         }
-        if columns[1]
-            .add(max_calibration_slider(&mut right_max_x, "Max"))
-            .changed()
-        {
+        if max_calibration_value(&mut columns[1], &mut right_max_x, t("common.max")).changed() {
             calibration.set_right_max_x(right_max_x);
         }
-        columns[0].label("Right Stick Y-Axis");
+        columns[0].label(t("calibration.right_y_axis"));
         columns[1].label("");
-        if columns[0]
-            .add(min_calibration_slider(&mut right_min_y, "Min"))
-            .changed()
-        {
+        if min_calibration_value(&mut columns[0], &mut right_min_y, t("common.min")).changed() {
             calibration.set_right_min_y(right_min_y);
         }
-        if columns[1]
-            .add(max_calibration_slider(&mut right_max_y, "Max"))
-            .changed()
-        {
-            calibration.set_right_min_y(right_max_y);
+        if max_calibration_value(&mut columns[1], &mut right_max_y, t("common.max")).changed() {
+            calibration.set_right_max_y(right_max_y);
         }
     });
 }
@@ -419,41 +1097,45 @@ fn stick_center_calibration(
     state: &mut DeviceConnected,
     sh: StatusHandler,
 ) {
-    ui.heading("Analog Sticks Center Calibration");
-    ui.label("Don't touch the analog sticks and press the Add Sample key to add sample, ");
-    ui.label("or press Finish to save calibration results.");
+    ui.heading(t("calibration.sticks_center_heading"));
+    ui.label(t("calibration.sticks_center_hint"));
+    ui.label(t("calibration.sticks_center_hint_finish"));
     let ConnectedDevice::DualShock4(_, ds4) = &state.device;
     let ds4_data = sh.handle_error(ds4.read_last_data()).flatten();
     if let Some(ds4_data) = &ds4_data {
         ui.columns(2, |columns| {
             let stick_position = ds4_data.left_stick_position();
             columns[0].add(stick_preview_plot(
-                "Left Stick Preview",
+                t("calibration.left_stick_preview"),
                 stick_position,
                 0f64,
                 0f64,
+                state.stick_plot_style,
             ));
             let stick_position = ds4_data.right_stick_position();
             columns[1].add(stick_preview_plot(
-                "Right Stick Preview",
+                t("calibration.right_stick_preview"),
                 stick_position,
                 0f64,
                 0f64,
+                state.stick_plot_style,
             ));
             ctx.request_repaint();
         });
     }
-    if ui.add(button_triangle("Add Sample")).clicked()
-        || ds4_data.as_ref().map(|d| d.triangle()).unwrap_or(false)
+    let capturing = state.sample_capture.is_some();
+    if ui
+        .add_enabled(!capturing, button_triangle(t("common.add_sample")))
+        .clicked()
+        || (!capturing && ds4_data.as_ref().map(|d| d.triangle()).unwrap_or(false))
     {
-        {
-            let _ = sh.handle_error(ds4.set_calibration_command(CalibrationType::Measure(
-                CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::Center),
-            )));
-        }
-        update_calibration_wizard_panel(state, sh.clone());
+        add_stick_center_sample(state, sh.clone());
     }
-    if ui.add(button_cross("Finish")).clicked() || ds4_data.map(|d| d.cross()).unwrap_or(false) {
+    stick_center_sample_capture(ui, ctx, state, sh.clone());
+    if ui.add(button_cross(t("common.finish"))).clicked()
+        || ds4_data.map(|d| d.cross()).unwrap_or(false)
+    {
+        state.sample_capture = None;
         {
             let ConnectedDevice::DualShock4(_, ds4) = &state.device;
             let _ = sh.handle_error(ds4.set_calibration_command(CalibrationType::Stop(
@@ -464,14 +1146,74 @@ fn stick_center_calibration(
     }
 }
 
+fn add_stick_center_sample(state: &mut DeviceConnected, sh: StatusHandler) -> bool {
+    let measured = {
+        let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+        sh.handle_error(ds4.set_calibration_command(CalibrationType::Measure(
+            CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::Center),
+        )))
+        .is_some()
+    };
+    update_calibration_wizard_panel(state, sh);
+    measured
+}
+
+fn stick_center_sample_capture(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    state: &mut DeviceConnected,
+    sh: StatusHandler,
+) {
+    let Some(capture) = &mut state.sample_capture else {
+        if ui
+            .button(tf("calibration.capture_samples", &[&SAMPLE_CAPTURE_COUNT]))
+            .on_hover_text(t("help.capture_samples"))
+            .clicked()
+        {
+            state.sample_capture = Some(SampleCapture::new(SAMPLE_CAPTURE_COUNT, Instant::now()));
+            ctx.request_repaint();
+        }
+        return;
+    };
+    let mut cancelled = false;
+    ui.horizontal(|ui| {
+        ui.spinner();
+        ui.label(tf(
+            "calibration.capturing_samples",
+            &[&capture.captured, &SAMPLE_CAPTURE_COUNT],
+        ));
+        cancelled = ui.button(t("common.cancel")).clicked();
+    });
+    if cancelled {
+        state.sample_capture = None;
+        return;
+    }
+    let now = Instant::now();
+    if capture.take_due(now) {
+        let finished = capture.is_finished();
+        if !add_stick_center_sample(state, sh.clone()) {
+            state.sample_capture = None;
+            return;
+        }
+        if finished {
+            state.sample_capture = None;
+            sh.message(tf("calibration.samples_captured", &[&SAMPLE_CAPTURE_COUNT]));
+            return;
+        }
+    }
+    if let Some(capture) = &state.sample_capture {
+        ctx.request_repaint_after(capture.next_at.saturating_duration_since(now));
+    }
+}
+
 fn stick_min_max_calibration(
     ui: &mut egui::Ui,
     ctx: &egui::Context,
     state: &mut DeviceConnected,
     sh: StatusHandler,
 ) {
-    ui.heading("Analog Sticks Min/Max Range Calibration");
-    ui.label("Move analog sticks all around their range and press finish.");
+    ui.heading(t("calibration.sticks_min_max_heading"));
+    ui.label(t("calibration.sticks_min_max_hint"));
     let ConnectedDevice::DualShock4(_, ds4) = &state.device;
     let ds4_data = sh.handle_error(ds4.read_last_data()).flatten();
     if let Some(ds4_data) = &ds4_data {
@@ -481,25 +1223,29 @@ fn stick_min_max_calibration(
                 ui.label(with_gamepad_font(GAMEPAD_FONT_LEFT_ANALOG_CLOCKWISE).size(96f32));
             });
             columns[0].add(stick_preview_plot(
-                "Left Stick Preview",
+                t("calibration.left_stick_preview"),
                 stick_position,
                 0f64,
                 0f64,
+                state.stick_plot_style,
             ));
             let stick_position = ds4_data.right_stick_position();
             columns[1].vertical_centered(|ui| {
                 ui.label(with_gamepad_font(GAMEPAD_FONT_RIGHT_ANALOG_CLOCKWISE).size(96f32));
             });
             columns[1].add(stick_preview_plot(
-                "Right Stick Preview",
+                t("calibration.right_stick_preview"),
                 stick_position,
                 0f64,
                 0f64,
+                state.stick_plot_style,
             ));
             ctx.request_repaint();
         });
     }
-    if ui.add(button_cross("Finish")).clicked() || ds4_data.map(|d| d.cross()).unwrap_or(false) {
+    if ui.add(button_cross(t("common.finish"))).clicked()
+        || ds4_data.map(|d| d.cross()).unwrap_or(false)
+    {
         {
             let _ = sh.handle_error(ds4.set_calibration_command(CalibrationType::Stop(
                 CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::MinMax),
@@ -509,41 +1255,130 @@ fn stick_min_max_calibration(
     }
 }
 
-fn motion_calibration_value_form(
+pub fn motion_calibration_value_form(
     ui: &mut egui::Ui,
-    calibration: &mut MotionCalibration,
+    motion_sensor: &mut MotionSensor,
     sh: StatusHandler,
 ) {
+    let calibration = &mut motion_sensor.calibration;
     let mut value_string = hex::encode(calibration.buf);
     ui.spacing_mut().text_edit_width = 600f32;
-    if ui
-        .add(egui::TextEdit::singleline(&mut value_string))
-        .changed()
-    {
+    let response = ui.add(egui::TextEdit::singleline(&mut value_string));
+    if response.gained_focus() {
+        motion_sensor.edit_start = Some(calibration.clone());
+    }
+    if response.changed() {
         let max_len = calibration.buf.len() * 2;
         value_string.truncate(max_len);
         value_string = format!("{:0<width$}", value_string, width = max_len);
         sh.handle_error(hex::decode_to_slice(value_string, &mut calibration.buf));
     }
+    if response.lost_focus() {
+        if let Some(edit_start) = motion_sensor.edit_start.take() {
+            if edit_start != *calibration {
+                motion_sensor.history.commit(edit_start);
+            }
+        }
+    }
+    undo_redo_buttons(ui, &mut motion_sensor.history, calibration);
+    motion_calibration_values_grid(ui, &calibration.values(motion_sensor.connection_type));
+}
+
+fn motion_calibration_values_grid(ui: &mut egui::Ui, values: &MotionCalibrationValues) {
+    egui::Grid::new("Motion Calibration Values")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label(t("calibration.gyroscope_bias"));
+            ui.label(format!("{:?}", values.gyroscope_bias));
+            ui.end_row();
+            ui.label(t("calibration.gyroscope_plus"));
+            ui.label(format!("{:?}", values.gyroscope_plus));
+            ui.end_row();
+            ui.label(t("calibration.gyroscope_minus"));
+            ui.label(format!("{:?}", values.gyroscope_minus));
+            ui.end_row();
+            ui.label(t("calibration.gyroscope_speed"));
+            ui.label(format!(
+                "[{}, {}]",
+                values.gyroscope_speed_plus, values.gyroscope_speed_minus
+            ));
+            ui.end_row();
+            for (key, [plus, minus]) in [
+                "calibration.accelerometer_x",
+                "calibration.accelerometer_y",
+                "calibration.accelerometer_z",
+            ]
+            .into_iter()
+            .zip(values.accelerometer)
+            {
+                ui.label(t(key));
+                ui.label(format!("[{}, {}]", plus, minus));
+                ui.end_row();
+            }
+        });
 }
 
 fn motion_calibration(ui: &mut egui::Ui, state: &mut DeviceConnected, sh: StatusHandler) {
-    ui.heading("Motion Sensor Calibration Value");
+    ui.heading(t("calibration.motion_sensor_heading"));
 
-    if let super::Panel::Calibration(Panel::MotionSensor(calibration)) = &mut state.panel {
-        motion_calibration_value_form(ui, calibration, sh.clone());
+    if let super::Panel::Calibration(Panel::MotionSensor(motion_sensor)) = &mut state.panel {
+        motion_calibration_value_form(ui, motion_sensor, sh.clone());
+        // Only the form is changed, writing still requires Write to Device
+        if ui
+            .button(t("calibration.load_identity"))
+            .on_hover_text(t("help.load_identity"))
+            .clicked()
+        {
+            let mut identity = motion_sensor.calibration.clone();
+            identity.set_values(
+                &MotionCalibrationValues::identity(),
+                motion_sensor.connection_type,
+            );
+            if identity != motion_sensor.calibration {
+                motion_sensor
+                    .history
+                    .commit(std::mem::replace(&mut motion_sensor.calibration, identity));
+            }
+        }
     }
 
-    if ui.button("Read from Device").clicked() {
+    if ui.button(t("calibration.read_from_device")).clicked() {
         let ConnectedDevice::DualShock4(_, ds4) = &state.device;
         if let Some(calibration_from_device) = sh.handle_error(ds4.read_motion_calibration_data()) {
-            state.panel = super::Panel::Calibration(Panel::MotionSensor(calibration_from_device));
+            state.panel = super::Panel::Calibration(Panel::MotionSensor(MotionSensor::new(
+                calibration_from_device,
+                ds4.connection_type(),
+            )));
         }
     }
-    if ui.button("Write to Device").clicked() {
-        if let super::Panel::Calibration(Panel::MotionSensor(calibration)) = &state.panel {
-            let ConnectedDevice::DualShock4(_, ds4) = &state.device;
-            let _ = sh.handle_error(ds4.set_motion_calibration_data(calibration));
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    let write_supported = ds4.is_write_supported(CalibrationWrite::Motion);
+    if ui
+        .add_enabled(
+            !state.safe_mode && write_supported,
+            egui::Button::new(t("calibration.write_to_device")),
+        )
+        .on_hover_text(t("help.write_motion_calibration"))
+        .on_disabled_hover_text(if write_supported {
+            t("help.safe_mode")
+        } else {
+            t("calibration.motion_write_not_supported")
+        })
+        .clicked()
+    {
+        if let super::Panel::Calibration(Panel::MotionSensor(motion_sensor)) = &mut state.panel {
+            if sh
+                .handle_error(ds4.write_motion_calibration_verified(&motion_sensor.calibration))
+                .is_some()
+            {
+                motion_sensor.device_calibration = motion_sensor.calibration.clone();
+                sh.message(t("calibration.motion_sensor_written"));
+            }
+        }
+    }
+    if let super::Panel::Calibration(Panel::MotionSensor(motion_sensor)) = &state.panel {
+        if motion_sensor.is_dirty() {
+            ui.label(RichText::new(t("calibration.unsaved_changes")).color(Color32::YELLOW));
         }
     }
 }
@@ -555,26 +1390,39 @@ fn triggers_calibration(
     type_: TriggerKeyCalibrationType,
     sh: StatusHandler,
 ) {
+    let force_finish = ui
+        .horizontal(|ui| {
+            ui.heading(t("calibration.triggers_heading"));
+            ui.button(t("calibration.force_finish"))
+                .on_hover_text(t("help.force_finish_triggers"))
+                .clicked()
+        })
+        .inner;
+    if force_finish {
+        force_finish_trigger_calibration(state, &type_, sh);
+        return;
+    }
     let ConnectedDevice::DualShock4(_, ds4) = &state.device;
-    ui.heading("Triggers Calibration");
+
+    let data = sh.handle_error(ds4.read_last_data()).flatten();
+    if let Some(data) = &data {
+        state
+            .trigger_capture
+            .record(&type_, [data.l2_trigger(), data.r2_trigger()]);
+    }
+    let data = data.unwrap_or(Data::zeroed());
 
     let next_step = match type_ {
         TriggerKeyCalibrationType::RecordMaxSample(lr) => {
-            ui.label(format!(
-                "Press {} trigger(s) and click Next to continue",
-                lr
-            ));
+            ui.label(tf("calibration.triggers_press", &[&lr]));
             TriggerKeyCalibrationType::RecordRangeSample(lr)
         }
         TriggerKeyCalibrationType::RecordRangeSample(lr) => {
-            ui.label(format!(
-                "Press several times {} trigger(s) all range and click Next to continue",
-                lr
-            ));
+            ui.label(tf("calibration.triggers_press_range", &[&lr]));
             TriggerKeyCalibrationType::RecordMinSample(lr)
         }
         TriggerKeyCalibrationType::RecordMinSample(lr) => {
-            ui.label(format!("Release {} trigger(s) and click Finish", lr));
+            ui.label(tf("calibration.triggers_release", &[&lr]));
             TriggerKeyCalibrationType::Unknown(lr)
         }
         TriggerKeyCalibrationType::Unknown(lr) => {
@@ -592,22 +1440,26 @@ fn triggers_calibration(
         TriggerKeyCalibrationType::None => TriggerKeyCalibrationType::None,
     };
 
-    let data = sh
-        .handle_error(ds4.read_last_data())
-        .flatten()
-        .unwrap_or(Data::zeroed());
     ui.columns(2, |columns| {
-        columns[0].add(trigger_bar(data.l2_trigger(), "Left Trigger"));
-        columns[1].add(trigger_bar(data.r2_trigger(), "Right Trigger"));
+        columns[0].add(trigger_bar(
+            data.l2_trigger(),
+            None,
+            t("common.left_trigger"),
+        ));
+        columns[1].add(trigger_bar(
+            data.r2_trigger(),
+            None,
+            t("common.right_trigger"),
+        ));
     });
 
     if let TriggerKeyCalibrationType::Unknown(lr) = next_step {
-        if ui.add(button_triangle("Add Sample")).clicked() || data.triangle() {
+        if ui.add(button_triangle(t("common.add_sample"))).clicked() || data.triangle() {
             state.panel = super::Panel::Calibration(Panel::Wizard(CalibrationWizard::TriggerKey(
                 TriggerKeyCalibrationType::Unknown(lr.clone()),
             )))
         }
-        if ui.add(button_cross("Finish")).clicked() || data.cross() {
+        if ui.add(button_cross(t("common.finish"))).clicked() || data.cross() {
             if let Some(_) = sh.handle_error(ds4.set_calibration_command(CalibrationType::Stop(
                 CalibrationDeviceType::TriggerKey(TriggerKeyCalibrationType::Unknown(lr)),
             ))) {
@@ -615,7 +1467,7 @@ fn triggers_calibration(
             }
         }
     } else {
-        if ui.button("Next").clicked() {
+        if ui.button(t("calibration.next")).clicked() {
             if let Some(_) = sh.handle_error(ds4.set_calibration_command(CalibrationType::Measure(
                 CalibrationDeviceType::TriggerKey(next_step.clone()),
             ))) {
@@ -629,15 +1481,48 @@ fn triggers_calibration(
     ctx.request_repaint();
 }
 
+// The calibration is started with the Unknown type, so the stop is the same for every step
+fn trigger_stop_command(type_: &TriggerKeyCalibrationType) -> CalibrationType {
+    let lr = match type_ {
+        TriggerKeyCalibrationType::RecordMaxSample(lr)
+        | TriggerKeyCalibrationType::RecordRangeSample(lr)
+        | TriggerKeyCalibrationType::RecordMinSample(lr)
+        | TriggerKeyCalibrationType::Unknown(lr) => lr.clone(),
+        TriggerKeyCalibrationType::None => TriggerKeyLeftRight::Both,
+    };
+    CalibrationType::Stop(CalibrationDeviceType::TriggerKey(
+        TriggerKeyCalibrationType::Unknown(lr),
+    ))
+}
+
+// Returns to the wizard start even when the stop fails, so a stuck step can't lock the panels
+fn force_finish_trigger_calibration(
+    state: &mut DeviceConnected,
+    type_: &TriggerKeyCalibrationType,
+    sh: StatusHandler,
+) {
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    let _ = sh.handle_error(ds4.set_calibration_command(trigger_stop_command(type_)));
+    state.active_calibration = None;
+    state.trigger_capture = TriggerThresholdCapture::default();
+    state.calibration_log.finish(SessionResult::Failed, None);
+    state.calibration_flag_watch.refresh();
+    state.switch_panel(super::Panel::Calibration(Panel::Wizard(
+        CalibrationWizard::Start,
+    )));
+}
+
 fn stick_preview_plot<'a>(
     title: &'a str,
     stick_position: StickPosition,
     normalized_x_adjustment: f64,
     normalized_y_adjustment: f64,
+    style: StickPlotStyle,
 ) -> impl egui::Widget + 'a {
     move |ui: &mut egui::Ui| {
         ui.vertical_centered(|ui| {
             ui.label(title);
+            let (radius, width) = (style.point_radius(ui), style.line_width(ui));
             egui_plot::Plot::new(title)
                 .view_aspect(1f32)
                 .include_x(-1.1f64)
@@ -648,40 +1533,223 @@ fn stick_preview_plot<'a>(
                 .allow_drag(false)
                 .allow_scroll(false)
                 .show(ui, |plot_ui| {
-                    plot_ui.line(circle_line(0f64, 0f64, 1f64).color(Color32::GRAY));
+                    plot_ui.line(
+                        circle_line(0f64, 0f64, 1f64)
+                            .color(Color32::GRAY)
+                            .width(width),
+                    );
                     let (x, y) = (stick_position.normalized_x(), stick_position.normalized_y());
-                    let points = Points::new([x, y]).radius(3f32).color(Color32::RED);
+                    let points = Points::new([x, y]).radius(radius).color(Color32::RED);
                     plot_ui.points(points);
                     let (x, y) = (x + normalized_x_adjustment, y + normalized_y_adjustment);
-                    let points = Points::new([x, y]).radius(3f32).color(Color32::GREEN);
+                    let points = Points::new([x, y]).radius(radius).color(Color32::GREEN);
                     plot_ui.points(points);
-                })
-                .response
+                });
+            // Without an adjustment the adjusted point covers the reported one
+            let adjusted = normalized_x_adjustment != 0f64 || normalized_y_adjustment != 0f64;
+            let mut legend = if adjusted {
+                vec![
+                    (Color32::RED, t("output.legend_position")),
+                    (Color32::GREEN, t("calibration.legend_adjusted_position")),
+                ]
+            } else {
+                vec![(Color32::GREEN, t("output.legend_position"))]
+            };
+            legend.push((Color32::GRAY, t("output.legend_range")));
+            stick_plot_legend(ui, &legend);
         })
             .response
     }
 }
 
-fn center_calibration_slider<'a>(value: &'a mut i16, text: &'a str) -> egui::Slider<'a> {
-    egui::Slider::new(value, -512i16..=512i16)
-        .clamping(SliderClamping::Always)
-        .text(text)
-        .logarithmic(true)
-        .step_by(1f64)
+fn center_calibration_value(ui: &mut egui::Ui, value: &mut i16, text: &str) -> egui::Response {
+    calibration_value(ui, value, -512i16..=512i16, text, true)
+}
+
+fn min_calibration_value(ui: &mut egui::Ui, value: &mut i16, text: &str) -> egui::Response {
+    calibration_value(ui, value, -4048i16..=0i16, text, false)
+}
+
+fn max_calibration_value(ui: &mut egui::Ui, value: &mut i16, text: &str) -> egui::Response {
+    calibration_value(ui, value, 0i16..=4048i16, text, false)
 }
 
-fn min_calibration_slider<'a>(value: &'a mut i16, text: &'a str) -> egui::Slider<'a> {
-    egui::Slider::new(value, -4048i16..=0i16)
-        .clamping(SliderClamping::Always)
-        .text(text)
-        .logarithmic(false)
-        .step_by(1f64)
+// Slider with a numeric field for exact values, both edit the same clamped value
+fn calibration_value(
+    ui: &mut egui::Ui,
+    value: &mut i16,
+    range: RangeInclusive<i16>,
+    text: &str,
+    logarithmic: bool,
+) -> egui::Response {
+    ui.horizontal(|ui| {
+        let slider = ui.add(
+            egui::Slider::new(value, range.clone())
+                .clamping(SliderClamping::Always)
+                .logarithmic(logarithmic)
+                .step_by(1f64)
+                .show_value(false),
+        );
+        let field = ui.add(egui::DragValue::new(value).range(range).speed(1f64));
+        ui.label(text);
+        slider | field
+    })
+    .inner
 }
 
-fn max_calibration_slider<'a>(value: &'a mut i16, text: &'a str) -> egui::Slider<'a> {
-    egui::Slider::new(value, 0i16..=4048i16)
-        .clamping(SliderClamping::Always)
-        .text(text)
-        .logarithmic(false)
-        .step_by(1f64)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trigger_threshold_capture() {
+        let mut capture = TriggerThresholdCapture::default();
+        let lr = TriggerKeyLeftRight::Both;
+        capture.record(
+            &TriggerKeyCalibrationType::RecordMaxSample(lr.clone()),
+            [240, 255],
+        );
+        assert_eq!(capture.thresholds(), [None, None]);
+        capture.record(
+            &TriggerKeyCalibrationType::RecordRangeSample(lr.clone()),
+            [100, 0],
+        );
+        for values in [[60, 9], [3, 4], [12, 5]] {
+            capture.record(
+                &TriggerKeyCalibrationType::RecordMinSample(lr.clone()),
+                values,
+            );
+        }
+        let [left, right] = capture.thresholds();
+        let left = left.unwrap();
+        assert_eq!(
+            left,
+            TriggerThresholds {
+                min: 3,
+                dead_zone: 12,
+                max: 240
+            }
+        );
+        assert_eq!(right.unwrap().max, 255);
+        assert_eq!(left.calibrated(0), Some(0f32));
+        assert_eq!(left.calibrated(126), Some(0.5));
+        assert_eq!(left.calibrated(255), Some(1f32));
+        let empty = TriggerThresholds {
+            min: 0,
+            dead_zone: 200,
+            max: 200,
+        };
+        assert_eq!(empty.calibrated(100), None);
+    }
+
+    #[test]
+    fn trigger_stop_is_the_same_for_every_step() {
+        let lr = TriggerKeyLeftRight::Both;
+        for type_ in [
+            TriggerKeyCalibrationType::RecordMaxSample(lr.clone()),
+            TriggerKeyCalibrationType::RecordRangeSample(lr.clone()),
+            TriggerKeyCalibrationType::RecordMinSample(lr.clone()),
+            TriggerKeyCalibrationType::Unknown(lr.clone()),
+            TriggerKeyCalibrationType::None,
+        ] {
+            assert!(matches!(
+                trigger_stop_command(&type_),
+                CalibrationType::Stop(CalibrationDeviceType::TriggerKey(
+                    TriggerKeyCalibrationType::Unknown(TriggerKeyLeftRight::Both)
+                ))
+            ));
+        }
+    }
+
+    #[test]
+    fn sample_capture_waits_for_interval() {
+        let start = Instant::now();
+        let mut capture = SampleCapture::new(2, start);
+        assert!(capture.take_due(start));
+        assert!(!capture.take_due(start + SAMPLE_CAPTURE_INTERVAL / 2));
+        assert!(!capture.is_finished());
+        assert!(capture.take_due(start + SAMPLE_CAPTURE_INTERVAL));
+        assert!(capture.is_finished());
+        assert!(!capture.take_due(start + SAMPLE_CAPTURE_INTERVAL * 10));
+        assert_eq!(capture.captured, 2);
+    }
+
+    #[test]
+    fn snapshot_diff_marks_only_changed_values() {
+        let mut calibration = StickCenterCalibration::default();
+        let before = CalibrationSnapshot::from_calibration_data(&CalibrationData::StickCenter(
+            calibration.clone(),
+            vec![],
+        ));
+        calibration.set_left_x(100);
+        let after = CalibrationSnapshot::from_calibration_data(&CalibrationData::StickCenter(
+            calibration,
+            vec![],
+        ));
+
+        let diff = before.diff(&after);
+        let changed: Vec<&str> = diff
+            .iter()
+            .filter(|value| value.changed())
+            .map(|value| value.name)
+            .collect();
+        assert_eq!(diff.len(), 4);
+        assert_eq!(changed, vec!["calibration.left_x_center"]);
+    }
+
+    #[test]
+    fn stick_scope_filters_other_stick() {
+        let calibration = StickCenterCalibration::default();
+        let snapshot = CalibrationSnapshot::from_calibration_data(&CalibrationData::StickCenter(
+            calibration,
+            vec![],
+        ));
+        let names = |scope: StickScope| -> Vec<&str> {
+            snapshot
+                .diff(&snapshot)
+                .iter()
+                .map(|value| value.name)
+                .filter(|name| scope.includes(name))
+                .collect()
+        };
+        assert_eq!(names(StickScope::Both).len(), 4);
+        let stick_center = CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::Center);
+        assert!(StickScope::Both.is_supported(&stick_center));
+        assert!(!StickScope::Left.is_supported(&stick_center));
+        assert!(!StickScope::Right.is_supported(&stick_center));
+        assert_eq!(
+            names(StickScope::Left),
+            vec!["calibration.left_x_center", "calibration.left_y_center"]
+        );
+        assert_eq!(
+            names(StickScope::Right),
+            vec!["calibration.right_x_center", "calibration.right_y_center"]
+        );
+        assert_eq!(StickScope::default(), StickScope::Both);
+    }
+
+    #[test]
+    fn snapshot_diff_of_other_calibration_type() {
+        let before = CalibrationSnapshot::from_calibration_data(&CalibrationData::None(vec![1]));
+        let after = CalibrationSnapshot::from_calibration_data(&CalibrationData::StickMinMax(
+            StickMinMaxCalibration::default(),
+        ));
+
+        let diff = before.diff(&after);
+        assert!(diff
+            .iter()
+            .all(|value| value.before.is_none() && value.changed()));
+    }
+
+    #[test]
+    fn calibration_value_clamps_to_range() {
+        let (mut max, mut center) = (5000i16, -600i16);
+        let _ = egui::Context::default().run(Default::default(), |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                max_calibration_value(ui, &mut max, "");
+                center_calibration_value(ui, &mut center, "");
+            });
+        });
+        assert_eq!((max, center), (4048, -512));
+    }
 }
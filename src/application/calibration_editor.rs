@@ -0,0 +1,199 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::application::calibration::{
+    motion_calibration_value_form, stick_center_calibration_form, stick_min_max_calibration_form,
+    MotionSensor,
+};
+use crate::application::i18n::{t, tf};
+use crate::application::StatusHandler;
+use crate::dual_shock_4::{
+    ConnectionType, MotionCalibration, StickCenterCalibration, StickMinMaxCalibration,
+    TriggersCalibration,
+};
+use eframe::egui;
+use eframe::egui::{Color32, RichText, ScrollArea};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+// Files don't record the connection, the USB layout of the motion calibration is assumed
+const EDITOR_CONNECTION_TYPE: ConnectionType = ConnectionType::Usb;
+
+// Every editable calibration section as hex, the same encoding as the forms show
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CalibrationFile {
+    stick_center: String,
+    stick_min_max: String,
+    // Length depends on the firmware, so it isn't checked
+    triggers: String,
+    motion: String,
+}
+
+// Calibration edited in memory without a device, nothing is written to a controller
+#[derive(Clone)]
+pub struct CalibrationEditor {
+    stick_center: StickCenterCalibration,
+    stick_min_max: StickMinMaxCalibration,
+    triggers: TriggersCalibration,
+    triggers_hex: String,
+    motion: MotionSensor,
+}
+
+impl Default for CalibrationEditor {
+    fn default() -> Self {
+        Self {
+            stick_center: StickCenterCalibration::default(),
+            stick_min_max: StickMinMaxCalibration::default(),
+            triggers: TriggersCalibration::default(),
+            triggers_hex: String::new(),
+            motion: MotionSensor::new(MotionCalibration::default(), EDITOR_CONNECTION_TYPE),
+        }
+    }
+}
+
+fn section<const N: usize>(hex: &str, name: &str) -> Result<[u8; N], String> {
+    let bytes =
+        hex::decode(hex.trim()).map_err(|e| tf("calibration.editor_invalid_hex", &[&name, &e]))?;
+    let len = bytes.len();
+    bytes
+        .try_into()
+        .map_err(|_| tf("calibration.editor_invalid_length", &[&name, &len, &N]))
+}
+
+impl CalibrationEditor {
+    // File can be edited by hand, so the fixed sections are checked for their lengths
+    fn from_json(json: &str) -> Result<Self, String> {
+        let file: CalibrationFile = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let triggers = hex::decode(file.triggers.trim())
+            .map_err(|e| tf("calibration.editor_invalid_hex", &[&"triggers", &e]))?;
+        Ok(Self {
+            stick_center: StickCenterCalibration {
+                buf: section(&file.stick_center, "stick_center")?,
+            },
+            stick_min_max: StickMinMaxCalibration {
+                buf: section(&file.stick_min_max, "stick_min_max")?,
+            },
+            triggers_hex: hex::encode(&triggers),
+            triggers: TriggersCalibration { buf: triggers },
+            motion: MotionSensor::new(
+                MotionCalibration {
+                    buf: section(&file.motion, "motion")?,
+                },
+                EDITOR_CONNECTION_TYPE,
+            ),
+        })
+    }
+
+    fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(&CalibrationFile {
+            stick_center: hex::encode(self.stick_center.buf),
+            stick_min_max: hex::encode(self.stick_min_max.buf),
+            triggers: hex::encode(&self.triggers.buf),
+            motion: hex::encode(self.motion.calibration().buf),
+        })
+    }
+
+    fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read_to_string(path).map_err(|e| format!("{:?}: {}", path, e))?;
+        Self::from_json(&json).map_err(|e| format!("{:?}: {}", path, e))
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let json = self.to_json().map_err(|e| e.to_string())?;
+        fs::write(path, json).map_err(|e| format!("{:?}: {}", path, e))
+    }
+}
+
+fn load_save_buttons(ui: &mut egui::Ui, editor: &mut CalibrationEditor, sh: StatusHandler) {
+    ui.horizontal(|ui| {
+        if ui.button(t("calibration.editor_load")).clicked() {
+            if let Some(file) = rfd::FileDialog::new()
+                .add_filter("json", &["json"])
+                .pick_file()
+            {
+                match CalibrationEditor::load(&file) {
+                    Ok(loaded) => *editor = loaded,
+                    Err(e) => sh.error(e.into()),
+                }
+            }
+        }
+        if ui.button(t("calibration.editor_save")).clicked() {
+            if let Some(file) = rfd::FileDialog::new()
+                .add_filter("json", &["json"])
+                .set_file_name("ds4_calibration.json")
+                .save_file()
+            {
+                match editor.save(&file) {
+                    Ok(()) => sh.message(tf("calibration.editor_saved", &[&file.display()])),
+                    Err(e) => sh.error(e.into()),
+                }
+            }
+        }
+    });
+}
+
+fn triggers_form(ui: &mut egui::Ui, editor: &mut CalibrationEditor) {
+    ui.add(egui::TextEdit::singleline(&mut editor.triggers_hex));
+    match hex::decode(editor.triggers_hex.trim()) {
+        Ok(bytes) => editor.triggers.buf = bytes,
+        Err(e) => {
+            ui.label(RichText::new(e.to_string()).color(Color32::RED));
+        }
+    }
+}
+
+pub fn calibration_editor(ui: &mut egui::Ui, editor: &mut CalibrationEditor, sh: StatusHandler) {
+    ui.heading(t("calibration.editor_heading"));
+    ui.label(RichText::new(t("calibration.editor_offline")).color(Color32::YELLOW));
+    load_save_buttons(ui, editor, sh.clone());
+    ui.separator();
+    ScrollArea::vertical().show(ui, |ui| {
+        egui::CollapsingHeader::new(t("calibration.sticks_center_heading"))
+            .default_open(true)
+            .show(ui, |ui| {
+                stick_center_calibration_form(ui, &mut editor.stick_center)
+            });
+        egui::CollapsingHeader::new(t("calibration.sticks_min_max_heading")).show(ui, |ui| {
+            stick_min_max_calibration_form(ui, &mut editor.stick_min_max)
+        });
+        egui::CollapsingHeader::new(t("calibration.editor_triggers"))
+            .show(ui, |ui| triggers_form(ui, editor));
+        egui::CollapsingHeader::new(t("calibration.motion_sensor_heading")).show(ui, |ui| {
+            motion_calibration_value_form(ui, &mut editor.motion, sh)
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calibration_file_round_trip() {
+        let mut editor = CalibrationEditor::default();
+        editor.stick_center.set_left_x(12);
+        editor.stick_min_max.set_right_max_y(-34);
+        editor.triggers.buf = vec![1, 2, 3];
+        let loaded = CalibrationEditor::from_json(&editor.to_json().unwrap()).unwrap();
+        assert_eq!(loaded.stick_center, editor.stick_center);
+        assert_eq!(loaded.stick_min_max, editor.stick_min_max);
+        assert_eq!(loaded.triggers, editor.triggers);
+        assert_eq!(loaded.triggers_hex, "010203");
+        assert_eq!(loaded.motion.calibration(), editor.motion.calibration());
+    }
+
+    #[test]
+    fn calibration_file_section_lengths_are_checked() {
+        let editor = CalibrationEditor::default();
+        let mut file: CalibrationFile = serde_json::from_str(&editor.to_json().unwrap()).unwrap();
+        file.stick_center.push_str("00");
+        let json = serde_json::to_string(&file).unwrap();
+        assert!(CalibrationEditor::from_json(&json).is_err());
+
+        file.stick_center.truncate(file.stick_center.len() - 2);
+        file.motion = "zz".to_string();
+        let json = serde_json::to_string(&file).unwrap();
+        assert!(CalibrationEditor::from_json(&json).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::application::i18n::t;
+use crate::application::{ConnectedDevice, DeviceConnected};
+use crate::dual_shock_4::{AnalogStickCalibrationType, CalibrationDeviceType, CalibrationFlag};
+use eframe::egui;
+use eframe::egui::{Color32, RichText};
+use log::warn;
+use std::time::{Duration, Instant};
+
+// One small feature report per second, the wizards read the calibration state more often
+pub const CALIBRATION_FLAG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlagBit {
+    Gyroscope,
+    Accelerometer,
+    SticksMinMax,
+    SticksCenter,
+    LeftTrigger,
+    RightTrigger,
+}
+
+impl FlagBit {
+    pub const ALL: [FlagBit; 6] = [
+        FlagBit::Gyroscope,
+        FlagBit::Accelerometer,
+        FlagBit::SticksMinMax,
+        FlagBit::SticksCenter,
+        FlagBit::LeftTrigger,
+        FlagBit::RightTrigger,
+    ];
+
+    fn is_ok(&self, flag: &CalibrationFlag) -> bool {
+        match self {
+            FlagBit::Gyroscope => flag.is_gyroscope_calib_ok(),
+            FlagBit::Accelerometer => flag.is_accelerometer_calib_ok(),
+            FlagBit::SticksMinMax => flag.is_stick_min_max_calib_ok(),
+            FlagBit::SticksCenter => flag.is_stick_center_calib_ok(),
+            FlagBit::LeftTrigger => flag.is_l2_calib_ok(),
+            FlagBit::RightTrigger => flag.is_r2_calib_ok(),
+        }
+    }
+
+    // The motion sensor bits aren't set by a wizard of the application
+    fn is_calibrated_by(&self, device_type: &CalibrationDeviceType) -> bool {
+        match device_type {
+            CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::Center) => {
+                *self == FlagBit::SticksCenter
+            }
+            CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::MinMax) => {
+                *self == FlagBit::SticksMinMax
+            }
+            CalibrationDeviceType::TriggerKey(_) => {
+                matches!(self, FlagBit::LeftTrigger | FlagBit::RightTrigger)
+            }
+            _ => false,
+        }
+    }
+
+    fn text(&self) -> &'static str {
+        match self {
+            FlagBit::Gyroscope => t("calibration.gyroscope_calibrated"),
+            FlagBit::Accelerometer => t("calibration.accelerometer_calibrated"),
+            FlagBit::SticksMinMax => t("calibration.sticks_min_max_calibrated"),
+            FlagBit::SticksCenter => t("calibration.sticks_center_calibrated"),
+            FlagBit::LeftTrigger => t("calibration.left_trigger_calibrated"),
+            FlagBit::RightTrigger => t("calibration.right_trigger_calibrated"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlagBitState {
+    Calibrated,
+    NotCalibrated,
+    // Not set yet, but a wizard that sets it is running
+    InProgress,
+}
+
+// Calibration flag polled while the Calibration panel is shown, bits are compared with the
+// first read of the connection
+#[derive(Debug, Default)]
+pub struct CalibrationFlagWatch {
+    last_poll: Option<Instant>,
+    first: Option<CalibrationFlag>,
+    current: Option<CalibrationFlag>,
+    error: Option<String>,
+}
+
+impl CalibrationFlagWatch {
+    // Reads the flag when the interval passed, true when it was read
+    pub fn update(
+        &mut self,
+        now: Instant,
+        read: impl FnOnce() -> Result<CalibrationFlag, String>,
+    ) -> bool {
+        if matches!(self.last_poll, Some(last) if now - last < CALIBRATION_FLAG_POLL_INTERVAL) {
+            return false;
+        }
+        self.last_poll = Some(now);
+        match read() {
+            Ok(flag) => {
+                self.first.get_or_insert_with(|| flag.clone());
+                self.current = Some(flag);
+                self.error = None;
+            }
+            Err(e) => {
+                warn!("Cannot poll the calibration flag: {}", e);
+                self.error = Some(e);
+            }
+        }
+        true
+    }
+
+    // The next update reads the flag, used after a wizard finished
+    pub fn refresh(&mut self) {
+        self.last_poll = None;
+    }
+
+    pub fn state(
+        &self,
+        bit: FlagBit,
+        active_calibration: Option<&CalibrationDeviceType>,
+    ) -> Option<FlagBitState> {
+        let current = self.current.as_ref()?;
+        Some(if bit.is_ok(current) {
+            FlagBitState::Calibrated
+        } else if active_calibration.is_some_and(|device_type| bit.is_calibrated_by(device_type)) {
+            FlagBitState::InProgress
+        } else {
+            FlagBitState::NotCalibrated
+        })
+    }
+
+    // Changed since the first read of the connection
+    pub fn is_changed(&self, bit: FlagBit) -> bool {
+        match (&self.first, &self.current) {
+            (Some(first), Some(current)) => bit.is_ok(first) != bit.is_ok(current),
+            _ => false,
+        }
+    }
+}
+
+pub fn calibration_flag_watch(ui: &mut egui::Ui, ctx: &egui::Context, state: &mut DeviceConnected) {
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    state.calibration_flag_watch.update(Instant::now(), || {
+        ds4.read_calibration_flag().map_err(|e| e.to_string())
+    });
+    ctx.request_repaint_after(CALIBRATION_FLAG_POLL_INTERVAL);
+    let watch = &state.calibration_flag_watch;
+    ui.horizontal_wrapped(|ui| {
+        ui.label(t("calibration.flag_watch"))
+            .on_hover_text(t("help.calibration_flag_watch"));
+        for bit in FlagBit::ALL {
+            let Some(bit_state) = watch.state(bit, state.active_calibration.as_ref()) else {
+                ui.label(RichText::new(format!("? {}", bit.text())).weak());
+                continue;
+            };
+            let (mark, color, hover) = match bit_state {
+                FlagBitState::Calibrated => ("✔", Color32::GREEN, t("calibration.flag_calibrated")),
+                FlagBitState::NotCalibrated => {
+                    ("✖", Color32::GRAY, t("calibration.flag_not_calibrated"))
+                }
+                FlagBitState::InProgress => {
+                    ("⟳", Color32::YELLOW, t("calibration.flag_in_progress"))
+                }
+            };
+            let text = RichText::new(format!("{} {}", mark, bit.text())).color(color);
+            if watch.is_changed(bit) {
+                ui.label(text.strong().underline()).on_hover_text(format!(
+                    "{}\n{}",
+                    hover,
+                    t("calibration.flag_changed")
+                ));
+            } else {
+                ui.label(text).on_hover_text(hover);
+            }
+        }
+        if let Some(error) = &watch.error {
+            ui.label(RichText::new(error).color(ui.visuals().warn_fg_color));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flag(stick_bits: u8) -> CalibrationFlag {
+        let mut flag = CalibrationFlag::default();
+        flag.buf[2] = stick_bits;
+        flag
+    }
+
+    #[test]
+    fn polls_are_throttled_until_refresh() {
+        let mut watch = CalibrationFlagWatch::default();
+        let start = Instant::now();
+        assert!(watch.update(start, || Ok(flag(0))));
+        assert!(!watch.update(start + Duration::from_millis(500), || Ok(flag(0x02))));
+        watch.refresh();
+        assert!(watch.update(start + Duration::from_millis(600), || Ok(flag(0x02))));
+        assert!(watch.is_changed(FlagBit::SticksCenter));
+        assert!(!watch.is_changed(FlagBit::SticksMinMax));
+        assert!(watch.update(start + Duration::from_secs(2), || Err("NAK".to_string())));
+        // The last read flag stays
+        assert!(watch.is_changed(FlagBit::SticksCenter));
+    }
+
+    #[test]
+    fn in_progress_only_for_the_running_wizard() {
+        let mut watch = CalibrationFlagWatch::default();
+        assert_eq!(watch.state(FlagBit::SticksCenter, None), None);
+        watch.update(Instant::now(), || Ok(flag(0x01)));
+        let center = CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::Center);
+        assert_eq!(
+            watch.state(FlagBit::SticksCenter, Some(&center)),
+            Some(FlagBitState::InProgress)
+        );
+        assert_eq!(
+            watch.state(FlagBit::SticksCenter, None),
+            Some(FlagBitState::NotCalibrated)
+        );
+        assert_eq!(
+            watch.state(FlagBit::SticksMinMax, Some(&center)),
+            Some(FlagBitState::Calibrated)
+        );
+        assert_eq!(
+            watch.state(FlagBit::LeftTrigger, Some(&center)),
+            Some(FlagBitState::NotCalibrated)
+        );
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::dual_shock_4::{CalibrationData, CalibrationDeviceType};
+use chrono::{DateTime, Local};
+use log::warn;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+pub const CALIBRATION_LOG_FILE_NAME: &str = "calibration-sessions.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionResult {
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct CalibrationSession {
+    serial_number: Option<String>,
+    calibration_type: String,
+    started_at: DateTime<Local>,
+    finished_at: Option<DateTime<Local>>,
+    result: Option<SessionResult>,
+    calibration_data: Option<String>,
+}
+
+// Appends a JSON line per finished calibration session, failures are only logged
+#[derive(Debug)]
+pub struct CalibrationLog {
+    path: PathBuf,
+    // Don't record the serial number of the device
+    pub scrub_serial_number: bool,
+    current: Option<CalibrationSession>,
+}
+
+impl CalibrationLog {
+    pub fn new(path: PathBuf, scrub_serial_number: bool) -> Self {
+        Self {
+            path,
+            scrub_serial_number,
+            current: None,
+        }
+    }
+
+    pub fn start(&mut self, serial_number: &str, calibration_type: &CalibrationDeviceType) {
+        self.current = Some(CalibrationSession {
+            serial_number: (!self.scrub_serial_number).then(|| serial_number.to_string()),
+            calibration_type: format!("{:?}", calibration_type),
+            started_at: Local::now(),
+            finished_at: None,
+            result: None,
+            calibration_data: None,
+        });
+    }
+
+    // Does nothing when no session was started
+    pub fn finish(&mut self, result: SessionResult, calibration_data: Option<&CalibrationData>) {
+        let Some(mut session) = self.current.take() else {
+            return;
+        };
+        session.finished_at = Some(Local::now());
+        session.result = Some(result);
+        session.calibration_data = calibration_data.map(|data| hex::encode(data.bytes()));
+        if let Err(e) = self.append(&session) {
+            warn!("Cannot write calibration session to {:?}: {}", self.path, e);
+        }
+    }
+
+    fn append(&self, session: &CalibrationSession) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(session)?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn appends_finished_sessions_only() {
+        let path = std::env::temp_dir().join(format!(
+            "ds4-gui-calibration-log-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let mut log = CalibrationLog::new(path.clone(), true);
+
+        log.finish(SessionResult::Failed, None);
+        log.start("1c:a0:b8:00:00:01", &CalibrationDeviceType::MotionSensor);
+        log.finish(
+            SessionResult::Completed,
+            Some(&CalibrationData::None(vec![0xab, 0xcd])),
+        );
+        log.scrub_serial_number = false;
+        log.start("1c:a0:b8:00:00:01", &CalibrationDeviceType::MotionSensor);
+        log.finish(SessionResult::Failed, None);
+
+        let lines = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(!lines[0].contains("1c:a0:b8"));
+        assert!(lines[0].contains(r#""result":"completed""#));
+        assert!(lines[0].contains(r#""calibration_data":"abcd""#));
+        assert!(lines[1].contains("1c:a0:b8"));
+        assert!(lines[1].contains(r#""result":"failed""#));
+    }
+}
@@ -0,0 +1,26 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use eframe::egui;
+use eframe::egui::{Align2, Color32, RichText};
+
+// Returns Some(true) when confirmed, Some(false) when cancelled and None while waiting
+pub fn confirmation_dialog(ctx: &egui::Context, title: &str, text: &str) -> Option<bool> {
+    let mut result = None;
+    egui::Window::new(title)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, (0f32, 0f32))
+        .show(ctx, |ui| {
+            ui.label(RichText::new(text).color(Color32::YELLOW));
+            ui.horizontal(|ui| {
+                if ui.button("Confirm").clicked() {
+                    result = Some(true);
+                }
+                if ui.button("Cancel").clicked() {
+                    result = Some(false);
+                }
+            });
+        });
+    result
+}
@@ -0,0 +1,172 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::application::{Error, Result};
+use hidapi::DeviceInfo as HidDeviceInfo;
+use std::ffi::CString;
+
+// Listed device, equal only with the same path. The HID path may change when the device
+// reconnects, so devices reporting a serial number are recognized by it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceId {
+    path: CString,
+    serial_number: Option<String>,
+}
+
+impl DeviceId {
+    pub fn new(path: CString, serial_number: Option<&str>) -> Self {
+        Self {
+            path,
+            serial_number: serial_number
+                .filter(|serial_number| !serial_number.is_empty())
+                .map(str::to_string),
+        }
+    }
+
+    pub fn path(&self) -> &CString {
+        &self.path
+    }
+
+    pub fn serial_number(&self) -> Option<&str> {
+        self.serial_number.as_deref()
+    }
+
+    // A device without a serial number is only recognized by its path
+    pub fn is_same_device(&self, other: &DeviceId) -> bool {
+        match (&self.serial_number, &other.serial_number) {
+            (Some(serial_number), Some(other_serial_number)) => {
+                serial_number == other_serial_number
+            }
+            _ => self.path == other.path,
+        }
+    }
+
+    // The same device listed under another path
+    pub fn is_moved(&self, other: &DeviceId) -> bool {
+        self.is_same_device(other) && self.path != other.path
+    }
+
+    // Entry of this device in the list, its own path first. A pad may be listed twice, e.g.
+    // over USB and Bluetooth, so it's only found under another path when its own is gone
+    pub fn find_listed<'a>(
+        &self,
+        ids: impl Iterator<Item = &'a DeviceId> + Clone,
+    ) -> Option<&'a DeviceId> {
+        ids.clone()
+            .find(|id| *id == self)
+            .or_else(|| ids.into_iter().find(|id| id.is_same_device(self)))
+    }
+}
+
+// Listed device with the serial number, its current path is opened. Clones may share one serial
+// number, so it selects a device only when a single one reports it
+pub fn find_by_serial_number<'a>(
+    ids: impl IntoIterator<Item = &'a DeviceId>,
+    serial_number: &str,
+) -> Result<&'a DeviceId> {
+    let found: Vec<&DeviceId> = ids
+        .into_iter()
+        .filter(|id| id.serial_number() == Some(serial_number))
+        .collect();
+    match found.as_slice() {
+        [id] => Ok(id),
+        [] => Err(Error::DeviceNotFound(serial_number.to_string())),
+        _ => Err(Error::AmbiguousSerialNumber(
+            serial_number.to_string(),
+            found.len(),
+        )),
+    }
+}
+
+impl From<&HidDeviceInfo> for DeviceId {
+    fn from(device: &HidDeviceInfo) -> Self {
+        Self::new(CString::from(device.path()), device.serial_number())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(path: &str, serial_number: Option<&str>) -> DeviceId {
+        DeviceId::new(CString::new(path).unwrap(), serial_number)
+    }
+
+    #[test]
+    fn serial_number_survives_path_change() {
+        let connected = id(
+            "\\\\?\\HID#VID_054C&PID_09CC#7&1",
+            Some("a4:53:85:01:02:03"),
+        );
+        let reconnected = id(
+            "\\\\?\\HID#VID_054C&PID_09CC#7&2",
+            Some("a4:53:85:01:02:03"),
+        );
+        assert_ne!(connected, reconnected);
+        assert!(connected.is_same_device(&reconnected));
+        assert!(connected.is_moved(&reconnected));
+        assert!(!connected.is_moved(&connected.clone()));
+
+        // Another pad of the same model
+        let other = id(
+            "\\\\?\\HID#VID_054C&PID_09CC#7&1",
+            Some("a4:53:85:0a:0b:0c"),
+        );
+        assert!(!connected.is_same_device(&other));
+        assert!(!connected.is_moved(&other));
+    }
+
+    #[test]
+    fn no_serial_number_compares_paths() {
+        let connected = id("/dev/hidraw3", None);
+        assert_eq!(connected, id("/dev/hidraw3", Some("")));
+        assert!(!connected.is_same_device(&id("/dev/hidraw4", None)));
+        // Only one side reports a serial number
+        let serial_number = Some("a4:53:85:01:02:03");
+        assert!(connected.is_same_device(&id("/dev/hidraw3", serial_number)));
+        assert!(!connected.is_same_device(&id("/dev/hidraw4", serial_number)));
+    }
+
+    #[test]
+    fn pad_listed_twice_isnt_moved() {
+        let serial_number = Some("a4:53:85:01:02:03");
+        let ids = [
+            id("/dev/hidraw3", serial_number),
+            id("/dev/hidraw5", serial_number),
+        ];
+        let connected = id("/dev/hidraw5", serial_number);
+        let listed = connected.find_listed(ids.iter()).unwrap();
+        assert_eq!(listed, &connected);
+        assert!(!connected.is_moved(listed));
+
+        // The old path is gone
+        let reconnected = id("/dev/hidraw7", serial_number);
+        let listed = reconnected.find_listed(ids.iter()).unwrap();
+        assert_eq!(listed.path().to_str(), Ok("/dev/hidraw3"));
+        assert!(reconnected.is_moved(listed));
+        assert!(id("/dev/hidraw7", None).find_listed(ids.iter()).is_none());
+    }
+
+    #[test]
+    fn serial_number_lookup() {
+        let ids = [
+            id("/dev/hidraw3", Some("a4:53:85:01:02:03")),
+            id("/dev/hidraw4", None),
+            id("/dev/hidraw5", Some("a4:53:85:0a:0b:0c")),
+            id("/dev/hidraw6", Some("a4:53:85:0a:0b:0c")),
+        ];
+        let found = find_by_serial_number(&ids, "a4:53:85:01:02:03").unwrap();
+        assert_eq!(found.path().to_str(), Ok("/dev/hidraw3"));
+        assert!(matches!(
+            find_by_serial_number(&ids, "a4:53:85:0a:0b:0c"),
+            Err(Error::AmbiguousSerialNumber(_, 2))
+        ));
+        assert!(matches!(
+            find_by_serial_number(&ids, "a4:53:85:ff:ff:ff"),
+            Err(Error::DeviceNotFound(_))
+        ));
+        // Paths aren't serial numbers
+        assert!(find_by_serial_number(&ids, "/dev/hidraw4").is_err());
+        assert!(find_by_serial_number(&ids, "").is_err());
+    }
+}
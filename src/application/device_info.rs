@@ -2,12 +2,43 @@
 // SPDX-License-Identifier: GPL-3.0
 
 use crate::application;
-use crate::application::{ConnectedDevice, UNDEFINED_STRING};
+use crate::application::diagnostics::diagnostics_bundle;
+use crate::application::i18n::t;
+use crate::application::{
+    ConnectedDevice, DeviceConnected, Panel, StatusHandler, UNDEFINED_STRING,
+};
+use crate::dual_shock_4::{
+    CalibrationFlag, ConnectionType, FirmwareInfo, ReportLayout, TrackRecord,
+};
 use eframe::egui;
+use eframe::egui::{Color32, RichText};
 use hidapi::BusType;
 use std::ffi::CString;
 
-pub fn device_info(ui: &mut egui::Ui, info: &DeviceInfo) {
+pub fn device_info(ui: &mut egui::Ui, state: &mut DeviceConnected, sh: StatusHandler) {
+    if ui
+        .button("Refresh All")
+        .on_hover_text(t("help.refresh_all"))
+        .clicked()
+    {
+        refresh_all(state, sh.clone());
+    }
+    if ui
+        .button("Read Track Record")
+        .on_hover_text(t("help.read_track_record"))
+        .clicked()
+    {
+        read_track_record(state, sh.clone());
+    }
+    ui.separator();
+    if let Panel::DeviceInfo(info) = &state.panel {
+        info_grid(ui, info, state.permanent);
+    }
+    ui.separator();
+    diagnostics_bundle(ui, state, sh);
+}
+
+fn info_grid(ui: &mut egui::Ui, info: &DeviceInfo, permanent: bool) {
     egui::Grid::new("Info").num_columns(2).show(ui, |ui| {
         ui.label("Path:");
         ui.label(format!("{:?}", info.path));
@@ -51,6 +82,51 @@ pub fn device_info(ui: &mut egui::Ui, info: &DeviceInfo) {
         ui.label("Bus Type:");
         ui.label(format!("{:?}", info.bus_type));
         ui.end_row();
+        ui.label("Connection Type:");
+        if info.connection_type_forced {
+            ui.label(
+                RichText::new(format!(
+                    "{} (forced, detected {})",
+                    info.connection_type, info.detected_connection_type
+                ))
+                .color(Color32::YELLOW),
+            );
+        } else {
+            ui.label(info.connection_type.to_string());
+        }
+        ui.end_row();
+        if let Some(firmware_info) = &info.firmware_info {
+            ui.label("Firmware Build:");
+            ui.label(firmware_info.build_date());
+            ui.end_row();
+            ui.label("Hardware Version:");
+            ui.label(format!("{:#06x}", firmware_info.hardware_version()));
+            ui.end_row();
+            ui.label("Firmware Version:");
+            ui.label(format!("{:#06x}", firmware_info.firmware_version()));
+            ui.end_row();
+        }
+        ui.label("Report Layout:");
+        ui.label(info.report_layout.to_string());
+        ui.end_row();
+        if let Some(calibration_flag) = &info.calibration_flag {
+            ui.label("Calibration Flag:");
+            ui.label(hex::encode(calibration_flag.buf));
+            ui.end_row();
+        }
+        if let Some(battery_percent) = info.battery_percent {
+            ui.label("Battery:");
+            ui.label(format!("{}%", battery_percent));
+            ui.end_row();
+        }
+        if let Some(track_record) = &info.track_record {
+            ui.label("Track Record:");
+            ui.label(hex::encode(track_record.buf));
+            ui.end_row();
+        }
+        ui.label("Permanent Memory:");
+        ui.label(permanent.to_string());
+        ui.end_row();
     });
 }
 
@@ -64,6 +140,15 @@ pub struct DeviceInfo {
     pub product_string: Option<String>,
     pub interface_number: i32,
     pub bus_type: BusType,
+    pub connection_type: ConnectionType,
+    pub detected_connection_type: ConnectionType,
+    pub connection_type_forced: bool,
+    pub firmware_info: Option<FirmwareInfo>,
+    pub report_layout: ReportLayout,
+    // Only known after a refresh
+    pub calibration_flag: Option<CalibrationFlag>,
+    pub battery_percent: Option<u8>,
+    pub track_record: Option<TrackRecord>,
 }
 
 impl DeviceInfo {
@@ -72,7 +157,7 @@ impl DeviceInfo {
     ) -> application::Result<Self> {
         Ok(match connected_device {
             ConnectedDevice::DualShock4(_device, ds4) => {
-                let info = ds4.hid_device().get_device_info()?;
+                let info = ds4.device_info();
                 Self {
                     path: CString::from(info.path()),
                     vendor_id: info.vendor_id(),
@@ -83,8 +168,76 @@ impl DeviceInfo {
                     product_string: info.product_string().map(|s| s.to_string()),
                     interface_number: info.interface_number(),
                     bus_type: info.bus_type(),
+                    connection_type: ds4.connection_type(),
+                    detected_connection_type: ds4.detected_connection_type(),
+                    connection_type_forced: ds4.is_connection_type_forced(),
+                    firmware_info: ds4.firmware_info(),
+                    report_layout: ds4.report_layout(),
+                    calibration_flag: None,
+                    battery_percent: None,
+                    track_record: None,
                 }
             }
         })
     }
 }
+
+// Every read is attempted, the cached values are replaced only when all of them are done
+fn refresh_all(state: &mut DeviceConnected, sh: StatusHandler) {
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    let mut failed = false;
+    let firmware_info = read(&sh, &mut failed, "firmware info", ds4.read_firmware_info());
+    let calibration_flag = read(
+        &sh,
+        &mut failed,
+        "calibration flag",
+        ds4.read_calibration_flag(),
+    );
+    let permanent = read(&sh, &mut failed, "permanent state", ds4.read_permanent());
+    let battery_percent = read(&sh, &mut failed, "battery level", ds4.read_last_data())
+        .flatten()
+        .map(|data| data.battery_capacity_percent());
+
+    if let Some(firmware_info) = firmware_info {
+        ds4.update_firmware_info(firmware_info);
+    }
+    if let Some(permanent) = permanent {
+        state.permanent = permanent;
+    }
+    let Some(mut info) = sh.handle_error(DeviceInfo::from_connected_device(&state.device)) else {
+        return;
+    };
+    if let Panel::DeviceInfo(previous) = &mut state.panel {
+        info.calibration_flag = calibration_flag.or(previous.calibration_flag.take());
+        info.battery_percent = battery_percent.or(previous.battery_percent);
+        info.track_record = previous.track_record.take();
+    }
+    state.panel = Panel::DeviceInfo(info);
+    if !failed {
+        sh.message("Device data refreshed");
+    }
+}
+
+fn read_track_record(state: &mut DeviceConnected, sh: StatusHandler) {
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    let track_record = sh.handle_error(ds4.read_track_record());
+    if let (Some(track_record), Panel::DeviceInfo(info)) = (track_record, &mut state.panel) {
+        info.track_record = Some(track_record);
+    }
+}
+
+fn read<T>(
+    sh: &StatusHandler,
+    failed: &mut bool,
+    name: &str,
+    result: crate::dual_shock_4::Result<T>,
+) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(e) => {
+            *failed = true;
+            sh.error(format!("Cannot read {}: {}", name, e).into());
+            None
+        }
+    }
+}
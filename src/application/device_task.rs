@@ -0,0 +1,77 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::application::i18n::t;
+use crate::dual_shock_4::{Cancel, DualShock4, Error, Result};
+use eframe::egui;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+
+const TASK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Device operation that takes longer than a frame, it runs on its own thread and the UI polls
+// the result. Its reports still go through the device thread in order with the others
+pub struct DeviceTask<T> {
+    result: Receiver<Result<T>>,
+    cancel: Cancel,
+}
+
+impl<T: Send + 'static> DeviceTask<T> {
+    pub fn spawn(
+        ds4: &Arc<DualShock4>,
+        name: &str,
+        task: impl FnOnce(&DualShock4) -> Result<T> + Send + 'static,
+    ) -> Result<Self> {
+        let cancel = ds4.cancel_token();
+        cancel.reset();
+        let ds4 = ds4.clone();
+        let (sender, result) = channel();
+        std::thread::Builder::new()
+            .name(format!("ds4-{}", name))
+            .spawn(move || {
+                let _busy = ds4.hold_busy();
+                // Nobody waits for the result when the panel was closed meanwhile
+                let _ = sender.send(task(&ds4));
+            })
+            .map_err(|e| format!("Cannot start {}: {}", name, e))?;
+        Ok(Self { result, cancel })
+    }
+
+    // None while the task is running
+    pub fn poll(&self) -> Option<Result<T>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(Error::Message(
+                "Device operation stopped unexpectedly".to_string(),
+            ))),
+        }
+    }
+}
+
+// Shows the running task and takes its result once it's finished
+pub fn poll_task<T: Send + 'static>(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    task: &mut Option<DeviceTask<T>>,
+    text: &str,
+) -> Option<Result<T>> {
+    let running = task.as_ref()?;
+    let result = running.poll();
+    if result.is_some() {
+        // A cancel clicked after the task finished would stop the next operation
+        running.cancel.reset();
+        *task = None;
+    } else {
+        ui.horizontal(|ui| {
+            ui.spinner();
+            ui.label(text);
+            if ui.button(t("common.cancel")).clicked() {
+                running.cancel.cancel();
+            }
+        });
+        ctx.request_repaint_after(TASK_POLL_INTERVAL);
+    }
+    result
+}
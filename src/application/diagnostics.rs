@@ -0,0 +1,301 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::application;
+use crate::application::device_info::DeviceInfo;
+use crate::application::{ConnectedDevice, DeviceConnected, Error, Panel, StatusHandler};
+use crate::dual_shock_4::ConnectionType;
+use chrono::{DateTime, Local};
+use eframe::egui;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{Seek, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const SERIAL_NUMBER_PLACEHOLDER: &str = "<serial number>";
+
+// Parts of the bundle, the serial number and the flash dump are opt-in for privacy
+#[derive(Debug, Clone)]
+pub struct BundleComponents {
+    pub report: bool,
+    pub log: bool,
+    pub protocol_trace: bool,
+    // Read from the device on export, may contain the paired host address
+    pub flash_dump: bool,
+    // Serial number, device path and Bluetooth addresses, scrubbed otherwise
+    pub serial_number: bool,
+}
+
+impl Default for BundleComponents {
+    fn default() -> Self {
+        Self {
+            report: true,
+            log: true,
+            protocol_trace: true,
+            flash_dump: false,
+            serial_number: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DiagnosticReport {
+    application_version: &'static str,
+    os: &'static str,
+    arch: &'static str,
+    created_at: DateTime<Local>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    serial_number: Option<String>,
+    vendor_id: u16,
+    product_id: u16,
+    release_number: u16,
+    connection_type: ConnectionType,
+    detected_connection_type: ConnectionType,
+    report_layout: String,
+    firmware_build_date: Option<String>,
+    hardware_version: Option<u16>,
+    firmware_version: Option<u16>,
+    // Only known after a refresh of the Device Info panel
+    calibration_flag: Option<String>,
+    battery_percent: Option<u8>,
+    permanent: bool,
+}
+
+impl DiagnosticReport {
+    fn new(info: &DeviceInfo, permanent: bool, serial_number: bool) -> Self {
+        let firmware_info = info.firmware_info.as_ref();
+        Self {
+            application_version: env!("CARGO_PKG_VERSION"),
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            created_at: Local::now(),
+            path: serial_number.then(|| info.path.to_string_lossy().into_owned()),
+            serial_number: info.serial_number.clone().filter(|_| serial_number),
+            vendor_id: info.vendor_id,
+            product_id: info.product_id,
+            release_number: info.release_number,
+            connection_type: info.connection_type,
+            detected_connection_type: info.detected_connection_type,
+            report_layout: format!("{:?}", info.report_layout),
+            firmware_build_date: firmware_info.map(|info| info.build_date()),
+            hardware_version: firmware_info.map(|info| info.hardware_version()),
+            firmware_version: firmware_info.map(|info| info.firmware_version()),
+            calibration_flag: info
+                .calibration_flag
+                .as_ref()
+                .map(|flag| hex::encode(flag.buf)),
+            battery_percent: info.battery_percent,
+            permanent,
+        }
+    }
+}
+
+// Serial number occurrences are replaced in the text components
+fn scrub_serial_number(text: &str, serial_number: Option<&str>) -> String {
+    match serial_number.filter(|serial_number| !serial_number.is_empty()) {
+        Some(serial_number) => text.replace(serial_number, SERIAL_NUMBER_PLACEHOLDER),
+        None => text.to_string(),
+    }
+}
+
+// The serial number offset in the flash isn't known, so the address bytes in either order and
+// its text are zeroed wherever they occur. The flash CRC doesn't match afterwards
+fn scrub_flash_serial_number(flash: &mut [u8], serial_number: Option<&str>) {
+    let Some(serial_number) = serial_number.filter(|serial_number| !serial_number.is_empty())
+    else {
+        return;
+    };
+    let mut patterns = vec![serial_number.as_bytes().to_vec()];
+    if let Ok(address) = hex::decode(serial_number.replace(':', "")) {
+        patterns.push(address.iter().rev().copied().collect());
+        patterns.push(address);
+    }
+    for pattern in patterns.iter().filter(|pattern| !pattern.is_empty()) {
+        let mut offset = 0;
+        while let Some(found) = flash[offset..]
+            .windows(pattern.len())
+            .position(|window| window == pattern)
+        {
+            let start = offset + found;
+            flash[start..start + pattern.len()].fill(0);
+            offset = start + pattern.len();
+        }
+    }
+}
+
+fn bundle_entries(
+    state: &DeviceConnected,
+    info: &DeviceInfo,
+    components: &BundleComponents,
+) -> application::Result<Vec<(&'static str, Vec<u8>)>> {
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    let scrubbed_serial_number = info
+        .serial_number
+        .as_deref()
+        .filter(|_| !components.serial_number);
+    let mut entries = Vec::new();
+    if components.report {
+        let report = DiagnosticReport::new(info, state.permanent, components.serial_number);
+        let json = serde_json::to_string_pretty(&report).map_err(|e| {
+            Error::Io(
+                "Cannot serialize the diagnostic report".to_string(),
+                e.into(),
+            )
+        })?;
+        entries.push(("report.json", json.into_bytes()));
+    }
+    if components.log {
+        let log = std::fs::read_to_string(&state.log_file)
+            .map_err(|e| Error::Io(format!("Cannot read the log file {:?}", state.log_file), e))?;
+        let log = scrub_serial_number(&log, scrubbed_serial_number);
+        entries.push(("ds4-gui.log", log.into_bytes()));
+    }
+    if components.protocol_trace {
+        let mut trace = ds4.protocol_trace().clone();
+        trace.scrub_sensitive = !components.serial_number;
+        let json = trace
+            .to_json()
+            .map_err(|e| Error::Io("Cannot serialize the protocol trace".to_string(), e.into()))?;
+        let json = scrub_serial_number(&json, scrubbed_serial_number);
+        entries.push(("protocol_trace.json", json.into_bytes()));
+    }
+    if components.flash_dump {
+        let flash_mirror = ds4.read_flash_mirror().map_err(|e| {
+            crate::dual_shock_4::Error::Message(format!("Cannot read the flash dump: {}", e))
+        })?;
+        let mut flash = flash_mirror.buf.to_vec();
+        scrub_flash_serial_number(&mut flash, scrubbed_serial_number);
+        entries.push(("ds4_ieep.hex", flash));
+    }
+    Ok(entries)
+}
+
+fn write_bundle(
+    writer: impl Write + Seek,
+    entries: &[(&str, Vec<u8>)],
+) -> zip::result::ZipResult<()> {
+    let mut zip = ZipWriter::new(writer);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (name, content) in entries {
+        zip.start_file(*name, options)?;
+        zip.write_all(content)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+fn export_bundle(
+    state: &DeviceConnected,
+    info: &DeviceInfo,
+    components: &BundleComponents,
+    file: &Path,
+) -> application::Result<()> {
+    let entries = bundle_entries(state, info, components)?;
+    let context = || format!("Cannot write the diagnostics bundle {:?}", file);
+    let file = OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .open(file)
+        .map_err(|e| Error::Io(context(), e))?;
+    write_bundle(file, &entries).map_err(|e| Error::Io(context(), e.into()))
+}
+
+pub fn diagnostics_bundle(ui: &mut egui::Ui, state: &mut DeviceConnected, sh: StatusHandler) {
+    egui::CollapsingHeader::new("Diagnostics Bundle").show(ui, |ui| {
+        let components = &mut state.diagnostics_bundle;
+        ui.checkbox(&mut components.report, "Diagnostic report");
+        ui.checkbox(&mut components.log, "Application log");
+        ui.checkbox(&mut components.protocol_trace, "Protocol trace");
+        ui.checkbox(&mut components.flash_dump, "Flash dump")
+            .on_hover_text("Read from the device on export, takes a few seconds");
+        ui.checkbox(
+            &mut components.serial_number,
+            "Serial number and Bluetooth addresses",
+        )
+        .on_hover_text("Scrubbed from the bundle when unchecked");
+        let any = components.report
+            || components.log
+            || components.protocol_trace
+            || components.flash_dump;
+        if ui
+            .add_enabled(any, egui::Button::new("Export Diagnostics Bundle"))
+            .clicked()
+        {
+            if let Some(file) = rfd::FileDialog::new()
+                .add_filter("zip", &["zip"])
+                .set_file_name("ds4_diagnostics.zip")
+                .save_file()
+            {
+                if let Panel::DeviceInfo(info) = &state.panel {
+                    let components = &state.diagnostics_bundle;
+                    if sh
+                        .handle_error(export_bundle(state, info, components, &file))
+                        .is_some()
+                    {
+                        sh.message(format!("Diagnostics bundle saved to {:?}", file));
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+    use zip::ZipArchive;
+
+    #[test]
+    fn bundle_round_trip() {
+        let entries = vec![
+            ("report.json", b"{}".to_vec()),
+            ("ds4_ieep.hex", vec![0xffu8; 0x800]),
+        ];
+        let mut buf = Cursor::new(Vec::new());
+        write_bundle(&mut buf, &entries).unwrap();
+
+        let mut archive = ZipArchive::new(buf).unwrap();
+        assert_eq!(archive.len(), entries.len());
+        for (name, content) in &entries {
+            let mut read = Vec::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_end(&mut read)
+                .unwrap();
+            assert_eq!(&read, content);
+        }
+    }
+
+    #[test]
+    fn serial_number_scrubbing() {
+        let log = "Connected 1c:a0:b8:00:00:01\nReport sent";
+        assert_eq!(
+            scrub_serial_number(log, Some("1c:a0:b8:00:00:01")),
+            "Connected <serial number>\nReport sent"
+        );
+        assert_eq!(scrub_serial_number(log, Some("")), log);
+        assert_eq!(scrub_serial_number(log, None), log);
+    }
+
+    #[test]
+    fn flash_serial_number_scrubbing() {
+        let mut flash = vec![0xffu8; 0x40];
+        flash[0x10..0x16].copy_from_slice(&[0x01, 0x00, 0x00, 0xb8, 0xa0, 0x1c]);
+        flash[0x20..0x26].copy_from_slice(&[0x1c, 0xa0, 0xb8, 0x00, 0x00, 0x01]);
+        let original = flash.clone();
+        scrub_flash_serial_number(&mut flash, None);
+        assert_eq!(flash, original);
+
+        scrub_flash_serial_number(&mut flash, Some("1c:a0:b8:00:00:01"));
+        assert_eq!(flash[0x10..0x16], [0; 6]);
+        assert_eq!(flash[0x20..0x26], [0; 6]);
+        assert_eq!(flash[..0x10], original[..0x10]);
+        assert_eq!(flash[0x30..], original[0x30..]);
+    }
+}
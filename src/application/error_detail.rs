@@ -0,0 +1,184 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::application::i18n::t;
+use crate::application::{Error, StatusHandler};
+use crate::dual_shock_4;
+use chrono::{DateTime, Local};
+use eframe::egui;
+use hidapi::HidError;
+use std::panic::Location;
+
+// Error with the place it was handled at, the status bar shows only its Display.
+// Both texts are made when the error is handled, so the detail can be sent between threads
+pub struct ErrorDetail {
+    pub message: String,
+    text: String,
+}
+
+impl ErrorDetail {
+    pub fn new(error: Box<dyn std::error::Error>, location: &'static Location<'static>) -> Self {
+        Self {
+            message: error.to_string(),
+            text: text(error.as_ref(), location, Local::now()),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.text.clone()
+    }
+}
+
+// Plain text for bug reports, the causes follow the error from the outermost one
+fn text(
+    error: &(dyn std::error::Error + 'static),
+    location: &'static Location<'static>,
+    time: DateTime<Local>,
+) -> String {
+    let mut lines = vec![
+        format!("Error: {}", error),
+        format!("Failed at: {}", location),
+        format!("Time: {}", time.format("%Y-%m-%d %H:%M:%S%.3f")),
+    ];
+    lines.extend(causes(error));
+    let mut source = error.source();
+    while let Some(error) = source {
+        lines.push(format!("Caused by: {}", error));
+        source = error.source();
+    }
+    lines.push(format!("Debug: {:?}", error));
+    lines.join("\n")
+}
+
+fn causes(error: &(dyn std::error::Error + 'static)) -> Vec<String> {
+    if let Some(error) = error.downcast_ref::<Error>() {
+        application_error_causes(error)
+    } else if let Some(error) = error.downcast_ref::<dual_shock_4::Error>() {
+        device_error_causes(error)
+    } else if let Some(error) = error.downcast_ref::<HidError>() {
+        hid_error_causes(error)
+    } else if let Some(error) = error.downcast_ref::<std::io::Error>() {
+        io_error_causes(error)
+    } else {
+        Vec::new()
+    }
+}
+
+fn application_error_causes(error: &Error) -> Vec<String> {
+    match error {
+        Error::DualShock4(error) => device_error_causes(error),
+        Error::HidError(error) => hid_error_causes(error),
+        Error::Io(context, error) => {
+            let mut lines = vec![format!("Operation: {}", context)];
+            lines.extend(io_error_causes(error));
+            lines
+        }
+        Error::Eframe(error) => vec![format!("Kind: window or graphics backend: {}", error)],
+        Error::Logger(_) => vec!["Kind: logging is already initialized".to_string()],
+        Error::Startup(_) => vec!["Kind: startup".to_string()],
+        Error::DeviceNotFound(_) => {
+            vec!["Kind: no listed device has the serial number".to_string()]
+        }
+        Error::AmbiguousSerialNumber(_, _) => {
+            vec!["Kind: several listed devices have the serial number".to_string()]
+        }
+    }
+}
+
+fn device_error_causes(error: &dual_shock_4::Error) -> Vec<String> {
+    match error {
+        dual_shock_4::Error::HidError(error) => hid_error_causes(error),
+        dual_shock_4::Error::OutOfRange(value, range) => vec![format!(
+            "Kind: value {} is outside of {}..{}",
+            value, range.start, range.end
+        )],
+        dual_shock_4::Error::InvalidReport => vec![
+            "Kind: the device answered with a report of another id or a wrong checksum".to_string(),
+        ],
+        dual_shock_4::Error::UnsupportedDevice(_, _) => {
+            vec!["Kind: the device isn't a DualShock 4".to_string()]
+        }
+        dual_shock_4::Error::Message(_) => vec!["Kind: device operation".to_string()],
+        dual_shock_4::Error::Cancelled => vec!["Kind: cancelled by the user".to_string()],
+        dual_shock_4::Error::ReportTooLarge(_, _) => {
+            vec![
+                "Kind: the report wasn't sent, it's larger than the HID interface accepts"
+                    .to_string(),
+            ]
+        }
+        dual_shock_4::Error::WriteNotSupported(_) => vec![
+            "Kind: an earlier write was ignored by the firmware, writes are off until reconnect"
+                .to_string(),
+        ],
+        dual_shock_4::Error::SafeMode => vec![
+            "Kind: the report wasn't sent, writes are off in the safe mode until restart"
+                .to_string(),
+        ],
+    }
+}
+
+fn hid_error_causes(error: &HidError) -> Vec<String> {
+    match error {
+        HidError::HidApiError { message } => vec![
+            "Kind: HID".to_string(),
+            format!("OS error: {}", message.trim()),
+        ],
+        HidError::IoError { error } => {
+            let mut lines = vec!["Kind: HID".to_string()];
+            lines.extend(io_error_causes(error));
+            lines
+        }
+        error => vec![format!("Kind: HID: {}", error)],
+    }
+}
+
+fn io_error_causes(error: &std::io::Error) -> Vec<String> {
+    let mut lines = vec![format!("I/O error kind: {}", error.kind())];
+    if let Some(code) = error.raw_os_error() {
+        lines.push(format!("OS error code: {}", code));
+    }
+    lines
+}
+
+// Text of the last error, kept after the status bar moved on
+pub fn error_detail_window(
+    ctx: &egui::Context,
+    detail: &Option<String>,
+    open: &mut bool,
+    sh: StatusHandler,
+) {
+    let Some(text) = detail else {
+        *open = false;
+        return;
+    };
+    egui::Window::new(t("error_detail"))
+        .open(open)
+        .collapsible(false)
+        .default_width(480f32)
+        .show(ctx, |ui| {
+            if ui.button(t("error_detail.copy")).clicked() {
+                ui.ctx().copy_text(text.clone());
+                sh.message(t("error_detail.copied"));
+            }
+            ui.separator();
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.add(egui::Label::new(egui::RichText::new(text.as_str()).monospace()).wrap());
+            });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hid_error_text_has_os_error() {
+        let error = Error::from(dual_shock_4::Error::HidError(HidError::HidApiError {
+            message: "Access is denied.\r\n".to_string(),
+        }));
+        let detail = ErrorDetail::new(Box::new(error), Location::caller());
+        let text = detail.text();
+        assert!(text.contains("OS error: Access is denied."));
+        assert!(text.contains(&format!("Failed at: {}", file!())));
+    }
+}
@@ -1,21 +1,131 @@
 // Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
 // SPDX-License-Identifier: GPL-3.0
 
-use crate::application::{ConnectedDevice, DeviceConnected, Panel, StatusHandler};
-use crate::dual_shock_4::{FlashMirror, TestCommand};
+use crate::application::device_task::{poll_task, DeviceTask};
+use crate::application::i18n::t;
+use crate::application::{ConnectedDevice, DeviceConnected, Panel, Progress, StatusHandler};
+use crate::dual_shock_4::{
+    DualShock4, FlashMirror, FlashMirrorRead, StickCenterCalibration, TestCommand,
+    FLASH_MIRROR_SIZE,
+};
+use chrono::{DateTime, Local};
 use eframe::egui;
-use eframe::egui::{Color32, RichText};
+use eframe::egui::text::LayoutJob;
+use eframe::egui::{Color32, RichText, TextFormat};
 use std::fs::OpenOptions;
 use std::io::{Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Part of the frame spent reading, so the UI stays responsive during the flash read
+const FLASH_READ_FRAME_BUDGET: Duration = Duration::from_millis(20);
+const FLASH_READ_OPERATION: &str = "Flash read";
+const HEX_VIEW_BYTES_PER_ROW: usize = 16;
+const HEX_VIEW_HEIGHT: f32 = 240f32;
+const HEX_VIEW_REGION_COLOR: Color32 = Color32::LIGHT_BLUE;
 
 #[derive(Default)]
 pub struct Flash {
     flash_mirror: Option<FlashMirror>,
+    // Read time of a mirror restored from the cache, None for fresh reads and files
+    cached_read_at: Option<DateTime<Local>>,
+    // Read from the device in progress, spread over frames
+    reading: Option<FlashMirrorRead>,
+}
+
+impl Flash {
+    pub fn from_cache(cache: Option<&CachedFlashMirror>) -> Self {
+        match cache {
+            Some(cache) => Self {
+                flash_mirror: Some(cache.flash_mirror.clone()),
+                cached_read_at: Some(cache.read_at),
+                ..Default::default()
+            },
+            None => Self::default(),
+        }
+    }
+}
+
+// Last mirror read from a device, survives panel switches and reconnects of the same device
+pub struct CachedFlashMirror {
+    device_key: String,
+    flash_mirror: FlashMirror,
+    read_at: DateTime<Local>,
+}
+
+impl CachedFlashMirror {
+    pub fn is_for(&self, device_key: &str) -> bool {
+        self.device_key == device_key
+    }
+}
+
+fn read_flash_from_device(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    state: &mut DeviceConnected,
+    sh: StatusHandler,
+) {
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    let Panel::Flash(flash) = &mut state.panel else {
+        return;
+    };
+    let Some(reading) = &mut flash.reading else {
+        return;
+    };
+    let frame_started = Instant::now();
+    while !reading.is_finished() && frame_started.elapsed() < FLASH_READ_FRAME_BUDGET {
+        // Cancellation is reported as an error and discards the partly read mirror
+        if sh.handle_error(reading.read_next_word(ds4)).is_none() {
+            flash.reading = None;
+            sh.progress_finished(FLASH_READ_OPERATION);
+            return;
+        }
+    }
+    if let Some(flash_mirror_from_device) = reading.flash_mirror() {
+        state.flash_mirror_cache = Some(CachedFlashMirror {
+            device_key: state.device_key.clone(),
+            flash_mirror: flash_mirror_from_device.clone(),
+            read_at: Local::now(),
+        });
+        flash.flash_mirror = Some(flash_mirror_from_device);
+        flash.cached_read_at = None;
+        flash.reading = None;
+        sh.progress_finished(FLASH_READ_OPERATION);
+        return;
+    }
+    let label = match reading.eta() {
+        Some(eta) => format!(
+            "{}: {} / {} bytes, about {:.0}s left",
+            FLASH_READ_OPERATION,
+            reading.bytes_read(),
+            FLASH_MIRROR_SIZE,
+            eta.as_secs_f32().ceil()
+        ),
+        None => format!(
+            "{}: {} / {} bytes",
+            FLASH_READ_OPERATION,
+            reading.bytes_read(),
+            FLASH_MIRROR_SIZE
+        ),
+    };
+    sh.progress(Progress {
+        operation: FLASH_READ_OPERATION,
+        current: reading.bytes_read(),
+        total: FLASH_MIRROR_SIZE,
+        label,
+    });
+    ui.horizontal(|ui| {
+        ui.label("Reading flash from the device...");
+        if ui.button("Cancel").clicked() {
+            ds4.cancel_token().cancel();
+        }
+    });
+    ctx.request_repaint();
 }
 
 pub fn flash(
     ui: &mut egui::Ui,
-    _ctx: &egui::Context,
+    ctx: &egui::Context,
     state: &mut DeviceConnected,
     sh: StatusHandler,
 ) {
@@ -24,63 +134,266 @@ pub fn flash(
             &mut state.permanent,
             "Save changes to permanent memory (WARNING: you can brick you device)",
         )
+        .on_hover_text(t("help.permanent"))
         .changed()
     {
         let ConnectedDevice::DualShock4(_, ds4) = &state.device;
-        let _ = sh.handle_error(ds4.set_test_command(TestCommand::SetPermanent(state.permanent)));
-        state.permanent = sh.handle_error(ds4.read_permanent()).unwrap_or(false);
+        // The checkbox shows the device state again when the command isn't sent
+        if sh
+            .handle_error(ds4.set_test_command(TestCommand::SetPermanent(state.permanent)))
+            .is_none()
+        {
+            state.permanent = !state.permanent;
+        }
+        state.refresh_permanent(sh.clone());
     }
-    if ui.button("Read Flash From Device").clicked() {
-        let ConnectedDevice::DualShock4(_, ds4) = &state.device;
-        if let Some(flash_mirror_from_device) = sh.handle_error(ds4.read_flash_mirror()) {
-            if let Panel::Flash(Flash { flash_mirror }) = &mut state.panel {
-                *flash_mirror = Some(flash_mirror_from_device);
-            }
+    let reading = matches!(
+        &state.panel,
+        Panel::Flash(Flash {
+            reading: Some(_),
+            ..
+        })
+    );
+    let mut read_from_device = ui
+        .add_enabled(!reading, egui::Button::new("Read Flash From Device"))
+        .on_hover_text(t("help.read_flash"))
+        .clicked();
+    if let Panel::Flash(Flash {
+        cached_read_at: Some(read_at),
+        ..
+    }) = &state.panel
+    {
+        ui.horizontal(|ui| {
+            ui.label(
+                RichText::new(format!("Read at {}, may be stale", read_at.format("%H:%M")))
+                    .color(Color32::YELLOW),
+            );
+            read_from_device |= ui
+                .add_enabled(!reading, egui::Button::new("Re-read"))
+                .clicked();
+        });
+    }
+    if read_from_device {
+        if let Panel::Flash(flash) = &mut state.panel {
+            let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+            ds4.cancel_token().reset();
+            flash.reading = Some(FlashMirrorRead::default());
+        }
+    }
+    read_flash_from_device(ui, ctx, state, sh.clone());
+    if let Panel::Flash(flash) = &mut state.panel {
+        load_flash_file(ui, flash, sh.clone());
+        if let Some(flash_mirror) = &flash.flash_mirror {
+            flash_mirror_crc(ui, flash_mirror);
+            save_flash_file(ui, flash_mirror, sh.clone());
         }
+        restore_stick_center(ui, flash);
     }
-    if ui.button("Load Flash From File").clicked() {
+}
+
+// Flash dump inspection without a controller, the device actions aren't shown at all
+pub fn offline_flash(ui: &mut egui::Ui, flash: &mut Flash, sh: StatusHandler) {
+    ui.heading("Flash File Inspector");
+    ui.label(
+        RichText::new("No controller is connected, nothing can be read from or written to it")
+            .color(Color32::YELLOW),
+    );
+    load_flash_file(ui, flash, sh.clone());
+    if let Some(flash_mirror) = &flash.flash_mirror {
+        flash_mirror_crc(ui, flash_mirror);
+        save_flash_file(ui, flash_mirror, sh);
+        ui.separator();
+        stick_center_calibration_view(ui, &flash_mirror.stick_center_calibration());
+        hex_view(ui, flash_mirror);
+    }
+}
+
+fn load_flash_file(ui: &mut egui::Ui, flash: &mut Flash, sh: StatusHandler) {
+    if ui
+        .button("Load Flash From File")
+        .on_hover_text(t("help.load_flash_file"))
+        .clicked()
+    {
         if let Some(file) = rfd::FileDialog::new()
             .add_filter("hex", &["hex"])
             .pick_file()
         {
-            if let Panel::Flash(Flash { flash_mirror }) = &mut state.panel {
-                let file_options = OpenOptions::new().read(true).open(file);
-                *flash_mirror = None;
-                if let Some(mut file) = sh.handle_error(file_options) {
-                    let mut flash_mirror_from_file = FlashMirror::default();
-                    sh.handle_error(file.read_exact(&mut flash_mirror_from_file.buf));
-                    *flash_mirror = Some(flash_mirror_from_file);
+            let file_options = OpenOptions::new().read(true).open(file);
+            flash.flash_mirror = None;
+            flash.cached_read_at = None;
+            if let Some(mut file) = sh.handle_error(file_options) {
+                let mut bytes = Vec::new();
+                if sh.handle_error(file.read_to_end(&mut bytes)).is_some() {
+                    // Dumps of --dump-flash may be Intel HEX, the rest are raw
+                    flash.flash_mirror = if bytes.first() == Some(&b':') {
+                        sh.handle_error(FlashMirror::from_intel_hex(&String::from_utf8_lossy(
+                            &bytes,
+                        )))
+                    } else {
+                        let mut flash_mirror_from_file = FlashMirror::default();
+                        // A short dump would leave the mirror partly zeroed
+                        sh.handle_error(
+                            bytes.as_slice().read_exact(&mut flash_mirror_from_file.buf),
+                        )
+                        .map(|_| flash_mirror_from_file)
+                    };
                 }
             }
         }
     }
-    if let Panel::Flash(Flash {
-        flash_mirror: Some(flash_mirror),
-    }) = &state.panel
+}
+
+fn flash_mirror_crc(ui: &mut egui::Ui, flash_mirror: &FlashMirror) {
+    ui.horizontal(|ui| {
+        ui.label("Flash Mirror CRC: ");
+        if flash_mirror.check_crc() {
+            ui.label(RichText::new("Correct").color(Color32::GREEN));
+        } else {
+            ui.label(RichText::new("Invalid").color(Color32::RED));
+        }
+    });
+}
+
+fn save_flash_file(ui: &mut egui::Ui, flash_mirror: &FlashMirror, sh: StatusHandler) {
+    if ui
+        .button("Save Flash Dump to File")
+        .on_hover_text(t("help.save_flash_file"))
+        .clicked()
     {
-        ui.horizontal(|ui| {
-            ui.label("Flash Mirror CRC: ");
-            if flash_mirror.check_crc() {
-                ui.label(RichText::new("Correct").color(Color32::GREEN));
-            } else {
-                ui.label(RichText::new("Invalid").color(Color32::RED));
+        if let Some(file) = rfd::FileDialog::new()
+            .set_file_name("ds4_ieep.hex")
+            .save_file()
+        {
+            let file_options = OpenOptions::new().create_new(true).write(true).open(file);
+            if let Some(mut file) = sh.handle_error(file_options) {
+                sh.handle_error(file.write_all(&flash_mirror.buf));
             }
-        });
+        }
     }
-    if let Panel::Flash(Flash {
-        flash_mirror: Some(flash_mirror),
-    }) = &state.panel
+}
+
+fn stick_center_calibration_view(ui: &mut egui::Ui, calibration: &StickCenterCalibration) {
+    ui.label("Stick Center Calibration:");
+    egui::Grid::new("Flash stick center")
+        .num_columns(2)
+        .show(ui, |ui| {
+            for (name, value) in [
+                ("Left X", calibration.left_x()),
+                ("Left Y", calibration.left_y()),
+                ("Right X", calibration.right_x()),
+                ("Right Y", calibration.right_y()),
+            ] {
+                ui.label(name);
+                ui.label(value.to_string());
+                ui.end_row();
+            }
+        });
+}
+
+// Bytes of the known regions are highlighted and the region names follow the row
+fn hex_row(ui: &egui::Ui, flash_mirror: &FlashMirror, offset: usize) -> LayoutJob {
+    let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+    let format = |color| TextFormat::simple(font_id.clone(), color);
+    let text_color = ui.visuals().text_color();
+    let mut job = LayoutJob::default();
+    let mut names = Vec::new();
+    job.append(&format!("{:04x}:", offset), 0.0, format(text_color));
+    for (index, byte) in flash_mirror.buf[offset..offset + HEX_VIEW_BYTES_PER_ROW]
+        .iter()
+        .enumerate()
     {
-        if ui.button("Save Flash Dump to File").clicked() {
-            if let Some(file) = rfd::FileDialog::new()
-                .set_file_name("ds4_ieep.hex")
-                .save_file()
-            {
-                let file_options = OpenOptions::new().create_new(true).write(true).open(file);
-                if let Some(mut file) = sh.handle_error(file_options) {
-                    sh.handle_error(file.write_all(&flash_mirror.buf));
-                }
+        let region = FlashMirror::region_at(offset + index);
+        if let Some(region) = region {
+            if !names.contains(&region.name) {
+                names.push(region.name);
             }
         }
+        let color = region.map_or(text_color, |_| HEX_VIEW_REGION_COLOR);
+        job.append(&format!(" {:02x}", byte), 0.0, format(color));
+    }
+    if !names.is_empty() {
+        job.append(
+            &format!("  {}", names.join(", ")),
+            0.0,
+            format(HEX_VIEW_REGION_COLOR),
+        );
+    }
+    job
+}
+
+fn hex_view(ui: &mut egui::Ui, flash_mirror: &FlashMirror) {
+    egui::CollapsingHeader::new("Hex View").show(ui, |ui| {
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let rows = FLASH_MIRROR_SIZE / HEX_VIEW_BYTES_PER_ROW;
+        egui::ScrollArea::vertical()
+            .max_height(HEX_VIEW_HEIGHT)
+            .show_rows(ui, row_height, rows, |ui, rows| {
+                for row in rows {
+                    ui.label(hex_row(ui, flash_mirror, row * HEX_VIEW_BYTES_PER_ROW));
+                }
+            });
+        ui.separator();
+        for region in FlashMirror::regions() {
+            ui.label(
+                RichText::new(format!(
+                    "{:04x}..{:04x}: {}",
+                    region.range.start, region.range.end, region.name
+                ))
+                .monospace()
+                .color(HEX_VIEW_REGION_COLOR),
+            );
+        }
+    });
+}
+
+pub fn spawn_stick_center_calibration(
+    ds4: &Arc<DualShock4>,
+    sh: &StatusHandler,
+) -> Option<DeviceTask<StickCenterCalibration>> {
+    sh.handle_error(DeviceTask::spawn(
+        ds4,
+        "stick-center",
+        DualShock4::calibrate_stick_center,
+    ))
+}
+
+// Stick center measured by the firmware, read back from the flash when it's stored there
+pub fn poll_stick_center_calibration(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    task: &mut Option<DeviceTask<StickCenterCalibration>>,
+    sh: &StatusHandler,
+) -> Option<StickCenterCalibration> {
+    let result = poll_task(ui, ctx, task, "Calibrating stick center")?;
+    let calibration = sh.handle_error(result)?;
+    sh.message("Stick center calibrated");
+    Some(calibration)
+}
+
+fn stick_center_text(calibration: &StickCenterCalibration) -> String {
+    format!(
+        "left {}, {}, right {}, {}",
+        calibration.left_x(),
+        calibration.left_y(),
+        calibration.right_x(),
+        calibration.right_y()
+    )
+}
+
+// No known command writes a given stick center: the calibration commands only let the firmware
+// measure the resting sticks and the flash has no documented write. The restore stays disabled,
+// the factory values of the mirror are shown for a manual check
+fn restore_stick_center(ui: &mut egui::Ui, flash: &Flash) {
+    if let Some(flash_mirror) = &flash.flash_mirror {
+        ui.separator();
+        ui.label(format!(
+            "Factory stick center in the flash mirror: {}",
+            stick_center_text(&flash_mirror.stick_center_calibration())
+        ));
+        ui.add_enabled(
+            false,
+            egui::Button::new("Restore Factory Stick Center From Flash Mirror"),
+        )
+        .on_disabled_hover_text(t("help.restore_stick_center_unsupported"));
     }
 }
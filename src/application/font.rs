@@ -2,7 +2,9 @@
 // SPDX-License-Identifier: GPL-3.0
 
 use eframe::egui;
-use eframe::egui::{FontFamily, RichText, WidgetText};
+use eframe::egui::{FontFamily, FontId, RichText, WidgetText};
+use log::warn;
+use std::sync::OnceLock;
 
 pub const GAMEPAD_FONT_SYMBOL: &str = "\u{243C}";
 pub const GAMEPAD_FONT_TRIANGLE: &str = "\u{21E1}";
@@ -25,8 +27,62 @@ pub const GAMEPAD_FONT_BOTH_ANALOG_CLOCKWISE: &str = "\u{21AD}";
 
 pub const GAMEPAD_FONT_FAMILY: &str = "GamepadFont";
 
+// Every glyph of the gamepad font with the text shown in the default font when it's missing
+const GAMEPAD_GLYPHS: [(&str, &str); 18] = [
+    (GAMEPAD_FONT_SYMBOL, "DS4"),
+    (GAMEPAD_FONT_TRIANGLE, "△"),
+    (GAMEPAD_FONT_CIRCLE, "○"),
+    (GAMEPAD_FONT_CROSS, "✕"),
+    (GAMEPAD_FONT_SQUARE, "□"),
+    (GAMEPAD_FONT_LEFT_ANALOG, "L3"),
+    (GAMEPAD_FONT_RIGHT_ANALOG, "R3"),
+    (GAMEPAD_FONT_L1, "L1"),
+    (GAMEPAD_FONT_R1, "R1"),
+    (GAMEPAD_FONT_L2, "L2"),
+    (GAMEPAD_FONT_R2, "R2"),
+    (GAMEPAD_FONT_OPTIONS, "Options"),
+    (GAMEPAD_FONT_SHARE, "Share"),
+    (GAMEPAD_FONT_PS, "PS"),
+    (GAMEPAD_FONT_T_PAD, "Touchpad"),
+    (GAMEPAD_FONT_RIGHT_ANALOG_CLOCKWISE, "R3 ↻"),
+    (GAMEPAD_FONT_LEFT_ANALOG_CLOCKWISE, "L3 ↻"),
+    (GAMEPAD_FONT_BOTH_ANALOG_CLOCKWISE, "L3 R3 ↻"),
+];
+
+// Glyphs and their fallbacks, known after the first frame started
+static MISSING_GAMEPAD_GLYPHS: OnceLock<Vec<(&str, &str)>> = OnceLock::new();
+
+fn missing_glyphs(has_glyph: impl Fn(&str) -> bool) -> Vec<(&'static str, &'static str)> {
+    GAMEPAD_GLYPHS
+        .into_iter()
+        .filter(|(glyph, _)| !has_glyph(glyph))
+        .collect()
+}
+
+// Fonts are loaded by the first frame, so this is called from every update and checks once
+pub fn check_gamepad_glyphs(ctx: &egui::Context) {
+    MISSING_GAMEPAD_GLYPHS.get_or_init(|| {
+        let font_id = FontId::new(14f32, FontFamily::Name(GAMEPAD_FONT_FAMILY.into()));
+        let missing = missing_glyphs(|glyph| ctx.fonts(|fonts| fonts.has_glyphs(&font_id, glyph)));
+        for (glyph, fallback) in &missing {
+            warn!(
+                "Gamepad font has no glyph {}, shown as {:?}",
+                glyph.escape_unicode(),
+                fallback
+            );
+        }
+        missing
+    });
+}
+
 pub fn with_gamepad_font(text: &str) -> RichText {
-    RichText::from(text).family(FontFamily::Name(GAMEPAD_FONT_FAMILY.into()))
+    let fallback = MISSING_GAMEPAD_GLYPHS
+        .get()
+        .and_then(|missing| missing.iter().find(|(glyph, _)| *glyph == text));
+    match fallback {
+        Some((_, fallback)) => RichText::from(*fallback),
+        None => RichText::from(text).family(FontFamily::Name(GAMEPAD_FONT_FAMILY.into())),
+    }
 }
 
 pub fn button_cross(text: impl Into<WidgetText>) -> egui::widgets::Button<'static> {
@@ -36,3 +92,15 @@ pub fn button_cross(text: impl Into<WidgetText>) -> egui::widgets::Button<'stati
 pub fn button_triangle(text: impl Into<WidgetText>) -> egui::widgets::Button<'static> {
     egui::widgets::Button::new(text).shortcut_text(with_gamepad_font(GAMEPAD_FONT_TRIANGLE))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback_is_per_glyph() {
+        let missing = missing_glyphs(|glyph| glyph != GAMEPAD_FONT_L1 && glyph != GAMEPAD_FONT_PS);
+        assert_eq!(missing, [(GAMEPAD_FONT_L1, "L1"), (GAMEPAD_FONT_PS, "PS")]);
+        assert!(missing_glyphs(|_| true).is_empty());
+    }
+}
@@ -0,0 +1,1063 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::application::i18n::t;
+use crate::application::{ConnectedDevice, DeviceConnected, Panel, StatusHandler};
+use crate::dual_shock_4::stick_jitter::JitterAxis;
+use crate::dual_shock_4::{
+    CalibrationFlag, Data, DualShock4, MotionCalibrationValues, StickPosition,
+};
+use eframe::egui;
+use eframe::egui::{Color32, RichText};
+use egui_plot::{Line, Plot, PlotPoints};
+use std::time::{Duration, Instant};
+
+const STICK_TEST_DURATION: Duration = Duration::from_secs(2);
+const STICK_DRIFT_WARN: f64 = 0.05;
+const STICK_DRIFT_FAIL: f64 = 0.15;
+const STICK_NOISE_WARN: f64 = 0.02;
+const STICK_NOISE_FAIL: f64 = 0.05;
+const BATTERY_WARN_PERCENT: u8 = 20;
+// Cross-talk of a barely moved axis isn't meaningful
+const CROSS_TALK_MIN_MOVEMENT: f64 = 0.5;
+const DRIFT_CHECK_DURATION: Duration = Duration::from_secs(1);
+// Well above the failing noise floor, so only a touched stick aborts the check
+const DRIFT_CHECK_MOVEMENT: f64 = 0.1;
+const MOTION_TEST_DURATION: Duration = Duration::from_secs(3);
+const MOTION_TEST_MIN_SAMPLES: u32 = 100;
+// A still controller changes less than this between reports, far above the sensor noise
+const MOTION_STILL_GYROSCOPE_CHANGE_DPS: f64 = 3.0;
+const MOTION_STILL_ACCELEROMETER_CHANGE_G: f64 = 0.03;
+// Steady rotation doesn't change between reports, but no zero-rate offset is that large
+const MOTION_STILL_MAX_DPS: f64 = 20.0;
+const GYROSCOPE_ZERO_RATE_WARN_DPS: f64 = 1.0;
+const GYROSCOPE_ZERO_RATE_FAIL_DPS: f64 = 3.0;
+const ACCELEROMETER_OFFSET_WARN_G: f64 = 0.05;
+const ACCELEROMETER_OFFSET_FAIL_G: f64 = 0.1;
+// Gravity of a controller lying flat with the buttons up, read on +Y. SDL passes the DualShock 4
+// axes through unchanged and documents +1g on Y for a device lying flat
+const FLAT_ACCELEROMETER_G: [f64; 3] = [0.0, 1.0, 0.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum HealthStatus {
+    Pass,
+    NeedsTest,
+    Unknown,
+    Warn,
+    Fail,
+}
+
+impl HealthStatus {
+    fn text(&self) -> RichText {
+        match self {
+            HealthStatus::Pass => RichText::new("Pass").color(Color32::GREEN),
+            HealthStatus::NeedsTest => RichText::new("Needs test").color(Color32::LIGHT_BLUE),
+            HealthStatus::Unknown => RichText::new("Unknown").color(Color32::GRAY),
+            HealthStatus::Warn => RichText::new("Warn").color(Color32::YELLOW),
+            HealthStatus::Fail => RichText::new("Fail").color(Color32::RED),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HealthItem {
+    pub name: &'static str,
+    pub status: HealthStatus,
+    pub details: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HealthReport {
+    pub items: Vec<HealthItem>,
+}
+
+impl HealthReport {
+    pub fn new(health: &Health, data: Option<&Data>) -> Self {
+        let mut items = vec![
+            calibration_flag_item(&health.calibration_flag),
+            battery_item(data),
+            flash_crc_item(&health.flash_crc),
+        ];
+        items.extend(stick_items(
+            ["Left stick drift", "Left stick noise"],
+            &health.left_stick,
+            health.stick_test_state(),
+        ));
+        items.extend(stick_items(
+            ["Right stick drift", "Right stick noise"],
+            &health.right_stick,
+            health.stick_test_state(),
+        ));
+        Self { items }
+    }
+
+    // Items that still need a test don't affect the overall status
+    pub fn overall(&self) -> HealthStatus {
+        self.items
+            .iter()
+            .map(|item| item.status)
+            .filter(|status| *status != HealthStatus::NeedsTest)
+            .fold(HealthStatus::Pass, |worst, status| {
+                if status > worst {
+                    status
+                } else {
+                    worst
+                }
+            })
+    }
+}
+
+fn calibration_flag_item(flag: &Result<CalibrationFlag, String>) -> HealthItem {
+    let (status, details) = match flag {
+        Ok(flag) => {
+            let not_calibrated: Vec<&str> = [
+                ("accelerometer", flag.is_accelerometer_calib_ok()),
+                ("gyroscope", flag.is_gyroscope_calib_ok()),
+                ("sticks min/max", flag.is_stick_min_max_calib_ok()),
+                ("sticks center", flag.is_stick_center_calib_ok()),
+                ("left trigger", flag.is_l2_calib_ok()),
+                ("right trigger", flag.is_r2_calib_ok()),
+            ]
+            .into_iter()
+            .filter(|(_, ok)| !ok)
+            .map(|(name, _)| name)
+            .collect();
+            if not_calibrated.is_empty() {
+                (HealthStatus::Pass, "All calibrated".to_string())
+            } else {
+                (
+                    HealthStatus::Warn,
+                    format!("Not calibrated: {}", not_calibrated.join(", ")),
+                )
+            }
+        }
+        Err(error) => (HealthStatus::Unknown, format!("Read failed: {}", error)),
+    };
+    HealthItem {
+        name: "Calibration flags",
+        status,
+        details,
+    }
+}
+
+fn battery_item(data: Option<&Data>) -> HealthItem {
+    let (status, details) = match data {
+        Some(data) => {
+            let percent = data.battery_capacity_percent();
+            let status = if percent < BATTERY_WARN_PERCENT {
+                HealthStatus::Warn
+            } else {
+                HealthStatus::Pass
+            };
+            let cable = if data.cable_connected() {
+                ", cable connected"
+            } else {
+                ""
+            };
+            (status, format!("{}%{}", percent, cable))
+        }
+        None => (HealthStatus::Unknown, "No input data".to_string()),
+    };
+    HealthItem {
+        name: "Battery",
+        status,
+        details,
+    }
+}
+
+fn flash_crc_item(flash_crc: &Option<Result<bool, String>>) -> HealthItem {
+    let (status, details) = match flash_crc {
+        Some(Ok(true)) => (HealthStatus::Pass, "Correct".to_string()),
+        Some(Ok(false)) => (HealthStatus::Fail, "Invalid".to_string()),
+        Some(Err(error)) => (HealthStatus::Unknown, format!("Read failed: {}", error)),
+        None => (
+            HealthStatus::NeedsTest,
+            "Requires a flash read (takes a few seconds)".to_string(),
+        ),
+    };
+    HealthItem {
+        name: "Flash CRC",
+        status,
+        details,
+    }
+}
+
+fn stick_items(
+    [drift_name, noise_name]: [&'static str; 2],
+    stats: &DriftStats,
+    test_state: StickTestState,
+) -> [HealthItem; 2] {
+    let (drift, noise) = match test_state {
+        StickTestState::NotStarted => {
+            let details = "Requires a short test with untouched sticks".to_string();
+            (
+                (HealthStatus::NeedsTest, details.clone()),
+                (HealthStatus::NeedsTest, details),
+            )
+        }
+        StickTestState::Running => {
+            let details = format!("Testing... ({} samples)", stats.count());
+            (
+                (HealthStatus::NeedsTest, details.clone()),
+                (HealthStatus::NeedsTest, details),
+            )
+        }
+        StickTestState::Finished if stats.is_empty() => {
+            let details = "No samples collected".to_string();
+            (
+                (HealthStatus::Unknown, details.clone()),
+                (HealthStatus::Unknown, details),
+            )
+        }
+        StickTestState::Finished => (
+            (
+                threshold_status(stats.offset(), STICK_DRIFT_WARN, STICK_DRIFT_FAIL),
+                format!("Resting offset {:.3}", stats.offset()),
+            ),
+            (
+                threshold_status(stats.noise(), STICK_NOISE_WARN, STICK_NOISE_FAIL),
+                format!("Noise floor {:.3}", stats.noise()),
+            ),
+        ),
+    };
+    [
+        HealthItem {
+            name: drift_name,
+            status: drift.0,
+            details: drift.1,
+        },
+        HealthItem {
+            name: noise_name,
+            status: noise.0,
+            details: noise.1,
+        },
+    ]
+}
+
+fn threshold_status(value: f64, warn: f64, fail: f64) -> HealthStatus {
+    if value >= fail {
+        HealthStatus::Fail
+    } else if value >= warn {
+        HealthStatus::Warn
+    } else {
+        HealthStatus::Pass
+    }
+}
+
+// Accumulates resting stick positions to estimate center offset and noise
+#[derive(Debug, Clone, Default)]
+pub struct DriftStats {
+    count: u32,
+    sum_x: f64,
+    sum_y: f64,
+    sum_squares_x: f64,
+    sum_squares_y: f64,
+}
+
+impl DriftStats {
+    pub fn add(&mut self, stick_position: &StickPosition) {
+        let (x, y) = (stick_position.normalized_x(), stick_position.normalized_y());
+        self.count += 1;
+        self.sum_x += x;
+        self.sum_y += y;
+        self.sum_squares_x += x.powi(2);
+        self.sum_squares_y += y.powi(2);
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> (f64, f64) {
+        if self.is_empty() {
+            return (0f64, 0f64);
+        }
+        let count = self.count as f64;
+        (self.sum_x / count, self.sum_y / count)
+    }
+
+    // Distance of the mean resting position from the center
+    pub fn offset(&self) -> f64 {
+        let (x, y) = self.mean();
+        (x.powi(2) + y.powi(2)).sqrt()
+    }
+
+    // Largest standard deviation of both axes
+    pub fn noise(&self) -> f64 {
+        if self.is_empty() {
+            return 0f64;
+        }
+        let count = self.count as f64;
+        let (mean_x, mean_y) = self.mean();
+        let variance_x = (self.sum_squares_x / count - mean_x.powi(2)).max(0f64);
+        let variance_y = (self.sum_squares_y / count - mean_y.powi(2)).max(0f64);
+        variance_x.sqrt().max(variance_y.sqrt())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DriftCheckStep {
+    Sampling,
+    // A stick was moved during the sample, there is no result
+    Moved,
+    // Resting offsets of the sticks that are off-center
+    Finished {
+        left: Option<f64>,
+        right: Option<f64>,
+    },
+}
+
+// Short resting sample after connecting, assuming the sticks aren't touched
+pub struct DriftCheck {
+    started: Instant,
+    first: Option<[StickPosition; 2]>,
+    left_stick: DriftStats,
+    right_stick: DriftStats,
+}
+
+impl DriftCheck {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            first: None,
+            left_stick: DriftStats::default(),
+            right_stick: DriftStats::default(),
+        }
+    }
+
+    pub fn update(&mut self, now: Instant, data: Option<&Data>) -> DriftCheckStep {
+        if let Some(data) = data {
+            let positions = [data.left_stick_position(), data.right_stick_position()];
+            let first = self.first.get_or_insert_with(|| positions.clone());
+            let moved = positions.iter().zip(first.iter()).any(|(position, first)| {
+                let x = position.normalized_x() - first.normalized_x();
+                let y = position.normalized_y() - first.normalized_y();
+                (x.powi(2) + y.powi(2)).sqrt() > DRIFT_CHECK_MOVEMENT
+            });
+            if moved {
+                return DriftCheckStep::Moved;
+            }
+            self.left_stick.add(&positions[0]);
+            self.right_stick.add(&positions[1]);
+        }
+        if now.saturating_duration_since(self.started) < DRIFT_CHECK_DURATION {
+            return DriftCheckStep::Sampling;
+        }
+        let drift = |stats: &DriftStats| {
+            Some(stats.offset()).filter(|offset| !stats.is_empty() && *offset >= STICK_DRIFT_WARN)
+        };
+        DriftCheckStep::Finished {
+            left: drift(&self.left_stick),
+            right: drift(&self.right_stick),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StickAxis {
+    X,
+    Y,
+}
+
+// Movement of the orthogonal axis while only one axis is moved
+#[derive(Debug, Clone, Default)]
+pub struct CrossTalkStats {
+    // Position at the step start, the orthogonal axis movement is measured from it
+    rest: Option<(f64, f64)>,
+    // Indexed by the moved axis
+    movement: [f64; 2],
+    bleed: [f64; 2],
+}
+
+impl CrossTalkStats {
+    fn add(&mut self, axis: StickAxis, stick_position: &StickPosition) {
+        let (x, y) = (stick_position.normalized_x(), stick_position.normalized_y());
+        let (rest_x, rest_y) = *self.rest.get_or_insert((x, y));
+        let (moved, orthogonal) = match axis {
+            StickAxis::X => ((x - rest_x).abs(), (y - rest_y).abs()),
+            StickAxis::Y => ((y - rest_y).abs(), (x - rest_x).abs()),
+        };
+        let index = axis as usize;
+        self.movement[index] = self.movement[index].max(moved);
+        self.bleed[index] = self.bleed[index].max(orthogonal);
+    }
+
+    fn next_step(&mut self) {
+        self.rest = None;
+    }
+
+    // Percent of the moved axis range that leaked into the orthogonal axis
+    fn percent(&self, axis: StickAxis) -> Option<f64> {
+        let index = axis as usize;
+        (self.movement[index] >= CROSS_TALK_MIN_MOVEMENT)
+            .then(|| self.bleed[index] / self.movement[index] * 100f64)
+    }
+
+    fn text(&self, axis: StickAxis) -> String {
+        match self.percent(axis) {
+            Some(percent) => format!("{:.1}%", percent),
+            None => "Not enough movement".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CrossTalkStep {
+    NotStarted,
+    Move(StickAxis),
+    Finished,
+}
+
+#[derive(Debug, Clone)]
+pub struct CrossTalk {
+    step: CrossTalkStep,
+    left_stick: CrossTalkStats,
+    right_stick: CrossTalkStats,
+}
+
+impl Default for CrossTalk {
+    fn default() -> Self {
+        Self {
+            step: CrossTalkStep::NotStarted,
+            left_stick: CrossTalkStats::default(),
+            right_stick: CrossTalkStats::default(),
+        }
+    }
+}
+
+impl CrossTalk {
+    fn results_text(&self) -> String {
+        [
+            ("Left stick", &self.left_stick),
+            ("Right stick", &self.right_stick),
+        ]
+        .iter()
+        .map(|(name, stats)| {
+            format!(
+                "{}: X to Y {}, Y to X {}",
+                name,
+                stats.text(StickAxis::X),
+                stats.text(StickAxis::Y)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct MotionSample {
+    gyroscope: [f64; 3],
+    accelerometer: [f64; 3],
+}
+
+impl MotionSample {
+    fn is_still_since(&self, previous: &MotionSample) -> bool {
+        let max_change = |a: &[f64; 3], b: &[f64; 3]| {
+            a.iter()
+                .zip(b)
+                .map(|(a, b)| (a - b).abs())
+                .fold(0f64, f64::max)
+        };
+        max_change(&self.gyroscope, &previous.gyroscope) <= MOTION_STILL_GYROSCOPE_CHANGE_DPS
+            && max_change(&self.accelerometer, &previous.accelerometer)
+                <= MOTION_STILL_ACCELEROMETER_CHANGE_G
+            && self
+                .gyroscope
+                .iter()
+                .all(|dps| dps.abs() <= MOTION_STILL_MAX_DPS)
+    }
+}
+
+// Gyroscope zero-rate and 1g check of a controller lying flat, samples during movement are
+// rejected
+#[derive(Debug, Clone, Default)]
+pub struct MotionSelfTest {
+    started: Option<Instant>,
+    counter: Option<u8>,
+    previous: Option<MotionSample>,
+    gyroscope_sum: [f64; 3],
+    accelerometer_sum: [f64; 3],
+    accepted: u32,
+    rejected: u32,
+}
+
+impl MotionSelfTest {
+    fn start(&mut self, now: Instant) {
+        *self = Self {
+            started: Some(now),
+            ..Self::default()
+        };
+    }
+
+    fn state(&self, now: Instant) -> StickTestState {
+        match self.started {
+            None => StickTestState::NotStarted,
+            Some(started) if now.saturating_duration_since(started) < MOTION_TEST_DURATION => {
+                StickTestState::Running
+            }
+            Some(_) => StickTestState::Finished,
+        }
+    }
+
+    // The same report is returned until the device sends a new one, so repeats are skipped
+    fn add(&mut self, data: &Data, calibration: &MotionCalibrationValues) {
+        if self.counter == Some(data.counter()) {
+            return;
+        }
+        self.counter = Some(data.counter());
+        let sample = MotionSample {
+            gyroscope: calibration.gyroscope_dps(data.gyroscope()),
+            accelerometer: calibration.accelerometer_g(data.accelerometer()),
+        };
+        let still = self
+            .previous
+            .as_ref()
+            .is_some_and(|previous| sample.is_still_since(previous));
+        if still {
+            for axis in 0..3 {
+                self.gyroscope_sum[axis] += sample.gyroscope[axis];
+                self.accelerometer_sum[axis] += sample.accelerometer[axis];
+            }
+            self.accepted += 1;
+        } else if self.previous.is_some() {
+            self.rejected += 1;
+        }
+        self.previous = Some(sample);
+    }
+
+    fn mean(&self, sum: &[f64; 3]) -> [f64; 3] {
+        sum.map(|value| value / self.accepted.max(1) as f64)
+    }
+
+    // None until enough still samples are collected
+    fn items(&self) -> Option<[HealthItem; 2]> {
+        if self.accepted < MOTION_TEST_MIN_SAMPLES {
+            return None;
+        }
+        let gyroscope = self.mean(&self.gyroscope_sum);
+        let accelerometer = self.mean(&self.accelerometer_sum);
+        let gyroscope_offset = gyroscope.iter().fold(0f64, |max, dps| max.max(dps.abs()));
+        let accelerometer_offset = accelerometer
+            .iter()
+            .zip(FLAT_ACCELEROMETER_G)
+            .fold(0f64, |max, (g, expected)| max.max((g - expected).abs()));
+        Some([
+            HealthItem {
+                name: "Gyroscope zero-rate",
+                status: threshold_status(
+                    gyroscope_offset,
+                    GYROSCOPE_ZERO_RATE_WARN_DPS,
+                    GYROSCOPE_ZERO_RATE_FAIL_DPS,
+                ),
+                details: format!(
+                    "X {:+.2}, Y {:+.2}, Z {:+.2} deg/s",
+                    gyroscope[0], gyroscope[1], gyroscope[2]
+                ),
+            },
+            HealthItem {
+                name: "Accelerometer 1g",
+                status: threshold_status(
+                    accelerometer_offset,
+                    ACCELEROMETER_OFFSET_WARN_G,
+                    ACCELEROMETER_OFFSET_FAIL_G,
+                ),
+                details: format!(
+                    "X {:+.3}, Y {:+.3}, Z {:+.3} g",
+                    accelerometer[0], accelerometer[1], accelerometer[2]
+                ),
+            },
+        ])
+    }
+
+    fn results_text(&self, calibration_source: &str) -> String {
+        let samples = format!(
+            "Samples: {} accepted, {} rejected during movement",
+            self.accepted, self.rejected
+        );
+        let results = match self.items() {
+            Some(items) => items
+                .iter()
+                .map(|item| format!("{}: {:?}, {}", item.name, item.status, item.details))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            None => "Not enough still samples, keep the controller flat and untouched".to_string(),
+        };
+        format!(
+            "{}\n{}\nCalibration: {}",
+            results, samples, calibration_source
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StickTestState {
+    NotStarted,
+    Running,
+    Finished,
+}
+
+pub struct Health {
+    calibration_flag: Result<CalibrationFlag, String>,
+    flash_crc: Option<Result<bool, String>>,
+    left_stick: DriftStats,
+    right_stick: DriftStats,
+    stick_test_started: Option<Instant>,
+    cross_talk: CrossTalk,
+    // Nominal values are used when the device calibration can't be read
+    motion_calibration: Result<MotionCalibrationValues, String>,
+    motion_self_test: MotionSelfTest,
+    // Shown with --dev only
+    jitter_axis: JitterAxis,
+}
+
+impl Health {
+    pub fn from_connected_device(connected_device: &ConnectedDevice) -> Self {
+        let ConnectedDevice::DualShock4(_, ds4) = connected_device;
+        Self {
+            calibration_flag: ds4.read_calibration_flag().map_err(|e| e.to_string()),
+            flash_crc: None,
+            left_stick: DriftStats::default(),
+            right_stick: DriftStats::default(),
+            stick_test_started: None,
+            cross_talk: CrossTalk::default(),
+            motion_calibration: ds4
+                .read_motion_calibration_data()
+                .map_err(|e| e.to_string())
+                .map(|calibration| calibration.values(ds4.connection_type()))
+                .and_then(|values| {
+                    if values.has_valid_ranges() {
+                        Ok(values)
+                    } else {
+                        Err("Empty calibration ranges".to_string())
+                    }
+                }),
+            motion_self_test: MotionSelfTest::default(),
+            jitter_axis: JitterAxis::default(),
+        }
+    }
+
+    fn stick_test_state(&self) -> StickTestState {
+        match self.stick_test_started {
+            None => StickTestState::NotStarted,
+            Some(started) if started.elapsed() < STICK_TEST_DURATION => StickTestState::Running,
+            Some(_) => StickTestState::Finished,
+        }
+    }
+}
+
+pub fn health(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    state: &mut DeviceConnected,
+    sh: StatusHandler,
+) {
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    let data = sh.handle_error(ds4.read_last_data()).flatten();
+    let dev = state.dev;
+    let Panel::Health(health) = &mut state.panel else {
+        return;
+    };
+    if health.stick_test_state() == StickTestState::Running {
+        if let Some(data) = &data {
+            health.left_stick.add(&data.left_stick_position());
+            health.right_stick.add(&data.right_stick_position());
+        }
+    }
+    if let (CrossTalkStep::Move(axis), Some(data)) = (health.cross_talk.step, &data) {
+        let cross_talk = &mut health.cross_talk;
+        cross_talk.left_stick.add(axis, &data.left_stick_position());
+        cross_talk
+            .right_stick
+            .add(axis, &data.right_stick_position());
+    }
+    if health.motion_self_test.state(Instant::now()) == StickTestState::Running {
+        if let Some(data) = &data {
+            let calibration = health
+                .motion_calibration
+                .clone()
+                .unwrap_or_else(|_| MotionCalibrationValues::identity());
+            health.motion_self_test.add(data, &calibration);
+        }
+    }
+
+    let report = HealthReport::new(health, data.as_ref());
+    ui.heading("Controller Health");
+    ui.horizontal(|ui| {
+        ui.label("Overall:");
+        ui.label(report.overall().text().strong());
+    });
+    ui.separator();
+    egui::Grid::new("Health")
+        .num_columns(3)
+        .striped(true)
+        .show(ui, |ui| {
+            for item in &report.items {
+                ui.label(item.name);
+                ui.label(item.status.text());
+                ui.label(&item.details);
+                ui.end_row();
+            }
+        });
+    ui.separator();
+    ui.horizontal(|ui| {
+        let running = health.stick_test_state() == StickTestState::Running;
+        if ui
+            .add_enabled(!running, egui::Button::new("Run Stick Test"))
+            .on_hover_text("Don't touch the sticks during the test")
+            .clicked()
+        {
+            health.left_stick.clear();
+            health.right_stick.clear();
+            health.stick_test_started = Some(Instant::now());
+        }
+        if ui
+            .button("Check Flash CRC")
+            .on_hover_text(t("help.check_flash_crc"))
+            .clicked()
+        {
+            health.flash_crc = Some(
+                ds4.read_flash_mirror()
+                    .map(|flash_mirror| flash_mirror.check_crc())
+                    .map_err(|e| e.to_string()),
+            );
+        }
+        if ui
+            .button("Re-read Calibration Flags")
+            .on_hover_text(t("help.reread_calibration_flags"))
+            .clicked()
+        {
+            health.calibration_flag = ds4.read_calibration_flag().map_err(|e| e.to_string());
+        }
+    });
+    ui.separator();
+    cross_talk(ui, &mut health.cross_talk);
+    ui.separator();
+    motion_self_test(ui, health);
+    ui.separator();
+    button_chatter(ui, ds4);
+    if dev {
+        ui.separator();
+        jitter_spectrum(ui, ds4, &mut health.jitter_axis);
+    }
+    ctx.request_repaint();
+}
+
+fn jitter_spectrum(ui: &mut egui::Ui, ds4: &DualShock4, axis: &mut JitterAxis) {
+    ui.label(RichText::new("Stick Jitter Spectrum").strong())
+        .on_hover_text(t("help.jitter_spectrum"));
+    // Copied, so the device thread isn't blocked while the spectrum is computed
+    let jitter = ds4.stick_jitter().clone();
+    ui.horizontal(|ui| {
+        for value in JitterAxis::ALL {
+            ui.radio_value(axis, value, format!("{:?}", value));
+        }
+        if ui.button("Reset").clicked() {
+            ds4.stick_jitter().clear();
+        }
+    });
+    match jitter.sample_rate() {
+        Some(sample_rate) => ui.label(format!(
+            "{} reports at {:.0} Hz from the device clock",
+            jitter.len(),
+            sample_rate
+        )),
+        None => ui.label("Waiting for input reports"),
+    };
+    let spectrum = jitter.spectrum(*axis);
+    Plot::new("Stick Jitter Spectrum")
+        .height(160f32)
+        .x_axis_label("Hz")
+        .y_axis_label("Amplitude")
+        .allow_scroll(false)
+        .show(ui, |plot_ui| {
+            plot_ui.line(Line::new(PlotPoints::new(spectrum)))
+        });
+}
+
+fn button_chatter(ui: &mut egui::Ui, ds4: &DualShock4) {
+    ui.label(RichText::new("Button Chatter").strong())
+        .on_hover_text(t("help.button_chatter"));
+    let mut detector = ds4.button_chatter();
+    ui.horizontal(|ui| {
+        let mut window_ms = detector.settings.window.as_millis() as u64;
+        ui.label("Window:");
+        if ui
+            .add(
+                egui::DragValue::new(&mut window_ms)
+                    .range(50..=5000)
+                    .suffix(" ms"),
+            )
+            .changed()
+        {
+            detector.settings.window = Duration::from_millis(window_ms);
+        }
+        ui.label("Short pulses:");
+        ui.add(egui::DragValue::new(&mut detector.settings.threshold).range(1..=50));
+        if ui.button("Reset").clicked() {
+            detector.reset();
+        }
+    });
+    let chattering: Vec<_> = detector
+        .counts()
+        .filter(|(_, chatter)| *chatter > 0)
+        .collect();
+    if chattering.is_empty() {
+        ui.label("No chatter detected, press the buttons normally to test them");
+        return;
+    }
+    egui::Grid::new("Button Chatter")
+        .num_columns(2)
+        .striped(true)
+        .show(ui, |ui| {
+            for (button, chatter) in chattering {
+                ui.label(format!("{:?}", button));
+                ui.label(RichText::new(chatter.to_string()).color(Color32::YELLOW));
+                ui.end_row();
+            }
+        });
+}
+
+fn motion_self_test(ui: &mut egui::Ui, health: &mut Health) {
+    ui.label(RichText::new("Motion Sensor Self-Test").strong());
+    let calibration_source = match &health.motion_calibration {
+        Ok(_) => "device".to_string(),
+        Err(error) => format!("nominal, device calibration unavailable ({})", error),
+    };
+    let self_test = &mut health.motion_self_test;
+    match self_test.state(Instant::now()) {
+        StickTestState::Running => {
+            ui.label(
+                RichText::new("Keep the controller flat on the table and don't touch it.")
+                    .color(Color32::YELLOW),
+            );
+            ui.label(format!(
+                "Testing... ({} samples, {} rejected during movement)",
+                self_test.accepted, self_test.rejected
+            ));
+            if ui.button("Cancel").clicked() {
+                *self_test = MotionSelfTest::default();
+            }
+        }
+        state => {
+            if state == StickTestState::Finished {
+                match self_test.items() {
+                    Some(items) => {
+                        egui::Grid::new("Motion self-test")
+                            .num_columns(3)
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for item in &items {
+                                    ui.label(item.name);
+                                    ui.label(item.status.text());
+                                    ui.label(&item.details);
+                                    ui.end_row();
+                                }
+                            });
+                    }
+                    None => {
+                        ui.label(
+                            RichText::new("Too much movement, not enough still samples")
+                                .color(Color32::RED),
+                        );
+                    }
+                }
+                let results = self_test.results_text(&calibration_source);
+                if ui.button("Copy Results").clicked() {
+                    ui.ctx().copy_text(results);
+                }
+            } else {
+                ui.label(
+                    "Place the controller flat on a table with the buttons up, \
+                    then start the test and don't touch it.",
+                );
+            }
+            if ui
+                .button("Run Motion Self-Test")
+                .on_hover_text(t("help.motion_self_test"))
+                .clicked()
+            {
+                self_test.start(Instant::now());
+            }
+        }
+    }
+}
+
+fn cross_talk(ui: &mut egui::Ui, cross_talk: &mut CrossTalk) {
+    ui.label(RichText::new("Stick Cross-Talk").strong());
+    match cross_talk.step {
+        CrossTalkStep::NotStarted | CrossTalkStep::Finished => {
+            if cross_talk.step == CrossTalkStep::Finished {
+                let results = cross_talk.results_text();
+                ui.label(&results);
+                if ui.button("Copy Results").clicked() {
+                    ui.ctx().copy_text(results);
+                }
+            }
+            if ui
+                .button("Run Cross-Talk Test")
+                .on_hover_text("Guided test, move one stick axis at a time")
+                .clicked()
+            {
+                *cross_talk = CrossTalk {
+                    step: CrossTalkStep::Move(StickAxis::X),
+                    ..Default::default()
+                };
+            }
+        }
+        CrossTalkStep::Move(StickAxis::X) => {
+            ui.label("Step 1 of 2: move both sticks fully left and right, keep them level.");
+            ui.horizontal(|ui| {
+                if ui.button("Next").clicked() {
+                    cross_talk.left_stick.next_step();
+                    cross_talk.right_stick.next_step();
+                    cross_talk.step = CrossTalkStep::Move(StickAxis::Y);
+                }
+                if ui.button("Cancel").clicked() {
+                    cross_talk.step = CrossTalkStep::NotStarted;
+                }
+            });
+        }
+        CrossTalkStep::Move(StickAxis::Y) => {
+            ui.label("Step 2 of 2: move both sticks fully up and down, keep them straight.");
+            ui.horizontal(|ui| {
+                if ui.button("Finish").clicked() {
+                    cross_talk.step = CrossTalkStep::Finished;
+                }
+                if ui.button("Cancel").clicked() {
+                    cross_talk.step = CrossTalkStep::NotStarted;
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(x: u8, y: u8) -> StickPosition {
+        StickPosition { x, y }
+    }
+
+    #[test]
+    fn cross_talk_ignores_moved_axis() {
+        let mut stats = CrossTalkStats::default();
+        for (x, y) in [(128, 128), (255, 140), (0, 116), (128, 128)] {
+            stats.add(StickAxis::X, &position(x, y));
+        }
+        stats.next_step();
+        for (x, y) in [(128, 128), (128, 255), (128, 0)] {
+            stats.add(StickAxis::Y, &position(x, y));
+        }
+
+        let x_percent = stats.percent(StickAxis::X).unwrap();
+        assert!((x_percent - 12f64 / 128f64 * 100f64).abs() < 1f64);
+        assert_eq!(stats.percent(StickAxis::Y), Some(0f64));
+    }
+
+    fn sticks(left: (u8, u8), right: (u8, u8)) -> Data {
+        let mut data = Data::zeroed();
+        data.set_left_stick_position(position(left.0, left.1));
+        data.set_right_stick_position(position(right.0, right.1));
+        data
+    }
+
+    #[test]
+    fn drift_check_reports_off_center_sticks() {
+        let start = Instant::now();
+        let mut check = DriftCheck::new(start);
+        let resting = sticks((140, 128), (128, 127));
+        assert_eq!(
+            check.update(start, Some(&resting)),
+            DriftCheckStep::Sampling
+        );
+        assert_eq!(check.update(start, None), DriftCheckStep::Sampling);
+        let DriftCheckStep::Finished { left, right } =
+            check.update(start + DRIFT_CHECK_DURATION, Some(&resting))
+        else {
+            panic!("Drift check isn't finished");
+        };
+        assert!(left.unwrap() > STICK_DRIFT_WARN);
+        assert_eq!(right, None);
+    }
+
+    #[test]
+    fn drift_check_aborts_on_movement() {
+        let start = Instant::now();
+        let mut check = DriftCheck::new(start);
+        check.update(start, Some(&sticks((128, 128), (128, 128))));
+        let moved = sticks((128, 128), (128, 160));
+        assert_eq!(check.update(start, Some(&moved)), DriftCheckStep::Moved);
+    }
+
+    fn motion(gyroscope: [i16; 3], accelerometer: [i16; 3]) -> Data {
+        let mut data = Data::zeroed();
+        for (index, value) in gyroscope.iter().chain(&accelerometer).enumerate() {
+            data.buf[13 + index * 2..15 + index * 2].copy_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+
+    fn run_motion_test(samples: impl Iterator<Item = Data>) -> MotionSelfTest {
+        let mut test = MotionSelfTest::default();
+        test.start(Instant::now());
+        for (counter, mut data) in samples.enumerate() {
+            data.buf[7] = (counter as u8) << 2;
+            test.add(&data, &MotionCalibrationValues::identity());
+        }
+        test
+    }
+
+    #[test]
+    fn motion_self_test_measures_offsets() {
+        // 0.5 deg/s on X and 1.02g on Y
+        let flat = motion([8, 0, 0], [0, 8356, 0]);
+        let test = run_motion_test(vec![flat; 200].into_iter());
+        let [gyroscope, accelerometer] = test.items().unwrap();
+        assert_eq!(gyroscope.status, HealthStatus::Pass);
+        assert_eq!(gyroscope.details, "X +0.50, Y +0.00, Z +0.00 deg/s");
+        assert_eq!(accelerometer.status, HealthStatus::Pass);
+        assert_eq!(accelerometer.details, "X +0.000, Y +1.020, Z +0.000 g");
+    }
+
+    #[test]
+    fn motion_self_test_rejects_movement() {
+        let flat = motion([0, 0, 0], [0, 8192, 0]);
+        let shaken = motion([800, 0, 0], [2000, 6000, 0]);
+        let samples = (0..200).map(|i| {
+            if i % 2 == 0 {
+                flat.clone()
+            } else {
+                shaken.clone()
+            }
+        });
+        let test = run_motion_test(samples);
+        assert_eq!(test.accepted, 0);
+        assert_eq!(test.rejected, 199);
+        assert!(test.items().is_none());
+
+        // Tilted, but still
+        let tilted = motion([0, 0, 0], [4096, 7094, 0]);
+        let test = run_motion_test(vec![tilted; 200].into_iter());
+        assert_eq!(test.rejected, 0);
+        assert_eq!(test.items().unwrap()[1].status, HealthStatus::Fail);
+    }
+
+    #[test]
+    fn cross_talk_needs_movement() {
+        let mut stats = CrossTalkStats::default();
+        for (x, y) in [(128, 128), (150, 150)] {
+            stats.add(StickAxis::X, &position(x, y));
+        }
+        assert_eq!(stats.percent(StickAxis::X), None);
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+// Index of the current language in Language::ALL
+static CURRENT_LANGUAGE: AtomicUsize = AtomicUsize::new(0);
+static STRING_TABLES: OnceLock<Vec<HashMap<String, String>>> = OnceLock::new();
+
+// Shipped languages must be covered by the default egui fonts
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Language {
+    #[default]
+    English,
+    Ukrainian,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::Ukrainian];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::Ukrainian => "Українська",
+        }
+    }
+
+    fn strings(&self) -> &'static str {
+        match self {
+            Language::English => include_str!("../../assets/i18n/en.json"),
+            Language::Ukrainian => include_str!("../../assets/i18n/uk.json"),
+        }
+    }
+
+    fn index(&self) -> usize {
+        Self::ALL
+            .iter()
+            .position(|language| language == self)
+            .unwrap_or(0)
+    }
+}
+
+fn string_tables() -> &'static Vec<HashMap<String, String>> {
+    STRING_TABLES.get_or_init(|| {
+        Language::ALL
+            .iter()
+            .map(|language| {
+                serde_json::from_str(language.strings()).unwrap_or_else(|e| {
+                    error!("Cannot parse {} strings: {}", language.name(), e);
+                    HashMap::new()
+                })
+            })
+            .collect()
+    })
+}
+
+pub fn set_language(language: Language) {
+    CURRENT_LANGUAGE.store(language.index(), Ordering::Relaxed);
+}
+
+// Falls back to English and then to the key itself
+pub fn t(key: &'static str) -> &'static str {
+    let tables = string_tables();
+    let current = CURRENT_LANGUAGE.load(Ordering::Relaxed);
+    tables
+        .get(current)
+        .and_then(|table| table.get(key))
+        .or_else(|| tables[Language::English.index()].get(key))
+        .map(|value| value.as_str())
+        .unwrap_or(key)
+}
+
+// Replaces "{}" placeholders of the translated string in order
+pub fn tf(key: &'static str, args: &[&dyn Display]) -> String {
+    let mut result = t(key).to_string();
+    for arg in args {
+        result = result.replacen("{}", &arg.to_string(), 1);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_key_falls_back_to_key() {
+        assert_eq!(t("missing.key"), "missing.key");
+        assert_eq!(tf("missing.{}", &[&1]), "missing.1");
+    }
+
+    #[test]
+    fn languages_have_same_keys() {
+        let tables = string_tables();
+        let english = &tables[Language::English.index()];
+        assert!(!english.is_empty());
+        for (language, table) in Language::ALL.iter().zip(tables) {
+            let mut missing: Vec<&String> = english
+                .keys()
+                .filter(|key| !table.contains_key(*key))
+                .collect();
+            missing.sort();
+            assert!(missing.is_empty(), "{:?} misses {:?}", language, missing);
+        }
+    }
+}
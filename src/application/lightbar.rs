@@ -0,0 +1,77 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use std::time::{Duration, Instant};
+
+// Battery level changes slowly, output reports every frame would only load the link
+pub const BATTERY_LIGHTBAR_INTERVAL: Duration = Duration::from_secs(5);
+
+// Green when charged, yellow at half and red when drained, like the console does
+pub fn battery_color(percent: u8) -> [u8; 3] {
+    let percent = u16::from(percent.min(100));
+    if percent >= 50 {
+        [((100 - percent) * 255 / 50) as u8, 255, 0]
+    } else {
+        [255, (percent * 255 / 50) as u8, 0]
+    }
+}
+
+// Sends the battery color only when the interval passed and the color changed
+#[derive(Debug, Default)]
+pub struct BatteryLightbar {
+    last_update: Option<Instant>,
+    last_color: Option<[u8; 3]>,
+}
+
+impl BatteryLightbar {
+    pub fn update(
+        &mut self,
+        now: Instant,
+        battery_percent: impl FnOnce() -> Option<u8>,
+    ) -> Option<[u8; 3]> {
+        if matches!(self.last_update, Some(last) if now - last < BATTERY_LIGHTBAR_INTERVAL) {
+            return None;
+        }
+        self.last_update = Some(now);
+        let color = battery_color(battery_percent()?);
+        (self.last_color.replace(color) != Some(color)).then_some(color)
+    }
+
+    // The next update sends the color even if it didn't change
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn battery_color_gradient() {
+        assert_eq!(battery_color(100), [0, 255, 0]);
+        assert_eq!(battery_color(50), [255, 255, 0]);
+        assert_eq!(battery_color(25), [255, 127, 0]);
+        assert_eq!(battery_color(0), [255, 0, 0]);
+    }
+
+    #[test]
+    fn updates_are_throttled() {
+        let mut lightbar = BatteryLightbar::default();
+        let start = Instant::now();
+        assert_eq!(lightbar.update(start, || Some(100)), Some([0, 255, 0]));
+        assert_eq!(
+            lightbar.update(start + Duration::from_secs(1), || Some(5)),
+            None
+        );
+        // Unchanged color isn't sent again
+        assert_eq!(
+            lightbar.update(start + BATTERY_LIGHTBAR_INTERVAL, || Some(100)),
+            None
+        );
+        assert_eq!(
+            lightbar.update(start + BATTERY_LIGHTBAR_INTERVAL * 2, || Some(5)),
+            Some([255, 25, 0])
+        );
+    }
+}
@@ -1,28 +1,216 @@
 // Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
 // SPDX-License-Identifier: GPL-3.0
 
+use crate::application::calibration::TriggerThresholds;
 use crate::application::font::with_gamepad_font;
 use crate::application::font::*;
-use crate::application::{ConnectedDevice, DeviceConnected, Panel, StatusHandler};
-use crate::dual_shock_4::{DPadState, Data, StickPosition};
+use crate::application::i18n::t;
+use crate::application::i18n::tf;
+use crate::application::recording::Recording;
+use crate::application::remap::{Remap, RemapRule, Stick};
+use crate::application::{ConnectedDevice, DeviceConnected, Error, Panel, StatusHandler};
+use crate::dual_shock_4::data_log::DATA_LOG_FILE_EXTENSION;
+use crate::dual_shock_4::touch_gesture::{Gesture, SwipeDirection, TouchGesture};
+use crate::dual_shock_4::{
+    polar, DPadState, Data, DualShock4, StickCenterCalibration, StickPosition, TouchPoint,
+    TOUCHPAD_HEIGHT, TOUCHPAD_WIDTH,
+};
+use chrono::{DateTime, Local};
 use eframe::egui;
+use eframe::egui::{remap, Color32, ColorImage, Pos2, Rect, RichText, Stroke};
 use egui_plot::{Line, Plot, PlotPoints, Points};
-use eframe::egui::{remap, Color32, RichText, WidgetText};
-use std::f64::consts::{PI, TAU};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::f64::consts::TAU;
+use std::fs;
+use std::fs::File;
 use std::i16;
-use std::ops::Rem;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 const STICK_HISTORY_DEGREES: usize = 360;
 const STICK_HISTORY_SECTORS: usize = 36;
 const STICK_HISTORY_SECTOR_DEGREE: usize = STICK_HISTORY_DEGREES / STICK_HISTORY_SECTORS;
 
+const TRIGGER_DEAD_ZONE_COLOR: Color32 = Color32::from_rgba_premultiplied(96, 0, 0, 96);
+const TRIGGER_THRESHOLD_COLOR: Color32 = Color32::YELLOW;
+
+pub const STICK_PLOT_POINT_RADIUS: f32 = 3f32;
+pub const STICK_PLOT_MAX_POINT_RADIUS: f32 = 12f32;
+// egui_plot line default
+pub const STICK_PLOT_LINE_WIDTH: f32 = 1.5f32;
+pub const STICK_PLOT_MAX_LINE_WIDTH: f32 = 6f32;
+
+const STICK_TRAIL_DEFAULT_LENGTH: usize = 32;
+const STICK_PLOT_LEGEND_SWATCH_RADIUS: f32 = 4f32;
+const STICK_TRAIL_MAX_LENGTH: usize = 256;
+
+const TOUCH_GESTURE_HISTORY_LENGTH: usize = 8;
+
+const SCREENSHOTS_DIR_NAME: &str = "screenshots";
+// Backends without screenshot support never reply
+const SCREENSHOT_TIMEOUT: Duration = Duration::from_secs(2);
+
 const BUTTON_SIZE: f32 = 32f32;
 const BUTTON_FONT_SIZE: f32 = 32f32;
+const TOUCHPAD_VIEW_WIDTH: f32 = 320f32;
+const TOUCH_POINT_RADIUS: f32 = 8f32;
 
 #[derive(Default)]
 pub struct Output {
     pub left_stick_history: StickHistory,
     pub right_stick_history: StickHistory,
+    pub left_stick_trail: StickTrail,
+    pub right_stick_trail: StickTrail,
+    pub stick_plot_options: StickPlotOptions,
+    pub touch_gesture: TouchGesture,
+    // Latest first
+    pub touch_gestures: VecDeque<Gesture>,
+    // Device isn't polled while paused, the last data stays on screen
+    pub paused: bool,
+    pub last_data: Option<Data>,
+    pub recording: Recording,
+    pub marker_label: String,
+    // Visualization shows the input with the remap applied
+    pub remap_preview: bool,
+    // Numbers instead of the plots and bars
+    pub table_view: bool,
+    pub calibration_compare: CalibrationCompare,
+    // Visible part of the panel in the last frame
+    pub panel_rect: Option<Rect>,
+    pub screenshot: Option<PendingScreenshot>,
+}
+
+// Requested from the viewport, the image arrives with the events of a later frame
+pub struct PendingScreenshot {
+    file: PathBuf,
+    // The whole window is saved when not known
+    region: Option<Rect>,
+    requested_at: Instant,
+}
+
+// Reported and uncalibrated plots of one stick, replace the plots of both sticks when shown
+pub struct CalibrationCompare {
+    pub shown: bool,
+    pub stick: Stick,
+    // Read when first shown, kept until the panel is closed
+    stick_center: Option<Result<StickCenterCalibration, String>>,
+}
+
+impl Default for CalibrationCompare {
+    fn default() -> Self {
+        Self {
+            shown: false,
+            stick: Stick::Left,
+            stick_center: None,
+        }
+    }
+}
+
+impl Output {
+    fn update(&mut self, data: Data) {
+        let left = data.left_stick_position();
+        let right = data.right_stick_position();
+        let trail_length = self.stick_plot_options.trail_length;
+        self.left_stick_history
+            .update(left.normalized_x(), left.normalized_y());
+        self.left_stick_trail
+            .push(left.normalized_x(), left.normalized_y(), trail_length);
+        self.right_stick_history
+            .update(right.normalized_x(), right.normalized_y());
+        self.right_stick_trail
+            .push(right.normalized_x(), right.normalized_y(), trail_length);
+        self.update_touch_gestures(&data);
+        self.last_data = Some(data);
+    }
+
+    fn update_touch_gestures(&mut self, data: &Data) {
+        if let Some(gesture) = self
+            .touch_gesture
+            .update(data.touch_packet_counter(), data.touch_points())
+        {
+            self.touch_gestures.push_front(gesture);
+            self.touch_gestures.truncate(TOUCH_GESTURE_HISTORY_LENGTH);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StickPlotOptions {
+    pub show_peaks: bool,
+    pub show_trail: bool,
+    pub trail_length: usize,
+    pub show_raw_values: bool,
+}
+
+impl Default for StickPlotOptions {
+    fn default() -> Self {
+        Self {
+            show_peaks: true,
+            show_trail: false,
+            trail_length: STICK_TRAIL_DEFAULT_LENGTH,
+            show_raw_values: false,
+        }
+    }
+}
+
+// Stick plot colors from the settings
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StickPlotColors {
+    pub position: [u8; 3],
+    pub peaks: [u8; 3],
+    pub trail: [u8; 3],
+}
+
+impl Default for StickPlotColors {
+    fn default() -> Self {
+        let rgb = |color: Color32| [color.r(), color.g(), color.b()];
+        Self {
+            position: rgb(Color32::RED),
+            peaks: rgb(Color32::LIGHT_YELLOW),
+            trail: rgb(Color32::RED),
+        }
+    }
+}
+
+// Stick plot sizes from the settings in points at 1x scale, the defaults are the egui_plot look
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StickPlotStyle {
+    pub point_radius: f32,
+    pub line_width: f32,
+}
+
+impl Default for StickPlotStyle {
+    fn default() -> Self {
+        Self {
+            point_radius: STICK_PLOT_POINT_RADIUS,
+            line_width: STICK_PLOT_LINE_WIDTH,
+        }
+    }
+}
+
+impl StickPlotStyle {
+    // Sizes grow with the pixels per point, so they stay visible on high-DPI displays
+    pub fn point_radius(&self, ui: &egui::Ui) -> f32 {
+        self.point_radius.clamp(1f32, STICK_PLOT_MAX_POINT_RADIUS) * ui.ctx().pixels_per_point()
+    }
+
+    pub fn line_width(&self, ui: &egui::Ui) -> f32 {
+        self.line_width.clamp(0.5f32, STICK_PLOT_MAX_LINE_WIDTH) * ui.ctx().pixels_per_point()
+    }
+
+    // Peaks and trail points keep their size relative to the position point
+    fn point_scale(&self, ui: &egui::Ui) -> f32 {
+        self.point_radius(ui) / STICK_PLOT_POINT_RADIUS
+    }
+}
+
+fn color(rgb: [u8; 3]) -> Color32 {
+    Color32::from_rgb(rgb[0], rgb[1], rgb[2])
 }
 
 #[derive(Debug)]
@@ -41,9 +229,8 @@ impl Default for StickHistory {
 
 impl StickHistory {
     pub fn update(&mut self, x: f64, y: f64) {
-        let distance = (x.powi(2) + y.powi(2)).sqrt();
-        let angle = ((y.atan2(x) + PI * 2f64).to_degrees() as usize).rem(STICK_HISTORY_DEGREES);
-        let sector = angle / STICK_HISTORY_SECTOR_DEGREE;
+        let (angle, distance) = polar(x, y);
+        let sector = (angle as usize % STICK_HISTORY_DEGREES) / STICK_HISTORY_SECTOR_DEGREE;
         self.max_distance[sector] = self.max_distance[sector].max(distance);
     }
 
@@ -68,14 +255,46 @@ impl StickHistory {
     }
 }
 
+#[derive(Debug, Default)]
+pub struct StickTrail {
+    positions: VecDeque<(f64, f64)>,
+}
+
+impl StickTrail {
+    pub fn push(&mut self, x: f64, y: f64, length: usize) {
+        self.positions.push_back((x, y));
+        while self.positions.len() > length {
+            self.positions.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.positions.clear();
+    }
+
+    pub fn positions(&self) -> impl ExactSizeIterator<Item = &(f64, f64)> {
+        self.positions.iter()
+    }
+}
+
 fn stick_plot<'a>(
     title: &'a str,
     stick_position: StickPosition,
-    stick_history: &'a mut StickHistory,
+    stick_history: &'a StickHistory,
+    stick_trail: &'a StickTrail,
+    options: &'a StickPlotOptions,
+    colors: StickPlotColors,
+    style: StickPlotStyle,
 ) -> impl egui::Widget + 'a {
     move |ui: &mut egui::Ui| {
         ui.label(title);
-        Plot::new(title)
+        let (angle, magnitude) = stick_position.polar();
+        let (radius, width, scale) = (
+            style.point_radius(ui),
+            style.line_width(ui),
+            style.point_scale(ui),
+        );
+        let response = Plot::new(title)
             .view_aspect(1f32)
             .include_x(-1.1f64)
             .include_x(1.1f64)
@@ -86,16 +305,185 @@ fn stick_plot<'a>(
             .allow_scroll(false)
             .show(ui, |plot_ui| {
                 let (x, y) = (stick_position.normalized_x(), stick_position.normalized_y());
-                let points = Points::new([x, y]).radius(3f32).color(Color32::RED);
-                stick_history.update(x, y);
-                plot_ui.line(circle_line(0f64, 0f64, 1f64).color(Color32::GRAY));
-                plot_ui.points(stick_history_peaks(&stick_history).color(Color32::LIGHT_YELLOW));
+                let points = Points::new([x, y])
+                    .radius(radius)
+                    .color(color(colors.position));
+                plot_ui.line(
+                    circle_line(0f64, 0f64, 1f64)
+                        .color(Color32::GRAY)
+                        .width(width),
+                );
+                if options.show_peaks {
+                    plot_ui.points(
+                        stick_history_peaks(&stick_history)
+                            .radius(scale)
+                            .color(color(colors.peaks)),
+                    );
+                }
+                if options.show_trail {
+                    for trail_points in stick_trail_points(stick_trail, color(colors.trail), scale)
+                    {
+                        plot_ui.points(trail_points);
+                    }
+                }
                 plot_ui.points(points);
             })
-            .response
+            .response;
+        let mut legend = vec![
+            (color(colors.position), t("output.legend_position")),
+            (Color32::GRAY, t("output.legend_range")),
+        ];
+        if options.show_peaks {
+            legend.push((color(colors.peaks), t("output.legend_peaks")));
+        }
+        if options.show_trail {
+            legend.push((color(colors.trail), t("output.legend_trail")));
+        }
+        stick_plot_legend(ui, &legend);
+        ui.label(format!(
+            "{}: {:>5.1}°  {}: {:.3}",
+            t("output.angle"),
+            angle,
+            t("output.magnitude"),
+            magnitude
+        ));
+        if options.show_raw_values {
+            ui.label(format!(
+                "{}: {:>3}  {}: {:>3}",
+                t("output.raw_x"),
+                stick_position.x,
+                t("output.raw_y"),
+                stick_position.y
+            ));
+        }
+        response
+    }
+}
+
+// Both plots show the same sample, the uncalibrated one moved back by the stored stick center
+fn calibration_compare(
+    ui: &mut egui::Ui,
+    ds4: &DualShock4,
+    compare: &mut CalibrationCompare,
+    data: &Data,
+    colors: StickPlotColors,
+    style: StickPlotStyle,
+) {
+    let stick_center = compare.stick_center.get_or_insert_with(|| {
+        ds4.read_flash_stick_center_calibration().map_err(|e| {
+            warn!("Cannot read the stick center calibration: {}", e);
+            e.to_string()
+        })
+    });
+    ui.horizontal(|ui| {
+        ui.selectable_value(
+            &mut compare.stick,
+            Stick::Left,
+            t("output.compare_left_stick"),
+        );
+        ui.selectable_value(
+            &mut compare.stick,
+            Stick::Right,
+            t("output.compare_right_stick"),
+        );
+    });
+    let position = match compare.stick {
+        Stick::Left => data.left_stick_position(),
+        Stick::Right => data.right_stick_position(),
+    };
+    ui.columns(2, |columns| {
+        columns[0].add(compare_stick_plot(
+            t("output.compare_reported"),
+            (position.normalized_x(), position.normalized_y()),
+            colors,
+            style,
+        ));
+        match stick_center {
+            Ok(calibration) => {
+                let (center_x, center_y) = match compare.stick {
+                    Stick::Left => (calibration.left_x(), calibration.left_y()),
+                    Stick::Right => (calibration.right_x(), calibration.right_y()),
+                };
+                columns[1].add(compare_stick_plot(
+                    t("output.compare_uncalibrated"),
+                    position.uncalibrated_normalized(center_x, center_y),
+                    colors,
+                    style,
+                ));
+                columns[1].label(tf("output.compare_stored_center", &[&center_x, &center_y]));
+            }
+            Err(e) => {
+                let ui = &mut columns[1];
+                ui.label(t("output.compare_uncalibrated"));
+                ui.label(RichText::new(e.as_str()).color(ui.visuals().warn_fg_color));
+            }
+        }
+    });
+}
+
+fn compare_stick_plot<'a>(
+    title: &'a str,
+    (x, y): (f64, f64),
+    colors: StickPlotColors,
+    style: StickPlotStyle,
+) -> impl egui::Widget + 'a {
+    move |ui: &mut egui::Ui| {
+        ui.label(title);
+        let (radius, width) = (style.point_radius(ui), style.line_width(ui));
+        let response = Plot::new(title)
+            .view_aspect(1f32)
+            .include_x(-1.1f64)
+            .include_x(1.1f64)
+            .include_y(-1.1f64)
+            .include_y(1.1f64)
+            .allow_zoom(false)
+            .allow_drag(false)
+            .allow_scroll(false)
+            .show(ui, |plot_ui| {
+                plot_ui.line(
+                    circle_line(0f64, 0f64, 1f64)
+                        .color(Color32::GRAY)
+                        .width(width),
+                );
+                plot_ui.points(
+                    Points::new([x, y])
+                        .radius(radius)
+                        .color(color(colors.position)),
+                );
+            })
+            .response;
+        stick_plot_legend(
+            ui,
+            &[
+                (color(colors.position), t("output.legend_position")),
+                (Color32::GRAY, t("output.legend_range")),
+            ],
+        );
+        let (angle, distance) = polar(x, y);
+        ui.label(format!(
+            "{}: {:>5.1}°  {}: {:.3}",
+            t("output.angle"),
+            angle,
+            t("output.magnitude"),
+            distance.min(1f64)
+        ));
+        response
     }
 }
 
+// Colors of the drawn stick plot items, only the shown ones are passed
+pub fn stick_plot_legend(ui: &mut egui::Ui, entries: &[(Color32, &str)]) {
+    ui.horizontal_wrapped(|ui| {
+        for (color, text) in entries {
+            let size = egui::Vec2::splat(STICK_PLOT_LEGEND_SWATCH_RADIUS * 2f32);
+            let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+            ui.painter()
+                .circle_filled(rect.center(), STICK_PLOT_LEGEND_SWATCH_RADIUS, *color);
+            ui.label(RichText::new(*text).small());
+        }
+    });
+}
+
 pub fn circle_line(x: f64, y: f64, r: f64) -> Line {
     let n = 512;
     let circle_points: PlotPoints = (0..=n)
@@ -113,6 +501,20 @@ fn stick_history_peaks(stick_history: &StickHistory) -> Points {
     Points::new(plot_points)
 }
 
+fn stick_trail_points(stick_trail: &StickTrail, color: Color32, scale: f32) -> Vec<Points> {
+    let len = stick_trail.positions().len();
+    stick_trail
+        .positions()
+        .enumerate()
+        .map(|(i, (x, y))| {
+            let opacity = (i + 1) as f32 / (len + 1) as f32;
+            Points::new([*x, *y])
+                .radius(2f32 * scale)
+                .color(color.gamma_multiply(opacity))
+        })
+        .collect()
+}
+
 pub fn output(
     ui: &mut egui::Ui,
     ctx: &egui::Context,
@@ -120,107 +522,733 @@ pub fn output(
     sh: StatusHandler,
 ) {
     let ConnectedDevice::DualShock4(_, ds4) = &state.device;
-    let data = sh
-        .handle_error(ds4.read_last_data())
-        .flatten()
-        .unwrap_or(Data::zeroed());
-
-    ctx.request_repaint();
     if let Panel::Output(output) = &mut state.panel {
-        ui.columns(2, |columns| {
-            columns[0].add(stick_plot(
-                "Left stick plot",
-                data.left_stick_position(),
-                &mut output.left_stick_history,
-            ));
-            columns[1].add(stick_plot(
-                "Right stick plot",
-                data.right_stick_position(),
-                &mut output.right_stick_history,
-            ));
-            if columns[0].button("Clear history").clicked() {
-                output.left_stick_history.clear();
+        if !output.paused {
+            ctx.request_repaint();
+            // Zeroed data would look like a finger in the touchpad corner
+            if let Some(data) = sh.handle_error(ds4.read_last_data()).flatten() {
+                output.recording.push(Instant::now(), &data);
+                output.update(data);
             }
-            if columns[1].button("Clear history").clicked() {
-                output.right_stick_history.clear();
-            }
-        });
-        ui.separator();
-        ui.columns(2, |columns| {
-            columns[0].add(trigger_bar(data.l2_trigger(), "Left Trigger"));
-            columns[1].add(trigger_bar(data.r2_trigger(), "Right Trigger"));
+        }
+        ui.horizontal(|ui| {
+            pause_toggle(ui, &mut output.paused);
+            ui.toggle_value(&mut output.table_view, t("output.table_view"))
+                .on_hover_text(t("help.table_view"));
+            ui.toggle_value(
+                &mut output.calibration_compare.shown,
+                t("output.calibration_compare"),
+            )
+            .on_hover_text(t("help.calibration_compare"));
         });
-        ui.columns(3, |columns| {
-            columns[0].horizontal(|ui| {
-                ui.add(gamepad_button_label(data.l1(), GAMEPAD_FONT_L1));
-                ui.add(gamepad_button_label(data.l2(), GAMEPAD_FONT_L2));
-                ui.add(gamepad_button_label(data.l3(), GAMEPAD_FONT_LEFT_ANALOG));
+        recording_controls(ui, ctx, output, sh.clone());
+        data_log_controls(ui, ds4, sh.clone());
+        screenshot_button(
+            ui,
+            ctx,
+            output,
+            &state.data_dir,
+            &state.device_key,
+            sh.clone(),
+        );
+        remap_preview(ui, &mut output.remap_preview, &mut state.remap);
+        let data = output.last_data.clone().unwrap_or(Data::zeroed());
+        let data = if output.remap_preview {
+            state.remap.apply(&data)
+        } else {
+            data
+        };
+        copy_input_button(ui, output.last_data.as_ref().map(|_| &data), sh.clone());
+        if output.table_view {
+            data_table(ui, &data, output.last_data.is_some());
+        } else {
+            if output.calibration_compare.shown {
+                calibration_compare(
+                    ui,
+                    ds4,
+                    &mut output.calibration_compare,
+                    &data,
+                    state.stick_plot_colors,
+                    state.stick_plot_style,
+                );
+            } else {
+                ui.columns(2, |columns| {
+                    columns[0].add(stick_plot(
+                        t("output.left_stick_plot"),
+                        data.left_stick_position(),
+                        &output.left_stick_history,
+                        &output.left_stick_trail,
+                        &output.stick_plot_options,
+                        state.stick_plot_colors,
+                        state.stick_plot_style,
+                    ));
+                    columns[1].add(stick_plot(
+                        t("output.right_stick_plot"),
+                        data.right_stick_position(),
+                        &output.right_stick_history,
+                        &output.right_stick_trail,
+                        &output.stick_plot_options,
+                        state.stick_plot_colors,
+                        state.stick_plot_style,
+                    ));
+                    if columns[0].button(t("output.clear_history")).clicked() {
+                        output.left_stick_history.clear();
+                        output.left_stick_trail.clear();
+                    }
+                    if columns[1].button(t("output.clear_history")).clicked() {
+                        output.right_stick_history.clear();
+                        output.right_stick_trail.clear();
+                    }
+                });
+                stick_plot_options_form(ui, &mut output.stick_plot_options);
+            }
+            ui.separator();
+            let [left_trigger, right_trigger] = state.trigger_thresholds;
+            ui.columns(2, |columns| {
+                columns[0].add(trigger_bar(
+                    data.l2_trigger(),
+                    left_trigger,
+                    t("common.left_trigger"),
+                ));
+                columns[1].add(trigger_bar(
+                    data.r2_trigger(),
+                    right_trigger,
+                    t("common.right_trigger"),
+                ));
             });
-            columns[1].horizontal(|ui| {
-                ui.add(gamepad_button_label(data.share(), GAMEPAD_FONT_SHARE));
-                ui.add(gamepad_button_label(data.ps(), GAMEPAD_FONT_PS));
-                ui.add(gamepad_button_label(data.t_pad_click(), GAMEPAD_FONT_T_PAD));
-                ui.add(gamepad_button_label(data.options(), GAMEPAD_FONT_OPTIONS));
+            gamepad_buttons(ui, &data);
+            ui.columns(3, |columns| {
+                columns[0].add(gyroscope_accelerometer_bar(
+                    data.gyroscope_x(),
+                    t("output.gyroscope_x"),
+                ));
+                columns[1].add(gyroscope_accelerometer_bar(
+                    data.gyroscope_y(),
+                    t("output.gyroscope_y"),
+                ));
+                columns[2].add(gyroscope_accelerometer_bar(
+                    data.gyroscope_z(),
+                    t("output.gyroscope_z"),
+                ));
+                columns[0].add(gyroscope_accelerometer_bar(
+                    data.accelerometer_x(),
+                    t("output.accelerometer_x"),
+                ));
+                columns[1].add(gyroscope_accelerometer_bar(
+                    data.accelerometer_y(),
+                    t("output.accelerometer_y"),
+                ));
+                columns[2].add(gyroscope_accelerometer_bar(
+                    data.accelerometer_z(),
+                    t("output.accelerometer_z"),
+                ));
             });
-            columns[2].horizontal(|ui| {
-                ui.add(gamepad_button_label(data.r3(), GAMEPAD_FONT_RIGHT_ANALOG));
-                ui.add(gamepad_button_label(data.r2(), GAMEPAD_FONT_R2));
-                ui.add(gamepad_button_label(data.r1(), GAMEPAD_FONT_R1));
+            // Zeroed data would look like a finger in the touchpad corner
+            let touch_points = match &output.last_data {
+                Some(_) => data.touch_points(),
+                None => [None, None],
+            };
+            ui.add(touchpad_view(data.t_pad_click(), touch_points));
+            touch_gestures(ui, output);
+            ui.horizontal(|ui| {
+                ui.label(format!("{}: {}", t("output.battery"), data.battery()));
+                ui.label(format!("{}: {}", t("output.counter"), data.counter()));
+                ui.label(format!("{}: {}", t("output.timestamp"), data.timestamp()));
+                let packet_loss = ds4.packet_loss();
+                ui.label(tf(
+                    "output.dropped_reports",
+                    &[
+                        &packet_loss.lost(),
+                        &(packet_loss.lost() + packet_loss.received()),
+                        &format!("{:.2}", packet_loss.loss_percent()),
+                    ],
+                ))
+                .on_hover_text(t("help.dropped_reports"));
             });
-            columns[0].add(d_pad_label(data.d_pad()));
-            egui::Grid::new("Buttons")
-                .num_columns(3)
-                .max_col_width(BUTTON_SIZE)
-                .min_col_width(BUTTON_SIZE)
-                .min_row_height(BUTTON_SIZE)
-                .show(&mut columns[2], |ui| {
-                    ui.label("");
-                    ui.add(gamepad_button_label(data.triangle(), GAMEPAD_FONT_TRIANGLE));
-                    ui.end_row();
-                    ui.add(gamepad_button_label(data.square(), GAMEPAD_FONT_SQUARE));
-                    ui.label("");
-                    ui.add(gamepad_button_label(data.circle(), GAMEPAD_FONT_CIRCLE));
+        }
+        output.panel_rect = Some(ui.min_rect().intersect(ui.clip_rect()));
+        receive_screenshot(ctx, output, sh);
+    } else {
+        ui.label(t("output.unsupported_device"));
+    }
+}
+
+// Serial number or path of the device, with the characters not allowed in file names replaced
+fn screenshot_file_name(device_key: &str, now: DateTime<Local>) -> String {
+    let device: String = device_key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("ds4_{}_{}.png", device, now.format("%Y%m%d_%H%M%S"))
+}
+
+fn screenshot_button(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    output: &mut Output,
+    data_dir: &Path,
+    device_key: &str,
+    sh: StatusHandler,
+) {
+    if !ui
+        .add_enabled(
+            output.screenshot.is_none(),
+            egui::Button::new(t("output.save_screenshot")),
+        )
+        .on_hover_text(t("help.save_screenshot"))
+        .clicked()
+    {
+        return;
+    }
+    let dir = data_dir.join(SCREENSHOTS_DIR_NAME);
+    let _ = sh.handle_error(fs::create_dir_all(&dir));
+    if let Some(file) = rfd::FileDialog::new()
+        .add_filter("png", &["png"])
+        .set_directory(&dir)
+        .set_file_name(screenshot_file_name(device_key, Local::now()))
+        .save_file()
+    {
+        ctx.send_viewport_cmd(egui::ViewportCommand::Screenshot);
+        output.screenshot = Some(PendingScreenshot {
+            file,
+            region: output.panel_rect,
+            requested_at: Instant::now(),
+        });
+    }
+}
+
+fn receive_screenshot(ctx: &egui::Context, output: &mut Output, sh: StatusHandler) {
+    let Some(pending) = output.screenshot.take() else {
+        return;
+    };
+    let image = ctx.input(|input| {
+        input.events.iter().find_map(|event| match event {
+            egui::Event::Screenshot { image, .. } => Some(image.clone()),
+            _ => None,
+        })
+    });
+    match image {
+        Some(image) => {
+            let pixels_per_point = ctx.pixels_per_point();
+            let result = save_screenshot(&image, pending.region, pixels_per_point, &pending.file);
+            if sh.handle_error(result).is_some() {
+                sh.message(tf("output.screenshot_saved", &[&pending.file.display()]));
+            }
+        }
+        None if pending.requested_at.elapsed() > SCREENSHOT_TIMEOUT => {
+            sh.error(t("output.screenshot_failed").into());
+        }
+        None => {
+            ctx.request_repaint();
+            output.screenshot = Some(pending);
+        }
+    }
+}
+
+// Region is in points and cropped to the window, the image is in pixels
+fn save_screenshot(
+    image: &ColorImage,
+    region: Option<Rect>,
+    pixels_per_point: f32,
+    file: &Path,
+) -> crate::application::Result<()> {
+    let window = Rect::from_min_size(
+        Pos2::ZERO,
+        egui::vec2(image.width() as f32, image.height() as f32) / pixels_per_point,
+    );
+    let image = match region.map(|region| region.intersect(window)) {
+        Some(region) if region.is_positive() => image.region(&region, Some(pixels_per_point)),
+        _ => image.clone(),
+    };
+    let context = || format!("Cannot write the screenshot {:?}", file);
+    let writer = BufWriter::new(File::create(file).map_err(|e| Error::Io(context(), e))?);
+    let mut encoder = png::Encoder::new(writer, image.width() as u32, image.height() as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(image.as_raw()))
+        .map_err(|e| Error::Io(context(), e.into()))
+}
+
+// One row per decoded field in a fixed order, so the values stay in place while they change
+fn data_table(ui: &mut egui::Ui, data: &Data, received: bool) {
+    let snapshot = data.snapshot();
+    let buttons = &snapshot.buttons;
+    let stick = |stick: &StickPosition| {
+        [
+            (stick.x.to_string(), format!("{:+.3}", stick.normalized_x())),
+            (stick.y.to_string(), format!("{:+.3}", stick.normalized_y())),
+        ]
+    };
+    let trigger = |value: u8| {
+        (
+            value.to_string(),
+            format!("{:.3}", f64::from(value) / 255f64),
+        )
+    };
+    let raw = |value: String| (value, String::new());
+    let flag = |pressed: bool| raw(u8::from(pressed).to_string());
+    let [left_x, left_y] = stick(&snapshot.left_stick);
+    let [right_x, right_y] = stick(&snapshot.right_stick);
+    let mut rows = vec![
+        ("left_stick.x", left_x),
+        ("left_stick.y", left_y),
+        ("right_stick.x", right_x),
+        ("right_stick.y", right_y),
+        ("l2_trigger", trigger(snapshot.l2_trigger)),
+        ("r2_trigger", trigger(snapshot.r2_trigger)),
+        ("d_pad", raw(format!("{:?}", buttons.d_pad))),
+        ("triangle", flag(buttons.triangle)),
+        ("circle", flag(buttons.circle)),
+        ("cross", flag(buttons.cross)),
+        ("square", flag(buttons.square)),
+        ("l1", flag(buttons.l1)),
+        ("r1", flag(buttons.r1)),
+        ("l2", flag(buttons.l2)),
+        ("r2", flag(buttons.r2)),
+        ("l3", flag(buttons.l3)),
+        ("r3", flag(buttons.r3)),
+        ("share", flag(buttons.share)),
+        ("options", flag(buttons.options)),
+        ("ps", flag(buttons.ps)),
+        ("touchpad", flag(buttons.touchpad)),
+    ];
+    for (name, values) in [
+        (
+            ["gyroscope.x", "gyroscope.y", "gyroscope.z"],
+            snapshot.gyroscope,
+        ),
+        (
+            ["accelerometer.x", "accelerometer.y", "accelerometer.z"],
+            snapshot.accelerometer,
+        ),
+    ] {
+        rows.extend(name.into_iter().zip(values.map(|v| raw(v.to_string()))));
+    }
+    rows.extend([
+        (
+            "battery",
+            (
+                data.battery().to_string(),
+                format!("{}%", snapshot.battery_percent),
+            ),
+        ),
+        ("cable_connected", flag(snapshot.cable_connected)),
+        ("counter", raw(snapshot.counter.to_string())),
+        ("timestamp", raw(snapshot.timestamp.to_string())),
+        (
+            "touch_packet_counter",
+            raw(snapshot.touch_packet_counter.to_string()),
+        ),
+    ]);
+    // Zeroed data would look like a finger in the touchpad corner
+    for (name, point) in ["touch_point_1", "touch_point_2"]
+        .into_iter()
+        .zip(snapshot.touch_points)
+    {
+        let value = match point.filter(|_| received) {
+            Some(point) => format!("{} ({}, {})", point.id, point.x, point.y),
+            None => "-".to_string(),
+        };
+        rows.push((name, raw(value)));
+    }
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        egui::Grid::new("Data table")
+            .num_columns(3)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong(t("output.table_field"));
+                ui.strong(t("output.table_raw"));
+                ui.strong(t("output.table_normalized"));
+                ui.end_row();
+                for (name, (raw, normalized)) in rows {
+                    ui.monospace(name);
+                    ui.monospace(raw);
+                    ui.monospace(normalized);
                     ui.end_row();
-                    ui.label("");
-                    ui.add(gamepad_button_label(data.cross(), GAMEPAD_FONT_CROSS));
-                });
+                }
+            });
+    });
+}
+
+fn gamepad_buttons(ui: &mut egui::Ui, data: &Data) {
+    ui.columns(3, |columns| {
+        columns[0].horizontal(|ui| {
+            ui.add(gamepad_button_label(data.l1(), GAMEPAD_FONT_L1));
+            ui.add(gamepad_button_label(data.l2(), GAMEPAD_FONT_L2));
+            ui.add(gamepad_button_label(data.l3(), GAMEPAD_FONT_LEFT_ANALOG));
         });
-        ui.columns(3, |columns| {
-            columns[0].add(gyroscope_accelerometer_bar(
-                data.gyroscope_x(),
-                "Gyroscope X",
-            ));
-            columns[1].add(gyroscope_accelerometer_bar(
-                data.gyroscope_y(),
-                "Gyroscope Y",
-            ));
-            columns[2].add(gyroscope_accelerometer_bar(
-                data.gyroscope_z(),
-                "Gyroscope Z",
-            ));
-            columns[0].add(gyroscope_accelerometer_bar(
-                data.accelerometer_x(),
-                "Accelerometer X",
-            ));
-            columns[1].add(gyroscope_accelerometer_bar(
-                data.accelerometer_y(),
-                "Accelerometer Y",
-            ));
-            columns[2].add(gyroscope_accelerometer_bar(
-                data.accelerometer_z(),
-                "Accelerometer Z",
-            ));
+        columns[1].horizontal(|ui| {
+            ui.add(gamepad_button_label(data.share(), GAMEPAD_FONT_SHARE));
+            ui.add(gamepad_button_label(data.ps(), GAMEPAD_FONT_PS));
+            ui.add(gamepad_button_label(data.t_pad_click(), GAMEPAD_FONT_T_PAD));
+            ui.add(gamepad_button_label(data.options(), GAMEPAD_FONT_OPTIONS));
         });
-        ui.horizontal(|ui| {
-            ui.label(format!("Battery: {}", data.battery()));
-            ui.label(format!("Counter: {}", data.counter()));
-            ui.label(format!("Timestamp: {}", data.timestamp()));
+        columns[2].horizontal(|ui| {
+            ui.add(gamepad_button_label(data.r3(), GAMEPAD_FONT_RIGHT_ANALOG));
+            ui.add(gamepad_button_label(data.r2(), GAMEPAD_FONT_R2));
+            ui.add(gamepad_button_label(data.r1(), GAMEPAD_FONT_R1));
         });
-    } else {
-        ui.label("Unsupported device");
+        columns[0].add(d_pad_label(data.d_pad()));
+        egui::Grid::new("Buttons")
+            .num_columns(3)
+            .max_col_width(BUTTON_SIZE)
+            .min_col_width(BUTTON_SIZE)
+            .min_row_height(BUTTON_SIZE)
+            .show(&mut columns[2], |ui| {
+                ui.label("");
+                ui.add(gamepad_button_label(data.triangle(), GAMEPAD_FONT_TRIANGLE));
+                ui.end_row();
+                ui.add(gamepad_button_label(data.square(), GAMEPAD_FONT_SQUARE));
+                ui.label("");
+                ui.add(gamepad_button_label(data.circle(), GAMEPAD_FONT_CIRCLE));
+                ui.end_row();
+                ui.label("");
+                ui.add(gamepad_button_label(data.cross(), GAMEPAD_FONT_CROSS));
+            });
+    });
+}
+
+// Compact view for window capture, reads the same latest data as the Output panel
+pub fn overlay(ui: &mut egui::Ui, ctx: &egui::Context, state: &DeviceConnected, sh: StatusHandler) {
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    ctx.request_repaint();
+    let data = sh
+        .handle_error(ds4.read_last_data())
+        .flatten()
+        .unwrap_or(Data::zeroed());
+    ui.columns(2, |columns| {
+        let position = color(state.stick_plot_colors.position);
+        columns[0].add(overlay_stick(
+            "Overlay left stick",
+            data.left_stick_position(),
+            position,
+            state.stick_plot_style,
+        ));
+        columns[1].add(overlay_stick(
+            "Overlay right stick",
+            data.right_stick_position(),
+            position,
+            state.stick_plot_style,
+        ));
+        let [left_trigger, right_trigger] = state.trigger_thresholds;
+        columns[0].add(trigger_bar(
+            data.l2_trigger(),
+            left_trigger,
+            t("common.left_trigger"),
+        ));
+        columns[1].add(trigger_bar(
+            data.r2_trigger(),
+            right_trigger,
+            t("common.right_trigger"),
+        ));
+    });
+    gamepad_buttons(ui, &data);
+}
+
+fn overlay_stick<'a>(
+    id: &'a str,
+    stick_position: StickPosition,
+    color: Color32,
+    style: StickPlotStyle,
+) -> impl egui::Widget + 'a {
+    move |ui: &mut egui::Ui| {
+        let (scale, width) = (style.point_scale(ui), style.line_width(ui));
+        Plot::new(id)
+            .view_aspect(1f32)
+            .include_x(-1.1f64)
+            .include_x(1.1f64)
+            .include_y(-1.1f64)
+            .include_y(1.1f64)
+            .show_axes(false)
+            .show_grid(false)
+            .show_background(false)
+            .show_x(false)
+            .show_y(false)
+            .allow_zoom(false)
+            .allow_drag(false)
+            .allow_scroll(false)
+            .allow_boxed_zoom(false)
+            .show(ui, |plot_ui| {
+                let (x, y) = (stick_position.normalized_x(), stick_position.normalized_y());
+                plot_ui.line(
+                    circle_line(0f64, 0f64, 1f64)
+                        .color(Color32::GRAY)
+                        .width(width),
+                );
+                plot_ui.points(Points::new([x, y]).radius(6f32 * scale).color(color));
+            })
+            .response
     }
 }
 
+fn pause_toggle(ui: &mut egui::Ui, paused: &mut bool) {
+    ui.horizontal(|ui| {
+        ui.toggle_value(paused, t("output.pause"));
+        if *paused {
+            ui.label(
+                RichText::new(t("output.paused"))
+                    .strong()
+                    .color(ui.visuals().warn_fg_color),
+            );
+        }
+    });
+}
+
+// Copies the shown frame, so it's the paused one or the remapped one in the preview
+fn copy_input_button(ui: &mut egui::Ui, data: Option<&Data>, sh: StatusHandler) {
+    if ui
+        .add_enabled(data.is_some(), egui::Button::new(t("output.copy_input")))
+        .on_hover_text(t("help.copy_input"))
+        .clicked()
+    {
+        let Some(data) = data else {
+            return;
+        };
+        if let Some(json) = sh.handle_error(serde_json::to_string_pretty(&data.snapshot())) {
+            ui.ctx().copy_text(json);
+            sh.message(t("output.input_copied"));
+        }
+    }
+}
+
+fn remap_preview(ui: &mut egui::Ui, preview: &mut bool, remap: &mut Remap) {
+    ui.horizontal(|ui| {
+        ui.toggle_value(preview, t("output.remap_preview"))
+            .on_hover_text(t("help.remap_preview"));
+        if !*preview {
+            return;
+        }
+        ui.label(
+            RichText::new(t("output.remap_preview_only"))
+                .strong()
+                .color(ui.visuals().warn_fg_color),
+        );
+        ui.menu_button(t("output.add_remap_rule"), |ui| {
+            for rule in RemapRule::ALL {
+                if ui.button(rule.to_string()).clicked() {
+                    remap.rules.push(rule);
+                    ui.close_menu();
+                }
+            }
+        });
+    });
+    if *preview {
+        ui.horizontal_wrapped(|ui| {
+            let mut removed = None;
+            for (index, rule) in remap.rules.iter().enumerate() {
+                if ui
+                    .button(format!("{} ✖", rule))
+                    .on_hover_text(t("help.remove_remap_rule"))
+                    .clicked()
+                {
+                    removed = Some(index);
+                }
+            }
+            if let Some(index) = removed {
+                remap.rules.remove(index);
+            }
+        });
+    }
+}
+
+fn recording_controls(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    output: &mut Output,
+    sh: StatusHandler,
+) {
+    let recording = &mut output.recording;
+    ui.horizontal(|ui| {
+        if recording.is_active() {
+            if ui.button(t("output.stop_recording")).clicked() {
+                recording.stop();
+            }
+            ui.text_edit_singleline(&mut output.marker_label)
+                .on_hover_text(t("help.marker_label"));
+            // The key is ignored while typing the label
+            let marker_key = ctx.memory(|memory| memory.focused().is_none())
+                && ctx.input(|input| input.key_pressed(egui::Key::M));
+            if ui
+                .button(t("output.add_marker"))
+                .on_hover_text(t("help.add_marker"))
+                .clicked()
+                || marker_key
+            {
+                recording.add_marker(Instant::now(), &output.marker_label);
+            }
+            ui.label(
+                RichText::new(tf(
+                    "output.recording",
+                    &[&recording.frames.len(), &recording.markers.len()],
+                ))
+                .strong()
+                .color(ui.visuals().warn_fg_color),
+            );
+        } else {
+            if ui
+                .button(t("output.start_recording"))
+                .on_hover_text(t("help.start_recording"))
+                .clicked()
+            {
+                recording.start(Instant::now());
+            }
+            if !recording.is_empty() {
+                ui.label(tf(
+                    "output.recorded",
+                    &[&recording.frames.len(), &recording.markers.len()],
+                ));
+                if ui.button(t("output.export_csv")).clicked() {
+                    export_recording(recording_file("csv"), || Ok(recording.to_csv()), &sh);
+                }
+                if ui.button(t("output.export_json")).clicked() {
+                    export_recording(recording_file("json"), || recording.to_json(), &sh);
+                }
+            }
+        }
+    });
+}
+
+// Every input report at the native rate, written by the device connection rather than the UI
+fn data_log_controls(ui: &mut egui::Ui, ds4: &DualShock4, sh: StatusHandler) {
+    ui.horizontal(|ui| match ds4.data_log_written() {
+        Some(written) => {
+            if ui.button(t("output.stop_data_log")).clicked() {
+                if let Some(frames) = ds4
+                    .stop_data_log()
+                    .and_then(|result| sh.handle_error(result))
+                {
+                    sh.message(tf("output.data_log_saved", &[&frames]));
+                }
+            }
+            ui.label(
+                RichText::new(tf("output.data_log_recording", &[&written]))
+                    .strong()
+                    .color(ui.visuals().warn_fg_color),
+            );
+        }
+        None => {
+            if ui
+                .button(t("output.start_data_log"))
+                .on_hover_text(t("help.data_log"))
+                .clicked()
+            {
+                if let Some(file) = rfd::FileDialog::new()
+                    .add_filter(DATA_LOG_FILE_EXTENSION, &[DATA_LOG_FILE_EXTENSION])
+                    .set_file_name(format!("ds4_data.{}", DATA_LOG_FILE_EXTENSION))
+                    .save_file()
+                {
+                    let _ = sh.handle_error(
+                        File::create(&file).and_then(|file| ds4.start_data_log(file)),
+                    );
+                }
+            }
+        }
+    });
+}
+
+fn recording_file(extension: &str) -> Option<std::path::PathBuf> {
+    rfd::FileDialog::new()
+        .add_filter(extension, &[extension])
+        .set_file_name(format!("ds4_recording.{}", extension))
+        .save_file()
+}
+
+fn export_recording(
+    file: Option<std::path::PathBuf>,
+    contents: impl FnOnce() -> serde_json::Result<String>,
+    sh: &StatusHandler,
+) {
+    if let Some(file) = file {
+        if let Some(contents) = sh.handle_error(contents()) {
+            sh.handle_error(fs::write(file, contents));
+        }
+    }
+}
+
+// The click and the touches are separate inputs, a click may come without a touch
+fn touchpad_view(clicked: bool, touch_points: [Option<TouchPoint>; 2]) -> impl egui::Widget {
+    move |ui: &mut egui::Ui| {
+        let size = egui::vec2(
+            TOUCHPAD_VIEW_WIDTH,
+            TOUCHPAD_VIEW_WIDTH * TOUCHPAD_HEIGHT as f32 / TOUCHPAD_WIDTH as f32,
+        );
+        let (rect, response) = ui.allocate_exact_size(size, egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        let visuals = ui.visuals();
+        let (fill, stroke) = if clicked {
+            (
+                Color32::GREEN.gamma_multiply(0.2),
+                egui::Stroke::new(3f32, Color32::GREEN),
+            )
+        } else {
+            (
+                visuals.extreme_bg_color,
+                visuals.widgets.noninteractive.bg_stroke,
+            )
+        };
+        painter.rect(rect.shrink(1f32), 4f32, fill, stroke);
+        for point in touch_points.iter().flatten() {
+            let center = rect.min
+                + egui::vec2(
+                    point.x as f32 / TOUCHPAD_WIDTH as f32 * rect.width(),
+                    point.y as f32 / TOUCHPAD_HEIGHT as f32 * rect.height(),
+                );
+            painter.circle_filled(center, TOUCH_POINT_RADIUS, Color32::RED);
+            painter.text(
+                center,
+                egui::Align2::CENTER_CENTER,
+                point.id.to_string(),
+                egui::FontId::monospace(TOUCH_POINT_RADIUS * 1.5),
+                Color32::WHITE,
+            );
+        }
+        response.on_hover_text(t("help.touchpad_view"))
+    }
+}
+
+fn touch_gestures(ui: &mut egui::Ui, output: &mut Output) {
+    ui.horizontal(|ui| {
+        ui.label(format!("{}:", t("output.touchpad_gestures")));
+        for gesture in &output.touch_gestures {
+            ui.label(gesture_text(gesture));
+        }
+        if !output.touch_gestures.is_empty() && ui.button(t("output.clear_history")).clicked() {
+            output.touch_gestures.clear();
+        }
+    });
+}
+
+fn gesture_text(gesture: &Gesture) -> String {
+    match gesture {
+        Gesture::Tap { fingers } => tf("output.touch_tap", &[fingers]),
+        Gesture::Swipe { direction, fingers } => {
+            let direction = match direction {
+                SwipeDirection::Left => "⬅",
+                SwipeDirection::Right => "➡",
+                SwipeDirection::Up => "⬆",
+                SwipeDirection::Down => "⬇",
+            };
+            tf("output.touch_swipe", &[&direction, fingers])
+        }
+    }
+}
+
+fn stick_plot_options_form(ui: &mut egui::Ui, options: &mut StickPlotOptions) {
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut options.show_peaks, t("output.show_peaks"));
+        ui.checkbox(&mut options.show_trail, t("output.show_trail"));
+        ui.add_enabled(
+            options.show_trail,
+            egui::DragValue::new(&mut options.trail_length)
+                .range(1..=STICK_TRAIL_MAX_LENGTH)
+                .prefix(format!("{}: ", t("output.trail_length"))),
+        );
+        ui.checkbox(&mut options.show_raw_values, t("output.show_raw_values"));
+    });
+}
+
 pub fn d_pad_label<'a>(state: DPadState) -> impl egui::Widget + 'a {
     move |ui: &mut egui::Ui| {
         if state == DPadState::Released {
@@ -245,23 +1273,84 @@ pub fn d_pad_label<'a>(state: DPadState) -> impl egui::Widget + 'a {
     }
 }
 
-pub fn gyroscope_accelerometer_bar(value: i16, text: impl Into<WidgetText>) -> impl egui::Widget {
+// Signed value filled from the center, i16::MIN reaches the left edge and i16::MAX the right one
+pub fn gyroscope_accelerometer_bar(value: i16, text: &str) -> impl egui::Widget + '_ {
     move |ui: &mut egui::Ui| {
-        ui.add(
-            egui::ProgressBar::new(value as f32 / i16::MAX as f32 + 0.5f32)
-                .show_percentage()
-                .text(text),
-        )
+        let desired_size = egui::vec2(ui.available_width(), ui.spacing().interact_size.y);
+        let (rect, response) = ui.allocate_exact_size(desired_size, egui::Sense::hover());
+        if !ui.is_rect_visible(rect) {
+            return response;
+        }
+        let visuals = ui.visuals();
+        let painter = ui.painter_at(rect);
+        let rounding = rect.height() / 2f32;
+        painter.rect_filled(rect, rounding, visuals.extreme_bg_color);
+        let center = rect.center().x;
+        let end = center + rect.width() / 2f32 * f32::from(value) / -f32::from(i16::MIN);
+        painter.rect_filled(
+            Rect::from_x_y_ranges(center.min(end)..=center.max(end), rect.y_range()),
+            0f32,
+            visuals.selection.bg_fill,
+        );
+        painter.vline(
+            center,
+            rect.y_range(),
+            visuals.widgets.noninteractive.fg_stroke,
+        );
+        painter.text(
+            rect.center(),
+            egui::Align2::CENTER_CENTER,
+            format!("{}: {}", text, value),
+            egui::TextStyle::Button.resolve(ui.style()),
+            visuals.strong_text_color(),
+        );
+        response
     }
 }
 
-pub fn trigger_bar(value: u8, text: impl Into<WidgetText>) -> impl egui::Widget {
+// Raw value with the calibrated thresholds drawn over it when they are known
+pub fn trigger_bar(
+    value: u8,
+    thresholds: Option<TriggerThresholds>,
+    text: &str,
+) -> impl egui::Widget + '_ {
     move |ui: &mut egui::Ui| {
-        ui.add(
-            egui::ProgressBar::new(value as f32 / 255f32)
-                .show_percentage()
-                .text(text),
-        )
+        let raw = (f32::from(value) / 2.55).round();
+        let text = match thresholds.and_then(|thresholds| thresholds.calibrated(value)) {
+            Some(calibrated) => tf(
+                "output.trigger_calibrated",
+                &[&text, &raw, &(calibrated * 100f32).round()],
+            ),
+            None => tf("output.trigger_raw", &[&text, &raw]),
+        };
+        let response = ui.add(egui::ProgressBar::new(value as f32 / 255f32).text(text));
+        let Some(thresholds) = thresholds else {
+            return response;
+        };
+        let rect = response.rect;
+        let x = |value: u8| rect.left() + rect.width() * f32::from(value) / 255f32;
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(
+            Rect::from_x_y_ranges(x(thresholds.min)..=x(thresholds.dead_zone), rect.y_range()),
+            0f32,
+            TRIGGER_DEAD_ZONE_COLOR,
+        );
+        for value in [thresholds.dead_zone, thresholds.max] {
+            painter.vline(
+                x(value),
+                rect.y_range(),
+                Stroke::new(2f32, TRIGGER_THRESHOLD_COLOR),
+            );
+        }
+        response.on_hover_text(tf(
+            "help.trigger_thresholds",
+            &[
+                &thresholds.min,
+                &thresholds.dead_zone,
+                &thresholds.dead_zone,
+                &thresholds.max,
+            ],
+        ))
     }
 }
 
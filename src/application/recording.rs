@@ -0,0 +1,228 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::dual_shock_4::Data;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::time::Instant;
+
+// About 10 minutes of USB input
+const MAX_RECORDING_FRAMES: usize = 150_000;
+
+const CSV_HEADER: &str = "kind,elapsed_us,counter,left_x,left_y,right_x,right_y,l2,r2,label";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    // Monotonic time since the recording start
+    pub elapsed_us: u64,
+    pub counter: u8,
+    pub left_x: u8,
+    pub left_y: u8,
+    pub right_x: u8,
+    pub right_y: u8,
+    pub l2: u8,
+    pub r2: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Marker {
+    pub elapsed_us: u64,
+    pub label: String,
+}
+
+// Input frames of the Output panel with user markers on the same clock
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Recording {
+    #[serde(skip)]
+    started: Option<Instant>,
+    pub frames: Vec<RecordedFrame>,
+    pub markers: Vec<Marker>,
+}
+
+impl Recording {
+    pub fn is_active(&self) -> bool {
+        self.started.is_some()
+    }
+
+    // Previous frames and markers are dropped
+    pub fn start(&mut self, now: Instant) {
+        *self = Self {
+            started: Some(now),
+            ..Self::default()
+        };
+    }
+
+    pub fn stop(&mut self) {
+        self.started = None;
+    }
+
+    // The same report is returned until the device sends a new one, so repeats are skipped
+    pub fn push(&mut self, now: Instant, data: &Data) {
+        let Some(elapsed_us) = self.elapsed_us(now) else {
+            return;
+        };
+        if self.frames.len() >= MAX_RECORDING_FRAMES
+            || self.frames.last().map(|frame| frame.counter) == Some(data.counter())
+        {
+            return;
+        }
+        let left = data.left_stick_position();
+        let right = data.right_stick_position();
+        self.frames.push(RecordedFrame {
+            elapsed_us,
+            counter: data.counter(),
+            left_x: left.x,
+            left_y: left.y,
+            right_x: right.x,
+            right_y: right.y,
+            l2: data.l2_trigger(),
+            r2: data.r2_trigger(),
+        });
+    }
+
+    // Returns false when the recording isn't active
+    pub fn add_marker(&mut self, now: Instant, label: &str) -> bool {
+        let Some(elapsed_us) = self.elapsed_us(now) else {
+            return false;
+        };
+        // Keeps one CSV row per marker
+        let label = label.replace(['\r', '\n'], " ").trim().to_string();
+        self.markers.push(Marker { elapsed_us, label });
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty() && self.markers.is_empty()
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    // Frames and markers are merged by time, markers go after the frames of the same time
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(CSV_HEADER);
+        csv.push('\n');
+        let mut markers = self.markers.iter().peekable();
+        for frame in &self.frames {
+            while let Some(marker) = markers.next_if(|m| m.elapsed_us < frame.elapsed_us) {
+                write_marker(&mut csv, marker);
+            }
+            let _ = writeln!(
+                csv,
+                "frame,{},{},{},{},{},{},{},{},",
+                frame.elapsed_us,
+                frame.counter,
+                frame.left_x,
+                frame.left_y,
+                frame.right_x,
+                frame.right_y,
+                frame.l2,
+                frame.r2
+            );
+        }
+        markers.for_each(|marker| write_marker(&mut csv, marker));
+        csv
+    }
+
+    fn elapsed_us(&self, now: Instant) -> Option<u64> {
+        let started = self.started?;
+        Some(now.saturating_duration_since(started).as_micros() as u64)
+    }
+}
+
+fn write_marker(csv: &mut String, marker: &Marker) {
+    let _ = writeln!(
+        csv,
+        "marker,{},,,,,,,,\"{}\"",
+        marker.elapsed_us,
+        marker.label.replace('"', "\"\"")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn data(counter: u8, left_x: u8) -> Data {
+        let mut data = Data::zeroed();
+        data.buf[1] = left_x;
+        data.buf[7] = counter << 2;
+        data
+    }
+
+    fn recording() -> Recording {
+        let start = Instant::now();
+        let mut recording = Recording::default();
+        recording.start(start);
+        recording.push(start + Duration::from_millis(1), &data(1, 10));
+        recording.push(start + Duration::from_millis(2), &data(1, 10));
+        assert!(recording.add_marker(start + Duration::from_millis(3), "drift \"started\",\nhere"));
+        recording.push(start + Duration::from_millis(4), &data(2, 200));
+        recording.stop();
+        assert!(!recording.add_marker(start + Duration::from_millis(5), "ignored"));
+        recording
+    }
+
+    // Inverse of to_csv, only the export format is checked
+    fn from_csv(csv: &str) -> Recording {
+        let mut recording = Recording::default();
+        for line in csv.lines().skip(1) {
+            let fields: Vec<&str> = line.splitn(10, ',').collect();
+            let number = |i: usize| fields[i].parse::<u64>().unwrap();
+            match fields[0] {
+                "frame" => recording.frames.push(RecordedFrame {
+                    elapsed_us: number(1),
+                    counter: number(2) as u8,
+                    left_x: number(3) as u8,
+                    left_y: number(4) as u8,
+                    right_x: number(5) as u8,
+                    right_y: number(6) as u8,
+                    l2: number(7) as u8,
+                    r2: number(8) as u8,
+                }),
+                "marker" => recording.markers.push(Marker {
+                    elapsed_us: number(1),
+                    label: fields[9].trim_matches('"').replace("\"\"", "\""),
+                }),
+                kind => panic!("Unexpected row {}", kind),
+            }
+        }
+        recording
+    }
+
+    #[test]
+    fn markers_use_recording_clock() {
+        let recording = recording();
+        assert_eq!(recording.frames.len(), 2);
+        assert_eq!(
+            recording.markers,
+            vec![Marker {
+                elapsed_us: 3000,
+                label: "drift \"started\", here".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let recording = recording();
+        let json: Recording = serde_json::from_str(&recording.to_json().unwrap()).unwrap();
+        assert_eq!(
+            (&json.frames, &json.markers),
+            (&recording.frames, &recording.markers)
+        );
+
+        let csv = recording.to_csv();
+        assert_eq!(
+            csv.lines().nth(2).unwrap(),
+            "marker,3000,,,,,,,,\"drift \"\"started\"\", here\""
+        );
+        let csv = from_csv(&csv);
+        assert_eq!(
+            (&csv.frames, &csv.markers),
+            (&recording.frames, &recording.markers)
+        );
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::dual_shock_4::{Data, FaceButton};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Axis {
+    X,
+    Y,
+}
+
+// Settings-side copy of the face buttons, so the protocol type isn't tied to the settings format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Button {
+    Triangle,
+    Circle,
+    Cross,
+    Square,
+}
+
+impl From<Button> for FaceButton {
+    fn from(button: Button) -> Self {
+        match button {
+            Button::Triangle => FaceButton::Triangle,
+            Button::Circle => FaceButton::Circle,
+            Button::Cross => FaceButton::Cross,
+            Button::Square => FaceButton::Square,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RemapRule {
+    SwapSticks,
+    InvertAxis(Stick, Axis),
+    SwapFaceButtons(Button, Button),
+}
+
+impl RemapRule {
+    pub const ALL: [RemapRule; 7] = [
+        RemapRule::SwapSticks,
+        RemapRule::InvertAxis(Stick::Left, Axis::X),
+        RemapRule::InvertAxis(Stick::Left, Axis::Y),
+        RemapRule::InvertAxis(Stick::Right, Axis::X),
+        RemapRule::InvertAxis(Stick::Right, Axis::Y),
+        RemapRule::SwapFaceButtons(Button::Cross, Button::Circle),
+        RemapRule::SwapFaceButtons(Button::Square, Button::Triangle),
+    ];
+
+    fn apply(&self, data: &mut Data) {
+        match *self {
+            RemapRule::SwapSticks => {
+                let left = data.left_stick_position();
+                data.set_left_stick_position(data.right_stick_position());
+                data.set_right_stick_position(left);
+            }
+            RemapRule::InvertAxis(stick, axis) => {
+                let mut position = match stick {
+                    Stick::Left => data.left_stick_position(),
+                    Stick::Right => data.right_stick_position(),
+                };
+                let value = match axis {
+                    Axis::X => &mut position.x,
+                    Axis::Y => &mut position.y,
+                };
+                *value = u8::MAX - *value;
+                match stick {
+                    Stick::Left => data.set_left_stick_position(position),
+                    Stick::Right => data.set_right_stick_position(position),
+                }
+            }
+            RemapRule::SwapFaceButtons(a, b) => {
+                let (a, b) = (FaceButton::from(a), FaceButton::from(b));
+                let pressed = data.face_button(a);
+                data.set_face_button(a, data.face_button(b));
+                data.set_face_button(b, pressed);
+            }
+        }
+    }
+}
+
+impl Display for RemapRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemapRule::SwapSticks => write!(f, "Swap sticks"),
+            RemapRule::InvertAxis(stick, axis) => write!(f, "Invert {:?} {:?}", stick, axis),
+            RemapRule::SwapFaceButtons(a, b) => write!(f, "Swap {:?} and {:?}", a, b),
+        }
+    }
+}
+
+// Rules applied in order to the decoded input, only for the preview in the Output panel
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Remap {
+    pub rules: Vec<RemapRule>,
+}
+
+impl Remap {
+    pub fn apply(&self, data: &Data) -> Data {
+        let mut data = data.clone();
+        for rule in &self.rules {
+            rule.apply(&mut data);
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dual_shock_4::StickPosition;
+
+    fn input() -> Data {
+        let mut data = Data::zeroed();
+        data.set_left_stick_position(StickPosition { x: 10, y: 128 });
+        data.set_right_stick_position(StickPosition { x: 200, y: 255 });
+        data.set_face_button(FaceButton::Cross, true);
+        data
+    }
+
+    fn remap(rules: &[RemapRule]) -> Remap {
+        Remap {
+            rules: rules.to_vec(),
+        }
+    }
+
+    #[test]
+    fn rules_compose_in_order() {
+        let swap = RemapRule::SwapSticks;
+        let invert = RemapRule::InvertAxis(Stick::Left, Axis::X);
+        let data = remap(&[swap, invert]).apply(&input());
+        assert_eq!(data.left_stick_position().x, 55);
+        assert_eq!(data.right_stick_position().x, 10);
+        let data = remap(&[invert, swap]).apply(&input());
+        assert_eq!(data.left_stick_position().x, 200);
+        assert_eq!(data.right_stick_position().x, 245);
+
+        let buttons = RemapRule::SwapFaceButtons(Button::Cross, Button::Circle);
+        let swapped = remap(&[buttons]).apply(&input());
+        assert!(swapped.circle() && !swapped.cross());
+        // Applying a swap twice restores the input
+        assert_eq!(remap(&[buttons, buttons]).apply(&input()).buf, input().buf);
+    }
+
+    #[test]
+    fn invert_mirrors_range() {
+        let rule = remap(&[RemapRule::InvertAxis(Stick::Right, Axis::Y)]);
+        let data = rule.apply(&input());
+        assert_eq!(data.right_stick_position().y, 0);
+        assert_eq!(rule.apply(&data).right_stick_position().y, 255);
+    }
+
+    #[test]
+    fn serialization_round_trip() {
+        let remap = remap(&RemapRule::ALL);
+        let json = serde_json::to_string(&remap).unwrap();
+        assert_eq!(serde_json::from_str::<Remap>(&json).unwrap(), remap);
+    }
+}
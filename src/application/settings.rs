@@ -0,0 +1,335 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::application::confirmation::confirmation_dialog;
+use crate::application::i18n::{t, Language};
+use crate::application::output::{
+    StickPlotColors, StickPlotStyle, STICK_PLOT_MAX_LINE_WIDTH, STICK_PLOT_MAX_POINT_RADIUS,
+};
+use crate::application::remap::Remap;
+use crate::dual_shock_4::{
+    ConnectionType, DEFAULT_LIGHTBAR_COLOR, MAX_INPUT_REPORT_DRAIN_COUNT, MAX_REPORT_RETRIES,
+};
+use eframe::egui;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+// Frequent enough for hot-plug, rarer refreshes would make new devices look missing
+const MIN_DEVICE_REFRESH_INTERVAL_MS: u64 = 250;
+const MAX_DEVICE_REFRESH_INTERVAL_MS: u64 = 10_000;
+// Longer interval while the window is unfocused or minimized
+const UNFOCUSED_DEVICE_REFRESH_FACTOR: u32 = 5;
+
+// Persisted user preferences, unknown or missing fields fall back to defaults
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub language: Language,
+    // Reopen a device that briefly disappeared from the list
+    pub auto_reconnect: bool,
+    pub report_retries: u32,
+    // Input reports read per poll, the connection type default when not set
+    pub input_report_drain_count: Option<usize>,
+    // Detected from the bus type when not set
+    pub connection_type: Option<ConnectionType>,
+    // Leave the serial number out of the calibration session log
+    pub scrub_calibration_log: bool,
+    // Lightbar shows the battery level instead of the chosen color
+    pub battery_lightbar: bool,
+    // Sample the resting sticks for a second after connecting
+    pub drift_check_on_connect: bool,
+    pub lightbar_color: [u8; 3],
+    // Stick position, peaks and trail of the Output plots
+    pub stick_plot_colors: StickPlotColors,
+    // Point radius and line width of the stick plots, calibration previews included
+    pub stick_plot_style: StickPlotStyle,
+    // Edited in the Output panel
+    pub remap: Remap,
+    // Applied on the next start and can't be turned off from the safe mode
+    pub safe_mode: bool,
+    // Device list hot-plug check, clamped when used
+    pub device_refresh_interval_ms: u64,
+    // Stick center calibration is written to the flash after it succeeds
+    pub auto_flash_write: bool,
+    // Bluetooth link is kept active while the device is idle
+    pub keep_awake: bool,
+    // Test Commands panel is shown, its commands can brick the controller
+    pub test_commands: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            language: Language::default(),
+            auto_reconnect: true,
+            report_retries: 1,
+            input_report_drain_count: None,
+            connection_type: None,
+            scrub_calibration_log: true,
+            battery_lightbar: false,
+            drift_check_on_connect: true,
+            lightbar_color: DEFAULT_LIGHTBAR_COLOR,
+            stick_plot_colors: StickPlotColors::default(),
+            stick_plot_style: StickPlotStyle::default(),
+            remap: Remap::default(),
+            safe_mode: false,
+            device_refresh_interval_ms: 1000,
+            auto_flash_write: false,
+            keep_awake: false,
+            test_commands: false,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!("Cannot parse settings {:?}, using defaults: {}", path, e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    // Values edited in the settings file are kept in the sane range
+    pub fn device_refresh_interval(&self, focused: bool) -> Duration {
+        let interval = Duration::from_millis(self.device_refresh_interval_ms.clamp(
+            MIN_DEVICE_REFRESH_INTERVAL_MS,
+            MAX_DEVICE_REFRESH_INTERVAL_MS,
+        ));
+        if focused {
+            interval
+        } else {
+            interval * UNFOCUSED_DEVICE_REFRESH_FACTOR
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+// Returns true when any setting was changed
+pub fn settings_window(
+    ctx: &egui::Context,
+    open: &mut bool,
+    settings: &mut Settings,
+    safe_mode: bool,
+    test_commands_confirmation: &mut bool,
+) -> bool {
+    let mut changed = false;
+    egui::Window::new(t("settings"))
+        .open(open)
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            egui::Grid::new("Settings").num_columns(2).show(ui, |ui| {
+                ui.label(t("settings.language"));
+                egui::ComboBox::from_id_salt("Language")
+                    .selected_text(settings.language.name())
+                    .show_ui(ui, |ui| {
+                        for language in Language::ALL {
+                            changed |= ui
+                                .selectable_value(&mut settings.language, language, language.name())
+                                .changed();
+                        }
+                    });
+                ui.end_row();
+                ui.label(t("settings.auto_reconnect"));
+                changed |= ui.checkbox(&mut settings.auto_reconnect, "").changed();
+                ui.end_row();
+                ui.label(t("settings.report_retries"));
+                changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut settings.report_retries)
+                            .range(0..=MAX_REPORT_RETRIES),
+                    )
+                    .changed();
+                ui.end_row();
+                ui.label(t("settings.input_report_drain_count"))
+                    .on_hover_text(t("help.input_report_drain_count"));
+                ui.horizontal(|ui| {
+                    let mut auto = settings.input_report_drain_count.is_none();
+                    if ui
+                        .checkbox(&mut auto, t("settings.by_connection_type"))
+                        .changed()
+                    {
+                        settings.input_report_drain_count =
+                            (!auto).then(|| ConnectionType::Usb.input_report_drain_count());
+                        changed = true;
+                    }
+                    if let Some(drain_count) = &mut settings.input_report_drain_count {
+                        changed |= ui
+                            .add(
+                                egui::DragValue::new(drain_count)
+                                    .range(1..=MAX_INPUT_REPORT_DRAIN_COUNT),
+                            )
+                            .changed();
+                    }
+                });
+                ui.end_row();
+                ui.label(t("settings.connection_type"))
+                    .on_hover_text(t("help.connection_type"));
+                let auto = t("settings.by_bus_type").to_string();
+                egui::ComboBox::from_id_salt("Connection type")
+                    .selected_text(
+                        settings
+                            .connection_type
+                            .map_or(auto.clone(), |c| c.to_string()),
+                    )
+                    .show_ui(ui, |ui| {
+                        for (connection_type, text) in [
+                            (None, auto),
+                            (Some(ConnectionType::Usb), ConnectionType::Usb.to_string()),
+                            (
+                                Some(ConnectionType::Bluetooth),
+                                ConnectionType::Bluetooth.to_string(),
+                            ),
+                        ] {
+                            changed |= ui
+                                .selectable_value(
+                                    &mut settings.connection_type,
+                                    connection_type,
+                                    text,
+                                )
+                                .changed();
+                        }
+                    });
+                ui.end_row();
+                ui.label(t("settings.device_refresh_interval"))
+                    .on_hover_text(t("help.device_refresh_interval"));
+                changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut settings.device_refresh_interval_ms)
+                            .range(MIN_DEVICE_REFRESH_INTERVAL_MS..=MAX_DEVICE_REFRESH_INTERVAL_MS)
+                            .speed(10)
+                            .suffix(" ms"),
+                    )
+                    .changed();
+                ui.end_row();
+                ui.label(t("settings.scrub_calibration_log"));
+                changed |= ui
+                    .checkbox(&mut settings.scrub_calibration_log, "")
+                    .changed();
+                ui.end_row();
+                ui.label(t("settings.lightbar_color"));
+                changed |= ui
+                    .color_edit_button_srgb(&mut settings.lightbar_color)
+                    .changed();
+                ui.end_row();
+                ui.label(t("settings.battery_lightbar"));
+                changed |= ui.checkbox(&mut settings.battery_lightbar, "").changed();
+                ui.end_row();
+                ui.label(t("settings.keep_awake"));
+                changed |= ui
+                    .checkbox(&mut settings.keep_awake, "")
+                    .on_hover_text(t("help.keep_awake"))
+                    .changed();
+                ui.end_row();
+                ui.label(t("settings.stick_plot_colors"));
+                ui.horizontal(|ui| {
+                    let colors = &mut settings.stick_plot_colors;
+                    for (color, text) in [
+                        (&mut colors.position, t("settings.stick_plot_position")),
+                        (&mut colors.peaks, t("settings.stick_plot_peaks")),
+                        (&mut colors.trail, t("settings.stick_plot_trail")),
+                    ] {
+                        changed |= ui
+                            .color_edit_button_srgb(color)
+                            .on_hover_text(text)
+                            .changed();
+                    }
+                    if ui.button(t("common.reset")).clicked() {
+                        *colors = StickPlotColors::default();
+                        changed = true;
+                    }
+                });
+                ui.end_row();
+                ui.label(t("settings.stick_plot_style"))
+                    .on_hover_text(t("help.stick_plot_style"));
+                ui.horizontal(|ui| {
+                    let style = &mut settings.stick_plot_style;
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut style.point_radius)
+                                .range(1f32..=STICK_PLOT_MAX_POINT_RADIUS)
+                                .speed(0.1)
+                                .prefix(t("settings.stick_plot_point_radius")),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut style.line_width)
+                                .range(0.5f32..=STICK_PLOT_MAX_LINE_WIDTH)
+                                .speed(0.1)
+                                .prefix(t("settings.stick_plot_line_width")),
+                        )
+                        .changed();
+                    if ui.button(t("common.reset")).clicked() {
+                        *style = StickPlotStyle::default();
+                        changed = true;
+                    }
+                });
+                ui.end_row();
+                ui.label(t("settings.drift_check_on_connect"));
+                changed |= ui
+                    .checkbox(&mut settings.drift_check_on_connect, "")
+                    .on_hover_text(t("help.drift_check"))
+                    .changed();
+                ui.end_row();
+                ui.label(t("settings.auto_flash_write"));
+                changed |= ui
+                    .checkbox(&mut settings.auto_flash_write, "")
+                    .on_hover_text(t("help.auto_flash_write"))
+                    .changed();
+                ui.end_row();
+                ui.label(t("settings.safe_mode"));
+                changed |= ui
+                    .add_enabled(
+                        !safe_mode,
+                        egui::Checkbox::without_text(&mut settings.safe_mode),
+                    )
+                    .on_hover_text(t("help.safe_mode_setting"))
+                    .on_disabled_hover_text(t("help.safe_mode"))
+                    .changed();
+                ui.end_row();
+                ui.label(t("settings.test_commands"));
+                let mut test_commands = settings.test_commands;
+                if ui
+                    .checkbox(&mut test_commands, "")
+                    .on_hover_text(t("help.test_commands_setting"))
+                    .changed()
+                {
+                    // Turned on only after the confirmation
+                    if test_commands {
+                        *test_commands_confirmation = true;
+                    } else {
+                        settings.test_commands = false;
+                        changed = true;
+                    }
+                }
+                ui.end_row();
+            });
+        });
+    if *test_commands_confirmation {
+        if let Some(confirmed) = confirmation_dialog(
+            ctx,
+            t("settings.test_commands"),
+            t("settings.test_commands_confirmation"),
+        ) {
+            *test_commands_confirmation = false;
+            if confirmed {
+                settings.test_commands = true;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
@@ -0,0 +1,171 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::application::i18n::tf;
+use crate::dual_shock_4::{
+    StickCenterCalibration, STICK_CALIBRATION_HALF_RANGE, STICK_CALIBRATION_RANGE,
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+pub const STICK_PRESETS_FILE_NAME: &str = "stick-presets.json";
+
+const STICK_CENTER_RANGE: RangeInclusive<i16> = -(STICK_CALIBRATION_HALF_RANGE as i16)
+    ..=(STICK_CALIBRATION_RANGE - STICK_CALIBRATION_HALF_RANGE) as i16;
+
+// Named stick center calibration, values are offsets from the middle of the range
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StickPreset {
+    pub name: String,
+    // Product id of the model the preset was saved from
+    pub product_id: u16,
+    pub left_x: i16,
+    pub left_y: i16,
+    pub right_x: i16,
+    pub right_y: i16,
+}
+
+impl StickPreset {
+    pub fn from_calibration(
+        name: String,
+        product_id: u16,
+        calibration: &StickCenterCalibration,
+    ) -> Self {
+        Self {
+            name,
+            product_id,
+            left_x: calibration.left_x(),
+            left_y: calibration.left_y(),
+            right_x: calibration.right_x(),
+            right_y: calibration.right_y(),
+        }
+    }
+
+    pub fn calibration(&self) -> StickCenterCalibration {
+        let mut calibration = StickCenterCalibration::default();
+        calibration.set_left_x(self.left_x);
+        calibration.set_left_y(self.left_y);
+        calibration.set_right_x(self.right_x);
+        calibration.set_right_y(self.right_y);
+        calibration
+    }
+
+    // Presets file can be edited by hand, so the values are checked as well
+    pub fn validate(&self, product_id: u16) -> Result<(), String> {
+        if self.product_id != product_id {
+            return Err(tf(
+                "calibration.preset_model_mismatch",
+                &[
+                    &self.name,
+                    &format!("{:04x}", self.product_id),
+                    &format!("{:04x}", product_id),
+                ],
+            ));
+        }
+        let values = [self.left_x, self.left_y, self.right_x, self.right_y];
+        if let Some(value) = values
+            .iter()
+            .find(|value| !STICK_CENTER_RANGE.contains(value))
+        {
+            return Err(tf("calibration.preset_out_of_range", &[&self.name, value]));
+        }
+        Ok(())
+    }
+
+    // Largest difference of a single value, the firmware calibration can't be given the preset
+    // values, so it's compared with them
+    pub fn max_deviation(&self, calibration: &StickCenterCalibration) -> i32 {
+        [
+            (self.left_x, calibration.left_x()),
+            (self.left_y, calibration.left_y()),
+            (self.right_x, calibration.right_x()),
+            (self.right_y, calibration.right_y()),
+        ]
+        .iter()
+        .map(|(preset, calibrated)| (i32::from(*preset) - i32::from(*calibrated)).abs())
+        .max()
+        .unwrap_or(0)
+    }
+}
+
+#[derive(Debug)]
+pub struct StickPresetStore {
+    path: PathBuf,
+    pub presets: Vec<StickPreset>,
+}
+
+impl StickPresetStore {
+    pub fn load(path: PathBuf) -> Self {
+        let presets = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!("Cannot parse stick presets {:?}: {}", path, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self { path, presets }
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        fs::write(&self.path, serde_json::to_string_pretty(&self.presets)?)
+    }
+
+    // Replaces the preset with the same name
+    pub fn insert(&mut self, preset: StickPreset) {
+        match self.presets.iter_mut().find(|p| p.name == preset.name) {
+            Some(existing) => *existing = preset,
+            None => self.presets.push(preset),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DS4_V2_PRODUCT_ID: u16 = 0x09cc;
+
+    fn preset(left_x: i16) -> StickPreset {
+        let mut calibration = StickCenterCalibration::default();
+        calibration.set_left_x(left_x);
+        calibration.set_right_y(-20);
+        StickPreset::from_calibration("tight".to_string(), DS4_V2_PRODUCT_ID, &calibration)
+    }
+
+    #[test]
+    fn store_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("ds4-gui-stick-presets-{}.json", std::process::id()));
+        let mut store = StickPresetStore::load(path.clone());
+        assert!(store.presets.is_empty());
+        store.insert(preset(10));
+        store.insert(preset(30));
+        store.save().unwrap();
+
+        let loaded = StickPresetStore::load(path.clone());
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded.presets, vec![preset(30)]);
+        let calibration = loaded.presets[0].calibration();
+        assert_eq!((calibration.left_x(), calibration.right_y()), (30, -20));
+    }
+
+    #[test]
+    fn validation() {
+        assert!(preset(10).validate(DS4_V2_PRODUCT_ID).is_ok());
+        assert!(preset(10).validate(0x05c4).is_err());
+        let mut out_of_range = preset(10);
+        out_of_range.left_y = i16::MAX;
+        assert!(out_of_range.validate(DS4_V2_PRODUCT_ID).is_err());
+    }
+
+    #[test]
+    fn deviation_from_calibration() {
+        let mut calibration = preset(10).calibration();
+        assert_eq!(preset(10).max_deviation(&calibration), 0);
+        calibration.set_right_y(20);
+        assert_eq!(preset(10).max_deviation(&calibration), 40);
+    }
+}
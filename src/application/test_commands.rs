@@ -1,22 +1,30 @@
 // Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
 // SPDX-License-Identifier: GPL-3.0
 
+use crate::application::confirmation::confirmation_dialog;
 use crate::application::font::{
     with_gamepad_font, GAMEPAD_FONT_CIRCLE, GAMEPAD_FONT_CROSS, GAMEPAD_FONT_SQUARE,
     GAMEPAD_FONT_TRIANGLE,
 };
+use crate::application::i18n::{t, tf};
 use crate::application::{ConnectedDevice, DeviceConnected, StatusHandler};
-use crate::dual_shock_4::{TestCommand, TriggerKeyLeftRight};
+use crate::dual_shock_4::protocol_trace::TRACE_BUFFER_SIZE;
+use crate::dual_shock_4::{TestCommand, TriggerKeyLeftRight, UsbBtMode};
 use eframe::egui;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::Instant;
 
 pub fn test_commands(
     ui: &mut egui::Ui,
-    _ctx: &egui::Context,
+    ctx: &egui::Context,
     state: &mut DeviceConnected,
     sh: StatusHandler,
 ) {
     let ConnectedDevice::DualShock4(_, ds4) = &state.device;
     let mut update_test_data_required = false;
+    // Raw commands and a reset may change the permanent mode
+    let mut refresh_permanent_required = false;
     ui.heading("Test Commands");
     ui.label("If you Gamepad is not bricked yet, you can try some pretty buttons from this page");
     if let super::Panel::Test(Some(test_data), _, _) = &state.panel {
@@ -37,11 +45,16 @@ pub fn test_commands(
             ui.label("Test command in hex format. 0xa0:");
             ui.text_edit_singleline(brick_device_payload);
         });
-        if ui.button("Send Test Command").clicked() {
+        if ui
+            .button("Send Test Command")
+            .on_hover_text(t("help.send_test_command"))
+            .clicked()
+        {
             if let Ok(decoded) = hex::decode(brick_device_payload) {
                 let _ =
                     sh.handle_error(ds4.set_test_command(TestCommand::BrickYourDevice(decoded)));
                 update_test_data_required = true;
+                refresh_permanent_required = true;
             }
         }
     }
@@ -52,12 +65,21 @@ pub fn test_commands(
             ui.label("Custom report in hex format:");
             ui.text_edit_singleline(custom_report);
         });
-        if ui.button("Send Custom Report").clicked() {
+        if ui
+            .button("Send Custom Report")
+            .on_hover_text(t("help.send_custom_report"))
+            .clicked()
+        {
             if let Ok(decoded) = hex::decode(custom_report) {
                 let _ = sh.handle_error(ds4.send_custom_report(decoded.as_slice()));
                 update_test_data_required = true;
+                refresh_permanent_required = true;
             }
-        } else if ui.button("Get Custom Report").clicked() {
+        } else if ui
+            .button("Get Custom Report")
+            .on_hover_text(t("help.get_custom_report"))
+            .clicked()
+        {
             if let Ok(decoded) = hex::decode(custom_report) {
                 let _ = sh.handle_error(ds4.get_custom_report(decoded.as_slice()));
             }
@@ -71,28 +93,44 @@ pub fn test_commands(
     }
     ui.separator();
     ui.label("Force triggers calibration (It seems blocked in current firmware)");
-    if ui.button("Record Left trigger Min value").clicked() {
+    if ui
+        .button("Record Left trigger Min value")
+        .on_hover_text(t("help.record_trigger"))
+        .clicked()
+    {
         let _ = sh.handle_error(ds4.set_test_command(TestCommand::RecordTriggerMinMax(
             TriggerKeyLeftRight::Left,
             true,
         )));
         update_test_data_required = true;
     }
-    if ui.button("Record Left trigger Max value").clicked() {
+    if ui
+        .button("Record Left trigger Max value")
+        .on_hover_text(t("help.record_trigger"))
+        .clicked()
+    {
         let _ = sh.handle_error(ds4.set_test_command(TestCommand::RecordTriggerMinMax(
             TriggerKeyLeftRight::Left,
             false,
         )));
         update_test_data_required = true;
     }
-    if ui.button("Record Right trigger Min value").clicked() {
+    if ui
+        .button("Record Right trigger Min value")
+        .on_hover_text(t("help.record_trigger"))
+        .clicked()
+    {
         let _ = sh.handle_error(ds4.set_test_command(TestCommand::RecordTriggerMinMax(
             TriggerKeyLeftRight::Right,
             true,
         )));
         update_test_data_required = true;
     }
-    if ui.button("Record Right trigger Max value").clicked() {
+    if ui
+        .button("Record Right trigger Max value")
+        .on_hover_text(t("help.record_trigger"))
+        .clicked()
+    {
         let _ = sh.handle_error(ds4.set_test_command(TestCommand::RecordTriggerMinMax(
             TriggerKeyLeftRight::Right,
             false,
@@ -104,11 +142,23 @@ pub fn test_commands(
         update_test_data_required = true;
     }
     ui.separator();
-    if ui.button("Reset Device").clicked() {
+    if ui
+        .button("Reset Device")
+        .on_hover_text(t("help.reset_device"))
+        .clicked()
+    {
         let _ = sh.handle_error(ds4.set_test_command(TestCommand::ResetDevice));
         update_test_data_required = true;
+        refresh_permanent_required = true;
+    }
+    // The mode byte values are guessed, so the switch is only a development experiment
+    if state.dev {
+        ui.separator();
+        usb_bt_control(ui, ctx, state, sh.clone());
     }
     ui.separator();
+    protocol_trace(ui, state, sh.clone());
+    ui.separator();
     ui.horizontal(|ui| {
         ui.label("Super secret combination: ");
         ui.label(with_gamepad_font(GAMEPAD_FONT_TRIANGLE));
@@ -120,11 +170,57 @@ pub fn test_commands(
         ui.label(with_gamepad_font(GAMEPAD_FONT_CIRCLE));
     });
 
+    if refresh_permanent_required {
+        state.refresh_permanent(sh.clone());
+    }
     if update_test_data_required {
         update_test_data(state, sh);
     }
 }
 
+fn usb_bt_mode_text(mode: UsbBtMode) -> &'static str {
+    match mode {
+        UsbBtMode::Usb => t("test.usb_bt_mode_usb"),
+        UsbBtMode::Bluetooth => t("test.usb_bt_mode_bluetooth"),
+    }
+}
+
+fn usb_bt_control(
+    ui: &mut egui::Ui,
+    ctx: &egui::Context,
+    state: &mut DeviceConnected,
+    sh: StatusHandler,
+) {
+    ui.horizontal(|ui| {
+        ui.label(t("test.usb_bt_mode"));
+        for mode in UsbBtMode::ALL {
+            if ui
+                .button(usb_bt_mode_text(mode))
+                .on_hover_text(t("help.usb_bt_mode"))
+                .clicked()
+            {
+                state.usb_bt_control_confirmation = Some(mode);
+            }
+        }
+    });
+    let Some(mode) = state.usb_bt_control_confirmation else {
+        return;
+    };
+    let text = tf("test.usb_bt_mode_confirm", &[&usb_bt_mode_text(mode)]);
+    match confirmation_dialog(ctx, t("test.usb_bt_mode"), &text) {
+        Some(true) => {
+            state.usb_bt_control_confirmation = None;
+            let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+            if sh.handle_error(ds4.set_usb_bt_control(mode)).is_some() {
+                state.disconnect_expected = Some(Instant::now());
+                sh.message(tf("test.usb_bt_mode_sent", &[&usb_bt_mode_text(mode)]));
+            }
+        }
+        Some(false) => state.usb_bt_control_confirmation = None,
+        None => {}
+    }
+}
+
 fn update_test_data(state: &mut DeviceConnected, sh: StatusHandler) {
     if let super::Panel::Test(_, brick_device_payload, custom_report) = &state.panel {
         let ConnectedDevice::DualShock4(_, ds4) = &state.device;
@@ -136,3 +232,40 @@ fn update_test_data(state: &mut DeviceConnected, sh: StatusHandler) {
         );
     }
 }
+
+fn protocol_trace(ui: &mut egui::Ui, state: &DeviceConnected, sh: StatusHandler) {
+    let ConnectedDevice::DualShock4(_, ds4) = &state.device;
+    let mut trace = ds4.protocol_trace();
+    ui.label(format!(
+        "Protocol trace: {} feature report exchanges",
+        trace.len()
+    ));
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut trace.recording, "Record")
+            .on_hover_text(format!(
+                "Keep every exchange, otherwise only the last {} are kept",
+                TRACE_BUFFER_SIZE
+            ));
+        ui.checkbox(&mut trace.scrub_sensitive, "Scrub BD addresses and serial");
+        if ui.button("Clear Trace").clicked() {
+            trace.clear();
+        }
+        if ui
+            .add_enabled(!trace.is_empty(), egui::Button::new("Save Trace"))
+            .clicked()
+        {
+            if let Some(file) = rfd::FileDialog::new()
+                .add_filter("json", &["json"])
+                .set_file_name("ds4_trace.json")
+                .save_file()
+            {
+                if let Some(json) = sh.handle_error(trace.to_json()) {
+                    let file_options = OpenOptions::new().create_new(true).write(true).open(file);
+                    if let Some(mut file) = sh.handle_error(file_options) {
+                        sh.handle_error(file.write_all(json.as_bytes()));
+                    }
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,86 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use eframe::egui;
+use eframe::egui::{Key, KeyboardShortcut, Modifiers};
+use std::collections::VecDeque;
+use std::mem;
+
+const UNDO_STACK_SIZE: usize = 32;
+const UNDO_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+const REDO_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Y);
+
+// Bounded history of in-memory edits, never touches the device
+#[derive(Clone, Debug)]
+pub struct UndoStack<T> {
+    undo: VecDeque<T>,
+    redo: Vec<T>,
+}
+
+impl<T> Default for UndoStack<T> {
+    fn default() -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+        }
+    }
+}
+
+impl<T> UndoStack<T> {
+    pub fn commit(&mut self, previous: T) {
+        self.undo.push_back(previous);
+        if self.undo.len() > UNDO_STACK_SIZE {
+            self.undo.pop_front();
+        }
+        self.redo.clear();
+    }
+
+    pub fn undo(&mut self, current: &mut T) -> bool {
+        if let Some(previous) = self.undo.pop_back() {
+            self.redo.push(mem::replace(current, previous));
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn redo(&mut self, current: &mut T) -> bool {
+        if let Some(next) = self.redo.pop() {
+            self.undo.push_back(mem::replace(current, next));
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+// Undo/Redo buttons and shortcuts, shortcuts are ignored while a widget has keyboard focus
+pub fn undo_redo_buttons<T>(ui: &mut egui::Ui, stack: &mut UndoStack<T>, current: &mut T) {
+    let shortcuts_enabled = ui.ctx().memory(|memory| memory.focused().is_none());
+    ui.horizontal(|ui| {
+        if ui
+            .add_enabled(stack.can_undo(), egui::Button::new("Undo"))
+            .on_hover_text(ui.ctx().format_shortcut(&UNDO_SHORTCUT))
+            .clicked()
+            || (shortcuts_enabled && ui.input_mut(|input| input.consume_shortcut(&UNDO_SHORTCUT)))
+        {
+            stack.undo(current);
+        }
+        if ui
+            .add_enabled(stack.can_redo(), egui::Button::new("Redo"))
+            .on_hover_text(ui.ctx().format_shortcut(&REDO_SHORTCUT))
+            .clicked()
+            || (shortcuts_enabled && ui.input_mut(|input| input.consume_shortcut(&REDO_SHORTCUT)))
+        {
+            stack.redo(current);
+        }
+    });
+}
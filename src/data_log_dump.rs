@@ -0,0 +1,55 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::dual_shock_4::data_log::DataLogReader;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+const CSV_HEADER: &str = "elapsed_us,counter,left_x,left_y,right_x,right_y,l2,r2,report";
+
+// Prints the frames of a data log as CSV without starting the GUI, the report is hex encoded
+pub fn print_data_log(log: &Path, out: impl Write) -> io::Result<()> {
+    let mut reader = DataLogReader::new(BufReader::new(File::open(log)?))?;
+    let header = reader.header();
+    eprintln!(
+        "Data log of {:04x}:{:04x} {} over {}",
+        header.vendor_id,
+        header.product_id,
+        header
+            .serial_number
+            .as_deref()
+            .unwrap_or("without a serial number"),
+        header.connection_type
+    );
+    let mut out = BufWriter::new(out);
+    writeln!(out, "{}", CSV_HEADER)?;
+    let mut frames = 0u64;
+    for frame in reader.by_ref() {
+        let frame = frame?;
+        let data = &frame.data;
+        let (left, right) = (data.left_stick_position(), data.right_stick_position());
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{}",
+            frame.elapsed_us,
+            data.counter(),
+            left.x,
+            left.y,
+            right.x,
+            right.y,
+            data.l2_trigger(),
+            data.r2_trigger(),
+            hex::encode(data.buf)
+        )?;
+        frames += 1;
+    }
+    out.flush()?;
+    if reader.is_truncated() {
+        eprintln!("{} frames, the last frame was cut off", frames);
+    } else {
+        eprintln!("{} frames", frames);
+    }
+    Ok(())
+}
@@ -1,23 +1,91 @@
 // Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
 // SPDX-License-Identifier: GPL-3.0
 
-use crate::dual_shock_4::hid_report::{Report, ReportId};
-use hidapi::{HidDevice, HidError};
-use log::info;
-use std::ffi::CString;
+use crate::dual_shock_4::button_chatter::ChatterDetector;
+use crate::dual_shock_4::data_log::{DataLogHeader, DataLogWriter};
+use crate::dual_shock_4::device_thread::{BusyGuard, DeviceThread, HidTransport};
+use crate::dual_shock_4::hid_report::{
+    check_report_size, keep_awake_output_report, lightbar_output_report, reset_output_report,
+    Report, ReportId,
+};
+use crate::dual_shock_4::packet_loss::PacketLossTracker;
+use crate::dual_shock_4::protocol_trace::{ProtocolTrace, TraceDirection};
+use crate::dual_shock_4::stick_jitter::StickJitter;
+use hidapi::{BusType, DeviceInfo, HidDevice, HidError};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::f64::consts::TAU;
+use std::ffi::{CStr, CString};
 use std::fmt::{Debug, Display, Formatter};
-use std::mem;
 use std::ops::{Not, Range};
-
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+pub mod button_chatter;
+pub mod data_log;
+mod device_clock;
+mod device_thread;
+#[cfg(test)]
+mod fake_transport;
 mod hid_report;
+pub mod packet_loss;
+mod parse;
+pub mod protocol_trace;
+pub mod stick_jitter;
+pub mod touch_gesture;
+
+const SONY_VENDOR_ID: u16 = 0x054c;
+const DUAL_SHOCK_4_PRODUCT_IDS: [u16; 2] = [0x05c4, 0x09cc];
 
 const DATA_PACKET_SIZE: usize = 64;
+const BLUETOOTH_INPUT_REPORT_SIZE: usize = 78;
+// Bluetooth input report has two extra bytes between the report id and the USB layout
+const BLUETOOTH_INPUT_REPORT_OFFSET: usize = 2;
+const USB_INPUT_REPORT_ID: u8 = ReportId::InputReport as u8;
+const BLUETOOTH_INPUT_REPORT_ID: u8 = 0x11;
+// Reports read per poll to reach the freshest one, Bluetooth queues more of them on a slow link
+const USB_INPUT_REPORT_DRAIN_COUNT: usize = 16;
+const BLUETOOTH_INPUT_REPORT_DRAIN_COUNT: usize = 32;
+pub const MAX_INPUT_REPORT_DRAIN_COUNT: usize = 128;
+// Feature reports queued by the UI wait at most this long for a poll of the device thread,
+// only the first read of a poll waits for a report
+const INPUT_REPORT_READ_TIMEOUT_MS: i32 = 4;
+const DEFAULT_REPORT_RETRIES: u32 = 1;
+pub const MAX_REPORT_RETRIES: u32 = 5;
 const MOTION_CALIBRATION_DATA_SIZE: usize = 40;
+// Bytes after the calibration values aren't documented and may be reported differently
+const MOTION_CALIBRATION_VERIFIED_SIZE: usize = 34;
+const MOTION_CALIBRATION_WORDS: usize = MOTION_CALIBRATION_VERIFIED_SIZE / 2;
+// Reference rotation speed in deg/s and the matching raw gyroscope range, 1/16 deg/s per LSB
+const GYROSCOPE_NOMINAL_SPEED: i16 = 540;
+const GYROSCOPE_NOMINAL_RANGE: i16 = 8640;
+// Raw accelerometer value for 1g
+const ACCELEROMETER_NOMINAL_RANGE: i16 = 8192;
 const CALIBRATION_FLAG_SIZE: usize = 4;
 const CALIBRATION_STATE_SIZE: usize = 3;
 const CALIBRATION_RESULT_SIZE: usize = 3;
 const CALIBRATION_DATA_SIZE: usize = 13;
+// Some firmwares report no calibration data for a moment after a calibration finished
+const FINISHED_CALIBRATION_DATA_RETRIES: u32 = 3;
+const FINISHED_CALIBRATION_DATA_RETRY_DELAY: Duration = Duration::from_millis(50);
+// Measurements of the resting sticks taken by the stick center calibration
+const STICK_CENTER_CALIBRATION_MEASUREMENTS: usize = 10;
+const STICK_CENTER_CALIBRATION_MEASUREMENT_INTERVAL: Duration = Duration::from_millis(50);
 const TEST_DATA_SIZE: usize = 13;
+const FIRMWARE_INFO_SIZE: usize = 48;
+// Lightbar color the controller shows when connected to a PC
+pub const DEFAULT_LIGHTBAR_COLOR: [u8; 3] = [0, 0, 64];
+// Restoring the output state on exit must not hang the application
+const OUTPUT_RESTORE_TIMEOUT: Duration = Duration::from_millis(500);
+const DISCONNECT_CHECK_DELAY: Duration = Duration::from_millis(50);
+const FIRMWARE_BUILD_DATE_SIZE: usize = 16;
+const TRACK_RECORD_SIZE: usize = 48;
+// Input report offsets that may depend on the firmware
+const COMMON_STATUS_OFFSET: usize = 30;
+const COMMON_TOUCH_OFFSET: usize = 34;
 
 pub const TRIGGER_MIN_MAX_CALIBRATION_SAMPLES: u16 = 0x04E2;
 
@@ -33,25 +101,120 @@ const STICK_HISTORY_SECTORS: usize = 36;
 const STICK_HISTORY_SECTOR_DEGREE: usize = STICK_HISTORY_DEGREES / STICK_HISTORY_SECTORS;
 
 pub const FLASH_MIRROR_SIZE: usize = 0x800;
+const INTEL_HEX_RECORD_SIZE: usize = 16;
+const FLASH_STICK_CENTER_CALIBRATION_OFFSET: usize = 0x11a;
+const FLASH_STICK_CENTER_CALIBRATION_SIZE: usize = 8;
+const FLASH_PERMANENT_OFFSET: u16 = 12;
+// Mode byte followed by zeros, the size of the report 0x14 in the HID descriptor
+const USB_BT_CONTROL_PAYLOAD_SIZE: usize = 16;
+// Known flash mirror regions sorted by offset, the serial number, PCBA id and motion
+// calibration offsets aren't reverse-engineered yet
+static FLASH_REGIONS: [FlashRegion; 3] = [
+    FlashRegion {
+        name: "CRC",
+        range: 0..2,
+    },
+    FlashRegion {
+        name: "Permanent flag",
+        range: FLASH_PERMANENT_OFFSET as usize..FLASH_PERMANENT_OFFSET as usize + 2,
+    },
+    FlashRegion {
+        name: "Stick center calibration",
+        range: FLASH_STICK_CENTER_CALIBRATION_OFFSET
+            ..FLASH_STICK_CENTER_CALIBRATION_OFFSET + FLASH_STICK_CENTER_CALIBRATION_SIZE,
+    },
+];
+// Recent word round trips for the read ETA, Bluetooth ones are several times longer than USB
+const FLASH_READ_TIMING_WINDOW: usize = 64;
+// The first round trips are too few for a stable estimate
+const FLASH_READ_MIN_TIMED_WORDS: usize = 8;
+// Log targets of the per-report lines, so they can be filtered out. Only info lines use them,
+// errors and retries keep the module target
+pub const LOG_TARGET_REPORTS: &str = "ds4_gui::reports";
+// Flash word reads and writes, thousands of them for a flash mirror read
+pub const LOG_TARGET_FLASH_WORDS: &str = "ds4_gui::flash_words";
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
 
 #[derive(Debug)]
 pub enum Error {
     HidError(HidError),
     OutOfRange(i64, Range<i64>),
     InvalidReport,
-    ErrorMessage(String),
+    UnsupportedDevice(u16, u16),
+    Message(String),
+    // Stopped by the user, partial results are discarded
+    Cancelled,
+    // Report length and the largest accepted one
+    ReportTooLarge(usize, usize),
+    // The firmware ignored an earlier write of this calibration
+    WriteNotSupported(CalibrationWrite),
+    // Refused without sending, the device was opened in the safe mode
+    SafeMode,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Error::UnsupportedDevice(vendor_id, product_id) => write!(
+                f,
+                "Unsupported device: vendor id {:#06x}, product id {:#06x} is not a DualShock 4",
+                vendor_id, product_id
+            ),
+            Error::OutOfRange(value, range) => {
+                write!(f, "Value {:#x} is out of range {:#x?}", value, range)
+            }
+            Error::Message(message) => write!(f, "{}", message),
+            Error::Cancelled => write!(f, "Cancelled"),
+            Error::WriteNotSupported(write) => write!(
+                f,
+                "This firmware does not support writing {} calibration",
+                write
+            ),
+            Error::SafeMode => write!(f, "Not available in the safe mode"),
+            Error::ReportTooLarge(len, max) => write!(
+                f,
+                "Payload too large: the report is {} bytes, at most {} are accepted",
+                len, max
+            ),
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
 impl std::error::Error for Error {}
 
+// Both reports come from the firmware and don't depend on the input, so an idle controller still
+// returns its build date and calibration. An empty firmware info or the same bytes for two
+// different reports mean the answer wasn't made for the request. Clones may leave the motion
+// calibration all zeros, so that alone isn't suspicious
+fn feature_reports_suspicious(firmware_info: &[u8], motion_calibration: &[u8]) -> bool {
+    let len = firmware_info.len().min(motion_calibration.len());
+    firmware_info.iter().all(|b| *b == 0) || firmware_info[..len] == motion_calibration[..len]
+}
+
+// Calibration written by the application, the wizards are written by the controller itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CalibrationWrite {
+    StickCenter,
+    Motion,
+}
+
+impl Display for CalibrationWrite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CalibrationWrite::StickCenter => "stick center",
+            CalibrationWrite::Motion => "motion",
+        })
+    }
+}
+
 impl From<HidError> for Error {
     fn from(value: HidError) -> Self {
         Error::HidError(value)
@@ -60,266 +223,1069 @@ impl From<HidError> for Error {
 
 impl From<String> for Error {
     fn from(value: String) -> Self {
-        Self::ErrorMessage(value)
+        Self::Message(value)
     }
 }
 
-pub struct DualShock4 {
-    hid_device: HidDevice,
+// Set by the UI, long operations check it between words and chunks
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    // Called when a user operation starts, so an old request doesn't stop it. The reads of the
+    // operation don't reset it, so a request stops all of them
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.0.load(Ordering::Relaxed) {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectionType {
+    Usb,
+    Bluetooth,
+}
+
+impl Display for ConnectionType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionType::Usb => write!(f, "USB"),
+            ConnectionType::Bluetooth => write!(f, "Bluetooth"),
+        }
+    }
+}
+
+impl FromStr for ConnectionType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "usb" => Ok(ConnectionType::Usb),
+            "bluetooth" | "bt" => Ok(ConnectionType::Bluetooth),
+            _ => Err(format!(
+                "Unknown connection type {}, expected usb or bluetooth",
+                s
+            )),
+        }
+    }
+}
+
+impl From<BusType> for ConnectionType {
+    fn from(value: BusType) -> Self {
+        match value {
+            BusType::Bluetooth => ConnectionType::Bluetooth,
+            _ => ConnectionType::Usb,
+        }
+    }
+}
+
+impl ConnectionType {
+    fn input_report_size(&self) -> usize {
+        match self {
+            ConnectionType::Usb => DATA_PACKET_SIZE,
+            ConnectionType::Bluetooth => BLUETOOTH_INPUT_REPORT_SIZE,
+        }
+    }
+
+    pub fn input_report_drain_count(&self) -> usize {
+        match self {
+            ConnectionType::Usb => USB_INPUT_REPORT_DRAIN_COUNT,
+            ConnectionType::Bluetooth => BLUETOOTH_INPUT_REPORT_DRAIN_COUNT,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceOptions {
+    // Log outgoing reports instead of sending them
+    pub dry_run: bool,
+    // Repeats of a failed feature report, Bluetooth links drop them occasionally
+    pub report_retries: u32,
+    // Input reports read per poll, the connection type default when not set
+    pub input_report_drain_count: Option<usize>,
+    // Report layout assumption instead of the one detected from the bus type,
+    // applied when the device is opened
+    pub connection_type: Option<ConnectionType>,
+    // Refuse the reports that change the device state, reading stays available
+    pub safe_mode: bool,
+}
+
+impl Default for DeviceOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            report_retries: DEFAULT_REPORT_RETRIES,
+            input_report_drain_count: None,
+            connection_type: None,
+            safe_mode: false,
+        }
+    }
+}
+
+impl DeviceOptions {
+    fn input_report_drain_count(&self, connection_type: ConnectionType) -> usize {
+        self.input_report_drain_count
+            .unwrap_or_else(|| connection_type.input_report_drain_count())
+            .clamp(1, MAX_INPUT_REPORT_DRAIN_COUNT)
+    }
+}
+
+// Copy of the HID device info, the hidapi one can't be created without a device
+#[derive(Debug, Clone)]
+pub struct DeviceIdentity {
     path: CString,
+    vendor_id: u16,
+    product_id: u16,
+    serial_number: Option<String>,
+    release_number: u16,
+    manufacturer_string: Option<String>,
+    product_string: Option<String>,
+    interface_number: i32,
+    bus_type: BusType,
 }
 
-impl DualShock4 {
-    pub fn new(path: CString, hid_device: HidDevice) -> Self {
-        Self { hid_device, path }
+impl From<&DeviceInfo> for DeviceIdentity {
+    fn from(info: &DeviceInfo) -> Self {
+        Self {
+            path: info.path().to_owned(),
+            vendor_id: info.vendor_id(),
+            product_id: info.product_id(),
+            serial_number: info.serial_number().map(str::to_string),
+            release_number: info.release_number(),
+            manufacturer_string: info.manufacturer_string().map(str::to_string),
+            product_string: info.product_string().map(str::to_string),
+            interface_number: info.interface_number(),
+            bus_type: info.bus_type(),
+        }
     }
+}
 
-    pub fn read_last_data(&self) -> Result<Option<Data>> {
-        let mut last_filled = Data::zeroed();
-        let mut last_read = Data::zeroed();
-
-        self.hid_device.read(&mut last_read.buf)?;
-        for _ in 0..16 {
-            if last_read.buf[0] == 0u8
-                || (last_filled.buf[0] != 0u8 && last_read.counter() == last_filled.counter())
-            {
-                break;
+impl DeviceIdentity {
+    // Used when hidapi can't read the device info, only listed DualShock 4 devices are opened
+    fn assumed(path: CString) -> Self {
+        Self {
+            path,
+            vendor_id: SONY_VENDOR_ID,
+            product_id: DUAL_SHOCK_4_PRODUCT_IDS[1],
+            serial_number: None,
+            release_number: 0,
+            manufacturer_string: None,
+            product_string: None,
+            interface_number: -1,
+            bus_type: BusType::Usb,
+        }
+    }
+
+    pub fn path(&self) -> &CStr {
+        &self.path
+    }
+    pub fn vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+    pub fn product_id(&self) -> u16 {
+        self.product_id
+    }
+    pub fn serial_number(&self) -> Option<&str> {
+        self.serial_number.as_deref()
+    }
+    pub fn release_number(&self) -> u16 {
+        self.release_number
+    }
+    pub fn manufacturer_string(&self) -> Option<&str> {
+        self.manufacturer_string.as_deref()
+    }
+    pub fn product_string(&self) -> Option<&str> {
+        self.product_string.as_deref()
+    }
+    pub fn interface_number(&self) -> i32 {
+        self.interface_number
+    }
+    pub fn bus_type(&self) -> BusType {
+        self.bus_type
+    }
+}
+
+// The HID handle is owned by the device thread, see device_thread for the ownership model.
+// Shared with the threads of long operations, so the settings are changed through &self
+pub struct DualShock4 {
+    device_thread: DeviceThread,
+    device_info: DeviceIdentity,
+    // Read again by Device Info, the report layout is selected with it
+    firmware_info: Mutex<Option<FirmwareInfo>>,
+    report_layout: Mutex<ReportLayout>,
+    // Set when the lightbar or rumble were changed by an output report
+    output_changed: AtomicBool,
+    // Time of the last output report, the connection time before the first one
+    last_output: Mutex<Instant>,
+    // Calibration writes the firmware ignored, not tried again on this connection
+    rejected_writes: Mutex<HashSet<CalibrationWrite>>,
+    // Feature reports looked stale while connecting
+    interference_suspected: bool,
+    // Shared with the poll of the device thread
+    input_report_drain_count: Arc<AtomicUsize>,
+    report_retries: AtomicU32,
+    path: CString,
+    connection_type: ConnectionType,
+    // From the bus type, differs from the used one when it's forced
+    detected_connection_type: ConnectionType,
+    options: DeviceOptions,
+    protocol_trace: Mutex<ProtocolTrace>,
+    cancel: Cancel,
+    // Fed by the device thread with every input report
+    packet_loss: Arc<Mutex<PacketLossTracker>>,
+    button_chatter: Arc<Mutex<ChatterDetector>>,
+    stick_jitter: Arc<Mutex<StickJitter>>,
+    data_log: Arc<Mutex<Option<DataLogWriter>>>,
+}
+
+impl DualShock4 {
+    // Verifies the model before any DualShock 4 specific report is sent
+    pub fn new(path: CString, hid_device: HidDevice, options: DeviceOptions) -> Result<Self> {
+        let device_info = match hid_device.get_device_info() {
+            Ok(info) => DeviceIdentity::from(&info),
+            Err(e) => {
+                warn!("Cannot read device info, defaults are used: {}", e);
+                DeviceIdentity::assumed(path.clone())
             }
-            mem::swap(&mut last_read.buf, &mut last_filled.buf);
-            self.hid_device.read(&mut last_read.buf)?;
+        };
+        Self::with_transport(path, hid_device, device_info, options)
+    }
+
+    // Tests pass a fake transport here
+    fn with_transport<T: HidTransport>(
+        path: CString,
+        transport: T,
+        device_info: DeviceIdentity,
+        options: DeviceOptions,
+    ) -> Result<Self> {
+        if !is_dual_shock_4(device_info.vendor_id(), device_info.product_id()) {
+            return Err(Error::UnsupportedDevice(
+                device_info.vendor_id(),
+                device_info.product_id(),
+            ));
         }
+        let detected_connection_type: ConnectionType = device_info.bus_type().into();
+        let connection_type = match options.connection_type {
+            Some(forced) => {
+                warn!(
+                    "Connection type forced to {}, detected {} from the bus type {:?}",
+                    forced,
+                    detected_connection_type,
+                    device_info.bus_type()
+                );
+                forced
+            }
+            None => {
+                info!(
+                    "Connection type {} detected from the bus type {:?}",
+                    detected_connection_type,
+                    device_info.bus_type()
+                );
+                detected_connection_type
+            }
+        };
+        let serial_number = device_info
+            .serial_number()
+            .map(|serial_number| serial_number.to_string());
+        let mut buf = vec![0u8; connection_type.input_report_size()];
+        let input_report_drain_count = Arc::new(AtomicUsize::new(
+            options.input_report_drain_count(connection_type),
+        ));
+        let drain_count = input_report_drain_count.clone();
+        // A new tracker for every connection, so reconnects start from zero
+        let packet_loss = Arc::new(Mutex::new(PacketLossTracker::default()));
+        let thread_packet_loss = packet_loss.clone();
+        let button_chatter = Arc::new(Mutex::new(ChatterDetector::default()));
+        let thread_button_chatter = button_chatter.clone();
+        let stick_jitter = Arc::new(Mutex::new(StickJitter::default()));
+        let thread_stick_jitter = stick_jitter.clone();
+        let data_log: Arc<Mutex<Option<DataLogWriter>>> = Arc::default();
+        let thread_data_log = data_log.clone();
+        let device_thread = DeviceThread::spawn(transport, move |transport: &T| {
+            parse::drain_input_reports(
+                |buf, timeout_ms| transport.read_timeout(buf, timeout_ms),
+                INPUT_REPORT_READ_TIMEOUT_MS,
+                &mut buf,
+                connection_type,
+                drain_count.load(Ordering::Relaxed),
+                |data| {
+                    lock(&thread_packet_loss).push(data.counter());
+                    lock(&thread_button_chatter).push(data);
+                    lock(&thread_stick_jitter).push(data);
+                    if let Some(data_log) = lock(&thread_data_log).as_mut() {
+                        data_log.push(data);
+                    }
+                },
+            )
+        })?;
+        let product_id = device_info.product_id();
+        let mut ds4 = Self {
+            device_thread,
+            device_info,
+            firmware_info: Mutex::new(None),
+            report_layout: Mutex::new(ReportLayout::default()),
+            output_changed: AtomicBool::new(false),
+            last_output: Mutex::new(Instant::now()),
+            rejected_writes: Mutex::new(HashSet::new()),
+            interference_suspected: false,
+            input_report_drain_count,
+            report_retries: AtomicU32::new(options.report_retries),
+            path,
+            connection_type,
+            detected_connection_type,
+            options,
+            protocol_trace: Mutex::new(ProtocolTrace::new(serial_number)),
+            cancel: Cancel::default(),
+            packet_loss,
+            button_chatter,
+            stick_jitter,
+            data_log,
+        };
+        // Some controllers don't answer the firmware info report
+        let firmware_info = ds4
+            .read_firmware_info()
+            .inspect_err(|e| warn!("Cannot read firmware info: {}", e))
+            .ok();
+        *lock(&ds4.report_layout) = ReportLayout::select(product_id, firmware_info.as_ref());
+        *lock(&ds4.firmware_info) = firmware_info;
+        ds4.interference_suspected = ds4.check_interference();
+        Ok(ds4)
+    }
+
+    // Another application holding the interface can make the feature reports come back empty
+    // or stale instead of failing. Only checked when the firmware info was read at all
+    fn check_interference(&self) -> bool {
+        let Some(firmware_info) = self.firmware_info() else {
+            return false;
+        };
+        let motion_calibration = match self.read_motion_calibration_data() {
+            Ok(motion_calibration) => motion_calibration,
+            Err(e) => {
+                warn!("Cannot read motion calibration while connecting: {}", e);
+                return false;
+            }
+        };
+        let suspected = feature_reports_suspicious(&firmware_info.buf, &motion_calibration.buf);
+        if suspected {
+            warn!(
+                "Feature reports look empty or stale, another application may hold the device: \
+                firmware info {}, motion calibration {}",
+                hex::encode(firmware_info.buf),
+                hex::encode(motion_calibration.buf)
+            );
+        }
+        suspected
+    }
 
-        if last_filled.buf[0] != 0u8 {
-            Ok(Some(last_filled))
-        } else {
-            Ok(None)
+    // Advisory, the device is used as usual
+    pub fn interference_suspected(&self) -> bool {
+        self.interference_suspected
+    }
+
+    // Stops the running flash or chunked read, can be used from another thread
+    pub fn cancel_token(&self) -> Cancel {
+        self.cancel.clone()
+    }
+
+    pub fn packet_loss(&self) -> PacketLossTracker {
+        lock(&self.packet_loss).clone()
+    }
+
+    // Settings and counts can be changed while the device thread feeds it
+    pub fn button_chatter(&self) -> MutexGuard<'_, ChatterDetector> {
+        lock(&self.button_chatter)
+    }
+
+    // The device thread waits for the guard, so it shouldn't be held for long
+    pub fn stick_jitter(&self) -> MutexGuard<'_, StickJitter> {
+        lock(&self.stick_jitter)
+    }
+
+    // Every input report from now on is written, a running log is finished first
+    pub fn start_data_log<W: std::io::Write + Send + 'static>(
+        &self,
+        writer: W,
+    ) -> std::io::Result<()> {
+        let header = DataLogHeader {
+            vendor_id: self.device_info.vendor_id(),
+            product_id: self.device_info.product_id(),
+            connection_type: self.connection_type,
+            serial_number: self.device_info.serial_number().map(str::to_string),
+        };
+        let data_log = DataLogWriter::start(writer, &header)?;
+        if let Some(previous) = lock(&self.data_log).replace(data_log) {
+            previous.finish()?;
         }
+        Ok(())
+    }
+
+    // Count of the written frames, None when no log is running
+    pub fn stop_data_log(&self) -> Option<std::io::Result<u64>> {
+        let data_log = lock(&self.data_log).take()?;
+        Some(data_log.finish())
+    }
+
+    pub fn data_log_written(&self) -> Option<u64> {
+        lock(&self.data_log).as_ref().map(DataLogWriter::written)
+    }
+
+    // None while no device operation is queued or running
+    pub fn busy_for(&self) -> Option<Duration> {
+        self.device_thread.busy_for()
+    }
+
+    // Stops reading the input, e.g. while the Output panel is paused
+    pub fn set_input_paused(&self, paused: bool) {
+        self.device_thread.set_paused(paused);
+    }
+
+    // Held by the operations of other threads, so they show as busy while waiting between reports
+    pub fn hold_busy(&self) -> BusyGuard {
+        self.device_thread.hold_busy()
+    }
+
+    // Freshest input report read by the device thread
+    pub fn read_last_data(&self) -> Result<Option<Data>> {
+        let data = self.device_thread.last_data()?;
+        Ok(data.map(|data| data.with_layout(self.report_layout())))
+    }
+
+    pub fn read_firmware_info(&self) -> Result<FirmwareInfo> {
+        let report = self.get_report(ReportId::GetFirmInfo, FIRMWARE_INFO_SIZE)?;
+        parse::firmware_info(report.payload())
+    }
+
+    pub fn firmware_info(&self) -> Option<FirmwareInfo> {
+        lock(&self.firmware_info).clone()
+    }
+
+    // Not every firmware answers this report, the error is kept readable for the UI
+    pub fn read_track_record(&self) -> Result<TrackRecord> {
+        let report = self
+            .get_report(ReportId::GetTrackRecord, TRACK_RECORD_SIZE)
+            .map_err(|e| format!("Track record isn't supported by the device: {}", e))?;
+        parse::track_record(report.payload())
+    }
+
+    // Replaces the cached firmware info, the report layout is selected again
+    pub fn update_firmware_info(&self, firmware_info: FirmwareInfo) {
+        *lock(&self.report_layout) =
+            ReportLayout::select(self.device_info.product_id(), Some(&firmware_info));
+        *lock(&self.firmware_info) = Some(firmware_info);
+    }
+
+    pub fn report_layout(&self) -> ReportLayout {
+        *lock(&self.report_layout)
+    }
+
+    // Used for parsing and output reports, see DeviceOptions::connection_type
+    pub fn connection_type(&self) -> ConnectionType {
+        self.connection_type
+    }
+
+    pub fn detected_connection_type(&self) -> ConnectionType {
+        self.detected_connection_type
+    }
+
+    pub fn is_connection_type_forced(&self) -> bool {
+        self.options.connection_type.is_some()
     }
 
     pub fn read_motion_calibration_data(&self) -> Result<MotionCalibration> {
         let report = self.get_report(ReportId::GetMotionCalibData, MOTION_CALIBRATION_DATA_SIZE)?;
-        let mut data = MotionCalibration::default();
-        data.buf.copy_from_slice(report.payload());
-        Ok(data)
+        parse::motion_calibration(report.payload())
     }
 
     pub fn set_motion_calibration_data(&self, calibration: &MotionCalibration) -> Result<()> {
+        self.check_not_safe_mode()?;
         let report = Report::from_payload(ReportId::SetMotionCalibData, &calibration.buf);
         self.send_report(report)
     }
 
+    // Reads the calibration back, only the known calibration values are compared
+    pub fn write_motion_calibration_verified(&self, calibration: &MotionCalibration) -> Result<()> {
+        self.check_not_safe_mode()?;
+        self.check_write_supported(CalibrationWrite::Motion)?;
+        let before = self.read_motion_calibration_data()?;
+        self.set_motion_calibration_data(calibration)?;
+        if self.options.dry_run {
+            return Ok(());
+        }
+        let written = self.read_motion_calibration_data()?;
+        self.verify_calibration_write(
+            CalibrationWrite::Motion,
+            &before.buf[..MOTION_CALIBRATION_VERIFIED_SIZE],
+            &calibration.buf[..MOTION_CALIBRATION_VERIFIED_SIZE],
+            &written.buf[..MOTION_CALIBRATION_VERIFIED_SIZE],
+        )
+    }
+
+    pub fn is_write_supported(&self, write: CalibrationWrite) -> bool {
+        !lock(&self.rejected_writes).contains(&write)
+    }
+
+    fn check_not_safe_mode(&self) -> Result<()> {
+        if self.options.safe_mode {
+            Err(Error::SafeMode)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_write_supported(&self, write: CalibrationWrite) -> Result<()> {
+        if self.is_write_supported(write) {
+            Ok(())
+        } else {
+            Err(Error::WriteNotSupported(write))
+        }
+    }
+
+    // A silently ignored write leaves the old value in place, the write is then marked as
+    // not supported for the rest of the connection
+    fn verify_calibration_write<T: PartialEq + Debug + ?Sized>(
+        &self,
+        write: CalibrationWrite,
+        before: &T,
+        expected: &T,
+        written: &T,
+    ) -> Result<()> {
+        if written == expected {
+            return Ok(());
+        }
+        if written == before {
+            warn!("Write of {} calibration ignored by the firmware", write);
+            lock(&self.rejected_writes).insert(write);
+            return Err(Error::WriteNotSupported(write));
+        }
+        Err(format!(
+            "{} calibration verification failed, written {:02x?} but read {:02x?}",
+            write, expected, written
+        )
+        .into())
+    }
+
     pub fn read_calibration_flag(&self) -> Result<CalibrationFlag> {
         let report = self.get_report(ReportId::GetCalibFlag, CALIBRATION_FLAG_SIZE)?;
-        let mut state = CalibrationFlag::default();
-        state.buf.copy_from_slice(report.payload());
-        Ok(state)
+        parse::calibration_flag(report.payload())
     }
 
     pub fn read_calibration_state(&self) -> Result<CalibrationState> {
         let report = self.get_report(ReportId::GetCalibrationState, CALIBRATION_STATE_SIZE)?;
-        let mut buf = [0u8; 3];
-        buf.copy_from_slice(report.payload());
-        Ok(buf.try_into()?)
+        parse::calibration_state(report.payload())
     }
 
     pub fn read_calibration_result(&self) -> Result<CalibrationResult> {
         let report = self.get_report(ReportId::GetCalibrationResult, CALIBRATION_RESULT_SIZE)?;
-        let mut buf = [0u8; 3];
-        buf.copy_from_slice(report.payload());
-        Ok(buf.try_into()?)
+        parse::calibration_result(report.payload())
     }
 
     pub fn set_calibration_command(&self, command: CalibrationType) -> Result<()> {
+        self.check_not_safe_mode()?;
         let payload: [u8; 5] = command.into();
         let report = Report::from_payload(ReportId::SetCalibrationCommand, payload.as_slice());
         self.send_report(report)
     }
 
     pub fn set_test_command(&self, command: TestCommand) -> Result<()> {
-        self.send_report(command.into())
+        self.check_not_safe_mode()?;
+        self.send_report(command.try_into()?)
+    }
+
+    // The controller may drop the connection while switching, so the report is sent once. A
+    // failed send is logged as the expected disconnect when the input reads fail too
+    pub fn set_usb_bt_control(&self, mode: UsbBtMode) -> Result<()> {
+        self.check_not_safe_mode()?;
+        let report: Report = mode.into();
+        match self.send_feature_report(report.data()) {
+            Err(Error::HidError(error)) if self.is_disconnected() => {
+                info!(
+                    "Device disconnected after switching to {:?}: {}",
+                    mode, error
+                );
+                Ok(())
+            }
+            result => {
+                info!(target: LOG_TARGET_REPORTS, "Report sent: {:?}", report);
+                result
+            }
+        }
+    }
+
+    // The device thread polls the input between the commands, so a gone device fails it soon
+    fn is_disconnected(&self) -> bool {
+        std::thread::sleep(DISCONNECT_CHECK_DELAY);
+        self.device_thread.is_input_failing()
     }
 
     pub fn send_custom_report(&self, report: &[u8]) -> Result<()> {
-        self.hid_device.send_feature_report(report)?;
-        info!("Report sent: {:?}", report);
+        self.check_not_safe_mode()?;
+        check_report_size(report, self.connection_type)?;
+        self.send_feature_report(report)?;
+        info!(target: LOG_TARGET_REPORTS, "Report sent: {:?}", report);
+        Ok(())
+    }
+
+    // Output report, not a feature one, so it isn't recorded in the protocol trace
+    pub fn set_lightbar(&self, color: [u8; 3]) -> Result<()> {
+        let report = lightbar_output_report(self.connection_type, color);
+        if self.options.dry_run {
+            info!("Dry-run, report not sent: {}", hex::encode(&report));
+            return Ok(());
+        }
+        self.device_thread.write(&report)?;
+        self.output_changed.store(true, Ordering::Relaxed);
+        *lock(&self.last_output) = Instant::now();
+        info!("Lightbar color set: {}", hex::encode(color));
+        Ok(())
+    }
+
+    // An idle Bluetooth link may be dropped, so an output report that changes nothing is sent
+    // when no other one was written within the interval. True when it was sent
+    pub fn keep_awake(&self, interval: Duration) -> Result<bool> {
+        if self.connection_type != ConnectionType::Bluetooth
+            || lock(&self.last_output).elapsed() < interval
+        {
+            return Ok(false);
+        }
+        let report = keep_awake_output_report(self.connection_type);
+        if self.options.dry_run {
+            info!("Dry-run, report not sent: {}", hex::encode(&report));
+        } else {
+            self.device_thread.write(&report)?;
+            info!("Keep-awake report sent");
+        }
+        *lock(&self.last_output) = Instant::now();
+        Ok(true)
+    }
+
+    // Best-effort reset of the output state, does nothing when it wasn't changed
+    pub fn restore_output_state(&self) -> Result<()> {
+        if !self.output_changed.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let report = reset_output_report(self.connection_type, DEFAULT_LIGHTBAR_COLOR);
+        self.device_thread
+            .write_timeout(&report, OUTPUT_RESTORE_TIMEOUT)?;
+        info!("Output state restored");
         Ok(())
     }
 
     pub fn get_custom_report(&self, report: &[u8]) -> Result<Vec<u8>> {
+        check_report_size(report, self.connection_type)?;
         let mut buf = report.clone().to_vec();
-        self.hid_device.get_feature_report(buf.as_mut_slice())?;
-        info!("Report get: {:?}", buf);
+        self.get_feature_report(buf.as_mut_slice())?;
+        info!(target: LOG_TARGET_REPORTS, "Report get: {:?}", buf);
         Ok(buf)
     }
 
     pub fn read_test_data(&self) -> Result<TestData> {
-        let mut data: Vec<u8> = Vec::new();
-        let mut last_args = [255u8, 255u8];
-
-        loop {
-            let report = self.get_report(ReportId::GetTestData, TEST_DATA_SIZE)?;
-            let payload = report.payload();
-            let chunks = payload[2];
-            let current_chunk = payload[3];
-            let data_len = payload[4];
-            let args = [payload[0], payload[1]];
-
-            if args[0] == 255 {
-                break;
-            } else if last_args[0] != 255 && last_args != args {
-                return Err(format!(
-                    "Mismatch Test Data Arguments Type: {:?}  {:?}",
-                    last_args, args
-                )
-                .into());
-            }
-            if data_len > 8 {
-                return Err(format!("Invalid Test Data chunk len {}", data_len).into());
-            }
-            data.extend_from_slice(&payload[5usize..(5 + data_len) as usize]);
-            last_args = args;
-            if current_chunk + 1 >= chunks {
-                break;
-            }
-        }
+        let (args, data) = self.read_chunked(
+            ReportId::GetTestData,
+            TEST_DATA_SIZE,
+            "Test Data",
+            parse::test_data_chunk_key,
+        )?;
 
         Ok(TestData {
-            args: last_args,
+            args: args.unwrap_or([255u8, 255u8]),
             data,
         })
     }
 
     pub fn read_calibration_data(&self) -> Result<CalibrationData> {
-        let mut data: Vec<u8> = Vec::new();
-        let mut last_device = CalibrationDeviceType::None;
-
-        loop {
-            let report = self.get_report(ReportId::GetCalibrationData, CALIBRATION_DATA_SIZE)?;
-            let payload = report.payload();
-            let chunks = payload[2];
-            let current_chunk = payload[3];
-            let data_len = payload[4];
-            let device = [payload[0], payload[1], 0x00, 0x00].try_into()?;
-
-            if device == CalibrationDeviceType::None {
-                break;
-            } else if last_device != CalibrationDeviceType::None && last_device != device {
-                return Err(
-                    format!("Mismatch Device Type: {:?}  {:?}", last_device, device).into(),
-                );
-            }
-            if data_len > 8 {
-                return Err(format!("Invalid Calibration Data chunk len {}", data_len).into());
-            }
-            data.extend_from_slice(&payload[5usize..(5 + data_len) as usize]);
-            last_device = device;
-            if current_chunk + 1 >= chunks {
-                break;
-            }
+        let (last_device, data) = self.read_calibration_chunks()?;
+        parse::calibration_data(last_device.unwrap_or(CalibrationDeviceType::None), data)
+    }
+
+    // Used after the calibration result reported a completed calibration, so the data is
+    // expected. Reading no data is retried a few times, then returned as is
+    pub fn read_finished_calibration_data(&self) -> Result<CalibrationData> {
+        let (mut last_device, mut data) = self.read_calibration_chunks()?;
+        let mut retries = 0;
+        while last_device.is_none() && retries < FINISHED_CALIBRATION_DATA_RETRIES {
+            retries += 1;
+            info!(
+                "No calibration data after the calibration finished, retry {} of {}",
+                retries, FINISHED_CALIBRATION_DATA_RETRIES
+            );
+            std::thread::sleep(FINISHED_CALIBRATION_DATA_RETRY_DELAY);
+            (last_device, data) = self.read_calibration_chunks()?;
         }
-
-        Ok(match last_device {
-            CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::Center) => {
-                let mut calculated = StickCenterCalibration::default();
-                let mut samples: Vec<StickCenterCalibration> = Vec::new();
-                calculated.buf.copy_from_slice(&data.as_slice()[0..8]);
-                if let Some(samples_count) = data.as_slice().get(8) {
-                    for i in 0..(*samples_count as usize) {
-                        let mut sample = StickCenterCalibration::default();
-                        sample
-                            .buf
-                            .copy_from_slice(&data.as_slice()[(8 * i + 9)..(8 * i + 9 + 8)]);
-                        samples.push(sample);
-                    }
-                }
-                CalibrationData::StickCenter(calculated, samples)
-            }
-            CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::MinMax) => {
-                let mut calibration = StickMinMaxCalibration::default();
-                calibration.buf.copy_from_slice(&data.as_slice()[0..16]);
-                CalibrationData::StickMinMax(calibration)
-            }
-            CalibrationDeviceType::TriggerKey(_) => {
-                let mut calibration = TriggersCalibration::default();
-                calibration.buf.append(&mut data);
-                CalibrationData::Triggers(calibration)
-            }
-            CalibrationDeviceType::None => CalibrationData::None(data),
-            _ => {
-                info!("Calibration data slice: {:?}", data);
-                todo!()
-            }
-        })
+        parse::calibration_data(last_device.unwrap_or(CalibrationDeviceType::None), data)
+    }
+
+    fn read_calibration_chunks(&self) -> Result<(Option<CalibrationDeviceType>, Vec<u8>)> {
+        self.read_chunked(
+            ReportId::GetCalibrationData,
+            CALIBRATION_DATA_SIZE,
+            "Calibration Data",
+            parse::calibration_chunk_key,
+        )
+    }
+
+    // Reads a report split into chunks of [key, key, chunks, chunk index, data len, data..]
+    fn read_chunked<K: PartialEq + Debug>(
+        &self,
+        id: ReportId,
+        chunk_size: usize,
+        name: &str,
+        key: impl Fn(&[u8]) -> Result<Option<K>>,
+    ) -> Result<(Option<K>, Vec<u8>)> {
+        parse::reassemble_chunks(
+            || {
+                self.cancel.check()?;
+                Ok(self.get_report(id.clone(), chunk_size)?.payload().to_vec())
+            },
+            name,
+            key,
+        )
     }
 
     pub fn get_ieep_data(&self) -> Result<[u8; 2]> {
-        let report = self.get_report(ReportId::GetIeepData, 2)?;
-        let payload = report.payload();
-        Ok([payload[0], payload[1]])
+        let report = self.get_report_logged(ReportId::GetIeepData, 2, LOG_TARGET_FLASH_WORDS)?;
+        parse::ieep_data(report.payload())
     }
 
     pub fn read_flash_mirror(&self) -> Result<FlashMirror> {
-        let mut bytes: Vec<u8> = Vec::with_capacity(FLASH_MIRROR_SIZE);
-        for offset in 0..(FLASH_MIRROR_SIZE / 2) as u16 {
-            self.send_factory_command(FactoryCommand::SetIeepAddress(offset * 2))?;
-            let two_bytes = self.get_ieep_data()?;
-            bytes.push(two_bytes[0]);
-            bytes.push(two_bytes[1]);
+        self.read_flash_mirror_with_progress(|_| {})
+    }
+
+    // Progress is called after every word
+    pub fn read_flash_mirror_with_progress(
+        &self,
+        mut progress: impl FnMut(&FlashMirrorRead),
+    ) -> Result<FlashMirror> {
+        let mut read = FlashMirrorRead::default();
+        let started = Instant::now();
+        loop {
+            read.read_next_word(self)?;
+            progress(&read);
+            if let Some(flash_mirror) = read.flash_mirror() {
+                info!(
+                    "Flash mirror read, {} bytes in {:.1?}",
+                    read.bytes_read(),
+                    started.elapsed()
+                );
+                return Ok(flash_mirror);
+            }
         }
+    }
 
-        let mut flash_mirror = FlashMirror::default();
-        flash_mirror.buf.copy_from_slice(bytes.as_slice());
-        Ok(flash_mirror)
+    pub fn read_flash_stick_center_calibration(&self) -> Result<StickCenterCalibration> {
+        let mut calibration = StickCenterCalibration::default();
+        for word in 0..FLASH_STICK_CENTER_CALIBRATION_SIZE / 2 {
+            let offset = FLASH_STICK_CENTER_CALIBRATION_OFFSET + word * 2;
+            self.send_factory_command(FactoryCommand::SetIeepAddress(offset as u16))?;
+            let two_bytes = self.get_ieep_data()?;
+            calibration.buf[word * 2..word * 2 + 2].copy_from_slice(&two_bytes);
+        }
+        Ok(calibration)
+    }
+
+    // The firmware measures the center of the resting sticks itself, no command writes a given
+    // center. The result is stored to the flash only in the permanent mode and is read back then
+    pub fn calibrate_stick_center(&self) -> Result<StickCenterCalibration> {
+        self.check_not_safe_mode()?;
+        self.check_write_supported(CalibrationWrite::StickCenter)?;
+        let before = self.read_flash_stick_center_calibration()?;
+        let device_type = CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::Center);
+        // A started calibration always runs to the Stop, so it can only be cancelled before
+        self.cancel.check()?;
+        self.set_calibration_command(CalibrationType::Start(device_type.clone()))?;
+        let measured = (0..STICK_CENTER_CALIBRATION_MEASUREMENTS).try_for_each(|_| {
+            std::thread::sleep(STICK_CENTER_CALIBRATION_MEASUREMENT_INTERVAL);
+            self.set_calibration_command(CalibrationType::Measure(device_type.clone()))
+        });
+        // Stopped after a failed measurement too, so the controller doesn't stay in calibration
+        self.set_calibration_command(CalibrationType::Stop(device_type))?;
+        measured?;
+        if self.options.dry_run {
+            return Ok(before);
+        }
+        let CalibrationResult::Completed(_) = self.read_calibration_result()? else {
+            return Err(
+                "Stick center calibration wasn't completed by the controller"
+                    .to_string()
+                    .into(),
+            );
+        };
+        let CalibrationData::StickCenter(calculated, _) = self.read_finished_calibration_data()?
+        else {
+            return Err("No stick center calibration data after the calibration"
+                .to_string()
+                .into());
+        };
+        if !self.read_permanent()? {
+            info!("Stick center calibration isn't stored, the permanent mode is off");
+            return Ok(calculated);
+        }
+        let written = self.read_flash_stick_center_calibration()?;
+        self.verify_calibration_write(
+            CalibrationWrite::StickCenter,
+            &before,
+            &calculated,
+            &written,
+        )?;
+        Ok(calculated)
+    }
+
+    // Reads the stick center calibration back from the flash once
+    pub fn verify_flash_stick_center_calibration(
+        &self,
+        expected: &StickCenterCalibration,
+    ) -> Result<()> {
+        let written = self.read_flash_stick_center_calibration()?;
+        if written == *expected {
+            Ok(())
+        } else {
+            Err(format!(
+                "Stick center calibration in the flash is {:02x?}, expected {:02x?}",
+                written.buf, expected.buf
+            )
+            .into())
+        }
     }
 
+    // Selecting the flash address is only a read, so it stays available in the safe mode
     pub fn send_factory_command(&self, command: FactoryCommand) -> Result<()> {
+        if !matches!(command, FactoryCommand::SetIeepAddress(_)) {
+            self.check_not_safe_mode()?;
+        }
+        let target = match command {
+            FactoryCommand::SetIeepAddress(_) => LOG_TARGET_FLASH_WORDS,
+            FactoryCommand::TriggerMinMaxCalibration(_) => LOG_TARGET_REPORTS,
+        };
         let payload: [u8; 3] = command.into();
         let report = Report::from_payload(ReportId::SetFactoryCommand, &payload);
-        self.send_report(report)
+        self.send_report_logged(report, target)
     }
 
     pub fn read_permanent(&self) -> Result<bool> {
-        self.send_factory_command(FactoryCommand::SetIeepAddress(12))?;
+        self.send_factory_command(FactoryCommand::SetIeepAddress(FLASH_PERMANENT_OFFSET))?;
         let two_bytes = self.get_ieep_data()?;
         Ok(two_bytes[0] == 0)
     }
 
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        if self.options.dry_run {
+            info!("Dry-run, report not sent: {}", hex::encode(data));
+            return Ok(());
+        }
+        let started = Instant::now();
+        self.device_thread.send_feature_report(data)?;
+        self.protocol_trace()
+            .push(TraceDirection::Set, data, started);
+        Ok(())
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize> {
+        let started = Instant::now();
+        let len = self.device_thread.get_feature_report(buf)?;
+        self.protocol_trace()
+            .push(TraceDirection::Get, &buf[..len.min(buf.len())], started);
+        Ok(len)
+    }
+
+    pub fn protocol_trace(&self) -> MutexGuard<'_, ProtocolTrace> {
+        self.protocol_trace
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
     fn send_report(&self, report: Report) -> Result<()> {
-        self.hid_device.send_feature_report(report.data())?;
-        info!("Report sent: {:?}", report);
+        self.send_report_logged(report, LOG_TARGET_REPORTS)
+    }
+
+    fn send_report_logged(&self, report: Report, target: &str) -> Result<()> {
+        self.with_retries(report.id(), || self.send_feature_report(report.data()))?;
+        info!(target: target, "Report sent: {:?}", report);
         Ok(())
     }
 
     fn get_report(&self, id: ReportId, payload_size: usize) -> Result<Report> {
-        let mut report = Report::new(id, payload_size);
-        self.hid_device.get_feature_report(report.data_mut())?;
-        info!("Report received: {:?}", report);
-        if report.valid() {
-            Ok(report)
-        } else {
-            Err(Error::InvalidReport)
+        self.get_report_logged(id, payload_size, LOG_TARGET_REPORTS)
+    }
+
+    fn get_report_logged(&self, id: ReportId, payload_size: usize, target: &str) -> Result<Report> {
+        self.with_retries(&id, || {
+            let mut report = Report::new(id.clone(), payload_size);
+            self.get_feature_report(report.data_mut())?;
+            info!(target: target, "Report received: {:?}", report);
+            if report.valid() {
+                Ok(report)
+            } else {
+                Err(Error::InvalidReport)
+            }
+        })
+    }
+
+    // Re-issues the report after transient failures, at most report_retries times
+    fn with_retries<T>(&self, id: &ReportId, report: impl Fn() -> Result<T>) -> Result<T> {
+        let retries = self
+            .report_retries
+            .load(Ordering::Relaxed)
+            .min(MAX_REPORT_RETRIES);
+        let mut retry = 0;
+        loop {
+            match report() {
+                Err(error @ (Error::HidError(_) | Error::InvalidReport)) if retry < retries => {
+                    retry += 1;
+                    warn!(
+                        "Report {:?} failed: {}, retry {} of {}",
+                        id, error, retry, retries
+                    );
+                }
+                result => return result,
+            }
         }
     }
-    pub fn hid_device(&self) -> &HidDevice {
-        &self.hid_device
+
+    pub fn set_report_retries(&self, report_retries: u32) {
+        self.report_retries.store(report_retries, Ordering::Relaxed);
+    }
+
+    // Applied from the next poll of the device thread
+    pub fn set_input_report_drain_count(&self, drain_count: Option<usize>) {
+        self.input_report_drain_count.store(
+            drain_count.unwrap_or_else(|| self.connection_type.input_report_drain_count()),
+            Ordering::Relaxed,
+        );
+    }
+    pub fn device_info(&self) -> &DeviceIdentity {
+        &self.device_info
     }
     pub fn path(&self) -> &CString {
         &self.path
     }
 }
 
-#[derive(Debug)]
-#[repr(transparent)]
+pub fn is_dual_shock_4(vendor_id: u16, product_id: u16) -> bool {
+    vendor_id == SONY_VENDOR_ID && DUAL_SHOCK_4_PRODUCT_IDS.contains(&product_id)
+}
+
+// Input report layout of the firmware, unknown firmwares use the common one
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ReportLayout {
+    #[default]
+    Common,
+}
+
+impl ReportLayout {
+    // No firmware with a different layout is known yet
+    pub fn select(product_id: u16, firmware_info: Option<&FirmwareInfo>) -> Self {
+        let layout = ReportLayout::Common;
+        info!(
+            "Report layout {} selected for product {:04x}, firmware {:?}",
+            layout,
+            product_id,
+            firmware_info.map(|info| info.firmware_version())
+        );
+        layout
+    }
+
+    fn status_offset(&self) -> usize {
+        match self {
+            ReportLayout::Common => COMMON_STATUS_OFFSET,
+        }
+    }
+
+    fn touch_offset(&self) -> usize {
+        match self {
+            ReportLayout::Common => COMMON_TOUCH_OFFSET,
+        }
+    }
+}
+
+impl Display for ReportLayout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReportLayout::Common => write!(f, "Common"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FirmwareInfo {
+    pub buf: [u8; FIRMWARE_INFO_SIZE],
+}
+
+impl FirmwareInfo {
+    // Zero padded ASCII date and time, like "Sep 21 2018 04:50:51"
+    pub fn build_date(&self) -> String {
+        let text = |bytes: &[u8]| {
+            String::from_utf8_lossy(bytes)
+                .trim_end_matches('\0')
+                .to_string()
+        };
+        format!(
+            "{} {}",
+            text(&self.buf[..FIRMWARE_BUILD_DATE_SIZE]),
+            text(&self.buf[FIRMWARE_BUILD_DATE_SIZE..FIRMWARE_BUILD_DATE_SIZE * 2])
+        )
+    }
+
+    pub fn hardware_version(&self) -> u16 {
+        u16::from_le_bytes([self.buf[34], self.buf[35]])
+    }
+
+    pub fn firmware_version(&self) -> u16 {
+        u16::from_le_bytes([self.buf[40], self.buf[41]])
+    }
+}
+
+// Usage counters of the device. No documented layout was found, so the bytes are only shown
+#[derive(Debug, Clone)]
+pub struct TrackRecord {
+    pub buf: [u8; TRACK_RECORD_SIZE],
+}
+
+#[derive(Debug, Clone)]
 pub struct Data {
     pub buf: [u8; DATA_PACKET_SIZE],
+    layout: ReportLayout,
 }
 
 impl Data {
     pub fn zeroed() -> Self {
         Self {
             buf: [0u8; DATA_PACKET_SIZE],
+            layout: ReportLayout::default(),
         }
     }
+
+    pub fn with_layout(self, layout: ReportLayout) -> Self {
+        Self { layout, ..self }
+    }
+
+    // Converts an input report to the USB layout, returns None for non-input reports
+    pub fn from_input_report(report: &[u8], connection_type: ConnectionType) -> Option<Self> {
+        let mut data = Self::zeroed();
+        match (report.first()?, connection_type) {
+            (&USB_INPUT_REPORT_ID, _) => {
+                let len = report.len().min(DATA_PACKET_SIZE);
+                data.buf[..len].copy_from_slice(&report[..len]);
+            }
+            (&BLUETOOTH_INPUT_REPORT_ID, ConnectionType::Bluetooth) => {
+                let payload = report.get(1 + BLUETOOTH_INPUT_REPORT_OFFSET..)?;
+                let len = payload.len().min(DATA_PACKET_SIZE - 1);
+                data.buf[0] = USB_INPUT_REPORT_ID;
+                data.buf[1..1 + len].copy_from_slice(&payload[..len]);
+            }
+            _ => return None,
+        }
+        Some(data)
+    }
 }
 
 impl Data {
@@ -337,6 +1303,28 @@ impl Data {
         }
     }
 
+    pub fn set_left_stick_position(&mut self, position: StickPosition) {
+        self.buf[1] = position.x;
+        self.buf[2] = position.y;
+    }
+
+    pub fn set_right_stick_position(&mut self, position: StickPosition) {
+        self.buf[3] = position.x;
+        self.buf[4] = position.y;
+    }
+
+    pub fn face_button(&self, button: FaceButton) -> bool {
+        self.buf[5] & button.mask() != 0
+    }
+
+    pub fn set_face_button(&mut self, button: FaceButton, pressed: bool) {
+        if pressed {
+            self.buf[5] |= button.mask();
+        } else {
+            self.buf[5] &= !button.mask();
+        }
+    }
+
     pub fn triangle(&self) -> bool {
         self.buf[5] & 0b10000000 != 0
     }
@@ -450,9 +1438,154 @@ impl Data {
     pub fn accelerometer_z(&self) -> i16 {
         i16::from_le_bytes([self.buf[23], self.buf[24]])
     }
+
+    pub fn gyroscope(&self) -> [i16; 3] {
+        [self.gyroscope_x(), self.gyroscope_y(), self.gyroscope_z()]
+    }
+
+    pub fn accelerometer(&self) -> [i16; 3] {
+        [
+            self.accelerometer_x(),
+            self.accelerometer_y(),
+            self.accelerometer_z(),
+        ]
+    }
+
+    pub fn cable_connected(&self) -> bool {
+        self.buf[self.layout.status_offset()] & 0b00010000 != 0
+    }
+
+    // Counter of the touch packet, the same value means the coordinates weren't updated
+    pub fn touch_packet_counter(&self) -> u8 {
+        self.buf[self.layout.touch_offset()]
+    }
+
+    // Fingers of the first touch packet, None when the finger doesn't touch the touchpad
+    pub fn touch_points(&self) -> [Option<TouchPoint>; 2] {
+        let offset = self.layout.touch_offset();
+        [
+            TouchPoint::from_bytes(&self.buf[offset + 1..offset + 5]),
+            TouchPoint::from_bytes(&self.buf[offset + 5..offset + 9]),
+        ]
+    }
+
+    pub fn battery_capacity_percent(&self) -> u8 {
+        let battery_level = self.buf[self.layout.status_offset()] & 0b00001111;
+        match (battery_level, self.cable_connected()) {
+            (0..=9, _) => battery_level * 10 + 5,
+            // 11 is reported with the cable once charging has finished
+            (10..=11, true) => 100,
+            (_, false) => 100,
+            // Charging error levels
+            (_, true) => 0,
+        }
+    }
+
+    pub fn snapshot(&self) -> DataSnapshot {
+        DataSnapshot {
+            counter: self.counter(),
+            timestamp: self.timestamp(),
+            left_stick: self.left_stick_position(),
+            right_stick: self.right_stick_position(),
+            l2_trigger: self.l2_trigger(),
+            r2_trigger: self.r2_trigger(),
+            buttons: ButtonsSnapshot {
+                d_pad: self.d_pad(),
+                triangle: self.triangle(),
+                circle: self.circle(),
+                cross: self.cross(),
+                square: self.square(),
+                l1: self.l1(),
+                r1: self.r1(),
+                l2: self.l2(),
+                r2: self.r2(),
+                l3: self.l3(),
+                r3: self.r3(),
+                share: self.share(),
+                options: self.options(),
+                ps: self.ps(),
+                touchpad: self.t_pad_click(),
+            },
+            gyroscope: [self.gyroscope_x(), self.gyroscope_y(), self.gyroscope_z()],
+            accelerometer: [
+                self.accelerometer_x(),
+                self.accelerometer_y(),
+                self.accelerometer_z(),
+            ],
+            battery_percent: self.battery_capacity_percent(),
+            cable_connected: self.cable_connected(),
+            touch_packet_counter: self.touch_packet_counter(),
+            touch_points: self.touch_points(),
+            raw: hex::encode(self.buf),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+// Decoded input for bug reports. The JSON field names are a stable format,
+// new fields may be added but the existing ones are never renamed:
+// counter, timestamp (device clock), left_stick and right_stick ({x, y} 0..=255),
+// l2_trigger and r2_trigger, buttons, gyroscope and accelerometer ([x, y, z] raw),
+// battery_percent, cable_connected, touch_packet_counter,
+// touch_points (two {id, x, y} or null) and raw (hex of the USB layout report).
+#[derive(Debug, Clone, Serialize)]
+pub struct DataSnapshot {
+    pub counter: u8,
+    pub timestamp: u16,
+    pub left_stick: StickPosition,
+    pub right_stick: StickPosition,
+    pub l2_trigger: u8,
+    pub r2_trigger: u8,
+    pub buttons: ButtonsSnapshot,
+    pub gyroscope: [i16; 3],
+    pub accelerometer: [i16; 3],
+    pub battery_percent: u8,
+    pub cable_connected: bool,
+    pub touch_packet_counter: u8,
+    pub touch_points: [Option<TouchPoint>; 2],
+    pub raw: String,
+}
+
+// Pressed state of the buttons, d_pad is "up", "up_right", ..., "released"
+#[derive(Debug, Clone, Serialize)]
+pub struct ButtonsSnapshot {
+    pub d_pad: DPadState,
+    pub triangle: bool,
+    pub circle: bool,
+    pub cross: bool,
+    pub square: bool,
+    pub l1: bool,
+    pub r1: bool,
+    pub l2: bool,
+    pub r2: bool,
+    pub l3: bool,
+    pub r3: bool,
+    pub share: bool,
+    pub options: bool,
+    pub ps: bool,
+    pub touchpad: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaceButton {
+    Triangle,
+    Circle,
+    Cross,
+    Square,
+}
+
+impl FaceButton {
+    fn mask(&self) -> u8 {
+        match self {
+            FaceButton::Triangle => 0b10000000,
+            FaceButton::Circle => 0b01000000,
+            FaceButton::Cross => 0b00100000,
+            FaceButton::Square => 0b00010000,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum DPadState {
     UpLeft,
     Left,
@@ -479,6 +1612,126 @@ impl Default for MotionCalibration {
     }
 }
 
+// Little-endian words of the motion calibration report, gyroscope axes are pitch, yaw, roll
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MotionCalibrationValues {
+    pub gyroscope_bias: [i16; 3],
+    pub gyroscope_plus: [i16; 3],
+    pub gyroscope_minus: [i16; 3],
+    pub gyroscope_speed_plus: i16,
+    pub gyroscope_speed_minus: i16,
+    // Pairs of plus and minus values for X, Y and Z
+    pub accelerometer: [[i16; 2]; 3],
+}
+
+impl MotionCalibrationValues {
+    // Zero biases and nominal gains, raw sensor values are used as is
+    pub fn identity() -> Self {
+        Self {
+            gyroscope_bias: [0; 3],
+            gyroscope_plus: [GYROSCOPE_NOMINAL_RANGE; 3],
+            gyroscope_minus: [-GYROSCOPE_NOMINAL_RANGE; 3],
+            gyroscope_speed_plus: GYROSCOPE_NOMINAL_SPEED,
+            gyroscope_speed_minus: GYROSCOPE_NOMINAL_SPEED,
+            accelerometer: [[ACCELEROMETER_NOMINAL_RANGE, -ACCELEROMETER_NOMINAL_RANGE]; 3],
+        }
+    }
+
+    // Values with an empty range can't be converted to physical units
+    pub fn has_valid_ranges(&self) -> bool {
+        self.gyroscope_plus
+            .iter()
+            .zip(&self.gyroscope_minus)
+            .all(|(plus, minus)| plus != minus)
+            && self.accelerometer.iter().all(|[plus, minus]| plus != minus)
+    }
+
+    // Raw gyroscope values in deg/s, the speed pair matches the plus to minus range
+    pub fn gyroscope_dps(&self, raw: [i16; 3]) -> [f64; 3] {
+        let speed = self.gyroscope_speed_plus as f64 + self.gyroscope_speed_minus as f64;
+        std::array::from_fn(|axis| {
+            let range = self.gyroscope_plus[axis] as f64 - self.gyroscope_minus[axis] as f64;
+            (raw[axis] as f64 - self.gyroscope_bias[axis] as f64) * speed / range
+        })
+    }
+
+    // Raw accelerometer values in g, the plus and minus values are readings at +1g and -1g
+    pub fn accelerometer_g(&self, raw: [i16; 3]) -> [f64; 3] {
+        std::array::from_fn(|axis| {
+            let [plus, minus] = self.accelerometer[axis].map(f64::from);
+            let center = (plus + minus) / 2f64;
+            (raw[axis] as f64 - center) * 2f64 / (plus - minus)
+        })
+    }
+
+    fn words(&self, connection_type: ConnectionType) -> [i16; MOTION_CALIBRATION_WORDS] {
+        let mut words = [0i16; MOTION_CALIBRATION_WORDS];
+        words[..3].copy_from_slice(&self.gyroscope_bias);
+        for axis in 0..3 {
+            let (plus, minus) = gyroscope_range_indexes(axis, connection_type);
+            words[plus] = self.gyroscope_plus[axis];
+            words[minus] = self.gyroscope_minus[axis];
+        }
+        words[9] = self.gyroscope_speed_plus;
+        words[10] = self.gyroscope_speed_minus;
+        for (word, value) in words[11..]
+            .iter_mut()
+            .zip(self.accelerometer.iter().flatten())
+        {
+            *word = *value;
+        }
+        words
+    }
+}
+
+// Word indexes of the plus and minus gyroscope values of an axis. The USB report interleaves
+// them per axis, the Bluetooth one has the three plus values first, as the Linux
+// hid-playstation driver parses them
+fn gyroscope_range_indexes(axis: usize, connection_type: ConnectionType) -> (usize, usize) {
+    match connection_type {
+        ConnectionType::Usb => (3 + axis * 2, 4 + axis * 2),
+        ConnectionType::Bluetooth => (3 + axis, 6 + axis),
+    }
+}
+
+impl MotionCalibration {
+    pub fn values(&self, connection_type: ConnectionType) -> MotionCalibrationValues {
+        let word =
+            |index: usize| i16::from_le_bytes([self.buf[index * 2], self.buf[index * 2 + 1]]);
+        let range = |axis: usize| gyroscope_range_indexes(axis, connection_type);
+        MotionCalibrationValues {
+            gyroscope_bias: [word(0), word(1), word(2)],
+            gyroscope_plus: std::array::from_fn(|axis| word(range(axis).0)),
+            gyroscope_minus: std::array::from_fn(|axis| word(range(axis).1)),
+            gyroscope_speed_plus: word(9),
+            gyroscope_speed_minus: word(10),
+            accelerometer: [
+                [word(11), word(12)],
+                [word(13), word(14)],
+                [word(15), word(16)],
+            ],
+        }
+    }
+
+    // Bytes after the calibration values are kept
+    pub fn set_values(
+        &mut self,
+        values: &MotionCalibrationValues,
+        connection_type: ConnectionType,
+    ) {
+        for (index, word) in values.words(connection_type).into_iter().enumerate() {
+            self.buf[index * 2..index * 2 + 2].copy_from_slice(&word.to_le_bytes());
+        }
+    }
+}
+
+// Offset from the half range, clamped to the encoded range before the cast
+fn encode_stick_calibration_value(value: i16) -> [u8; 2] {
+    let raw = (value as i32 + STICK_CALIBRATION_HALF_RANGE as i32)
+        .clamp(0, STICK_CALIBRATION_RANGE as i32);
+    (raw as u16).to_le_bytes()
+}
+
 #[derive(Debug, Clone, PartialEq)]
 #[repr(transparent)]
 // Todo: change representation to parsed values
@@ -502,8 +1755,7 @@ impl StickCenterCalibration {
 
     fn set_value_at_index(&mut self, index: u8, value: i16) {
         let index = index * 2;
-        let raw = value + STICK_CALIBRATION_HALF_RANGE as i16;
-        let bytes = (raw as u16).clamp(0, STICK_CALIBRATION_RANGE).to_le_bytes();
+        let bytes = encode_stick_calibration_value(value);
         self.buf[index as usize] = bytes[0];
         self.buf[(index + 1) as usize] = bytes[1];
     }
@@ -580,8 +1832,7 @@ impl StickMinMaxCalibration {
 
     fn set_value_at_index(&mut self, index: u8, value: i16) {
         let index = index * 2;
-        let raw = value + STICK_CALIBRATION_HALF_RANGE as i16;
-        let bytes = (raw as u16).clamp(0, STICK_CALIBRATION_RANGE).to_le_bytes();
+        let bytes = encode_stick_calibration_value(value);
         self.buf[index as usize] = bytes[0];
         self.buf[(index + 1) as usize] = bytes[1];
     }
@@ -696,7 +1947,30 @@ pub struct TriggersCalibration {
     pub buf: Vec<u8>,
 }
 
-#[derive(Clone)]
+pub const TOUCHPAD_WIDTH: u16 = 1920;
+pub const TOUCHPAD_HEIGHT: u16 = 943;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct TouchPoint {
+    pub id: u8,
+    // 0..TOUCHPAD_WIDTH from left to right
+    pub x: u16,
+    // 0..TOUCHPAD_HEIGHT from top to bottom
+    pub y: u16,
+}
+
+impl TouchPoint {
+    // [inactive flag and id, x low, y low and x high, y high]
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        (bytes[0] & 0x80 == 0).then(|| Self {
+            id: bytes[0] & 0x7f,
+            x: u16::from(bytes[1]) | (u16::from(bytes[2] & 0x0f) << 8),
+            y: u16::from(bytes[2] >> 4) | (u16::from(bytes[3]) << 4),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct StickPosition {
     pub x: u8,
     pub y: u8,
@@ -710,6 +1984,30 @@ impl StickPosition {
     pub fn normalized_y(&self) -> f64 {
         STICK_NORMALIZED_CENTER - self.y as f64 / STICK_CENTER
     }
+
+    // Angle in degrees counterclockwise from the positive X-axis and magnitude in 0..=1
+    pub fn polar(&self) -> (f64, f64) {
+        let (angle, distance) = polar(self.normalized_x(), self.normalized_y());
+        (angle, distance.min(1f64))
+    }
+
+    // The firmware subtracts the stored center before reporting, that's why a stick center
+    // calibration fixes a drift in every application. Estimates the normalized position before
+    // that, the center is in calibration units with the Y-axis pointing down like the reported one
+    pub fn uncalibrated_normalized(&self, center_x: i16, center_y: i16) -> (f64, f64) {
+        let half_range = STICK_CALIBRATION_HALF_RANGE as f64;
+        (
+            self.normalized_x() + center_x as f64 / half_range,
+            self.normalized_y() - center_y as f64 / half_range,
+        )
+    }
+}
+
+// Angle in degrees 0..360 counterclockwise from the positive X-axis and distance from the center
+pub fn polar(x: f64, y: f64) -> (f64, f64) {
+    let angle = (y.atan2(x) + TAU).to_degrees() % 360f64;
+    let distance = (x.powi(2) + y.powi(2)).sqrt();
+    (angle, distance)
 }
 
 #[derive(Debug)]
@@ -937,6 +2235,13 @@ impl From<CalibrationDeviceType> for [u8; 4] {
     }
 }
 
+impl CalibrationDeviceType {
+    // Only the trigger params carry a left/right selector, the other devices are calibrated whole
+    pub fn selects_side(&self) -> bool {
+        matches!(self, CalibrationDeviceType::TriggerKey(_))
+    }
+}
+
 #[derive(Debug)]
 #[repr(u8)]
 pub enum CalibrationState {
@@ -985,9 +2290,30 @@ pub enum CalibrationData {
     None(Vec<u8>),
 }
 
+impl CalibrationData {
+    // Calculated data followed by samples, as read from the device
+    pub fn bytes(&self) -> Vec<u8> {
+        match self {
+            CalibrationData::StickCenter(calculated, samples) => {
+                let mut bytes = calculated.buf.to_vec();
+                for sample in samples {
+                    bytes.extend_from_slice(&sample.buf);
+                }
+                bytes
+            }
+            CalibrationData::StickMinMax(calibration) => calibration.buf.to_vec(),
+            CalibrationData::Triggers(calibration) => calibration.buf.clone(),
+            CalibrationData::None(data) => data.clone(),
+        }
+    }
+}
+
+// Payload of the factory command report, a command byte followed by two argument bytes
 #[derive(Debug, PartialEq, Clone)]
 pub enum FactoryCommand {
+    // The address is big-endian, unlike the little-endian values of the other reports
     SetIeepAddress(u16),
+    // The argument byte is sent twice
     TriggerMinMaxCalibration(TriggerMinMaxCalibrationType),
 }
 
@@ -1024,7 +2350,6 @@ impl From<FactoryCommand> for [u8; 3] {
                     TriggerMinMaxCalibrationType::SaveMax(TriggerKeyLeftRight::Right) => 0x08,
                     TriggerMinMaxCalibrationType::SaveMax(TriggerKeyLeftRight::Both) => 0x02 | 0x08,
                     TriggerMinMaxCalibrationType::SaveMax(TriggerKeyLeftRight::Unknown) => 0x00,
-                    _ => panic!("Unsupported Factory Command"),
                 };
                 [0x02, arg, arg]
             }
@@ -1032,6 +2357,45 @@ impl From<FactoryCommand> for [u8; 3] {
     }
 }
 
+// Link the controller prefers for the HID reports
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum UsbBtMode {
+    Usb,
+    Bluetooth,
+}
+
+impl UsbBtMode {
+    pub const ALL: [UsbBtMode; 2] = [UsbBtMode::Usb, UsbBtMode::Bluetooth];
+
+    // No documentation of the mode byte was found, the values are unverified. That's why the
+    // switch is only offered as an experiment with --dev
+    fn byte(&self) -> u8 {
+        match self {
+            UsbBtMode::Bluetooth => 0x01,
+            UsbBtMode::Usb => 0x02,
+        }
+    }
+}
+
+impl TryFrom<u8> for UsbBtMode {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        UsbBtMode::ALL
+            .into_iter()
+            .find(|mode| mode.byte() == value)
+            .ok_or(Error::OutOfRange(value.into(), 1..3))
+    }
+}
+
+impl From<UsbBtMode> for Report {
+    fn from(value: UsbBtMode) -> Self {
+        let mut payload = [0u8; USB_BT_CONTROL_PAYLOAD_SIZE];
+        payload[0] = value.byte();
+        Report::from_payload(ReportId::SetUsbBtControl, &payload)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum TestCommand {
     SetPermanent(bool),
@@ -1042,8 +2406,10 @@ pub enum TestCommand {
     BrickYourDevice(Vec<u8>),
 }
 
-impl From<TestCommand> for Report {
-    fn from(value: TestCommand) -> Self {
+impl TryFrom<TestCommand> for Report {
+    type Error = Error;
+
+    fn try_from(value: TestCommand) -> Result<Self> {
         let payload = match value {
             TestCommand::SetPermanent(true) => vec![0x0a, 0x02, 0x3e, 0x71, 0x7f, 0x89],
             TestCommand::SetPermanent(false) => vec![0x0a, 0x01, 0x00],
@@ -1068,9 +2434,15 @@ impl From<TestCommand> for Report {
             TestCommand::ReadTriggerMinMaxFromFlash => vec![0x08, 0x02],
             TestCommand::ResetDevice => vec![0x04, 0x01],
             TestCommand::BrickYourDevice(vec) => vec,
-            _ => panic!("Unsupported test command"),
+            TestCommand::RecordTriggerMinMax(TriggerKeyLeftRight::Unknown, _) => {
+                let message = "Unsupported test command, the trigger to record isn't selected";
+                return Err(Error::Message(message.to_string()));
+            }
         };
-        Report::from_payload(ReportId::SetTestCommand, payload.as_slice())
+        Ok(Report::from_payload(
+            ReportId::SetTestCommand,
+            payload.as_slice(),
+        ))
     }
 }
 
@@ -1102,6 +2474,12 @@ pub enum TriggerMinMaxCalibrationType {
     SaveMax(TriggerKeyLeftRight),
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlashRegion {
+    pub name: &'static str,
+    pub range: Range<usize>,
+}
+
 #[derive(Debug, Clone)]
 #[repr(transparent)]
 pub struct FlashMirror {
@@ -1136,6 +2514,57 @@ impl FlashMirror {
         self.calc_crc() == self.crc()
     }
 
+    // Data records of 16 bytes with 16-bit addresses and the end of file record
+    pub fn to_intel_hex(&self) -> String {
+        let mut hex = String::new();
+        for (index, chunk) in self.buf.chunks(INTEL_HEX_RECORD_SIZE).enumerate() {
+            let address = ((index * INTEL_HEX_RECORD_SIZE) as u16).to_be_bytes();
+            let mut record = vec![chunk.len() as u8, address[0], address[1], 0x00];
+            record.extend_from_slice(chunk);
+            let checksum = record
+                .iter()
+                .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+                .wrapping_neg();
+            record.push(checksum);
+            hex.push(':');
+            hex.push_str(&hex::encode_upper(record));
+            hex.push('\n');
+        }
+        hex.push_str(":00000001FF\n");
+        hex
+    }
+
+    // Reads the data records up to the end of file record, other record types aren't written
+    // by to_intel_hex
+    pub fn from_intel_hex(hex: &str) -> Result<Self> {
+        let mut flash_mirror = Self::default();
+        for line in hex.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let invalid = || format!("Invalid Intel HEX record {}", line);
+            let record = line
+                .strip_prefix(':')
+                .and_then(|record| hex::decode(record).ok())
+                .ok_or_else(invalid)?;
+            let checksum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            if record.len() < 5 || record.len() != record[0] as usize + 5 || checksum != 0 {
+                return Err(invalid().into());
+            }
+            match record[3] {
+                0x00 => {
+                    let address = u16::from_be_bytes([record[1], record[2]]) as usize;
+                    let data = &record[4..record.len() - 1];
+                    flash_mirror
+                        .buf
+                        .get_mut(address..address + data.len())
+                        .ok_or_else(invalid)?
+                        .copy_from_slice(data);
+                }
+                0x01 => return Ok(flash_mirror),
+                _ => return Err(invalid().into()),
+            }
+        }
+        Err("Intel HEX end of file record is missing".to_string().into())
+    }
+
     pub fn update_crc(&mut self) {
         let crc = self.calc_crc().to_le_bytes();
         self.buf[0] = crc[0];
@@ -1144,7 +2573,796 @@ impl FlashMirror {
 
     pub fn stick_center_calibration(&self) -> StickCenterCalibration {
         let mut calibration = StickCenterCalibration::default();
-        calibration.buf.copy_from_slice(&self.buf[0x11a..0x122]);
         calibration
+            .buf
+            .copy_from_slice(&self.buf[Self::stick_center_calibration_range()]);
+        calibration
+    }
+
+    pub fn regions() -> &'static [FlashRegion] {
+        &FLASH_REGIONS
+    }
+
+    pub fn region_at(offset: usize) -> Option<&'static FlashRegion> {
+        FLASH_REGIONS
+            .iter()
+            .find(|region| region.range.contains(&offset))
+    }
+
+    fn stick_center_calibration_range() -> Range<usize> {
+        FLASH_STICK_CENTER_CALIBRATION_OFFSET
+            ..FLASH_STICK_CENTER_CALIBRATION_OFFSET + FLASH_STICK_CENTER_CALIBRATION_SIZE
+    }
+}
+
+// Flash mirror read word by word, so the caller can show the progress and stop between words
+#[derive(Debug, Default)]
+pub struct FlashMirrorRead {
+    bytes: Vec<u8>,
+    word_times: VecDeque<Duration>,
+}
+
+impl FlashMirrorRead {
+    pub fn read_next_word(&mut self, ds4: &DualShock4) -> Result<()> {
+        if self.is_finished() {
+            return Ok(());
+        }
+        ds4.cancel.check()?;
+        let started = Instant::now();
+        ds4.send_factory_command(FactoryCommand::SetIeepAddress(self.bytes.len() as u16))?;
+        let word = ds4.get_ieep_data()?;
+        self.bytes.extend_from_slice(&word);
+        self.push_word_time(started.elapsed());
+        Ok(())
+    }
+
+    pub fn bytes_read(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.bytes.len() >= FLASH_MIRROR_SIZE
+    }
+
+    // Rolling average of the recent word times by the words left
+    pub fn eta(&self) -> Option<Duration> {
+        if self.word_times.len() < FLASH_READ_MIN_TIMED_WORDS {
+            return None;
+        }
+        let average = self.word_times.iter().sum::<Duration>() / self.word_times.len() as u32;
+        let words_left = FLASH_MIRROR_SIZE.saturating_sub(self.bytes.len()) / 2;
+        Some(average * words_left as u32)
+    }
+
+    // None until all the words are read
+    pub fn flash_mirror(&self) -> Option<FlashMirror> {
+        self.is_finished().then(|| {
+            let mut flash_mirror = FlashMirror::default();
+            flash_mirror.buf.copy_from_slice(&self.bytes);
+            flash_mirror
+        })
+    }
+
+    fn push_word_time(&mut self, time: Duration) {
+        if self.word_times.len() == FLASH_READ_TIMING_WINDOW {
+            self.word_times.pop_front();
+        }
+        self.word_times.push_back(time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dual_shock_4::fake_transport::{FakeResponse, FakeTransport};
+
+    #[test]
+    fn motion_calibration_identity_bytes() {
+        let mut calibration = MotionCalibration::default();
+        calibration.buf.fill(0xaa);
+        calibration.set_values(&MotionCalibrationValues::identity(), ConnectionType::Usb);
+        #[rustfmt::skip]
+        let expected: [u8; 34] = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // gyroscope bias
+            0xc0, 0x21, 0x40, 0xde, // gyroscope pitch plus and minus, 8640 and -8640
+            0xc0, 0x21, 0x40, 0xde, // gyroscope yaw
+            0xc0, 0x21, 0x40, 0xde, // gyroscope roll
+            0x1c, 0x02, 0x1c, 0x02, // gyroscope speed plus and minus, 540
+            0x00, 0x20, 0x00, 0xe0, // accelerometer X, 8192 and -8192
+            0x00, 0x20, 0x00, 0xe0, // accelerometer Y
+            0x00, 0x20, 0x00, 0xe0, // accelerometer Z
+        ];
+        assert_eq!(calibration.buf[..34], expected);
+        assert!(calibration.buf[34..].iter().all(|byte| *byte == 0xaa));
+        assert_eq!(
+            calibration.values(ConnectionType::Usb),
+            MotionCalibrationValues::identity()
+        );
+    }
+
+    #[test]
+    fn motion_calibration_gyroscope_layouts() {
+        let mut calibration = MotionCalibration::default();
+        for (index, word) in (3i16..9).enumerate() {
+            calibration.buf[6 + index * 2..8 + index * 2].copy_from_slice(&word.to_le_bytes());
+        }
+        let usb = calibration.values(ConnectionType::Usb);
+        assert_eq!(
+            (usb.gyroscope_plus, usb.gyroscope_minus),
+            ([3, 5, 7], [4, 6, 8])
+        );
+        let bluetooth = calibration.values(ConnectionType::Bluetooth);
+        assert_eq!(
+            (bluetooth.gyroscope_plus, bluetooth.gyroscope_minus),
+            ([3, 4, 5], [6, 7, 8])
+        );
+        let mut written = MotionCalibration::default();
+        written.set_values(&bluetooth, ConnectionType::Bluetooth);
+        assert_eq!(written, calibration);
+    }
+
+    #[test]
+    fn motion_values_to_physical_units() {
+        let mut values = MotionCalibrationValues::identity();
+        assert!(values.has_valid_ranges());
+        assert_eq!(values.gyroscope_dps([16, -160, 0]), [1f64, -10f64, 0f64]);
+        assert_eq!(values.accelerometer_g([0, -4096, 8192]), [0f64, -0.5, 1f64]);
+
+        values.gyroscope_bias = [8, 0, 0];
+        values.accelerometer[2] = [8292, -8092];
+        assert_eq!(values.gyroscope_dps([8, 0, 0])[0], 0f64);
+        assert_eq!(values.accelerometer_g([0, 0, 8292])[2], 1f64);
+
+        values.accelerometer[0] = [0, 0];
+        assert!(!values.has_valid_ranges());
+    }
+
+    #[test]
+    fn stick_calibration_values_clamp_to_encoded_range() {
+        let half_range = STICK_CALIBRATION_HALF_RANGE as i16;
+        let max = STICK_CALIBRATION_RANGE as i16 - half_range;
+        let mut center = StickCenterCalibration::default();
+        for (value, expected) in [
+            (i16::MIN, -half_range),
+            (-half_range - 1, -half_range),
+            (-half_range, -half_range),
+            (0, 0),
+            (max, max),
+            (max + 1, max),
+            (i16::MAX, max),
+        ] {
+            center.set_left_x(value);
+            assert_eq!(center.left_x(), expected, "Center value {}", value);
+        }
+
+        // Ends of the calibration sliders
+        let mut min_max = StickMinMaxCalibration::default();
+        for value in [-512, 512, -4048, 0, 4048] {
+            center.set_right_y(value);
+            min_max.set_left_min_x(value);
+            min_max.set_right_max_y(value);
+            for raw in [&center.buf[6..8], &min_max.buf[0..2], &min_max.buf[14..16]] {
+                let raw = u16::from_le_bytes([raw[0], raw[1]]);
+                assert!(raw <= STICK_CALIBRATION_RANGE, "Slider value {}", value);
+            }
+        }
+        min_max.set_left_min_x(-4048);
+        assert_eq!(min_max.left_min_x(), -half_range);
+        min_max.set_right_max_y(4048);
+        assert_eq!(min_max.right_max_y(), max);
+    }
+
+    #[test]
+    fn stick_position_uncalibrated_normalized() {
+        let position = StickPosition { x: 128, y: 128 };
+        assert_eq!(
+            position.uncalibrated_normalized(0, 0),
+            (position.normalized_x(), position.normalized_y())
+        );
+        // Reported centered, the sensor rests at the stored center
+        let (x, y) = position.uncalibrated_normalized(264, -248);
+        let sensor = StickPosition { x: 144, y: 112 };
+        assert!((x - sensor.normalized_x()).abs() < 0.01, "x {}", x);
+        assert!((y - sensor.normalized_y()).abs() < 0.01, "y {}", y);
+    }
+
+    #[test]
+    fn touch_points_decoding() {
+        let mut data = Data::zeroed();
+        data.buf[35..43].copy_from_slice(&[0x05, 0x7f, 0x27, 0x1d, 0x86, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            data.touch_points(),
+            [
+                Some(TouchPoint {
+                    id: 5,
+                    x: 0x77f,
+                    y: 0x1d2
+                }),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn flash_mirror_intel_hex() {
+        let mut flash_mirror = FlashMirror::default();
+        flash_mirror.buf[..4].copy_from_slice(&[0x01, 0x02, 0x03, 0x04]);
+        let hex = flash_mirror.to_intel_hex();
+        let lines: Vec<&str> = hex.lines().collect();
+        assert_eq!(lines.len(), FLASH_MIRROR_SIZE / INTEL_HEX_RECORD_SIZE + 1);
+        assert_eq!(lines[0], ":1000000001020304000000000000000000000000E6");
+        assert_eq!(lines[1], ":1000100000000000000000000000000000000000E0");
+        assert_eq!(lines.last(), Some(&":00000001FF"));
+
+        assert_eq!(
+            FlashMirror::from_intel_hex(&hex).unwrap().buf,
+            flash_mirror.buf
+        );
+        let corrupted = hex.replacen(":10000000010203", ":10000000010204", 1);
+        assert!(FlashMirror::from_intel_hex(&corrupted).is_err());
+        let truncated = hex.replace(":00000001FF", "");
+        assert!(FlashMirror::from_intel_hex(&truncated).is_err());
+    }
+
+    #[test]
+    fn flash_regions_are_sorted_and_disjoint() {
+        let regions = FlashMirror::regions();
+        for pair in regions.windows(2) {
+            assert!(pair[0].range.end <= pair[1].range.start);
+        }
+        assert!(regions
+            .iter()
+            .all(|region| region.range.end <= FLASH_MIRROR_SIZE));
+        assert!(regions
+            .iter()
+            .any(|region| region.range == FlashMirror::stick_center_calibration_range()));
+        assert_eq!(
+            FlashMirror::region_at(0x121).map(|region| region.name),
+            Some("Stick center calibration")
+        );
+        assert_eq!(FlashMirror::region_at(0x122), None);
+    }
+
+    #[test]
+    fn usb_bt_mode_round_trip() {
+        for (mode, byte) in [(UsbBtMode::Bluetooth, 0x01), (UsbBtMode::Usb, 0x02)] {
+            let report: Report = mode.into();
+            assert_eq!(report.data()[0], 0x14);
+            assert_eq!(report.payload().len(), USB_BT_CONTROL_PAYLOAD_SIZE);
+            assert_eq!(report.payload()[0], byte);
+            assert!(report.payload()[1..].iter().all(|byte| *byte == 0));
+            assert_eq!(UsbBtMode::try_from(report.payload()[0]).unwrap(), mode);
+        }
+        assert!(UsbBtMode::try_from(0x00).is_err());
+    }
+
+    #[test]
+    fn fake_usb_bt_control_disconnect_is_not_an_error() {
+        let transport = FakeTransport::default();
+        let ds4 = fake_ds4(&transport);
+        ds4.set_usb_bt_control(UsbBtMode::Usb).unwrap();
+        // The device is still there, so it's a real error
+        transport.nak_sends(1);
+        assert!(ds4.set_usb_bt_control(UsbBtMode::Bluetooth).is_err());
+        transport.disconnect();
+        ds4.set_usb_bt_control(UsbBtMode::Bluetooth).unwrap();
+        // Sent once, a retry would reach an already disconnected device
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0][..2], [0x14, 0x02]);
+    }
+
+    #[test]
+    fn fake_test_command_for_unknown_trigger_is_not_sent() {
+        let transport = FakeTransport::default();
+        let ds4 = fake_ds4(&transport);
+        let command = TestCommand::RecordTriggerMinMax(TriggerKeyLeftRight::Unknown, true);
+        assert!(matches!(
+            ds4.set_test_command(command),
+            Err(Error::Message(_))
+        ));
+        assert!(transport.sent().is_empty());
+        ds4.set_test_command(TestCommand::RecordTriggerMinMax(
+            TriggerKeyLeftRight::Right,
+            false,
+        ))
+        .unwrap();
+        ds4.set_test_command(TestCommand::SetPermanent(false))
+            .unwrap();
+        let sent = transport.sent();
+        assert_eq!(sent[0][..5], [0xa0, 0x08, 0x01, 0x02, 0x00]);
+        assert_eq!(sent[1][..4], [0xa0, 0x0a, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn fake_oversized_custom_report_is_not_sent() {
+        let transport = FakeTransport::default();
+        let ds4 = fake_ds4(&transport);
+        let mut report = vec![0u8; 65];
+        report[0] = ReportId::SetTestCommand as u8;
+        assert!(matches!(
+            ds4.send_custom_report(&report),
+            Err(Error::ReportTooLarge(65, 64))
+        ));
+        assert!(transport.sent().is_empty());
+        report.truncate(64);
+        ds4.send_custom_report(&report).unwrap();
+        assert_eq!(transport.sent().len(), 1);
+    }
+
+    fn fake_ds4(transport: &FakeTransport) -> DualShock4 {
+        fake_ds4_with_options(transport, DeviceOptions::default())
+    }
+
+    fn fake_ds4_with_options(transport: &FakeTransport, options: DeviceOptions) -> DualShock4 {
+        let device_info = DeviceIdentity {
+            path: CString::new("fake").unwrap(),
+            vendor_id: SONY_VENDOR_ID,
+            product_id: 0x09cc,
+            serial_number: None,
+            release_number: 0x0100,
+            manufacturer_string: None,
+            product_string: None,
+            interface_number: 3,
+            bus_type: BusType::Usb,
+        };
+        let path = device_info.path.clone();
+        // Firmware info isn't scripted, so it's NAKed while connecting
+        DualShock4::with_transport(path, transport.clone(), device_info, options).unwrap()
+    }
+
+    fn wait_for_counter(ds4: &DualShock4, counter: u8) -> Data {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            match ds4.read_last_data().unwrap() {
+                Some(data) if data.counter() == counter => return data,
+                _ if Instant::now() > deadline => panic!("No input report {}", counter),
+                _ => std::thread::yield_now(),
+            }
+        }
+    }
+
+    #[test]
+    fn data_snapshot_field_names() {
+        let mut data = Data::zeroed();
+        data.set_left_stick_position(StickPosition { x: 10, y: 20 });
+        data.set_face_button(FaceButton::Cross, true);
+        data.buf[5] |= 0b0000_0111;
+        let json = serde_json::to_value(data.snapshot()).unwrap();
+        let keys: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        // Sorted by serde_json, a rename breaks the bug report format
+        assert_eq!(
+            keys,
+            [
+                "accelerometer",
+                "battery_percent",
+                "buttons",
+                "cable_connected",
+                "counter",
+                "gyroscope",
+                "l2_trigger",
+                "left_stick",
+                "r2_trigger",
+                "raw",
+                "right_stick",
+                "timestamp",
+                "touch_packet_counter",
+                "touch_points",
+            ]
+        );
+        assert_eq!(json["left_stick"], serde_json::json!({"x": 10, "y": 20}));
+        assert_eq!(json["buttons"]["cross"], true);
+        assert_eq!(json["buttons"]["d_pad"], "up_left");
+        assert_eq!(json["raw"].as_str().unwrap().len(), DATA_PACKET_SIZE * 2);
+    }
+
+    #[test]
+    fn flash_read_eta_follows_recent_words() {
+        let mut read = FlashMirrorRead::default();
+        for _ in 1..FLASH_READ_MIN_TIMED_WORDS {
+            read.push_word_time(Duration::from_millis(1));
+        }
+        assert_eq!(read.eta(), None);
+        read.push_word_time(Duration::from_millis(1));
+        read.bytes = vec![0; FLASH_MIRROR_SIZE - 200];
+        assert_eq!(read.eta(), Some(Duration::from_millis(100)));
+
+        // Slower words, like after switching to Bluetooth, replace the old average
+        for _ in 0..FLASH_READ_TIMING_WINDOW {
+            read.push_word_time(Duration::from_millis(10));
+        }
+        assert_eq!(read.eta(), Some(Duration::from_secs(1)));
+        assert!(read.flash_mirror().is_none());
+    }
+
+    #[test]
+    fn fake_cancelled_flash_operations() {
+        let transport = FakeTransport::default();
+        let ds4 = fake_ds4(&transport);
+        let id = ReportId::GetIeepData as u8;
+        for _ in 0..20 {
+            transport.push_feature_report(FakeResponse::Report(vec![id, 0xaa, 0x55]), id);
+        }
+        let result = ds4.read_flash_mirror_with_progress(|read| {
+            if read.bytes_read() == 20 {
+                ds4.cancel_token().cancel();
+            }
+        });
+        assert!(matches!(result, Err(Error::Cancelled)));
+        // Checked before every word
+        assert_eq!(transport.sent().len(), 10);
+
+        // The chunked reads don't reset the request of the operation
+        assert!(matches!(ds4.read_test_data(), Err(Error::Cancelled)));
+        let sent = transport.sent().len();
+        assert!(matches!(
+            ds4.calibrate_stick_center(),
+            Err(Error::Cancelled)
+        ));
+        assert!(transport.sent()[sent..]
+            .iter()
+            .all(|report| report[0] != ReportId::SetCalibrationCommand as u8));
+
+        // A new operation isn't affected by the old request
+        ds4.cancel_token().reset();
+        let result = ds4.read_test_data();
+        assert!(!matches!(result, Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn fake_safe_mode_refuses_writes() {
+        let transport = FakeTransport::default();
+        let options = DeviceOptions {
+            safe_mode: true,
+            ..Default::default()
+        };
+        let ds4 = fake_ds4_with_options(&transport, options);
+        assert!(matches!(
+            ds4.set_test_command(TestCommand::SetPermanent(true)),
+            Err(Error::SafeMode)
+        ));
+        assert!(matches!(ds4.calibrate_stick_center(), Err(Error::SafeMode)));
+        assert!(matches!(
+            ds4.send_custom_report(&[ReportId::SetTestCommand as u8]),
+            Err(Error::SafeMode)
+        ));
+        assert!(transport.sent().is_empty());
+        // Reading the flash selects the address and still works
+        let id = ReportId::GetIeepData as u8;
+        transport.push_feature_report(FakeResponse::Report(vec![id, 0x00, 0x00]), id);
+        assert!(ds4.read_permanent().unwrap());
+        assert_eq!(transport.sent().len(), 1);
+    }
+
+    #[test]
+    fn fake_forced_connection_type() {
+        let transport = FakeTransport::default();
+        let options = DeviceOptions {
+            connection_type: Some(ConnectionType::Bluetooth),
+            ..Default::default()
+        };
+        let ds4 = fake_ds4_with_options(&transport, options);
+        assert!(ds4.is_connection_type_forced());
+        assert_eq!(ds4.detected_connection_type(), ConnectionType::Usb);
+        ds4.set_lightbar([1, 2, 3]).unwrap();
+        assert_eq!(
+            transport.written(),
+            [lightbar_output_report(ConnectionType::Bluetooth, [1, 2, 3])]
+        );
+        assert_eq!("BT".parse(), Ok(ConnectionType::Bluetooth));
+    }
+
+    #[test]
+    fn fake_calibration_reads() {
+        let transport = FakeTransport::default();
+        let ds4 = fake_ds4(&transport);
+        let id = ReportId::GetMotionCalibData as u8;
+        let mut report = vec![0x11; MOTION_CALIBRATION_DATA_SIZE + 1];
+        report[0] = id;
+        transport.push_feature_report(FakeResponse::Nak, id);
+        transport.push_feature_report(FakeResponse::Report(report), id);
+        let calibration = ds4.read_motion_calibration_data().unwrap();
+        assert_eq!(calibration.buf, [0x11; MOTION_CALIBRATION_DATA_SIZE]);
+
+        ds4.set_report_retries(0);
+        transport.push_feature_report(FakeResponse::Nak, id);
+        assert!(matches!(
+            ds4.read_motion_calibration_data(),
+            Err(Error::HidError(_))
+        ));
+
+        // The rest of a short read stays zeroed
+        let id = ReportId::GetCalibFlag as u8;
+        transport.push_feature_report(FakeResponse::Report(vec![id, 0x01]), id);
+        assert_eq!(ds4.read_calibration_flag().unwrap().buf, [0x01, 0, 0, 0]);
+    }
+
+    #[test]
+    fn battery_capacity_levels() {
+        let percent = |level: u8, cable: bool| {
+            let mut data = Data::zeroed();
+            data.buf[COMMON_STATUS_OFFSET] = level | if cable { 0b00010000 } else { 0 };
+            data.battery_capacity_percent()
+        };
+        assert_eq!(percent(0, false), 5);
+        assert_eq!(percent(9, true), 95);
+        assert_eq!(percent(10, true), 100);
+        assert_eq!(percent(11, true), 100);
+        assert_eq!(percent(11, false), 100);
+        assert_eq!(percent(14, true), 0);
+    }
+
+    #[test]
+    fn factory_command_bytes() {
+        let bytes = |command: FactoryCommand| <[u8; 3]>::from(command);
+        assert_eq!(bytes(FactoryCommand::SetIeepAddress(0)), [0xff, 0x00, 0x00]);
+        assert_eq!(
+            bytes(FactoryCommand::SetIeepAddress(FLASH_PERMANENT_OFFSET)),
+            [0xff, 0x00, 0x0c]
+        );
+        // The last word read by read_flash_mirror
+        let last_word = (FLASH_MIRROR_SIZE - 2) as u16;
+        assert_eq!(last_word, 0x7fe);
+        assert_eq!(
+            bytes(FactoryCommand::SetIeepAddress(last_word)),
+            [0xff, 0x07, 0xfe]
+        );
+
+        let trigger = |type_| bytes(FactoryCommand::TriggerMinMaxCalibration(type_));
+        assert_eq!(
+            trigger(TriggerMinMaxCalibrationType::StartRecordMinMax(
+                TriggerKeyLeftRight::Both
+            )),
+            [0x02, 0x4a, 0x4a]
+        );
+        assert_eq!(
+            trigger(TriggerMinMaxCalibrationType::SaveMin(
+                TriggerKeyLeftRight::Left
+            )),
+            [0x02, 0x82, 0x82]
+        );
+        assert_eq!(
+            trigger(TriggerMinMaxCalibrationType::SaveMax(
+                TriggerKeyLeftRight::Right
+            )),
+            [0x02, 0x08, 0x08]
+        );
+        assert_eq!(
+            trigger(TriggerMinMaxCalibrationType::SaveMax(
+                TriggerKeyLeftRight::Unknown
+            )),
+            [0x02, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn fake_finished_calibration_data_retries() {
+        let transport = FakeTransport::default();
+        let ds4 = fake_ds4(&transport);
+        let id = ReportId::GetCalibrationData as u8;
+        let mut empty = vec![0u8; CALIBRATION_DATA_SIZE + 1];
+        empty[..3].copy_from_slice(&[id, 0xff, 0xff]);
+        let mut chunk = vec![0u8; CALIBRATION_DATA_SIZE + 1];
+        chunk[..6].copy_from_slice(&[id, 0x03, 0x01, 0x01, 0x00, 0x02]);
+        chunk[6..8].copy_from_slice(&[0x10, 0x20]);
+        transport.push_feature_report(FakeResponse::Report(empty.clone()), id);
+        transport.push_feature_report(FakeResponse::Report(chunk.clone()), id);
+        let CalibrationData::Triggers(triggers) = ds4.read_finished_calibration_data().unwrap()
+        else {
+            panic!("Triggers calibration data expected");
+        };
+        assert_eq!(triggers.buf, [0x10, 0x20]);
+
+        // A device without data isn't read forever, the chunk after the last retry stays queued
+        for _ in 0..=FINISHED_CALIBRATION_DATA_RETRIES {
+            transport.push_feature_report(FakeResponse::Report(empty.clone()), id);
+        }
+        transport.push_feature_report(FakeResponse::Report(chunk), id);
+        assert!(matches!(
+            ds4.read_finished_calibration_data().unwrap(),
+            CalibrationData::None(_)
+        ));
+        assert!(matches!(
+            ds4.read_calibration_data().unwrap(),
+            CalibrationData::Triggers(_)
+        ));
+
+        // Other reads don't retry
+        transport.push_feature_report(FakeResponse::Report(empty), id);
+        assert!(matches!(
+            ds4.read_calibration_data().unwrap(),
+            CalibrationData::None(_)
+        ));
+    }
+
+    #[test]
+    fn suspicious_feature_reports() {
+        let mut firmware_info = [0u8; FIRMWARE_INFO_SIZE];
+        firmware_info[..11].copy_from_slice(b"Sep 21 2018");
+        let mut motion_calibration = [0u8; MOTION_CALIBRATION_DATA_SIZE];
+        motion_calibration[..4].copy_from_slice(&[0xfe, 0xff, 0x03, 0x00]);
+        assert!(!feature_reports_suspicious(
+            &firmware_info,
+            &motion_calibration
+        ));
+        assert!(feature_reports_suspicious(
+            &[0u8; FIRMWARE_INFO_SIZE],
+            &motion_calibration
+        ));
+        // Clone pads without a motion calibration
+        assert!(!feature_reports_suspicious(
+            &firmware_info,
+            &[0u8; MOTION_CALIBRATION_DATA_SIZE]
+        ));
+        // The firmware info returned again for the motion calibration request
+        let stale = &firmware_info[..MOTION_CALIBRATION_DATA_SIZE];
+        assert!(feature_reports_suspicious(&firmware_info, stale));
+    }
+
+    #[test]
+    fn fake_ignored_motion_calibration_write() {
+        let transport = FakeTransport::default();
+        let ds4 = fake_ds4(&transport);
+        let id = ReportId::GetMotionCalibData as u8;
+        let mut report = vec![id; MOTION_CALIBRATION_DATA_SIZE + 1];
+        report[1..].fill(0x11);
+        // The same calibration is read before and after the write
+        for _ in 0..2 {
+            transport.push_feature_report(FakeResponse::Report(report.clone()), id);
+        }
+        let calibration = MotionCalibration {
+            buf: [0x22; MOTION_CALIBRATION_DATA_SIZE],
+        };
+        assert!(ds4.is_write_supported(CalibrationWrite::Motion));
+        assert!(matches!(
+            ds4.write_motion_calibration_verified(&calibration),
+            Err(Error::WriteNotSupported(CalibrationWrite::Motion))
+        ));
+        assert!(!ds4.is_write_supported(CalibrationWrite::Motion));
+        assert!(ds4.is_write_supported(CalibrationWrite::StickCenter));
+        // Rejected without another write
+        let sent = transport.sent().len();
+        assert!(ds4.write_motion_calibration_verified(&calibration).is_err());
+        assert_eq!(transport.sent().len(), sent);
+    }
+
+    #[test]
+    fn fake_flash_stick_center_read() {
+        let transport = FakeTransport::default();
+        let ds4 = fake_ds4(&transport);
+        let id = ReportId::GetIeepData as u8;
+        for word in [[0x10, 0x00], [0x20, 0x00], [0xf0, 0xff], [0x00, 0x00]] {
+            transport.push_feature_report(FakeResponse::Report(vec![id, word[0], word[1]]), id);
+        }
+        // The first address is NAKed and sent again
+        transport.nak_sends(1);
+        let calibration = ds4.read_flash_stick_center_calibration().unwrap();
+        assert_eq!(
+            calibration.buf,
+            [0x10, 0x00, 0x20, 0x00, 0xf0, 0xff, 0x00, 0x00]
+        );
+
+        let addresses: Vec<usize> = transport
+            .sent()
+            .iter()
+            .map(|report| {
+                assert_eq!(report[..2], [ReportId::SetFactoryCommand as u8, 0xff]);
+                u16::from_be_bytes([report[2], report[3]]) as usize
+            })
+            .collect();
+        let expected: Vec<usize> = (0..4)
+            .map(|word| FLASH_STICK_CENTER_CALIBRATION_OFFSET + word * 2)
+            .collect();
+        assert_eq!(addresses, expected);
+    }
+
+    #[test]
+    fn fake_ignored_stick_center_calibration() {
+        let transport = FakeTransport::default();
+        let ds4 = fake_ds4(&transport);
+        let ieep = ReportId::GetIeepData as u8;
+        let flash_words = |transport: &FakeTransport| {
+            for _ in 0..FLASH_STICK_CENTER_CALIBRATION_SIZE / 2 {
+                transport.push_feature_report(FakeResponse::Report(vec![ieep, 0x10, 0x00]), ieep);
+            }
+        };
+        flash_words(&transport);
+        let id = ReportId::GetCalibrationResult as u8;
+        transport.push_feature_report(FakeResponse::Report(vec![id, 0x01, 0x01, 0x01]), id);
+        let id = ReportId::GetCalibrationData as u8;
+        let mut chunk = vec![0u8; CALIBRATION_DATA_SIZE + 1];
+        chunk[..6].copy_from_slice(&[id, 0x01, 0x01, 0x02, 0x00, 0x08]);
+        chunk[6..14].copy_from_slice(&[0x20, 0x00, 0x20, 0x00, 0x20, 0x00, 0x20, 0x00]);
+        transport.push_feature_report(FakeResponse::Report(chunk), id);
+        let mut chunk = vec![0u8; CALIBRATION_DATA_SIZE + 1];
+        chunk[..7].copy_from_slice(&[id, 0x01, 0x01, 0x02, 0x01, 0x01, 0x00]);
+        transport.push_feature_report(FakeResponse::Report(chunk), id);
+        // Permanent mode is on, the flash still has the old center
+        transport.push_feature_report(FakeResponse::Report(vec![ieep, 0x00, 0x00]), ieep);
+        flash_words(&transport);
+
+        assert!(matches!(
+            ds4.calibrate_stick_center(),
+            Err(Error::WriteNotSupported(CalibrationWrite::StickCenter))
+        ));
+        assert!(!ds4.is_write_supported(CalibrationWrite::StickCenter));
+        let commands: Vec<u8> = transport
+            .sent()
+            .iter()
+            .filter(|report| report[0] == ReportId::SetCalibrationCommand as u8)
+            .map(|report| report[1])
+            .collect();
+        let mut expected = vec![0x01];
+        expected.extend([0x03; STICK_CENTER_CALIBRATION_MEASUREMENTS]);
+        expected.push(0x02);
+        assert_eq!(commands, expected);
+    }
+
+    #[test]
+    fn fake_read_last_data_skips_duplicate_counters() {
+        let transport = FakeTransport::default();
+        let ds4 = fake_ds4(&transport);
+        let input_report = |counter: u8, left_x: u8, len: usize| {
+            let mut report = vec![0u8; len];
+            report[0] = USB_INPUT_REPORT_ID;
+            report[1] = left_x;
+            report[7] = counter << 2;
+            report
+        };
+        // The duplicate stops the drain with the freshest report
+        transport.push_input_report(input_report(1, 0x10, DATA_PACKET_SIZE));
+        transport.push_input_report(input_report(2, 0x20, DATA_PACKET_SIZE));
+        transport.push_input_report(input_report(2, 0x30, DATA_PACKET_SIZE));
+        assert_eq!(wait_for_counter(&ds4, 2).left_stick_position().x, 0x20);
+
+        transport.push_input_report(input_report(3, 0x42, 8));
+        assert_eq!(wait_for_counter(&ds4, 3).left_stick_position().x, 0x42);
+        assert_eq!(transport.input_reports_left(), 0);
+    }
+
+    #[test]
+    fn fake_keep_awake_is_throttled() {
+        let transport = FakeTransport::default();
+        let ds4 = fake_ds4(&transport);
+        assert!(!ds4.keep_awake(Duration::ZERO).unwrap());
+        let transport = FakeTransport::default();
+        let options = DeviceOptions {
+            connection_type: Some(ConnectionType::Bluetooth),
+            ..Default::default()
+        };
+        let ds4 = fake_ds4_with_options(&transport, options);
+        assert!(!ds4.keep_awake(Duration::from_secs(60)).unwrap());
+        assert!(ds4.keep_awake(Duration::ZERO).unwrap());
+        ds4.set_lightbar([1, 2, 3]).unwrap();
+        assert!(!ds4.keep_awake(Duration::from_secs(60)).unwrap());
+        let written = transport.written();
+        assert_eq!(
+            written,
+            [
+                keep_awake_output_report(ConnectionType::Bluetooth),
+                lightbar_output_report(ConnectionType::Bluetooth, [1, 2, 3]),
+            ]
+        );
+        // No enable flags, the lightbar isn't touched
+        assert_eq!(written[0][3], 0);
+    }
+
+    #[test]
+    fn fake_output_state_restore() {
+        let transport = FakeTransport::default();
+        let ds4 = fake_ds4(&transport);
+        ds4.restore_output_state().unwrap();
+        assert!(transport.written().is_empty());
+        ds4.set_lightbar([1, 2, 3]).unwrap();
+        ds4.restore_output_state().unwrap();
+        let written = transport.written();
+        assert_eq!(written.len(), 2);
+        assert_eq!(written[0][6..9], [1, 2, 3]);
+        assert_eq!(written[1][6..9], DEFAULT_LIGHTBAR_COLOR);
     }
 }
@@ -0,0 +1,250 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::dual_shock_4::device_clock::DeviceClock;
+use crate::dual_shock_4::{DPadState, Data, FaceButton};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Human taps hold a state longer than this, worn contacts bounce within a few reports
+const MAX_CHATTER_PULSE: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Button {
+    Triangle,
+    Circle,
+    Cross,
+    Square,
+    Up,
+    Down,
+    Left,
+    Right,
+    L1,
+    R1,
+    L2,
+    R2,
+    L3,
+    R3,
+    Share,
+    Options,
+    Ps,
+    TouchpadClick,
+}
+
+impl Button {
+    pub const ALL: [Button; 18] = [
+        Button::Triangle,
+        Button::Circle,
+        Button::Cross,
+        Button::Square,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+        Button::L1,
+        Button::R1,
+        Button::L2,
+        Button::R2,
+        Button::L3,
+        Button::R3,
+        Button::Share,
+        Button::Options,
+        Button::Ps,
+        Button::TouchpadClick,
+    ];
+
+    fn pressed(&self, data: &Data) -> bool {
+        match self {
+            Button::Triangle => data.face_button(FaceButton::Triangle),
+            Button::Circle => data.face_button(FaceButton::Circle),
+            Button::Cross => data.face_button(FaceButton::Cross),
+            Button::Square => data.face_button(FaceButton::Square),
+            Button::Up => matches!(
+                data.d_pad(),
+                DPadState::Up | DPadState::UpLeft | DPadState::UpRight
+            ),
+            Button::Down => matches!(
+                data.d_pad(),
+                DPadState::Down | DPadState::DownLeft | DPadState::DownRight
+            ),
+            Button::Left => matches!(
+                data.d_pad(),
+                DPadState::Left | DPadState::UpLeft | DPadState::DownLeft
+            ),
+            Button::Right => matches!(
+                data.d_pad(),
+                DPadState::Right | DPadState::UpRight | DPadState::DownRight
+            ),
+            Button::L1 => data.l1(),
+            Button::R1 => data.r1(),
+            Button::L2 => data.l2(),
+            Button::R2 => data.r2(),
+            Button::L3 => data.l3(),
+            Button::R3 => data.r3(),
+            Button::Share => data.share(),
+            Button::Options => data.options(),
+            Button::Ps => data.ps(),
+            Button::TouchpadClick => data.t_pad_click(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChatterSettings {
+    pub window: Duration,
+    // A button chatters when more short pulses than this are seen within the window
+    pub threshold: usize,
+}
+
+impl Default for ChatterSettings {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_millis(500),
+            threshold: 4,
+        }
+    }
+}
+
+// Times are of the device clock
+#[derive(Debug, Clone, Default)]
+struct ButtonState {
+    pressed: bool,
+    changed_at: Option<Duration>,
+    // Transitions after a state held shorter than MAX_CHATTER_PULSE
+    short_pulses: VecDeque<Duration>,
+    chatter: u64,
+}
+
+// Counts chatter bursts per button, fed by the device thread with every input report so
+// pulses shorter than a frame aren't missed
+#[derive(Debug, Clone, Default)]
+pub struct ChatterDetector {
+    pub settings: ChatterSettings,
+    // Reports drained in one poll were sent apart, only their timestamps tell how far
+    clock: DeviceClock,
+    buttons: [ButtonState; Button::ALL.len()],
+}
+
+impl ChatterDetector {
+    pub fn push(&mut self, data: &Data) {
+        let now = Duration::from_secs_f64(self.clock.push(data.timestamp()));
+        let settings = self.settings;
+        for (button, state) in Button::ALL.iter().zip(self.buttons.iter_mut()) {
+            let pressed = button.pressed(data);
+            if pressed == state.pressed {
+                continue;
+            }
+            state.pressed = pressed;
+            if let Some(changed_at) = state.changed_at.replace(now) {
+                if now - changed_at < MAX_CHATTER_PULSE {
+                    state.short_pulses.push_back(now);
+                }
+            }
+            while state
+                .short_pulses
+                .front()
+                .is_some_and(|pulse| now - *pulse > settings.window)
+            {
+                state.short_pulses.pop_front();
+            }
+            if state.short_pulses.len() > settings.threshold {
+                state.chatter += 1;
+                state.short_pulses.clear();
+            }
+        }
+    }
+
+    // Chatter bursts per button since the last reset
+    pub fn counts(&self) -> impl Iterator<Item = (Button, u64)> + '_ {
+        Button::ALL
+            .into_iter()
+            .zip(self.buttons.iter().map(|state| state.chatter))
+    }
+
+    // Keeps the settings and the current button states
+    pub fn reset(&mut self) {
+        for state in self.buttons.iter_mut() {
+            state.short_pulses.clear();
+            state.chatter = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dual_shock_4::device_clock::TIMESTAMP_TICK_SECONDS;
+
+    // Report of the cross button sent at the device time
+    fn cross(pressed: bool, at: Duration) -> Data {
+        let mut data = Data::zeroed();
+        data.set_face_button(FaceButton::Cross, pressed);
+        let ticks = (at.as_secs_f64() / TIMESTAMP_TICK_SECONDS).round() as u64;
+        data.buf[10..12].copy_from_slice(&(ticks as u16).to_le_bytes());
+        data
+    }
+
+    fn cross_chatter(detector: &ChatterDetector) -> u64 {
+        detector
+            .counts()
+            .find(|(button, _)| *button == Button::Cross)
+            .map(|(_, chatter)| chatter)
+            .unwrap()
+    }
+
+    // Alternating presses and releases of the cross button with the given state duration
+    fn toggle(detector: &mut ChatterDetector, start: Duration, period: Duration, count: u32) {
+        for i in 0..count {
+            detector.push(&cross(i % 2 == 0, start + period * i));
+        }
+    }
+
+    // Reports keep coming while the button is held, the device clock wraps around without them
+    fn hold(detector: &mut ChatterDetector, pressed: bool, start: Duration, end: Duration) {
+        let mut at = start;
+        while at < end {
+            detector.push(&cross(pressed, at));
+            at += Duration::from_millis(100);
+        }
+    }
+
+    #[test]
+    fn bouncing_contact_is_chatter() {
+        let mut detector = ChatterDetector::default();
+        toggle(&mut detector, Duration::ZERO, Duration::from_millis(4), 12);
+        assert_eq!(cross_chatter(&detector), 2);
+        detector.reset();
+        assert_eq!(cross_chatter(&detector), 0);
+        assert!(detector.counts().all(|(_, chatter)| chatter == 0));
+    }
+
+    #[test]
+    fn fast_tapping_is_not_chatter() {
+        let mut detector = ChatterDetector::default();
+        // Ten taps per second
+        toggle(&mut detector, Duration::ZERO, Duration::from_millis(50), 40);
+        assert_eq!(cross_chatter(&detector), 0);
+    }
+
+    #[test]
+    fn pulses_outside_the_window_are_dropped() {
+        let mut detector = ChatterDetector {
+            settings: ChatterSettings {
+                window: Duration::from_millis(100),
+                threshold: 2,
+            },
+            ..Default::default()
+        };
+        let second = Duration::from_secs(1);
+        let pulse = Duration::from_millis(5);
+        // Short pulses spread over more than the window don't add up
+        toggle(&mut detector, Duration::ZERO, pulse, 3);
+        hold(&mut detector, true, pulse * 3, second);
+        toggle(&mut detector, second, pulse, 3);
+        assert_eq!(cross_chatter(&detector), 0);
+        // A burst within the window does
+        hold(&mut detector, true, second + pulse * 3, second * 2);
+        toggle(&mut detector, second * 2, pulse, 5);
+        assert_eq!(cross_chatter(&detector), 1);
+    }
+}
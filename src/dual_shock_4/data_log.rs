@@ -0,0 +1,313 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+// Binary log of every input report at the native rate. Version 1, integers are little-endian:
+//   header: magic "DS4DLOG\0", version u16, vendor id u16, product id u16,
+//           connection type u8 (0 USB, 1 Bluetooth), serial number length u8, UTF-8 serial number
+//   frames until the end of the file: microseconds of the device clock since the first frame
+//           u64, never decreasing, followed by the 64 bytes of the input report in the USB layout
+
+use crate::dual_shock_4::device_clock::DeviceClock;
+use crate::dual_shock_4::{ConnectionType, Data, DATA_PACKET_SIZE};
+use log::warn;
+use std::io;
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+pub const DATA_LOG_VERSION: u16 = 1;
+pub const DATA_LOG_FILE_EXTENSION: &str = "ds4log";
+const DATA_LOG_MAGIC: [u8; 8] = *b"DS4DLOG\0";
+const DATA_LOG_FRAME_SIZE: usize = 8 + DATA_PACKET_SIZE;
+// About two seconds of USB input between the writes
+const DATA_LOG_BUFFER_SIZE: usize = DATA_LOG_FRAME_SIZE * 2048;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataLogHeader {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub connection_type: ConnectionType,
+    pub serial_number: Option<String>,
+}
+
+impl DataLogHeader {
+    fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let serial_number = self.serial_number.as_deref().unwrap_or("").as_bytes();
+        // Longer serial numbers aren't reported by the controller, cut at a char boundary
+        let mut len = serial_number.len().min(u8::MAX as usize);
+        while std::str::from_utf8(&serial_number[..len]).is_err() {
+            len -= 1;
+        }
+        writer.write_all(&DATA_LOG_MAGIC)?;
+        writer.write_all(&DATA_LOG_VERSION.to_le_bytes())?;
+        writer.write_all(&self.vendor_id.to_le_bytes())?;
+        writer.write_all(&self.product_id.to_le_bytes())?;
+        writer.write_all(&[
+            match self.connection_type {
+                ConnectionType::Usb => 0,
+                ConnectionType::Bluetooth => 1,
+            },
+            len as u8,
+        ])?;
+        writer.write_all(&serial_number[..len])
+    }
+
+    fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(ErrorKind::InvalidData, message);
+        let mut magic = [0u8; DATA_LOG_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != DATA_LOG_MAGIC {
+            return Err(invalid("Not a DualShock 4 data log".to_string()));
+        }
+        let mut fields = [0u8; 8];
+        reader.read_exact(&mut fields)?;
+        let version = u16::from_le_bytes([fields[0], fields[1]]);
+        if version != DATA_LOG_VERSION {
+            return Err(invalid(format!(
+                "Unsupported data log version {}, expected {}",
+                version, DATA_LOG_VERSION
+            )));
+        }
+        let connection_type = match fields[6] {
+            0 => ConnectionType::Usb,
+            1 => ConnectionType::Bluetooth,
+            value => {
+                return Err(invalid(format!(
+                    "Invalid data log connection type {}",
+                    value
+                )))
+            }
+        };
+        let mut serial_number = vec![0u8; fields[7] as usize];
+        reader.read_exact(&mut serial_number)?;
+        let serial_number = String::from_utf8(serial_number)
+            .map_err(|e| invalid(format!("Invalid data log serial number: {}", e)))?;
+        Ok(Self {
+            vendor_id: u16::from_le_bytes([fields[2], fields[3]]),
+            product_id: u16::from_le_bytes([fields[4], fields[5]]),
+            connection_type,
+            serial_number: (!serial_number.is_empty()).then_some(serial_number),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DataLogFrame {
+    pub elapsed_us: u64,
+    // In the common report layout, the firmware of the logged device isn't known
+    pub data: Data,
+}
+
+// The device thread only queues the frames, a writer thread does the file writes
+pub struct DataLogWriter {
+    clock: DeviceClock,
+    frames: Sender<[u8; DATA_LOG_FRAME_SIZE]>,
+    written: Arc<AtomicU64>,
+    handle: JoinHandle<io::Result<()>>,
+}
+
+impl DataLogWriter {
+    // The header is written before returning, so a file that can't be written fails here
+    pub fn start<W: Write + Send + 'static>(writer: W, header: &DataLogHeader) -> io::Result<Self> {
+        let mut writer = BufWriter::with_capacity(DATA_LOG_BUFFER_SIZE, writer);
+        header.write_to(&mut writer)?;
+        writer.flush()?;
+        let (frames, receiver) = channel::<[u8; DATA_LOG_FRAME_SIZE]>();
+        let written = Arc::new(AtomicU64::new(0));
+        let thread_written = written.clone();
+        let handle = std::thread::Builder::new()
+            .name("ds4-data-log".to_string())
+            .spawn(move || {
+                for frame in receiver {
+                    writer.write_all(&frame)?;
+                    thread_written.fetch_add(1, Ordering::Relaxed);
+                }
+                writer.flush()
+            })?;
+        Ok(Self {
+            clock: DeviceClock::default(),
+            frames,
+            written,
+            handle,
+        })
+    }
+
+    // Reports drained by one poll were sent apart, so the frames are timed by the device clock
+    pub fn push(&mut self, data: &Data) {
+        let elapsed_us = (self.clock.push(data.timestamp()) * 1_000_000f64).round() as u64;
+        let mut frame = [0u8; DATA_LOG_FRAME_SIZE];
+        frame[..8].copy_from_slice(&elapsed_us.to_le_bytes());
+        frame[8..].copy_from_slice(&data.buf);
+        // A failed writer is reported by finish
+        let _ = self.frames.send(frame);
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    // Writes the queued frames, returns the count of all written ones
+    pub fn finish(self) -> io::Result<u64> {
+        drop(self.frames);
+        match self.handle.join() {
+            Ok(result) => result.map(|()| self.written.load(Ordering::Relaxed)),
+            Err(_) => Err(io::Error::other("Data log writer stopped unexpectedly")),
+        }
+    }
+}
+
+// Iterates the frames of a data log, a cut last frame ends the iteration without an error
+pub struct DataLogReader<R: Read> {
+    reader: R,
+    header: DataLogHeader,
+    truncated: bool,
+}
+
+impl<R: Read> DataLogReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let header = DataLogHeader::read_from(&mut reader)?;
+        Ok(Self {
+            reader,
+            header,
+            truncated: false,
+        })
+    }
+
+    pub fn header(&self) -> &DataLogHeader {
+        &self.header
+    }
+
+    // The log ended in the middle of a frame, like after a crash while recording
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    fn read_frame(&mut self) -> io::Result<Option<DataLogFrame>> {
+        let mut frame = [0u8; DATA_LOG_FRAME_SIZE];
+        let mut len = 0;
+        while len < frame.len() {
+            match self.reader.read(&mut frame[len..]) {
+                Ok(0) => break,
+                Ok(read) => len += read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if len < frame.len() {
+            if len > 0 {
+                warn!("Data log is truncated, {} bytes of the last frame", len);
+                self.truncated = true;
+            }
+            return Ok(None);
+        }
+        let mut data = Data::zeroed();
+        data.buf.copy_from_slice(&frame[8..]);
+        Ok(Some(DataLogFrame {
+            elapsed_us: u64::from_le_bytes(frame[..8].try_into().unwrap()),
+            data,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for DataLogReader<R> {
+    type Item = io::Result<DataLogFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.truncated {
+            return None;
+        }
+        self.read_frame().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Shared buffer, the writer thread owns its end
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn header() -> DataLogHeader {
+        DataLogHeader {
+            vendor_id: 0x054c,
+            product_id: 0x09cc,
+            connection_type: ConnectionType::Bluetooth,
+            serial_number: Some("a4:53:85:01:02:03".to_string()),
+        }
+    }
+
+    // Reports 2 ms apart
+    fn data(counter: u8) -> Data {
+        let mut data = Data::zeroed();
+        data.buf[0] = 0x01;
+        data.buf[7] = counter << 2;
+        let timestamp = u16::from(counter) * 375;
+        data.buf[10..12].copy_from_slice(&timestamp.to_le_bytes());
+        data
+    }
+
+    fn log(frames: u8) -> Vec<u8> {
+        let buffer = SharedBuffer::default();
+        let mut writer = DataLogWriter::start(buffer.clone(), &header()).unwrap();
+        for counter in 0..frames {
+            writer.push(&data(counter));
+        }
+        assert_eq!(writer.finish().unwrap(), u64::from(frames));
+        let bytes = buffer.0.lock().unwrap().clone();
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = log(3);
+        let mut reader = DataLogReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.header(), &header());
+        let frames: Vec<DataLogFrame> = reader.by_ref().map(Result::unwrap).collect();
+        assert_eq!(
+            frames.iter().map(|f| f.elapsed_us).collect::<Vec<_>>(),
+            [0, 2000, 4000]
+        );
+        assert_eq!(frames[2].data.counter(), 2);
+        assert_eq!(frames[2].data.buf, data(2).buf);
+        assert!(!reader.is_truncated());
+    }
+
+    #[test]
+    fn truncated_log_keeps_complete_frames() {
+        let mut bytes = log(3);
+        bytes.truncate(bytes.len() - 10);
+        let mut reader = DataLogReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.by_ref().map(Result::unwrap).count(), 2);
+        assert!(reader.is_truncated());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn header_is_validated() {
+        let mut bytes = log(0);
+        bytes[DATA_LOG_MAGIC.len()] = 2;
+        let error = DataLogReader::new(bytes.as_slice()).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("version 2"));
+
+        bytes[0] = b'X';
+        assert!(DataLogReader::new(bytes.as_slice()).is_err());
+        // Cut in the header
+        assert!(DataLogReader::new(&log(0)[..12]).is_err());
+    }
+}
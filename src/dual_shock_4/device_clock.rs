@@ -0,0 +1,36 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+// Device clock ticks are 16/3 µs, the 16-bit counter wraps around about every 350 ms
+pub const TIMESTAMP_TICK_SECONDS: f64 = 16f64 / 3f64 / 1_000_000f64;
+
+// Report timestamps unwrapped into seconds since the first report. Reports drained together
+// keep their own times this way, a gap longer than the wrap around isn't seen
+#[derive(Debug, Clone, Default)]
+pub struct DeviceClock {
+    last: Option<u16>,
+    seconds: f64,
+}
+
+impl DeviceClock {
+    pub fn push(&mut self, timestamp: u16) -> f64 {
+        if let Some(last) = self.last {
+            self.seconds += f64::from(timestamp.wrapping_sub(last)) * TIMESTAMP_TICK_SECONDS;
+        }
+        self.last = Some(timestamp);
+        self.seconds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_clock_wraps_around() {
+        let mut clock = DeviceClock::default();
+        assert_eq!(clock.push(u16::MAX - 2), 0f64);
+        let seconds = clock.push(3);
+        assert!((seconds - 6f64 * TIMESTAMP_TICK_SECONDS).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,408 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+// Ownership model: the device handle is moved onto its own thread and is never shared.
+// Requests from the UI are queued as commands and executed in order between input
+// reads, so feature reports can't interleave with reads on the same handle.
+// Only the freshest input Data crosses back to the UI thread.
+
+use crate::dual_shock_4::{Data, Error, Result};
+use hidapi::HidDevice;
+use log::warn;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// Polling stops after a read error until a command arrives or the backoff passes
+const READ_ERROR_BACKOFF: Duration = Duration::from_millis(500);
+// Long sequences like the flash read still let the input through
+const MAX_COMMANDS_BETWEEN_POLLS: usize = 4;
+// While paused, the thread waits this long for a command before checking the flag again
+const PAUSED_COMMAND_WAIT: Duration = Duration::from_millis(50);
+
+// HID operations used by the device thread
+pub trait HidTransport: Send + 'static {
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize>;
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize>;
+    fn send_feature_report(&self, data: &[u8]) -> Result<()>;
+    fn write(&self, data: &[u8]) -> Result<usize>;
+}
+
+impl HidTransport for HidDevice {
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> Result<usize> {
+        Ok(HidDevice::read_timeout(self, buf, timeout_ms)?)
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize> {
+        Ok(HidDevice::get_feature_report(self, buf)?)
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        Ok(HidDevice::send_feature_report(self, data)?)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        Ok(HidDevice::write(self, data)?)
+    }
+}
+
+enum Command {
+    GetFeatureReport(Vec<u8>, Sender<Result<(Vec<u8>, usize)>>),
+    SendFeatureReport(Vec<u8>, Sender<Result<()>>),
+    WriteOutputReport(Vec<u8>, Sender<Result<usize>>),
+    Stop,
+}
+
+// Commands queued or running on the device thread
+#[derive(Default)]
+struct Busy {
+    in_flight: usize,
+    since: Option<Instant>,
+}
+
+// Counted from queueing until the command is executed or discarded with the stopped thread,
+// so a failed command can't leave the count behind
+struct InFlight(Arc<Mutex<Busy>>);
+
+impl InFlight {
+    fn new(busy: &Arc<Mutex<Busy>>) -> Self {
+        let mut state = lock(busy);
+        if state.in_flight == 0 {
+            state.since = Some(Instant::now());
+        }
+        state.in_flight += 1;
+        Self(busy.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut state = lock(&self.0);
+        state.in_flight -= 1;
+        if state.in_flight == 0 {
+            state.since = None;
+        }
+    }
+}
+
+// Keeps the thread counted as busy between the commands of a longer operation
+pub struct BusyGuard {
+    _in_flight: InFlight,
+}
+
+#[derive(Default)]
+struct Input {
+    data: Option<Data>,
+    // Reported once to the next reader
+    error: Option<String>,
+    // The last poll failed
+    failing: bool,
+}
+
+pub struct DeviceThread {
+    commands: Sender<(Command, InFlight)>,
+    input: Arc<Mutex<Input>>,
+    busy: Arc<Mutex<Busy>>,
+    // The input isn't read while set, commands still run
+    paused: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl DeviceThread {
+    // Poll is called between commands and must return within a few milliseconds
+    pub fn spawn<T: HidTransport>(
+        transport: T,
+        poll: impl FnMut(&T) -> Result<Option<Data>> + Send + 'static,
+    ) -> Result<Self> {
+        let (commands, receiver) = channel();
+        let input = Arc::new(Mutex::new(Input::default()));
+        let thread_input = input.clone();
+        let paused = Arc::new(AtomicBool::new(false));
+        let thread_paused = paused.clone();
+        let handle = std::thread::Builder::new()
+            .name("ds4-device".to_string())
+            .spawn(move || run(transport, receiver, thread_input, thread_paused, poll))
+            .map_err(|e| format!("Cannot start the device thread: {}", e))?;
+        Ok(Self {
+            commands,
+            input,
+            busy: Arc::default(),
+            paused,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize> {
+        let (report, len) = self.request(|reply| Command::GetFeatureReport(buf.to_vec(), reply))?;
+        buf.copy_from_slice(&report);
+        Ok(len)
+    }
+
+    pub fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        self.request(|reply| Command::SendFeatureReport(data.to_vec(), reply))
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<usize> {
+        self.request(|reply| Command::WriteOutputReport(data.to_vec(), reply))
+    }
+
+    // Gives up waiting when the device thread is stuck, the write may still happen later
+    pub fn write_timeout(&self, data: &[u8], timeout: Duration) -> Result<usize> {
+        let (reply, result) = channel();
+        self.queue(Command::WriteOutputReport(data.to_vec(), reply))?;
+        result
+            .recv_timeout(timeout)
+            .map_err(|_| Error::Message("Device thread didn't respond".to_string()))?
+    }
+
+    pub fn last_data(&self) -> Result<Option<Data>> {
+        let mut input = lock(&self.input);
+        match input.error.take() {
+            Some(error) => Err(Error::Message(error)),
+            None => Ok(input.data.clone()),
+        }
+    }
+
+    // Unlike last_data, doesn't take the reported error
+    pub fn is_input_failing(&self) -> bool {
+        lock(&self.input).failing
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    // How long the device thread has had commands to execute, None when it's idle
+    pub fn busy_for(&self) -> Option<Duration> {
+        lock(&self.busy).since.map(|since| since.elapsed())
+    }
+
+    pub fn hold_busy(&self) -> BusyGuard {
+        BusyGuard {
+            _in_flight: InFlight::new(&self.busy),
+        }
+    }
+
+    // Queues the command and waits for its result
+    fn request<R>(&self, command: impl FnOnce(Sender<Result<R>>) -> Command) -> Result<R> {
+        let (reply, result) = channel();
+        self.queue(command(reply))?;
+        result.recv().map_err(|_| device_thread_stopped())?
+    }
+
+    fn queue(&self, command: Command) -> Result<()> {
+        self.commands
+            .send((command, InFlight::new(&self.busy)))
+            .map_err(|_| device_thread_stopped())
+    }
+}
+
+impl Drop for DeviceThread {
+    fn drop(&mut self) {
+        let _ = self.queue(Command::Stop);
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                warn!("Device thread panicked");
+            }
+        }
+    }
+}
+
+fn run<T: HidTransport>(
+    transport: T,
+    commands: Receiver<(Command, InFlight)>,
+    input: Arc<Mutex<Input>>,
+    paused: Arc<AtomicBool>,
+    mut poll: impl FnMut(&T) -> Result<Option<Data>>,
+) {
+    let mut failed = false;
+    let mut commands_since_poll = 0;
+    loop {
+        let paused = paused.load(Ordering::Relaxed);
+        if paused || commands_since_poll < MAX_COMMANDS_BETWEEN_POLLS {
+            let wait = if paused {
+                Some(PAUSED_COMMAND_WAIT)
+            } else if failed {
+                Some(READ_ERROR_BACKOFF)
+            } else {
+                None
+            };
+            let command = match wait {
+                Some(wait) => commands.recv_timeout(wait).map_err(|e| match e {
+                    RecvTimeoutError::Timeout => TryRecvError::Empty,
+                    RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+                }),
+                None => commands.try_recv(),
+            };
+            match command {
+                Ok((Command::Stop, _)) | Err(TryRecvError::Disconnected) => return,
+                Ok((command, in_flight)) => {
+                    execute(&transport, command, in_flight);
+                    commands_since_poll += 1;
+                    continue;
+                }
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+        if paused {
+            continue;
+        }
+        commands_since_poll = 0;
+        match poll(&transport) {
+            Ok(data) => {
+                failed = false;
+                let mut input = lock(&input);
+                input.failing = false;
+                if data.is_some() {
+                    input.data = data;
+                }
+            }
+            Err(error) => {
+                let mut input = lock(&input);
+                // The same error would be repeated on every poll
+                if !failed {
+                    input.error = Some(error.to_string());
+                }
+                input.failing = true;
+                failed = true;
+            }
+        }
+    }
+}
+
+// The caller may have given up waiting, so the replies are best-effort.
+// The command stops counting as in flight before the reply wakes the caller
+fn execute(transport: &impl HidTransport, command: Command, in_flight: InFlight) {
+    match command {
+        Command::GetFeatureReport(mut buf, reply) => {
+            let result = transport.get_feature_report(&mut buf);
+            drop(in_flight);
+            let _ = reply.send(result.map(|len| (buf, len)));
+        }
+        Command::SendFeatureReport(data, reply) => {
+            let result = transport.send_feature_report(&data);
+            drop(in_flight);
+            let _ = reply.send(result);
+        }
+        Command::WriteOutputReport(data, reply) => {
+            let result = transport.write(&data);
+            drop(in_flight);
+            let _ = reply.send(result);
+        }
+        Command::Stop => {}
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn device_thread_stopped() -> Error {
+    Error::Message("Device thread stopped".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dual_shock_4::fake_transport::{FakeResponse, FakeTransport};
+
+    fn spawn(transport: &FakeTransport) -> Arc<DeviceThread> {
+        let thread = DeviceThread::spawn(transport.clone(), |transport| {
+            transport.read_timeout(&mut [], 0)?;
+            Ok(None)
+        });
+        Arc::new(thread.unwrap())
+    }
+
+    #[test]
+    fn commands_keep_order() {
+        let transport = FakeTransport::default();
+        let thread = spawn(&transport);
+        for id in 0..20 {
+            thread.send_feature_report(&[id]).unwrap();
+        }
+        transport.push_feature_report(FakeResponse::Report(vec![0x00, 0xab, 0xab]), 0x00);
+        let mut buf = [0u8; 3];
+        assert_eq!(thread.get_feature_report(&mut buf).unwrap(), 3);
+        assert_eq!(buf, [0x00, 0xab, 0xab]);
+
+        let sent: Vec<u8> = transport.order().into_iter().flatten().collect();
+        assert_eq!(sent, (0..20).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn queued_commands_do_not_starve_polling() {
+        let transport = FakeTransport::default();
+        let thread = spawn(&transport);
+        let senders: Vec<_> = (0..8)
+            .map(|id| {
+                let thread = thread.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        thread.send_feature_report(&[id]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.join().unwrap();
+        }
+
+        let order = transport.order();
+        let longest_run = order
+            .split(Option::is_none)
+            .map(<[Option<u8>]>::len)
+            .max()
+            .unwrap();
+        assert!(longest_run <= MAX_COMMANDS_BETWEEN_POLLS);
+    }
+
+    #[test]
+    fn busy_is_cleared_after_failed_commands() {
+        let transport = FakeTransport::default();
+        let thread = spawn(&transport);
+        assert!(thread.busy_for().is_none());
+        transport.nak_sends(1);
+        assert!(thread.send_feature_report(&[1]).is_err());
+        assert!(thread.busy_for().is_none());
+        thread.send_feature_report(&[1]).unwrap();
+        assert!(thread.busy_for().is_none());
+    }
+
+    #[test]
+    fn no_input_reads_while_paused() {
+        let transport = FakeTransport::default();
+        let thread = spawn(&transport);
+        thread.set_paused(true);
+        // A poll may still be running when the flag is set, it's done before the command
+        thread.send_feature_report(&[1]).unwrap();
+        let paused_at = transport.order().len();
+        std::thread::sleep(PAUSED_COMMAND_WAIT * 3);
+        thread.send_feature_report(&[2]).unwrap();
+        assert_eq!(transport.order()[paused_at - 1..], [Some(1), Some(2)]);
+
+        thread.set_paused(false);
+        while !transport.order().ends_with(&[None]) {
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn poll_error_is_reported_once() {
+        let thread = DeviceThread::spawn(FakeTransport::default(), |_| {
+            Err("Device disconnected".to_string().into())
+        })
+        .unwrap();
+        while thread.last_data().is_ok() {
+            std::thread::yield_now();
+        }
+        // Commands still run while polling fails
+        thread.send_feature_report(&[1]).unwrap();
+        thread.write(&[2]).unwrap();
+        assert!(thread.last_data().unwrap().is_none());
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+// Scriptable HidTransport, tests queue the device answers and check what was sent
+
+use crate::dual_shock_4::device_thread::HidTransport;
+use crate::dual_shock_4::{Error, Result};
+use hidapi::HidError;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+pub enum FakeResponse {
+    // Shorter reports than the requested buffer are short reads
+    Report(Vec<u8>),
+    // Stalled request, like a device that doesn't support the report
+    Nak,
+}
+
+#[derive(Default)]
+struct FakeState {
+    input_reports: VecDeque<Vec<u8>>,
+    // Answers by report id, unscripted reports are NAKed
+    feature_reports: HashMap<u8, VecDeque<FakeResponse>>,
+    send_naks: usize,
+    // Every call fails, like after the device was unplugged
+    disconnected: bool,
+    sent: Vec<Vec<u8>>,
+    written: Vec<Vec<u8>>,
+    // Ids of the sent feature reports with None for every input read, in the order of the calls
+    order: Vec<Option<u8>>,
+}
+
+// Clones share the script, one is moved to the device thread
+#[derive(Clone, Default)]
+pub struct FakeTransport {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeTransport {
+    pub fn push_input_report(&self, report: Vec<u8>) {
+        self.state().input_reports.push_back(report);
+    }
+
+    pub fn push_feature_report(&self, response: FakeResponse, id: u8) {
+        self.state()
+            .feature_reports
+            .entry(id)
+            .or_default()
+            .push_back(response);
+    }
+
+    // The next sent feature reports fail
+    pub fn nak_sends(&self, count: usize) {
+        self.state().send_naks = count;
+    }
+
+    pub fn disconnect(&self) {
+        self.state().disconnected = true;
+    }
+
+    pub fn sent(&self) -> Vec<Vec<u8>> {
+        self.state().sent.clone()
+    }
+
+    pub fn written(&self) -> Vec<Vec<u8>> {
+        self.state().written.clone()
+    }
+
+    pub fn order(&self) -> Vec<Option<u8>> {
+        self.state().order.clone()
+    }
+
+    pub fn input_reports_left(&self) -> usize {
+        self.state().input_reports.len()
+    }
+
+    fn state(&self) -> MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap()
+    }
+}
+
+fn nak() -> Error {
+    Error::HidError(HidError::HidApiError {
+        message: "Fake NAK".to_string(),
+    })
+}
+
+fn disconnected() -> Error {
+    Error::HidError(HidError::HidApiError {
+        message: "Fake device disconnected".to_string(),
+    })
+}
+
+fn copy(report: &[u8], buf: &mut [u8]) -> usize {
+    let len = report.len().min(buf.len());
+    buf[..len].copy_from_slice(&report[..len]);
+    len
+}
+
+impl HidTransport for FakeTransport {
+    fn read_timeout(&self, buf: &mut [u8], _timeout_ms: i32) -> Result<usize> {
+        let mut state = self.state();
+        state.order.push(None);
+        if state.disconnected {
+            return Err(disconnected());
+        }
+        Ok(state
+            .input_reports
+            .pop_front()
+            .map_or(0, |report| copy(&report, buf)))
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize> {
+        let response = self
+            .state()
+            .feature_reports
+            .get_mut(&buf[0])
+            .and_then(VecDeque::pop_front);
+        match response {
+            Some(FakeResponse::Report(report)) => Ok(copy(&report, buf)),
+            Some(FakeResponse::Nak) | None => Err(nak()),
+        }
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        let mut state = self.state();
+        state.order.push(Some(data[0]));
+        if state.disconnected {
+            return Err(disconnected());
+        }
+        if state.send_naks > 0 {
+            state.send_naks -= 1;
+            return Err(nak());
+        }
+        state.sent.push(data.to_vec());
+        Ok(())
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        self.state().written.push(data.to_vec());
+        Ok(data.len())
+    }
+}
@@ -1,6 +1,22 @@
 // Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
 // SPDX-License-Identifier: GPL-3.0
 
+use crate::dual_shock_4::{ConnectionType, Error, Result};
+
+const USB_OUTPUT_REPORT_SIZE: usize = 32;
+const BLUETOOTH_OUTPUT_REPORT_ID: u8 = 0x11;
+const BLUETOOTH_OUTPUT_REPORT_SIZE: usize = 78;
+// Enables HID output and the CRC of a Bluetooth output report
+const BLUETOOTH_OUTPUT_REPORT_FLAGS: u8 = 0xc0;
+// Bluetooth HID header of an output report, the CRC covers it as well
+const BLUETOOTH_OUTPUT_CRC_SEED: u8 = 0xa2;
+const OUTPUT_ENABLE_RUMBLE: u8 = 0x01;
+const OUTPUT_ENABLE_LIGHTBAR: u8 = 0x02;
+const OUTPUT_LIGHTBAR_OFFSET: usize = 6;
+// Largest report the HID interface accepts, report id included
+const USB_MAX_REPORT_SIZE: usize = 64;
+const BLUETOOTH_MAX_REPORT_SIZE: usize = BLUETOOTH_OUTPUT_REPORT_SIZE;
+
 #[derive(Debug)]
 pub struct Report {
     id: ReportId,
@@ -77,3 +93,124 @@ pub enum ReportId {
     GetFirmInfo = 0xa3,
     GetTestData = 0xa4,
 }
+
+pub fn max_report_size(connection_type: ConnectionType) -> usize {
+    match connection_type {
+        ConnectionType::Usb => USB_MAX_REPORT_SIZE,
+        ConnectionType::Bluetooth => BLUETOOTH_MAX_REPORT_SIZE,
+    }
+}
+
+// Hand-typed reports are checked before sending, the driver rejects a too long one with an
+// error that doesn't mention the size
+pub fn check_report_size(report: &[u8], connection_type: ConnectionType) -> Result<()> {
+    let max = max_report_size(connection_type);
+    if report.len() > max {
+        return Err(Error::ReportTooLarge(report.len(), max));
+    }
+    Ok(())
+}
+
+// Output report that changes only the lightbar color, rumble and flashing stay disabled
+pub fn lightbar_output_report(connection_type: ConnectionType, color: [u8; 3]) -> Vec<u8> {
+    output_report(connection_type, OUTPUT_ENABLE_LIGHTBAR, color)
+}
+
+// Stops the rumble and sets the lightbar color, zero rumble values are sent as well
+pub fn reset_output_report(connection_type: ConnectionType, color: [u8; 3]) -> Vec<u8> {
+    output_report(
+        connection_type,
+        OUTPUT_ENABLE_RUMBLE | OUTPUT_ENABLE_LIGHTBAR,
+        color,
+    )
+}
+
+// Output report without enable flags, the lightbar and rumble keep their state
+pub fn keep_awake_output_report(connection_type: ConnectionType) -> Vec<u8> {
+    output_report(connection_type, 0, [0; 3])
+}
+
+fn output_report(connection_type: ConnectionType, flags: u8, color: [u8; 3]) -> Vec<u8> {
+    match connection_type {
+        ConnectionType::Usb => {
+            let mut report = vec![0u8; USB_OUTPUT_REPORT_SIZE];
+            report[0] = ReportId::OutputDevice as u8;
+            report[1] = flags;
+            report[OUTPUT_LIGHTBAR_OFFSET..OUTPUT_LIGHTBAR_OFFSET + 3].copy_from_slice(&color);
+            report
+        }
+        ConnectionType::Bluetooth => {
+            let mut report = vec![0u8; BLUETOOTH_OUTPUT_REPORT_SIZE];
+            report[0] = BLUETOOTH_OUTPUT_REPORT_ID;
+            report[1] = BLUETOOTH_OUTPUT_REPORT_FLAGS;
+            report[3] = flags;
+            // Two bytes longer header than the USB report
+            let lightbar_offset = OUTPUT_LIGHTBAR_OFFSET + 2;
+            report[lightbar_offset..lightbar_offset + 3].copy_from_slice(&color);
+            let crc_offset = BLUETOOTH_OUTPUT_REPORT_SIZE - 4;
+            let crc = crc32(
+                std::iter::once(BLUETOOTH_OUTPUT_CRC_SEED)
+                    .chain(report[..crc_offset].iter().copied()),
+            );
+            report[crc_offset..].copy_from_slice(&crc.to_le_bytes());
+            report
+        }
+    }
+}
+
+fn crc32(bytes: impl Iterator<Item = u8>) -> u32 {
+    let mut crc = u32::MAX;
+    for byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_size_boundary() {
+        assert!(check_report_size(&[0u8; 64], ConnectionType::Usb).is_ok());
+        assert!(matches!(
+            check_report_size(&[0u8; 65], ConnectionType::Usb),
+            Err(Error::ReportTooLarge(65, 64))
+        ));
+        assert!(check_report_size(&[0u8; 78], ConnectionType::Bluetooth).is_ok());
+        assert!(check_report_size(&[0u8; 79], ConnectionType::Bluetooth).is_err());
+    }
+
+    #[test]
+    fn usb_lightbar_report() {
+        let report = lightbar_output_report(ConnectionType::Usb, [0x10, 0x20, 0x30]);
+        assert_eq!(report.len(), USB_OUTPUT_REPORT_SIZE);
+        assert_eq!(&report[..9], &[0x05, 0x02, 0, 0, 0, 0, 0x10, 0x20, 0x30]);
+        assert!(report[9..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn usb_reset_report_stops_rumble() {
+        let report = reset_output_report(ConnectionType::Usb, [0, 0, 0x40]);
+        assert_eq!(&report[..9], &[0x05, 0x03, 0, 0, 0, 0, 0, 0, 0x40]);
+    }
+
+    #[test]
+    fn bluetooth_lightbar_report_crc() {
+        let report = lightbar_output_report(ConnectionType::Bluetooth, [0xff, 0x00, 0x00]);
+        assert_eq!(report.len(), BLUETOOTH_OUTPUT_REPORT_SIZE);
+        assert_eq!(
+            &report[..11],
+            &[0x11, 0xc0, 0, 0x02, 0, 0, 0, 0, 0xff, 0, 0]
+        );
+        assert_eq!(crc32(b"123456789".iter().copied()), 0xcbf43926);
+        assert_eq!(&report[74..], &[0xa3, 0xc5, 0x17, 0x12]);
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+// Input report counter is 6 bits, gaps of a whole turn can't be detected
+const COUNTER_MODULO: u8 = 64;
+
+// Counts input reports missing between the received ones, fed by the device thread with every
+// drained report, not only the freshest one shown by the UI
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PacketLossTracker {
+    last_counter: Option<u8>,
+    received: u64,
+    lost: u64,
+}
+
+impl PacketLossTracker {
+    pub fn push(&mut self, counter: u8) {
+        if let Some(last_counter) = self.last_counter {
+            let gap = counter.wrapping_sub(last_counter) % COUNTER_MODULO;
+            // The same report read again
+            if gap == 0 {
+                return;
+            }
+            self.lost += gap as u64 - 1;
+        }
+        self.last_counter = Some(counter);
+        self.received += 1;
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    // Part of the reports sent by the controller that weren't received
+    pub fn loss_percent(&self) -> f64 {
+        let total = self.received + self.lost;
+        if total == 0 {
+            return 0f64;
+        }
+        self.lost as f64 / total as f64 * 100f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(counters: &[u8]) -> PacketLossTracker {
+        let mut tracker = PacketLossTracker::default();
+        for counter in counters {
+            tracker.push(*counter);
+        }
+        tracker
+    }
+
+    #[test]
+    fn gaps_counted_across_wraparound() {
+        let tracker = track(&[61, 62, 0, 1, 4]);
+        assert_eq!((tracker.received(), tracker.lost()), (5, 3));
+        assert!((tracker.loss_percent() - 37.5).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn repeated_counters_are_not_lost() {
+        let tracker = track(&[10, 10, 11, 11, 11, 12]);
+        assert_eq!((tracker.received(), tracker.lost()), (3, 0));
+        assert_eq!(PacketLossTracker::default().loss_percent(), 0f64);
+    }
+}
@@ -0,0 +1,589 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+// Decoding of report payloads, kept apart from the device I/O to be testable with captured bytes
+
+use crate::dual_shock_4::{
+    AnalogStickCalibrationType, CalibrationData, CalibrationDeviceType, CalibrationFlag,
+    CalibrationResult, CalibrationState, ConnectionType, Data, FirmwareInfo, MotionCalibration,
+    Result, StickCenterCalibration, StickMinMaxCalibration, TrackRecord, TriggersCalibration,
+};
+use log::info;
+use std::fmt::Debug;
+
+const STICK_CENTER_CALIBRATION_SIZE: usize = 8;
+const STICK_MIN_MAX_CALIBRATION_SIZE: usize = 16;
+
+fn fixed<const N: usize>(payload: &[u8], name: &str) -> Result<[u8; N]> {
+    payload
+        .try_into()
+        .map_err(|_| format!("Invalid {} size {}", name, payload.len()).into())
+}
+
+pub fn motion_calibration(payload: &[u8]) -> Result<MotionCalibration> {
+    Ok(MotionCalibration {
+        buf: fixed(payload, "Motion Calibration")?,
+    })
+}
+
+pub fn calibration_flag(payload: &[u8]) -> Result<CalibrationFlag> {
+    Ok(CalibrationFlag {
+        buf: fixed(payload, "Calibration Flag")?,
+    })
+}
+
+pub fn calibration_state(payload: &[u8]) -> Result<CalibrationState> {
+    Ok(fixed::<3>(payload, "Calibration State")?.try_into()?)
+}
+
+pub fn calibration_result(payload: &[u8]) -> Result<CalibrationResult> {
+    Ok(fixed::<3>(payload, "Calibration Result")?.try_into()?)
+}
+
+pub fn firmware_info(payload: &[u8]) -> Result<FirmwareInfo> {
+    Ok(FirmwareInfo {
+        buf: fixed(payload, "Firmware Info")?,
+    })
+}
+
+pub fn track_record(payload: &[u8]) -> Result<TrackRecord> {
+    Ok(TrackRecord {
+        buf: fixed(payload, "Track Record")?,
+    })
+}
+
+pub fn ieep_data(payload: &[u8]) -> Result<[u8; 2]> {
+    fixed(payload, "IEEP Data")
+}
+
+// None for the terminator chunk
+pub fn calibration_chunk_key(payload: &[u8]) -> Result<Option<CalibrationDeviceType>> {
+    let device: CalibrationDeviceType = [payload[0], payload[1], 0x00, 0x00].try_into()?;
+    Ok((device != CalibrationDeviceType::None).then_some(device))
+}
+
+pub fn test_data_chunk_key(payload: &[u8]) -> Result<Option<[u8; 2]>> {
+    Ok((payload[0] != 255).then(|| [payload[0], payload[1]]))
+}
+
+// Reassembled calibration data of the last calibrated device
+pub fn calibration_data(device: CalibrationDeviceType, data: Vec<u8>) -> Result<CalibrationData> {
+    let too_short = || {
+        format!(
+            "Calibration data of {:?} is too short: {}",
+            device,
+            data.len()
+        )
+    };
+    Ok(match device {
+        CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::Center) => {
+            let mut samples_data = data.chunks_exact(STICK_CENTER_CALIBRATION_SIZE);
+            let calculated = StickCenterCalibration {
+                buf: fixed(samples_data.next().ok_or_else(too_short)?, "Stick Center")?,
+            };
+            let mut samples: Vec<StickCenterCalibration> = Vec::new();
+            if let Some(samples_count) = data.get(STICK_CENTER_CALIBRATION_SIZE) {
+                let first_sample = STICK_CENTER_CALIBRATION_SIZE + 1;
+                for i in 0..(*samples_count as usize) {
+                    let start = first_sample + STICK_CENTER_CALIBRATION_SIZE * i;
+                    let sample = data
+                        .get(start..start + STICK_CENTER_CALIBRATION_SIZE)
+                        .ok_or_else(too_short)?;
+                    samples.push(StickCenterCalibration {
+                        buf: fixed(sample, "Stick Center Sample")?,
+                    });
+                }
+            }
+            CalibrationData::StickCenter(calculated, samples)
+        }
+        CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::MinMax) => {
+            let calibration = data
+                .get(..STICK_MIN_MAX_CALIBRATION_SIZE)
+                .ok_or_else(too_short)?;
+            CalibrationData::StickMinMax(StickMinMaxCalibration {
+                buf: fixed(calibration, "Stick Min/Max")?,
+            })
+        }
+        CalibrationDeviceType::TriggerKey(_) => {
+            CalibrationData::Triggers(TriggersCalibration { buf: data })
+        }
+        CalibrationDeviceType::None => CalibrationData::None(data),
+        // Unclassified data is returned verbatim for the hex view
+        _ => {
+            info!(
+                "Unknown calibration data for {:?}: {}",
+                device,
+                hex::encode(&data)
+            );
+            CalibrationData::None(data)
+        }
+    })
+}
+
+// Reads until the queue is empty, the counter repeats or drain_count reports were read,
+// reports of other ids are skipped and counted as well. Every input report is tracked for loss
+// Only the first read waits up to the timeout, the reports queued behind it are read without
+// blocking so a drain doesn't hold the device thread for every empty slot
+pub fn drain_input_reports(
+    mut read: impl FnMut(&mut [u8], i32) -> Result<usize>,
+    timeout_ms: i32,
+    buf: &mut [u8],
+    connection_type: ConnectionType,
+    drain_count: usize,
+    mut observe: impl FnMut(&Data),
+) -> Result<Option<Data>> {
+    let mut last_filled: Option<Data> = None;
+
+    for i in 0..drain_count.max(1) {
+        let len = read(buf, if i == 0 { timeout_ms } else { 0 })?;
+        if len == 0 {
+            break;
+        }
+        let Some(data) = Data::from_input_report(&buf[..len], connection_type) else {
+            info!("Unexpected input report skipped: {:?}", &buf[..len]);
+            continue;
+        };
+        observe(&data);
+        if matches!(&last_filled, Some(last) if last.counter() == data.counter()) {
+            break;
+        }
+        last_filled = Some(data);
+    }
+
+    Ok(last_filled)
+}
+
+const CHUNK_HEADER_SIZE: usize = 5;
+const CHUNK_MAX_DATA_SIZE: u8 = 8;
+
+// Concatenates chunk data until the terminator key or the last chunk index is received
+pub fn reassemble_chunks<K: PartialEq + Debug>(
+    mut next_payload: impl FnMut() -> Result<Vec<u8>>,
+    name: &str,
+    key: impl Fn(&[u8]) -> Result<Option<K>>,
+) -> Result<(Option<K>, Vec<u8>)> {
+    let mut data: Vec<u8> = Vec::new();
+    let mut last_key: Option<K> = None;
+
+    loop {
+        let payload = next_payload()?;
+        if payload.len() < CHUNK_HEADER_SIZE {
+            return Err(format!("Invalid {} chunk size {}", name, payload.len()).into());
+        }
+        let chunks = payload[2];
+        let current_chunk = payload[3];
+        let data_len = payload[4];
+        let Some(current_key) = key(&payload)? else {
+            break;
+        };
+
+        if let Some(last_key) = &last_key {
+            if *last_key != current_key {
+                return Err(
+                    format!("Mismatch {} Type: {:?}  {:?}", name, last_key, current_key).into(),
+                );
+            }
+        }
+        let data_end = CHUNK_HEADER_SIZE + data_len as usize;
+        if data_len > CHUNK_MAX_DATA_SIZE || data_end > payload.len() {
+            return Err(format!("Invalid {} chunk len {}", name, data_len).into());
+        }
+        data.extend_from_slice(&payload[CHUNK_HEADER_SIZE..data_end]);
+        last_key = Some(current_key);
+        if current_chunk + 1 >= chunks {
+            break;
+        }
+    }
+
+    Ok((last_key, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dual_shock_4::button_chatter::ChatterDetector;
+    use crate::dual_shock_4::packet_loss::PacketLossTracker;
+    use crate::dual_shock_4::{
+        FaceButton, BLUETOOTH_INPUT_REPORT_ID, CALIBRATION_DATA_SIZE, DATA_PACKET_SIZE,
+        MAX_INPUT_REPORT_DRAIN_COUNT, USB_INPUT_REPORT_ID,
+    };
+
+    const TEST_READ_TIMEOUT_MS: i32 = 4;
+
+    fn input_report(id: u8, counter: u8) -> Vec<u8> {
+        let mut report = vec![0u8; DATA_PACKET_SIZE];
+        report[0] = id;
+        report[7] = counter << 2;
+        report
+    }
+
+    fn drain(reports: Vec<Vec<u8>>, connection_type: ConnectionType) -> Option<Data> {
+        drain_count(reports, connection_type, MAX_INPUT_REPORT_DRAIN_COUNT)
+    }
+
+    fn drain_count(
+        reports: Vec<Vec<u8>>,
+        connection_type: ConnectionType,
+        drain_count: usize,
+    ) -> Option<Data> {
+        drain_tracked(
+            reports,
+            connection_type,
+            drain_count,
+            &mut PacketLossTracker::default(),
+        )
+    }
+
+    fn drain_tracked(
+        reports: Vec<Vec<u8>>,
+        connection_type: ConnectionType,
+        drain_count: usize,
+        packet_loss: &mut PacketLossTracker,
+    ) -> Option<Data> {
+        drain_timed(reports, connection_type, drain_count, packet_loss).0
+    }
+
+    // Also returns the timeouts of the reads
+    fn drain_timed(
+        reports: Vec<Vec<u8>>,
+        connection_type: ConnectionType,
+        drain_count: usize,
+        packet_loss: &mut PacketLossTracker,
+    ) -> (Option<Data>, Vec<i32>) {
+        let mut reports = reports.into_iter();
+        let mut timeouts = Vec::new();
+        let mut buf = vec![0u8; connection_type.input_report_size()];
+        let data = drain_input_reports(
+            |buf, timeout_ms| {
+                timeouts.push(timeout_ms);
+                Ok(reports.next().map_or(0, |report| {
+                    buf[..report.len()].copy_from_slice(&report);
+                    report.len()
+                }))
+            },
+            TEST_READ_TIMEOUT_MS,
+            &mut buf,
+            connection_type,
+            drain_count,
+            |data| packet_loss.push(data.counter()),
+        )
+        .unwrap();
+        (data, timeouts)
+    }
+
+    #[test]
+    fn drain_input_reports_waits_only_for_first_report() {
+        let reports = vec![
+            input_report(USB_INPUT_REPORT_ID, 1),
+            input_report(USB_INPUT_REPORT_ID, 2),
+        ];
+        let (data, timeouts) = drain_timed(
+            reports,
+            ConnectionType::Usb,
+            MAX_INPUT_REPORT_DRAIN_COUNT,
+            &mut PacketLossTracker::default(),
+        );
+        assert_eq!(data.map(|data| data.counter()), Some(2));
+        assert_eq!(timeouts, [TEST_READ_TIMEOUT_MS, 0, 0]);
+    }
+
+    #[test]
+    fn taps_drained_together_are_not_chatter() {
+        // Presses and releases 50 ms apart, a stalled poll drains them in one batch
+        let mut reports = Vec::new();
+        for i in 0..12u8 {
+            let mut data = Data::zeroed();
+            data.buf[0] = USB_INPUT_REPORT_ID;
+            data.set_face_button(FaceButton::Cross, i % 2 == 0);
+            data.buf[7] = i << 2;
+            let timestamp = u16::from(i).wrapping_mul(9375);
+            data.buf[10..12].copy_from_slice(&timestamp.to_le_bytes());
+            reports.push(data.buf.to_vec());
+        }
+        let mut reports = reports.into_iter();
+        let mut buf = vec![0u8; DATA_PACKET_SIZE];
+        let mut detector = ChatterDetector::default();
+        drain_input_reports(
+            |buf, _| {
+                Ok(reports.next().map_or(0, |report| {
+                    buf[..report.len()].copy_from_slice(&report);
+                    report.len()
+                }))
+            },
+            TEST_READ_TIMEOUT_MS,
+            &mut buf,
+            ConnectionType::Usb,
+            MAX_INPUT_REPORT_DRAIN_COUNT,
+            |data| detector.push(data),
+        )
+        .unwrap();
+        assert_eq!(reports.len(), 0);
+        assert!(detector.counts().all(|(_, chatter)| chatter == 0));
+    }
+
+    #[test]
+    fn drain_input_reports_skips_other_report_ids() {
+        let data = drain(
+            vec![
+                input_report(USB_INPUT_REPORT_ID, 1),
+                input_report(BLUETOOTH_INPUT_REPORT_ID, 5),
+                input_report(0x05, 6),
+                input_report(USB_INPUT_REPORT_ID, 2),
+                input_report(0x00, 7),
+            ],
+            ConnectionType::Usb,
+        );
+        assert_eq!(data.map(|data| data.counter()), Some(2));
+    }
+
+    #[test]
+    fn drain_input_reports_stops_on_repeated_counter() {
+        let data = drain(
+            vec![
+                input_report(USB_INPUT_REPORT_ID, 1),
+                input_report(USB_INPUT_REPORT_ID, 1),
+                input_report(USB_INPUT_REPORT_ID, 3),
+            ],
+            ConnectionType::Usb,
+        );
+        assert_eq!(data.map(|data| data.counter()), Some(1));
+    }
+
+    #[test]
+    fn drain_input_reports_tracks_every_report() {
+        let mut packet_loss = PacketLossTracker::default();
+        let reports = |counters: &[u8]| -> Vec<Vec<u8>> {
+            counters
+                .iter()
+                .map(|counter| input_report(USB_INPUT_REPORT_ID, *counter))
+                .collect()
+        };
+        // Only the last report is returned, the skipped ones aren't lost
+        drain_tracked(
+            reports(&[1, 2, 3]),
+            ConnectionType::Usb,
+            8,
+            &mut packet_loss,
+        );
+        drain_tracked(
+            reports(&[3, 4, 6]),
+            ConnectionType::Usb,
+            8,
+            &mut packet_loss,
+        );
+        assert_eq!((packet_loss.received(), packet_loss.lost()), (5, 1));
+    }
+
+    #[test]
+    fn drain_input_reports_limited_by_drain_count() {
+        let reports: Vec<_> = (1..=8)
+            .map(|counter| input_report(USB_INPUT_REPORT_ID, counter))
+            .collect();
+        // Zero is treated as a single read
+        for count in 0..=MAX_INPUT_REPORT_DRAIN_COUNT {
+            let data = drain_count(reports.clone(), ConnectionType::Usb, count);
+            let expected = count.clamp(1, reports.len()) as u8;
+            assert_eq!(data.map(|data| data.counter()), Some(expected));
+        }
+        let repeated = vec![
+            input_report(USB_INPUT_REPORT_ID, 1),
+            input_report(USB_INPUT_REPORT_ID, 1),
+            input_report(USB_INPUT_REPORT_ID, 2),
+        ];
+        let data = drain_count(repeated, ConnectionType::Usb, 1);
+        assert_eq!(data.map(|data| data.counter()), Some(1));
+    }
+
+    #[test]
+    fn drain_input_reports_without_input_reports() {
+        let data = drain(
+            vec![input_report(0x05, 1), input_report(0xa3, 2)],
+            ConnectionType::Bluetooth,
+        );
+        assert!(data.is_none());
+    }
+
+    fn chunk(key: u8, chunks: u8, index: u8, data: &[u8]) -> Vec<u8> {
+        let mut payload = vec![key, 0x00, chunks, index, data.len() as u8];
+        payload.extend_from_slice(data);
+        payload.resize(CALIBRATION_DATA_SIZE, 0x00);
+        payload
+    }
+
+    fn reassemble(payloads: Vec<Vec<u8>>) -> Result<(Option<u8>, Vec<u8>)> {
+        let mut payloads = payloads.into_iter();
+        reassemble_chunks(
+            || Ok(payloads.next().expect("Unexpected chunk read")),
+            "Test",
+            |payload| Ok((payload[0] != 0xff).then_some(payload[0])),
+        )
+    }
+
+    #[test]
+    fn reassemble_chunks_concatenates_until_last_chunk() {
+        let result = reassemble(vec![
+            chunk(0x01, 3, 0, &[1, 2, 3, 4, 5, 6, 7, 8]),
+            chunk(0x01, 3, 1, &[9, 10, 11, 12, 13, 14, 15, 16]),
+            chunk(0x01, 3, 2, &[17, 18]),
+        ])
+        .unwrap();
+        assert_eq!(result, (Some(0x01), (1u8..=18).collect()));
+    }
+
+    #[test]
+    fn reassemble_chunks_stops_on_terminator() {
+        let result = reassemble(vec![chunk(0x01, 3, 0, &[1, 2]), chunk(0xff, 0, 0, &[])]).unwrap();
+        assert_eq!(result, (Some(0x01), vec![1, 2]));
+    }
+
+    #[test]
+    fn reassemble_chunks_returns_empty_on_immediate_terminator() {
+        let result = reassemble(vec![chunk(0xff, 0, 0, &[])]).unwrap();
+        assert_eq!(result, (None, vec![]));
+    }
+
+    #[test]
+    fn reassemble_chunks_stops_on_single_chunk() {
+        let result = reassemble(vec![chunk(0x02, 1, 0, &[7; 8])]).unwrap();
+        assert_eq!(result, (Some(0x02), vec![7; 8]));
+    }
+
+    #[test]
+    fn reassemble_chunks_rejects_key_mismatch() {
+        let result = reassemble(vec![chunk(0x01, 2, 0, &[1]), chunk(0x02, 2, 1, &[2])]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reassemble_chunks_rejects_invalid_data_len() {
+        let mut payload = chunk(0x01, 1, 0, &[]);
+        payload[4] = CHUNK_MAX_DATA_SIZE + 1;
+        assert!(reassemble(vec![payload]).is_err());
+    }
+
+    #[test]
+    fn reassemble_chunks_rejects_short_payload() {
+        assert!(reassemble(vec![vec![0x01, 0x00]]).is_err());
+    }
+
+    #[test]
+    fn firmware_info_fields() {
+        let mut payload = [0u8; 48];
+        payload[..11].copy_from_slice(b"Sep 21 2018");
+        payload[16..24].copy_from_slice(b"04:50:51");
+        payload[34..36].copy_from_slice(&[0x00, 0x01]);
+        payload[40..42].copy_from_slice(&[0x14, 0xa0]);
+        let info = firmware_info(&payload).unwrap();
+        assert_eq!(info.build_date(), "Sep 21 2018 04:50:51");
+        assert_eq!(info.hardware_version(), 0x0100);
+        assert_eq!(info.firmware_version(), 0xa014);
+        assert!(firmware_info(&payload[..40]).is_err());
+    }
+
+    #[test]
+    fn track_record_keeps_bytes() {
+        let payload: Vec<u8> = (0..48).collect();
+        let record = track_record(&payload).unwrap();
+        assert_eq!(record.buf[..], payload[..]);
+        assert!(track_record(&payload[..47]).is_err());
+    }
+
+    #[test]
+    fn bluetooth_input_report_is_shifted_to_usb_layout() {
+        #[rustfmt::skip]
+        let report = [
+            BLUETOOTH_INPUT_REPORT_ID, 0xc0, 0x00, // report id and Bluetooth header
+            0x7f, 0x80, 0x81, 0x82, // sticks
+            0x28, 0x00, 0x04, // buttons and counter
+            0x10, 0xff, // triggers
+        ];
+        let data = Data::from_input_report(&report, ConnectionType::Bluetooth).unwrap();
+        assert_eq!(
+            data.buf[..10],
+            [0x01, 0x7f, 0x80, 0x81, 0x82, 0x28, 0x00, 0x04, 0x10, 0xff]
+        );
+        assert_eq!(data.counter(), 1);
+        assert_eq!(data.r2_trigger(), 0xff);
+        assert!(Data::from_input_report(&report, ConnectionType::Usb).is_none());
+    }
+
+    #[test]
+    fn stick_center_calibration_data_from_chunks() {
+        let mut payloads = vec![
+            chunk(
+                0x01,
+                3,
+                0,
+                &[0x10, 0x00, 0xf0, 0xff, 0x05, 0x00, 0xfb, 0xff],
+            ),
+            chunk(
+                0x01,
+                3,
+                1,
+                &[0x01, 0x12, 0x00, 0xee, 0xff, 0x06, 0x00, 0xfa],
+            ),
+            chunk(0x01, 3, 2, &[0xff]),
+        ]
+        .into_iter()
+        .map(|mut payload| {
+            // Analog stick center
+            payload[1] = 0x01;
+            payload
+        });
+        let (device, data) = reassemble_chunks(
+            || Ok(payloads.next().expect("Unexpected chunk read")),
+            "Calibration Data",
+            calibration_chunk_key,
+        )
+        .unwrap();
+        let device = device.unwrap();
+        assert_eq!(
+            device,
+            CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::Center)
+        );
+
+        let CalibrationData::StickCenter(calculated, samples) =
+            calibration_data(device, data).unwrap()
+        else {
+            panic!("Stick center calibration expected");
+        };
+        assert_eq!(
+            calculated.buf,
+            [0x10, 0x00, 0xf0, 0xff, 0x05, 0x00, 0xfb, 0xff]
+        );
+        assert_eq!(samples.len(), 1);
+        assert_eq!(
+            samples[0].buf,
+            [0x12, 0x00, 0xee, 0xff, 0x06, 0x00, 0xfa, 0xff]
+        );
+    }
+
+    #[test]
+    fn short_calibration_data_is_an_error() {
+        let center = CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::Center);
+        let min_max = CalibrationDeviceType::AnalogStick(AnalogStickCalibrationType::MinMax);
+        assert!(calibration_data(center.clone(), vec![0x00; 4]).is_err());
+        // Two samples announced, one received
+        let mut data = vec![0x00; 8];
+        data.push(2);
+        data.extend_from_slice(&[0x00; 8]);
+        assert!(calibration_data(center, data).is_err());
+        assert!(calibration_data(min_max, vec![0x00; 15]).is_err());
+    }
+
+    #[test]
+    fn calibration_state_payloads() {
+        assert!(matches!(
+            calibration_state(&[0x01, 0x02, 0x01]).unwrap(),
+            CalibrationState::Started(CalibrationDeviceType::AnalogStick(
+                AnalogStickCalibrationType::MinMax
+            ))
+        ));
+        assert!(matches!(
+            calibration_state(&[0x00, 0x00, 0xff]).unwrap(),
+            CalibrationState::Unknown
+        ));
+        assert!(calibration_state(&[0x01, 0x02]).is_err());
+        assert!(calibration_flag(&[0x00; 3]).is_err());
+    }
+}
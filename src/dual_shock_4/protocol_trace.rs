@@ -0,0 +1,167 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::dual_shock_4::hid_report::ReportId;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+pub const TRACE_BUFFER_SIZE: usize = 256;
+
+// Reports carrying Bluetooth addresses of the controller and the paired host
+const SENSITIVE_REPORT_IDS: [ReportId; 4] = [
+    ReportId::GetParingInfo,
+    ReportId::SetParingInfo,
+    ReportId::SetBdAdr,
+    ReportId::GetBdAdr,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceDirection {
+    Get,
+    Set,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEntry {
+    pub report_id: u8,
+    pub direction: TraceDirection,
+    pub payload: String,
+    // Since the trace start
+    pub timestamp_ms: u64,
+    pub round_trip_us: u64,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub scrubbed: bool,
+}
+
+// Feature report exchanges, keeps only the last entries unless recording
+#[derive(Debug, Clone, Serialize)]
+pub struct ProtocolTrace {
+    started_at: DateTime<Local>,
+    serial_number: Option<String>,
+    entries: VecDeque<TraceEntry>,
+    #[serde(skip)]
+    started: Instant,
+    #[serde(skip)]
+    pub recording: bool,
+    // Zero Bluetooth addresses and drop the serial number on export
+    #[serde(skip)]
+    pub scrub_sensitive: bool,
+}
+
+impl ProtocolTrace {
+    pub fn new(serial_number: Option<String>) -> Self {
+        Self {
+            started_at: Local::now(),
+            serial_number,
+            entries: VecDeque::new(),
+            started: Instant::now(),
+            recording: false,
+            scrub_sensitive: true,
+        }
+    }
+
+    // Report id is the first byte of the data, as sent to or received from the device
+    pub fn push(&mut self, direction: TraceDirection, data: &[u8], exchange_started: Instant) {
+        let Some((report_id, payload)) = data.split_first() else {
+            return;
+        };
+        self.entries.push_back(TraceEntry {
+            report_id: *report_id,
+            direction,
+            payload: hex::encode(payload),
+            timestamp_ms: exchange_started.duration_since(self.started).as_millis() as u64,
+            round_trip_us: exchange_started.elapsed().as_micros() as u64,
+            scrubbed: false,
+        });
+        if !self.recording {
+            while self.entries.len() > TRACE_BUFFER_SIZE {
+                self.entries.pop_front();
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.started_at = Local::now();
+        self.started = Instant::now();
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn scrub(&mut self) {
+        self.serial_number = None;
+        for entry in &mut self.entries {
+            if SENSITIVE_REPORT_IDS
+                .iter()
+                .any(|id| id.clone() as u8 == entry.report_id)
+            {
+                entry.payload = "0".repeat(entry.payload.len());
+                entry.scrubbed = true;
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        if self.scrub_sensitive {
+            let mut trace = self.clone();
+            trace.scrub();
+            serde_json::to_string_pretty(&trace)
+        } else {
+            serde_json::to_string_pretty(self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded_unless_recording() {
+        let mut trace = ProtocolTrace::new(None);
+        for _ in 0..TRACE_BUFFER_SIZE + 10 {
+            trace.push(TraceDirection::Get, &[0x11, 0x01, 0x02], Instant::now());
+        }
+        assert_eq!(trace.len(), TRACE_BUFFER_SIZE);
+
+        trace.recording = true;
+        for _ in 0..10 {
+            trace.push(TraceDirection::Get, &[0x11, 0x01, 0x02], Instant::now());
+        }
+        assert_eq!(trace.len(), TRACE_BUFFER_SIZE + 10);
+    }
+
+    #[test]
+    fn scrub_sensitive_fields() {
+        let mut trace = ProtocolTrace::new(Some("1c:a0:b8:00:00:01".to_string()));
+        trace.push(
+            TraceDirection::Get,
+            &[0x12, 0x01, 0x00, 0x00, 0xb8, 0xa0, 0x1c],
+            Instant::now(),
+        );
+        trace.push(
+            TraceDirection::Set,
+            &[0x08, 0xff, 0x00, 0x0c],
+            Instant::now(),
+        );
+
+        let json = trace.to_json().unwrap();
+        assert!(!json.contains("1c:a0:b8"));
+        assert!(!json.contains("b8a01c"));
+        assert!(json.contains("ff000c"));
+
+        trace.scrub_sensitive = false;
+        let json = trace.to_json().unwrap();
+        assert!(json.contains("1c:a0:b8"));
+        assert!(json.contains("b8a01c"));
+    }
+}
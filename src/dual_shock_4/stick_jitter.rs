@@ -0,0 +1,144 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::dual_shock_4::device_clock::DeviceClock;
+use crate::dual_shock_4::Data;
+use std::collections::VecDeque;
+use std::f64::consts::TAU;
+
+// About half a second over USB and two seconds over Bluetooth
+pub const JITTER_WINDOW_SAMPLES: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum JitterAxis {
+    #[default]
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+}
+
+impl JitterAxis {
+    pub const ALL: [JitterAxis; 4] = [
+        JitterAxis::LeftX,
+        JitterAxis::LeftY,
+        JitterAxis::RightX,
+        JitterAxis::RightY,
+    ];
+
+    fn value(&self, data: &Data) -> u8 {
+        match self {
+            JitterAxis::LeftX => data.left_stick_position().x,
+            JitterAxis::LeftY => data.left_stick_position().y,
+            JitterAxis::RightX => data.right_stick_position().x,
+            JitterAxis::RightY => data.right_stick_position().y,
+        }
+    }
+}
+
+// Stick axes of the latest input reports with their device time, fed by the device thread so
+// every report is seen at the native rate
+#[derive(Debug, Clone, Default)]
+pub struct StickJitter {
+    clock: DeviceClock,
+    samples: VecDeque<(f64, [u8; 4])>,
+}
+
+impl StickJitter {
+    pub fn push(&mut self, data: &Data) {
+        let seconds = self.clock.push(data.timestamp());
+        if self.samples.len() == JITTER_WINDOW_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples
+            .push_back((seconds, JitterAxis::ALL.map(|axis| axis.value(data))));
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    // Mean report rate of the window in Hz
+    pub fn sample_rate(&self) -> Option<f64> {
+        let (first, _) = self.samples.front()?;
+        let (last, _) = self.samples.back()?;
+        let duration = last - first;
+        (duration > 0f64).then(|| (self.samples.len() - 1) as f64 / duration)
+    }
+
+    // Frequency in Hz and amplitude in raw axis units, up to half of the mean rate. Every
+    // sample is taken at its own time, so a changing polling rate doesn't smear the peaks
+    pub fn spectrum(&self, axis: JitterAxis) -> Vec<[f64; 2]> {
+        let Some(sample_rate) = self.sample_rate() else {
+            return Vec::new();
+        };
+        let index = JitterAxis::ALL.iter().position(|a| *a == axis).unwrap_or(0);
+        let count = self.samples.len();
+        let mean = self
+            .samples
+            .iter()
+            .map(|(_, axes)| f64::from(axes[index]))
+            .sum::<f64>()
+            / count as f64;
+        (1..=count / 2)
+            .map(|bin| {
+                let frequency = bin as f64 * sample_rate / count as f64;
+                let (re, im) = self
+                    .samples
+                    .iter()
+                    .fold((0f64, 0f64), |(re, im), (t, axes)| {
+                        let value = f64::from(axes[index]) - mean;
+                        let angle = TAU * frequency * t;
+                        (re + value * angle.cos(), im - value * angle.sin())
+                    });
+                [frequency, 2f64 * re.hypot(im) / count as f64]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dual_shock_4::device_clock::TIMESTAMP_TICK_SECONDS;
+    use crate::dual_shock_4::StickPosition;
+
+    fn data(timestamp: u16, left_x: u8) -> Data {
+        let mut data = Data::zeroed();
+        data.set_left_stick_position(StickPosition { x: left_x, y: 128 });
+        data.buf[10..12].copy_from_slice(&timestamp.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn spectrum_peak_with_variable_polling_rate() {
+        let mut jitter = StickJitter::default();
+        let mut ticks = 0u32;
+        // 50 Hz oscillation of 4 units, reports alternate between 1 and 3 ms apart
+        for i in 0..JITTER_WINDOW_SAMPLES {
+            let seconds = f64::from(ticks) * TIMESTAMP_TICK_SECONDS;
+            let value = 128f64 + 4f64 * (TAU * 50f64 * seconds).sin();
+            jitter.push(&data(ticks as u16, value.round() as u8));
+            ticks += if i % 2 == 0 { 188 } else { 563 };
+        }
+        assert_eq!(jitter.len(), JITTER_WINDOW_SAMPLES);
+        let sample_rate = jitter.sample_rate().unwrap();
+        assert!((sample_rate - 500f64).abs() < 5f64);
+        let spectrum = jitter.spectrum(JitterAxis::LeftX);
+        let [frequency, amplitude] = spectrum
+            .iter()
+            .copied()
+            .max_by(|a, b| a[1].total_cmp(&b[1]))
+            .unwrap();
+        assert!((frequency - 50f64).abs() < 2f64);
+        assert!((amplitude - 4f64).abs() < 1f64);
+        assert!(jitter
+            .spectrum(JitterAxis::RightY)
+            .iter()
+            .all(|[_, amplitude]| *amplitude < 1e-9));
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::dual_shock_4::TouchPoint;
+
+// Shorter movements are treated as jitter of a resting finger
+const SWIPE_MIN_DISTANCE: i32 = 300;
+const TAP_MAX_DISTANCE: i32 = 60;
+// Both axes move for diagonal swipes, the main axis must clearly dominate
+const SWIPE_MIN_AXIS_RATIO: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    Tap {
+        fingers: usize,
+    },
+    Swipe {
+        direction: SwipeDirection,
+        fingers: usize,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Track {
+    id: u8,
+    start: (i32, i32),
+    last: (i32, i32),
+}
+
+impl Track {
+    fn movement(&self) -> (i32, i32) {
+        (self.last.0 - self.start.0, self.last.1 - self.start.1)
+    }
+}
+
+// Recognizes a gesture when all fingers are lifted from the touchpad
+#[derive(Debug, Default)]
+pub struct TouchGesture {
+    last_packet_counter: Option<u8>,
+    active: [Option<Track>; 2],
+    finished: Vec<Track>,
+}
+
+impl TouchGesture {
+    pub fn update(
+        &mut self,
+        packet_counter: u8,
+        points: [Option<TouchPoint>; 2],
+    ) -> Option<Gesture> {
+        if self.last_packet_counter == Some(packet_counter) {
+            return None;
+        }
+        self.last_packet_counter = Some(packet_counter);
+
+        for (slot, point) in self.active.iter_mut().zip(points) {
+            match (slot.as_mut(), point) {
+                (Some(track), Some(point)) if track.id == point.id => {
+                    track.last = (point.x.into(), point.y.into());
+                }
+                (_, point) => {
+                    if let Some(track) = slot.take() {
+                        self.finished.push(track);
+                    }
+                    *slot = point.map(|point| Track {
+                        id: point.id,
+                        start: (point.x.into(), point.y.into()),
+                        last: (point.x.into(), point.y.into()),
+                    });
+                }
+            }
+        }
+
+        if self.active.iter().any(Option::is_some) || self.finished.is_empty() {
+            return None;
+        }
+        let tracks = std::mem::take(&mut self.finished);
+        recognize(&tracks)
+    }
+}
+
+fn recognize(tracks: &[Track]) -> Option<Gesture> {
+    let fingers = tracks.len();
+    let movements: Vec<(i32, i32)> = tracks.iter().map(Track::movement).collect();
+    if movements
+        .iter()
+        .all(|(dx, dy)| dx.abs() <= TAP_MAX_DISTANCE && dy.abs() <= TAP_MAX_DISTANCE)
+    {
+        return Some(Gesture::Tap { fingers });
+    }
+
+    // All fingers must swipe the same way
+    let directions: Vec<Option<SwipeDirection>> = movements
+        .iter()
+        .map(|(dx, dy)| swipe_direction(*dx, *dy))
+        .collect();
+    match directions[0] {
+        Some(direction) if directions.iter().all(|other| *other == Some(direction)) => {
+            Some(Gesture::Swipe { direction, fingers })
+        }
+        _ => None,
+    }
+}
+
+fn swipe_direction(dx: i32, dy: i32) -> Option<SwipeDirection> {
+    if dx.abs() >= SWIPE_MIN_DISTANCE && dx.abs() >= dy.abs() * SWIPE_MIN_AXIS_RATIO {
+        Some(if dx > 0 {
+            SwipeDirection::Right
+        } else {
+            SwipeDirection::Left
+        })
+    } else if dy.abs() >= SWIPE_MIN_DISTANCE && dy.abs() >= dx.abs() * SWIPE_MIN_AXIS_RATIO {
+        Some(if dy > 0 {
+            SwipeDirection::Down
+        } else {
+            SwipeDirection::Up
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(id: u8, x: u16, y: u16) -> Option<TouchPoint> {
+        Some(TouchPoint { id, x, y })
+    }
+
+    fn feed(frames: Vec<[Option<TouchPoint>; 2]>) -> Vec<Gesture> {
+        let mut gesture = TouchGesture::default();
+        frames
+            .into_iter()
+            .enumerate()
+            .filter_map(|(counter, points)| gesture.update(counter as u8, points))
+            .collect()
+    }
+
+    #[test]
+    fn one_finger_swipe_and_tap() {
+        let gestures = feed(vec![
+            [point(1, 1500, 400), None],
+            [point(1, 1000, 450), None],
+            [point(1, 600, 420), None],
+            [None, None],
+            [point(2, 900, 500), None],
+            [point(2, 920, 480), None],
+            [None, None],
+        ]);
+        assert_eq!(
+            gestures,
+            vec![
+                Gesture::Swipe {
+                    direction: SwipeDirection::Left,
+                    fingers: 1
+                },
+                Gesture::Tap { fingers: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn two_finger_swipe() {
+        let gestures = feed(vec![
+            [point(1, 600, 100), point(2, 1200, 120)],
+            [point(1, 610, 500), point(2, 1190, 520)],
+            // Fingers are rarely lifted in the same frame
+            [None, point(2, 1180, 800)],
+            [None, None],
+        ]);
+        assert_eq!(
+            gestures,
+            vec![Gesture::Swipe {
+                direction: SwipeDirection::Down,
+                fingers: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn micro_movement_is_not_a_swipe() {
+        let gestures = feed(vec![
+            [point(1, 900, 400), None],
+            [point(1, 1000, 460), None],
+            [point(1, 1100, 400), None],
+            [None, None],
+        ]);
+        assert!(gestures.is_empty());
+    }
+
+    #[test]
+    fn stale_packet_is_ignored() {
+        let mut gesture = TouchGesture::default();
+        assert_eq!(gesture.update(1, [point(1, 100, 400), None]), None);
+        // Coordinates of a repeated packet counter are not processed
+        assert_eq!(gesture.update(1, [point(1, 1000, 400), None]), None);
+        assert_eq!(
+            gesture.update(2, [None, None]),
+            Some(Gesture::Tap { fingers: 1 })
+        );
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2023 Anton Kharuzhyi <publicantroids@gmail.com>
+// SPDX-License-Identifier: GPL-3.0
+
+use crate::application::find_device;
+use crate::dual_shock_4::{DeviceOptions, DualShock4, Result, FLASH_MIRROR_SIZE};
+use hidapi::HidApi;
+use log::{info, warn};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+const INTEL_HEX_EXTENSIONS: [&str; 3] = ["hex", "ihex", "ihx"];
+// Repeated backups to the same name get a numeric suffix instead of failing
+const MAX_NUMBERED_DUMPS: u32 = 1000;
+
+// Reads the flash of the device matching the serial number or path, or of the first
+// connected controller, without starting the GUI
+pub fn dump_flash(
+    device: Option<&str>,
+    out: &Path,
+    ignore_crc: bool,
+    options: DeviceOptions,
+) -> Result<()> {
+    let api = HidApi::new()?;
+    let id = find_device(&api, device).map_err(|e| e.to_string())?;
+    info!("Reading flash of {}", id.path().to_string_lossy());
+
+    let path = id.path().clone();
+    let hid_device = api.open_path(&path)?;
+    let ds4 = DualShock4::new(path, hid_device, options)?;
+    let mut last_percent = None;
+    let flash_mirror = ds4.read_flash_mirror_with_progress(|read| {
+        let percent = read.bytes_read() * 100 / FLASH_MIRROR_SIZE;
+        if last_percent.replace(percent) != Some(percent) && percent % 10 == 0 {
+            match read.eta() {
+                Some(eta) if !read.is_finished() => {
+                    info!("{}%, about {:.0}s left", percent, eta.as_secs_f32().ceil())
+                }
+                _ => info!("{}%", percent),
+            }
+        }
+    })?;
+
+    if !flash_mirror.check_crc() {
+        if !ignore_crc {
+            return Err(
+                "Flash CRC mismatch, use --ignore-crc to save the dump anyway"
+                    .to_string()
+                    .into(),
+            );
+        }
+        warn!("Flash CRC mismatch, saving anyway");
+    }
+
+    let intel_hex = out
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| INTEL_HEX_EXTENSIONS.contains(&extension));
+    let bytes = if intel_hex {
+        flash_mirror.to_intel_hex().into_bytes()
+    } else {
+        flash_mirror.buf.to_vec()
+    };
+    let (saved, mut file) =
+        create_unused(out).map_err(|e| format!("Cannot create {}: {}", out.display(), e))?;
+    file.write_all(&bytes)
+        .map_err(|e| format!("Cannot write {}: {}", saved.display(), e))?;
+    info!("Flash dump saved to {}", saved.display());
+    Ok(())
+}
+
+// Existing files are kept, the first free name of out, out-1, out-2.. is created
+fn create_unused(out: &Path) -> std::io::Result<(PathBuf, File)> {
+    for number in 0..MAX_NUMBERED_DUMPS {
+        let path = numbered_path(out, number);
+        match OpenOptions::new().create_new(true).write(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(std::io::Error::new(
+        ErrorKind::AlreadyExists,
+        format!("{} numbered dumps already exist", MAX_NUMBERED_DUMPS),
+    ))
+}
+
+fn numbered_path(out: &Path, number: u32) -> PathBuf {
+    if number == 0 {
+        return out.to_path_buf();
+    }
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match out.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, number),
+    };
+    out.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_dumps_are_numbered() {
+        let dir = std::env::temp_dir().join(format!("ds4-gui-flash-dump-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let out = dir.join("backup.hex");
+        let saved: Vec<PathBuf> = (0..3).map(|_| create_unused(&out).unwrap().0).collect();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(
+            saved,
+            [
+                out.clone(),
+                dir.join("backup-1.hex"),
+                dir.join("backup-2.hex")
+            ]
+        );
+        assert_eq!(numbered_path(Path::new("dump"), 3), Path::new("dump-3"));
+    }
+}
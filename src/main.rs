@@ -3,65 +3,213 @@
 
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use crate::application::Application;
-use clap::Parser;
-use log::LevelFilter;
-use simplelog::{ColorChoice, CombinedLogger, Config, TermLogger, TerminalMode, WriteLogger};
+use crate::application::{Application, Error, Options};
+use crate::dual_shock_4::{
+    ConnectionType, DeviceOptions, LOG_TARGET_FLASH_WORDS, LOG_TARGET_REPORTS,
+};
+use clap::{Parser, ValueEnum};
+use log::{error, warn, LevelFilter};
+use simplelog::{
+    ColorChoice, CombinedLogger, Config, ConfigBuilder, TermLogger, TerminalMode, WriteLogger,
+};
 use std::fs;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 mod application;
+mod data_log_dump;
 mod dual_shock_4;
+mod flash_dump;
 
 const APPLICATION_DIR: &str = "ds4-gui";
 const LOG_FILE_NAME: &str = "ds4-gui.log";
+const SETTINGS_FILE_NAME: &str = "settings.json";
+const WRITE_CHECK_FILE_NAME: &str = ".write-check";
+const LOG_ROTATION_SLOTS: u16 = 999;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     #[arg(short, long)]
     log_dir: Option<String>,
+    /// Directory for logs, settings and other application state
+    #[arg(long, env = "DS4_GUI_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+    /// Log reports that would be sent to the device instead of sending them
+    #[arg(long)]
+    dry_run: bool,
+    /// Save the flash dump to the file and exit, Intel HEX for .hex/.ihex/.ihx and raw otherwise.
+    /// An existing file is kept and a numeric suffix is added to the new name
+    #[arg(long, value_name = "OUT")]
+    dump_flash: Option<PathBuf>,
+    /// Serial number or path of the controller to open, or to dump with --dump-flash where the
+    /// first one is the default
+    #[arg(long)]
+    device: Option<String>,
+    /// Save the flash dump even if its CRC is invalid
+    #[arg(long, requires = "dump_flash")]
+    ignore_crc: bool,
+    /// Print the frames of a binary data log recorded in the Output panel as CSV and exit
+    #[arg(long, value_name = "LOG")]
+    print_data_log: Option<PathBuf>,
+    /// Assume the USB or Bluetooth report layout instead of detecting it from the bus type
+    #[arg(long, value_name = "usb|bluetooth")]
+    connection_type: Option<ConnectionType>,
+    /// Hide the flash and test commands and disable calibration writes until the restart
+    #[arg(long)]
+    safe: bool,
+    /// Start with the compact borderless view of the inputs for window capture, F9 toggles it
+    #[arg(long)]
+    overlay: bool,
+    /// Show advanced diagnostics meant for development
+    #[arg(long)]
+    dev: bool,
+    /// Leave the per-report lines of the categories out of the log, errors are always logged
+    #[arg(long, value_name = "CATEGORY", value_enum, value_delimiter = ',')]
+    quiet_log: Vec<LogCategory>,
 }
 
-fn main() -> application::Result<()> {
-    let args = Args::parse();
-    let default_log_dir = dirs::data_local_dir().unwrap().join(APPLICATION_DIR);
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LogCategory {
+    /// Every feature report sent to or received from the device
+    Reports,
+    /// Flash word reads and writes, a flash read or write still logs a summary line
+    FlashWords,
+}
 
-    let log_dir = args
-        .log_dir
-        .unwrap_or(default_log_dir.to_str().unwrap().to_string());
-    let log_dir = Path::new(&log_dir);
-    if !log_dir.exists() {
-        fs::create_dir_all(&log_dir).expect("Cannot create log dir");
+impl LogCategory {
+    fn target(&self) -> &'static str {
+        match self {
+            LogCategory::Reports => LOG_TARGET_REPORTS,
+            LogCategory::FlashWords => LOG_TARGET_FLASH_WORDS,
+        }
     }
-    let log_file = log_dir.join(LOG_FILE_NAME);
-    if log_file.exists() {
-        for i in 0u16..999 {
-            let mut rename_to = log_file.clone();
-            rename_to.set_extension(i.to_string());
-            if !rename_to.exists() {
-                fs::rename(&log_file, rename_to).unwrap();
-                break;
-            }
+}
+
+fn log_config(quiet: &[LogCategory]) -> Config {
+    let mut builder = ConfigBuilder::new();
+    for category in quiet {
+        builder.add_filter_ignore_str(category.target());
+    }
+    builder.build()
+}
+
+// Startup errors are logged as one line instead of a panic. Before the logger is up there is no
+// log file and the release build on Windows has no console, so the error is shown in a dialog
+fn main() {
+    if let Err(e) = run(Args::parse()) {
+        if log::max_level() != LevelFilter::Off {
+            error!("{}", e);
+        } else {
+            eprintln!("{}", e);
+            #[cfg(all(windows, not(debug_assertions)))]
+            rfd::MessageDialog::new()
+                .set_level(rfd::MessageLevel::Error)
+                .set_title("DS4 Utils")
+                .set_description(e.to_string())
+                .show();
         }
+        std::process::exit(1);
     }
+}
+
+fn run(args: Args) -> application::Result<()> {
+    let data_dir = match args.data_dir {
+        Some(data_dir) => data_dir,
+        None => dirs::data_local_dir()
+            .ok_or_else(|| {
+                Error::Startup("Cannot find the local data directory, use --data-dir".to_string())
+            })?
+            .join(APPLICATION_DIR),
+    };
+    prepare_data_dir(&data_dir)
+        .map_err(|e| Error::Io(format!("Cannot use data directory {:?}", data_dir), e))?;
+
+    let log_dir = args.log_dir.map(PathBuf::from).unwrap_or(data_dir.clone());
+    fs::create_dir_all(&log_dir)
+        .map_err(|e| Error::Io(format!("Cannot create log dir {:?}", log_dir), e))?;
+    let log_file = log_dir.join(LOG_FILE_NAME);
+    let rotated = rotate_log(&log_file)?;
+    let log = File::create(&log_file)
+        .map_err(|e| Error::Io(format!("Cannot create log file {:?}", log_file), e))?;
 
+    let log_config = log_config(&args.quiet_log);
+    // The command line modes report progress on stderr and keep stdout for their output
+    let terminal_mode = if args.dump_flash.is_some() || args.print_data_log.is_some() {
+        TerminalMode::Stderr
+    } else {
+        TerminalMode::Mixed
+    };
     CombinedLogger::init(vec![
         TermLogger::new(
             LevelFilter::Debug,
-            Config::default(),
-            TerminalMode::Mixed,
+            log_config.clone(),
+            terminal_mode,
             ColorChoice::Auto,
         ),
-        WriteLogger::new(
-            LevelFilter::Info,
-            Config::default(),
-            File::create(log_file).unwrap(),
-        ),
-    ])
-    .unwrap();
+        WriteLogger::new(LevelFilter::Info, log_config, log),
+    ])?;
+    if !rotated {
+        warn!(
+            "All {} log rotation slots are taken, the previous log was overwritten",
+            LOG_ROTATION_SLOTS
+        );
+    }
+
+    let device_options = DeviceOptions {
+        dry_run: args.dry_run,
+        connection_type: args.connection_type,
+        safe_mode: args.safe,
+        ..Default::default()
+    };
+    if let Some(out) = &args.dump_flash {
+        return flash_dump::dump_flash(
+            args.device.as_deref(),
+            out,
+            args.ignore_crc,
+            device_options,
+        )
+        .map_err(|e| Error::Startup(format!("Flash dump failed: {}", e)));
+    }
+    if let Some(log) = &args.print_data_log {
+        return data_log_dump::print_data_log(log, std::io::stdout().lock())
+            .map_err(|e| Error::Io(format!("Cannot print data log {:?}", log), e));
+    }
+
+    Application::show(Options {
+        device: device_options,
+        settings_file: data_dir.join(SETTINGS_FILE_NAME),
+        data_dir,
+        log_file,
+        connection_type: args.connection_type,
+        overlay: args.overlay,
+        safe_mode: args.safe,
+        dev: args.dev,
+        startup_device: args.device,
+    })
+}
+
+// Renames an existing log to the first free numbered slot, returns false when all are taken
+fn rotate_log(log_file: &Path) -> application::Result<bool> {
+    if !log_file.exists() {
+        return Ok(true);
+    }
+    for i in 0..LOG_ROTATION_SLOTS {
+        let rename_to = log_file.with_extension(i.to_string());
+        if !rename_to.exists() {
+            fs::rename(log_file, &rename_to)
+                .map_err(|e| Error::Io(format!("Cannot rotate log file to {:?}", rename_to), e))?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
 
-    Application::show()
+// Creates the directory if missing and checks that files can be written there
+fn prepare_data_dir(data_dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(data_dir)?;
+    let check_file = data_dir.join(WRITE_CHECK_FILE_NAME);
+    File::create(&check_file)?;
+    fs::remove_file(check_file)
 }